@@ -8,3 +8,22 @@ feel-useful-tip-me = Feel it useful? Tip me:
 latest-posts = Latest Posts
 view-all-posts = View all posts
 recent-posts = Recent Posts
+search = Search
+search-placeholder = Search...
+search-result-count = Found { $count } posts
+search-no-results = No posts match your search.
+comment-count = { $count ->
+    [0] No comments yet
+    [one] 1 comment
+   *[other] { $count } comments
+}
+comment-name = Name
+comment-email = Email (not shown)
+comment-body = Your comment, Markdown is supported
+comment-submit = Send
+comment-sent = Thank you! Your comment will appear after being approved.
+comment-invalid = Please enter your name, a valid email and the comment.
+comment-too-many = You are commenting too fast. Please try again later.
+tags = Tags
+posts-tagged = Posts tagged "{ $tag }"
+also-available-in = Also available in:
@@ -8,3 +8,21 @@ feel-useful-tip-me = Bài viết có ích phải không? Tặng thưởng:
 latest-posts = Bài viết mới nhất
 view-all-posts = Xem tất cả bài viết
 recent-posts = Bài viết gần đây
+search = Tìm kiếm
+search-placeholder = Tìm kiếm...
+search-result-count = Tìm thấy { $count } bài viết
+search-no-results = Không có bài viết nào khớp với từ khoá.
+comment-count = { $count ->
+    [0] Chưa có bình luận
+   *[other] { $count } bình luận
+}
+comment-name = Tên
+comment-email = Email (không hiển thị)
+comment-body = Bình luận của bạn, có thể dùng Markdown
+comment-submit = Gửi
+comment-sent = Cảm ơn bạn! Bình luận sẽ hiện ra sau khi được duyệt.
+comment-invalid = Vui lòng nhập tên, email hợp lệ và nội dung bình luận.
+comment-too-many = Bạn bình luận nhanh quá. Vui lòng thử lại sau.
+tags = Thẻ
+posts-tagged = Bài viết gắn thẻ "{ $tag }"
+also-available-in = Bài này còn có bản:
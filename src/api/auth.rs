@@ -1,28 +1,73 @@
-use axum::extract::State;
-use axum::http::StatusCode;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use axum::extract::{FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::{Json, debug_handler, response::Result as AxumResult};
 use axum_extra::extract::WithRejection;
-use gel_tokio::Client;
+use axum_login::AuthzBackend;
+use fred::prelude::Pool;
 use serde_json::Value;
+use tower_sessions::Session;
 use tracing::{debug, info};
-use validify::Validate;
+use validify::{Validate, ValidationError, ValidationErrors};
 
 use super::errors::ApiError;
-use crate::auth::AuthSession;
-use crate::auth::backend::Credentials;
+use super::structs::{
+    ForgotPasswordData, LoginUnlockData, PasswordResetData, SecondFactorData, SecondFactorRequired,
+};
+use super::two_factor::wrong_code;
+use crate::auth::backend::{Backend, Credentials};
+use crate::auth::lockout::Attempt;
+use crate::auth::permissions::Permission;
 use crate::auth::structs::LoginReqData;
+use crate::auth::totp::{self, PendingLogin};
+use crate::auth::{AuthSession, hash_password, lockout, reset, tokens};
+use crate::mail::{Email, MailSender};
 use crate::models::User;
+use crate::models::tokens::TokenScope;
+use crate::stores;
+use crate::types::AppState;
+use crate::utils::ratelimit::{check_rate_limit, get_client_ip};
+
+// Each IP address can ask for this many reset emails in the window
+const FORGOT_PASSWORD_RATE_LIMIT: u32 = 5;
+const FORGOT_PASSWORD_RATE_WINDOW: Duration = Duration::from_secs(3600);
+// Each email address can receive this many in the window, from whichever IP the requests come
+const FORGOT_PASSWORD_EMAIL_LIMIT: u32 = 3;
 
+/// Log in with email and password.
+/// Failed attempts are answered more and more slowly, then the email or IP is locked out for a while.
+/// Users with two-factor authentication get 202 and finish with `login_second_factor`.
 #[debug_handler]
 pub async fn login(
     mut auth_session: AuthSession,
-    State(_db): State<Client>,
+    session: Session,
+    headers: HeaderMap,
+    State(redis): State<Pool>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
-) -> AxumResult<Json<User>> {
+) -> AxumResult<Response> {
     let login_data: LoginReqData =
         serde_json::from_value(value).map_err(ApiError::JsonExtractionError)?;
     login_data.validate().map_err(ApiError::ValidationErrors)?;
     info!("Validated request data: {:?}", login_data);
+    let email = lockout::normalize_email(&login_data.email);
+    let ip_address = get_client_ip(&headers);
+    let ip = ip_address.as_deref();
+    // The attempt is counted and delayed before the password is checked,
+    // so that parallel guesses are slowed down too.
+    match lockout::claim_attempt(&email, ip, &redis)
+        .await
+        .map_err(ApiError::Redis)?
+    {
+        Attempt::Locked(remaining) => {
+            info!("Login for {email} from {ip:?} is locked out");
+            Err(ApiError::LockedOut(remaining))?;
+        }
+        Attempt::Allowed { delay } => tokio::time::sleep(delay).await,
+    }
     let cred = Credentials {
         email: login_data.email.clone(),
         password: login_data.password.expose_secret().clone(),
@@ -30,12 +75,114 @@ pub async fn login(
     let user = auth_session
         .authenticate(cred)
         .await
-        .map_err(|e| ApiError::LoginError(e.to_string()))?
-        .ok_or_else(|| {
-            info!("Failed to authenticate");
-            ApiError::LoginError("Wrong email or password".into())
-        })?;
-    info!("Logging in user: {:?}", user);
+        .map_err(|e| ApiError::LoginError(e.to_string()))?;
+    let Some(user) = user else {
+        info!("Failed to authenticate");
+        Err(ApiError::LoginError("Wrong email or password".into()))?
+    };
+    if user.has_2fa() {
+        // The session is only logged in when the second factor is given
+        info!("Waiting for second factor of {}", user.email);
+        // The second step claims its own attempt, which is taken back on success
+        lockout::release_ip_attempt(ip, &redis)
+            .await
+            .map_err(ApiError::Redis)?;
+        let pending = PendingLogin::new(user.id, email);
+        session
+            .insert(totp::PENDING_LOGIN_KEY, pending)
+            .await
+            .map_err(ApiError::Session)?;
+        let resp = SecondFactorRequired {
+            second_factor_required: true,
+        };
+        return Ok((StatusCode::ACCEPTED, Json(resp)).into_response());
+    }
+    lockout::clear_failures(&email, ip, &redis)
+        .await
+        .map_err(ApiError::Redis)?;
+    info!("Logging in user: {}", user.email);
+    auth_session.login(&user).await.map_err(|e| {
+        tracing::error!("Error logging in user: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(user).into_response())
+}
+
+/// Second step of login, for users with two-factor authentication.
+/// Takes a code from the authenticator app, or one of the recovery codes.
+/// Wrong codes count as failed logins, like wrong passwords.
+pub async fn login_second_factor(
+    mut auth_session: AuthSession,
+    session: Session,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    WithRejection(Json(data), _): WithRejection<Json<SecondFactorData>, ApiError>,
+) -> AxumResult<Json<User>> {
+    let AppState { db, redis, .. } = state;
+    let pending: PendingLogin = session
+        .get(totp::PENDING_LOGIN_KEY)
+        .await
+        .map_err(ApiError::Session)?
+        .filter(|p: &PendingLogin| !p.is_expired())
+        .ok_or(ApiError::Unauthorized)?;
+    let ip_address = get_client_ip(&headers);
+    let ip = ip_address.as_deref();
+    match lockout::claim_attempt(&pending.email, ip, &redis)
+        .await
+        .map_err(ApiError::Redis)?
+    {
+        Attempt::Locked(remaining) => {
+            session
+                .remove::<PendingLogin>(totp::PENDING_LOGIN_KEY)
+                .await
+                .map_err(ApiError::Session)?;
+            Err(ApiError::LockedOut(remaining))?;
+        }
+        Attempt::Allowed { delay } => tokio::time::sleep(delay).await,
+    }
+    let user = stores::user::get_user(pending.user_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .filter(|u| u.is_active)
+        .ok_or(ApiError::Unauthorized)?;
+    // 2FA may be turned off meanwhile, then the password step is to be done again
+    let secret = user.totp_secret.as_deref().ok_or(ApiError::Unauthorized)?;
+    let passed = match (data.code.as_deref(), data.recovery_code.as_deref()) {
+        (Some(code), _) => match totp::verify_code(secret, &user.email, code) {
+            Some(step) => stores::user::use_totp_step(user.id, step, &db)
+                .await
+                .map_err(ApiError::GelQueryError)?,
+            None => false,
+        },
+        (None, Some(code)) => {
+            let code_hash = totp::hash_recovery_code(code);
+            let used = stores::user::use_recovery_code(user.id, &code_hash, &db)
+                .await
+                .map_err(ApiError::GelQueryError)?;
+            if used {
+                tracing::warn!("{} used a recovery code to log in", user.email);
+            }
+            used
+        }
+        (None, None) => return Err(ApiError::NotEnoughData.into()),
+    };
+    if !passed {
+        info!("Wrong second factor for {}", user.email);
+        let field = if data.code.is_some() {
+            "code"
+        } else {
+            "recovery_code"
+        };
+        Err(wrong_code(field))?;
+    }
+    session
+        .remove::<PendingLogin>(totp::PENDING_LOGIN_KEY)
+        .await
+        .map_err(ApiError::Session)?;
+    lockout::clear_failures(&pending.email, ip, &redis)
+        .await
+        .map_err(ApiError::Redis)?;
+    info!("Logging in user: {}", user.email);
     auth_session.login(&user).await.map_err(|e| {
         tracing::error!("Error logging in user: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
@@ -53,3 +200,210 @@ pub async fn logout(mut auth_session: AuthSession) -> AxumResult<String> {
     }
     Ok("Bye".to_string())
 }
+
+/// Lift the login lockout of an email or IP address. Return the number of lifted lockouts.
+pub async fn unlock_login(
+    auth_session: ApiAuth,
+    State(redis): State<Pool>,
+    WithRejection(Json(data), _): WithRejection<Json<LoginUnlockData>, ApiError>,
+) -> AxumResult<Json<i64>> {
+    require_perm(&auth_session, Permission::ManageUsers).await?;
+    let email = data.email.as_deref().map(lockout::normalize_email);
+    let ip = data.ip.as_deref().map(str::trim);
+    if email.is_none() && ip.is_none() {
+        Err(ApiError::NotEnoughData)?;
+    }
+    let count = lockout::unlock(email.as_deref(), ip, &redis)
+        .await
+        .map_err(ApiError::Redis)?;
+    info!("Lifted {count} login lockouts for {email:?} {ip:?}");
+    Ok(Json(count))
+}
+
+/// Email a link to reset the password, if the address belongs to an active user.
+/// The response is the same either way, not to reveal who has an account.
+pub async fn forgot_password(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    WithRejection(Json(data), _): WithRejection<Json<ForgotPasswordData>, ApiError>,
+) -> AxumResult<StatusCode> {
+    let AppState {
+        db,
+        redis,
+        mailer,
+        password_reset_url,
+        ..
+    } = state;
+    // Without the client IP, all clients would share one counter, so only the email is limited
+    match get_client_ip(&headers) {
+        Some(ip) => {
+            let allowed = check_rate_limit(
+                &redis,
+                "forgot-password",
+                &ip,
+                FORGOT_PASSWORD_RATE_LIMIT,
+                FORGOT_PASSWORD_RATE_WINDOW,
+            )
+            .await
+            .map_err(ApiError::Redis)?;
+            if !allowed {
+                info!("{ip} asks for password reset too often");
+                Err(ApiError::TooManyRequests)?;
+            }
+        }
+        None => tracing::warn!("No client IP to limit password resets by"),
+    }
+    let email = data.email.trim();
+    let allowed = check_rate_limit(
+        &redis,
+        "forgot-password-email",
+        &lockout::normalize_email(email),
+        FORGOT_PASSWORD_EMAIL_LIMIT,
+        FORGOT_PASSWORD_RATE_WINDOW,
+    )
+    .await
+    .map_err(ApiError::Redis)?;
+    if !allowed {
+        // Same response as when an email is sent, not to reveal who has an account
+        info!("Too many password reset emails for {email}. Skip this one");
+        return Ok(StatusCode::ACCEPTED);
+    }
+    let user = stores::user::get_user_by_email(email, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .filter(|u| u.is_active);
+    let Some(user) = user else {
+        info!("No active user with email {email} to reset password");
+        return Ok(StatusCode::ACCEPTED);
+    };
+    let token = reset::issue_reset_token(&user, &redis)
+        .await
+        .map_err(ApiError::Redis)?;
+    let minutes = reset::TOKEN_TTL.as_secs() / 60;
+    let email = Email {
+        to: user.email,
+        subject: "Reset your password".into(),
+        body: format!(
+            "Hi {},\n\n\
+            Please open this link to set a new password. It expires in {minutes} minutes.\n\n\
+            {password_reset_url}?token={token}\n\n\
+            If you didn't ask for it, just ignore this email.\n",
+            user.username
+        ),
+    };
+    // Sending can be slow, and the response time must not tell whether the user exists.
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(&email).await {
+            tracing::error!("Failed to send password reset email to {}: {e}", email.to);
+        }
+    });
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set a new password with the token from the reset email. The token can only be used once.
+pub async fn reset_password(
+    State(state): State<AppState>,
+    WithRejection(Json(data), _): WithRejection<Json<PasswordResetData>, ApiError>,
+) -> AxumResult<StatusCode> {
+    let AppState { db, redis, .. } = state;
+    data.validate().map_err(ApiError::ValidationErrors)?;
+    let invalid_token = || {
+        let mut err = ValidationError::new_field_named("token", "invalid-token");
+        err.set_location("token");
+        let mut errors = ValidationErrors::new();
+        errors.add(err);
+        ApiError::ValidationErrors(errors)
+    };
+    let (user_id, fingerprint) = reset::consume_reset_token(&data.token, &redis)
+        .await
+        .map_err(ApiError::Redis)?
+        .ok_or_else(invalid_token)?;
+    let user = stores::user::get_user(user_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .filter(|u| u.is_active && reset::token_matches(u, &fingerprint))
+        .ok_or_else(invalid_token)?;
+    let hashed = hash_password(data.new_password.expose_secret());
+    stores::user::set_password(user.id, &hashed, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    info!("Password of {} is reset", user.email);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// User of an API request, logged in with the session cookie or an API token.
+/// The token is taken from the "Authorization: Bearer" header, and wins over the session.
+#[derive(Debug, Clone)]
+pub struct ApiAuth {
+    pub user: Option<User>,
+    /// Scopes of the API token, `None` if logged in with the session
+    pub scopes: Option<HashSet<TokenScope>>,
+    pub backend: Backend,
+}
+
+impl From<&AuthSession> for ApiAuth {
+    fn from(auth_session: &AuthSession) -> Self {
+        Self {
+            user: auth_session.user.clone(),
+            scopes: None,
+            backend: auth_session.backend.clone(),
+        }
+    }
+}
+
+impl FromRequestParts<AppState> for ApiAuth {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth_session = AuthSession::from_request_parts(parts, state)
+            .await
+            .map_err(|(_status, msg)| ApiError::Other(msg.into()))?;
+        let Some(token) = tokens::get_bearer_token(&parts.headers) else {
+            return Ok(Self::from(&auth_session));
+        };
+        let grant = stores::token::use_token(&tokens::hash_token(token), &state.db)
+            .await
+            .map_err(ApiError::GelQueryError)?
+            .ok_or(ApiError::Unauthorized)?;
+        let scopes: HashSet<TokenScope> = grant.scopes.into_iter().collect();
+        // Writing is checked against the permissions, but reading only needs to be logged in
+        if parts.method.is_safe() && !scopes.contains(&TokenScope::Read) {
+            debug!("Token of {} lacks the Read scope", grant.user.email);
+            return Err(ApiError::Forbidden);
+        }
+        Ok(Self {
+            user: Some(grant.user),
+            scopes: Some(scopes),
+            backend: auth_session.backend,
+        })
+    }
+}
+
+/// Tell if the user has the permission. With an API token, its scopes must allow it, too.
+pub async fn has_perm(auth: &ApiAuth, user: &User, perm: Permission) -> Result<bool, ApiError> {
+    if let Some(scopes) = &auth.scopes {
+        let allowed = scopes
+            .iter()
+            .any(|s| Permission::of_scope(*s).contains(&perm));
+        if !allowed {
+            return Ok(false);
+        }
+    }
+    auth.backend
+        .has_perm(user, perm)
+        .await
+        .map_err(ApiError::GelQueryError)
+}
+
+/// Get the logged-in user if they have the permission, or fail with 401 or 403
+pub async fn require_perm(auth_session: &ApiAuth, perm: Permission) -> Result<User, ApiError> {
+    let user = auth_session.user.clone().ok_or(ApiError::Unauthorized)?;
+    if !has_perm(auth_session, &user, perm).await? {
+        debug!("User {} lacks permission {perm:?}", user.email);
+        return Err(ApiError::Forbidden);
+    }
+    Ok(user)
+}
@@ -0,0 +1,110 @@
+use std::num::NonZeroU16;
+
+use axum::extract::{OriginalUri, Path, Query, State};
+use axum::{Json, http::StatusCode, response::Result as AxumResult};
+use axum_extra::extract::WithRejection;
+use gel_tokio::Client as EdgeClient;
+use uuid::Uuid;
+
+use super::auth::{ApiAuth, require_perm};
+use super::errors::ApiError;
+use super::paging::gen_pagination_links;
+use super::structs::{CommentListQuery, CommentPatchData, NPaging, ObjectListResponse};
+use crate::auth::permissions::Permission;
+use crate::consts::DEFAULT_PAGE_SIZE;
+use crate::models::Comment;
+use crate::stores;
+use crate::utils::page_cache::{CacheGroup, PageCache};
+
+/// List comments for moderation. Pass `?status=Pending` to get the moderation queue.
+pub async fn list_comments(
+    auth_session: ApiAuth,
+    Query(query): Query<CommentListQuery>,
+    OriginalUri(original_uri): OriginalUri,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<ObjectListResponse<Comment>>> {
+    require_perm(&auth_session, Permission::ManageContent).await?;
+    let CommentListQuery {
+        page,
+        per_page,
+        status,
+    } = query;
+    let page = page.unwrap_or(NonZeroU16::MIN);
+    let per_page = per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = ((page.get() - 1) * (per_page as u16)) as i64;
+    let limit = per_page as i64;
+    let comments = stores::comment::get_comments(status, Some(offset), Some(limit), &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    let count = stores::comment::count_comments(status, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    let total_pages =
+        NonZeroU16::new((count as f64 / per_page as f64).ceil() as u16).unwrap_or(NonZeroU16::MIN);
+    let paging = NPaging {
+        page: Some(page),
+        per_page: Some(per_page),
+    };
+    let links = gen_pagination_links(&paging, count, original_uri);
+    let resp = ObjectListResponse {
+        objects: comments,
+        count,
+        total_pages,
+        links,
+    };
+    Ok(Json(resp))
+}
+
+pub async fn get_comment(
+    WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<Comment>> {
+    require_perm(&auth_session, Permission::ManageContent).await?;
+    let comment = stores::comment::get_comment(id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("Comment".into()))?;
+    Ok(Json(comment))
+}
+
+/// Approve or reject a comment
+pub async fn update_comment_partial(
+    WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
+    WithRejection(Json(data), _): WithRejection<Json<CommentPatchData>, ApiError>,
+) -> AxumResult<Json<Comment>> {
+    require_perm(&auth_session, Permission::ManageContent).await?;
+    let comment = stores::comment::update_comment_status(id, data.status, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("Comment".into()))?;
+    // Approved comments are shown on the post page
+    cache
+        .invalidate_or_warn(&[CacheGroup::Post(comment.post.id)])
+        .await;
+    Ok(Json(comment))
+}
+
+pub async fn delete_comment(
+    WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
+) -> AxumResult<StatusCode> {
+    require_perm(&auth_session, Permission::ManageContent).await?;
+    let comment = stores::comment::get_comment(id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("Comment".into()))?;
+    stores::comment::delete_comment(id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("Comment".into()))?;
+    cache
+        .invalidate_or_warn(&[CacheGroup::Post(comment.post.id)])
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
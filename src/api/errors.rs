@@ -1,7 +1,9 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use axum::extract::rejection::{JsonRejection, PathRejection};
 use axum::http::StatusCode;
+use axum::http::header::RETRY_AFTER;
 use axum::{response::IntoResponse, Json};
 use gel_errors::display::display_error_verbose;
 use gel_errors::kinds as EdErrKind;
@@ -10,6 +12,7 @@ use serde_json::value::Value;
 use thiserror::Error;
 use validify::ValidationError as VE;
 
+use crate::storage::StorageError;
 use crate::types::ApiErrorShape;
 
 #[derive(Debug, Error)]
@@ -22,18 +25,38 @@ pub enum ApiError {
     JsonExtractionError(#[from] serde_json::Error),
     #[error(transparent)]
     GelQueryError(#[from] gel_errors::Error),
+    #[error(transparent)]
+    Redis(#[from] fred::error::Error),
+    #[error(transparent)]
+    Session(#[from] tower_sessions::session::Error),
     #[error("{0} not found")]
     ObjectNotFound(String),
     #[error("Please login")]
     Unauthorized,
+    #[error("You don't have permission to do this")]
+    Forbidden,
+    #[error("Too many requests. Please try again later")]
+    TooManyRequests,
+    #[error("Too many failed logins. Please try again later")]
+    LockedOut(Duration),
     #[error("Error logging in")]
     LoginError(String),
     #[error("Not enough data")]
     NotEnoughData,
+    #[error("{0}")]
+    Conflict(String),
     #[error(transparent)]
     ValidationErrors(#[from] validify::ValidationErrors),
     #[error("Bunny API error: {0}")]
     Bunny(#[from] reqwest::Error),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("Invalid upload: {0}")]
+    BadUpload(String),
+    #[error("File is larger than {0} bytes")]
+    PayloadTooLarge(usize),
+    #[error("Unsupported file type: {0}")]
+    UnsupportedMediaType(String),
     #[error("Other error: {0}")]
     Other(String),
 }
@@ -66,10 +89,26 @@ impl IntoResponse for ApiError {
                     (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
                 }
             }
+            Self::Redis(ref e) => {
+                tracing::error!("Redis error: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
+            }
+            Self::Session(ref e) => {
+                tracing::error!("Session error: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
+            }
             Self::ObjectNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
             Self::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
+            Self::Forbidden => (StatusCode::FORBIDDEN, self.to_string()),
+            Self::TooManyRequests => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+            Self::LockedOut(remaining) => {
+                let payload = ApiErrorShape::from(self.to_string());
+                let retry_after = [(RETRY_AFTER, remaining.as_secs().max(1).to_string())];
+                return (StatusCode::TOO_MANY_REQUESTS, retry_after, Json(payload)).into_response();
+            }
             Self::LoginError(e) => (StatusCode::UNAUTHORIZED, e.to_string()),
             Self::NotEnoughData => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()),
+            Self::Conflict(message) => (StatusCode::CONFLICT, message),
             Self::ValidationErrors(e) => {
                 let resp: ApiErrorShape = flatten_validation_errors(e).into();
                 return (StatusCode::UNPROCESSABLE_ENTITY, Json(resp)).into_response();
@@ -78,6 +117,24 @@ impl IntoResponse for ApiError {
                 tracing::error!("Bunny API error: {}", e);
                 (StatusCode::BAD_REQUEST, format!("Bunny API error: {}", e))
             },
+            Self::Storage(e) => {
+                let status = match e {
+                    StorageError::InvalidPath(_) | StorageError::Bunny(_) => {
+                        StatusCode::BAD_REQUEST
+                    }
+                    StorageError::Io(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        StatusCode::NOT_FOUND
+                    }
+                    StorageError::Io(_) | StorageError::Remote(_) => {
+                        tracing::error!("Storage error: {}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    }
+                };
+                (status, e.to_string())
+            }
+            Self::BadUpload(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            Self::PayloadTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()),
+            Self::UnsupportedMediaType(_) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, self.to_string()),
             Self::Other(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
         };
         let payload = ApiErrorShape::from(message);
@@ -121,6 +178,32 @@ pub fn deduce_message(code: &str, params: &HashMap<&str, Value>) -> Option<Strin
     if code == "url" {
         return Some("Must be a valid URL".into());
     }
+    if code == "schedule-draft" {
+        return Some("A post scheduled for the future must be published".into());
+    }
+    if code == "self-translation" {
+        return Some("A post cannot be a translation of itself".into());
+    }
+    if code == "self-lockout" {
+        return Some("You cannot lock yourself out".into());
+    }
+    if code == "past-expiry" {
+        return Some("Must be in the future".into());
+    }
+    if code == "invalid-token" {
+        return Some("The link is invalid or has expired".into());
+    }
+    if code == "wrong-code" {
+        return Some("Wrong code".into());
+    }
+    if code == "wrong-password" {
+        return Some("Wrong password".into());
+    }
+    if code == "too-long" {
+        return params
+            .get("max")
+            .map(|max| format!("Must be at most {max} characters long"));
+    }
     params.get("min").and_then(|cond| {
         params
             .get("value")
@@ -1,6 +1,7 @@
-// API endpoint collection to manage remote files on bunny.net storage.
+// API endpoint collection to manage files on bunny.net storage, or a local directory.
 // The /api/files/browse/<path> endpoint is modeled after Bunny Storage API.
 // The /api/files/browse/<path> DELETE endpoint is for deleting files.
+// The /api/files/browse/<path> PUT and POST endpoints are for uploading images.
 
 pub mod routes;
 pub mod views;
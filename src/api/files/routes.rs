@@ -1,8 +1,14 @@
 use crate::types::AppState;
+use axum::extract::DefaultBodyLimit;
 use axum::routing::{Router, get};
 
 pub fn get_router() -> Router<AppState> {
-    let single_file_router = get(super::views::browse_files).delete(super::views::delete_file);
+    let single_file_router = get(super::views::browse_files)
+        .delete(super::views::delete_file)
+        .put(super::views::upload_file)
+        .post(super::views::upload_file)
+        // Upload size is checked while streaming to Bunny, against our own setting
+        .layer(DefaultBodyLimit::disable());
 
     Router::new()
         .route("/browse", single_file_router.clone())
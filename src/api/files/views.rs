@@ -1,15 +1,27 @@
+use std::io;
+use std::pin::pin;
+
+use crate::api::auth::{ApiAuth, require_perm};
 use crate::api::errors::ApiError;
+use crate::auth::permissions::Permission;
+use crate::storage::{FileStorage, StorageBackend, StoredFile, normalize_dir_path};
 use crate::types::AppState;
+use crate::utils::images::{self, VariantRegistry};
+use crate::worker::{JobQueue, Task};
 use axum::{
     Json,
-    extract::{Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{FromRequest, Multipart, Path, Request, State},
+    http::{StatusCode, header::CONTENT_TYPE},
     response::IntoResponse,
 };
 use chrono::{DateTime, Utc};
-use reqwest::Client;
+use futures_util::{Stream, StreamExt, stream};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error, info};
+use slugrs::slugify;
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+use uuid::Uuid;
 
 /// Response structure for file listing
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,143 +38,52 @@ pub struct FileResponse {
     pub direct_url: Option<String>,
 }
 
-/// Bunny API response structure
-#[derive(Serialize, Deserialize)]
-pub struct BunnyApiResponse {
-    #[serde(rename = "Guid")]
-    pub guid: String,
-    #[serde(rename = "StorageZoneName")]
-    pub storage_zone_name: String,
-    #[serde(rename = "Path")]
-    pub path: String,
-    #[serde(rename = "ObjectName")]
-    pub object_name: String,
-    #[serde(rename = "Length")]
-    pub length: i64,
-    #[serde(rename = "LastChanged")]
-    pub last_changed: String,
-    #[serde(rename = "IsDirectory")]
-    pub is_directory: bool,
-    #[serde(rename = "ServerId")]
-    pub server_id: i32,
-    #[serde(rename = "UserId")]
-    pub user_id: String,
-    #[serde(rename = "DateCreated")]
-    pub date_created: String,
-    #[serde(rename = "StorageZoneId")]
-    pub storage_zone_id: i32,
+impl FileResponse {
+    pub fn from_stored(file: StoredFile, storage: &impl FileStorage) -> Self {
+        // Only files (not directories) have direct URL
+        let direct_url = (!file.is_directory).then(|| storage.public_url(&file.path()));
+        Self {
+            dir_path: format!("/{}", file.dir_path),
+            size: file.size.try_into().unwrap_or(i64::MAX),
+            created_at: file.created_at,
+            modified_at: file.modified_at,
+            is_directory: file.is_directory,
+            name: file.name,
+            direct_url,
+        }
+    }
 }
 
-const BUNNY_API_HOST: &str = "sg.storage.bunnycdn.com";
-const STORAGE_ZONE_NAME: &str = "quan-images";
+// Only images are uploaded via our API
+const UPLOADABLE_TYPES: [&str; 6] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/svg+xml",
+];
 
 /// List files in a directory
 ///
 /// GET /api/files/browse/*file_path
 ///
 /// Returns a list of files and directories in the specified path.
-/// For files (not directories), includes a `direct_url` field with the CDN URL.
+/// For files (not directories), includes a `direct_url` field with the public URL.
 pub async fn browse_files(
     Path(file_path): Path<String>,
-    State(state): State<AppState>,
+    auth_session: ApiAuth,
+    State(storage): State<StorageBackend>,
 ) -> Result<Json<Vec<FileResponse>>, ApiError> {
+    require_perm(&auth_session, Permission::ManageFiles).await?;
     debug!("browse_files called with file_path: {}", file_path);
     info!("Browsing files at path: {}", file_path);
 
-    let url = format!(
-        "https://{}/{}/{}/",
-        BUNNY_API_HOST, STORAGE_ZONE_NAME, file_path
-    );
-    debug!("Making request to Bunny API: {}", url);
-
-    let client = Client::new();
-    let response = client
-        .get(&url)
-        .header("AccessKey", &state.bunny_api_key)
-        .send()
-        .await
-        .map_err(|e| {
-            error!("Failed to send request to Bunny API: {}", e);
-            ApiError::Bunny(e)
-        })?;
-
-    debug!("Bunny API response status: {}", response.status());
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        error!("Bunny API error {}: {}", status, error_text);
-        return Err(ApiError::Other(format!(
-            "Bunny API error {}: {}",
-            status, error_text
-        )));
-    }
-
-    // Parse Bunny's response and map it to our simplified format
-    let bunny_response: Vec<BunnyApiResponse> = response.json().await.map_err(|e| {
-        error!("Failed to parse Bunny API response: {}", e);
-        ApiError::Other(format!("Failed to parse Bunny API response: {}", e))
-    })?;
-
-    debug!("Received {} items from Bunny API", bunny_response.len());
-
-    let files: Vec<FileResponse> = bunny_response
+    let files: Vec<FileResponse> = storage
+        .list(&file_path)
+        .await?
         .into_iter()
-        .map(|item| {
-            debug!(
-                "Processing item: {} (directory: {})",
-                item.object_name, item.is_directory
-            );
-
-            // Parse datetime strings from Bunny API
-            // Bunny returns dates without timezone (e.g., "2026-02-22T06:46:45.179")
-            let created_at =
-                chrono::NaiveDateTime::parse_from_str(&item.date_created, "%Y-%m-%dT%H:%M:%S%.f")
-                    .ok()
-                    .map(|dt| DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc));
-
-            let modified_at =
-                chrono::NaiveDateTime::parse_from_str(&item.last_changed, "%Y-%m-%dT%H:%M:%S%.f")
-                    .ok()
-                    .map(|dt| DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc));
-
-            // Strip storage zone prefix from path (e.g., "/quan-images/blogs/" -> "/blogs/")
-            let zone_prefix = format!("/{}/", STORAGE_ZONE_NAME);
-            let public_path = item
-                .path
-                .strip_prefix(&zone_prefix)
-                .map(|s| format!("/{}", s))
-                .unwrap_or_else(|| item.path.clone());
-
-            // Generate direct CDN URL for files (not directories)
-            let direct_url = if item.is_directory {
-                None
-            } else {
-                // Construct CDN URL: https://<cdn_host>/<path><filename>
-                // Use original path for CDN URL as it includes the zone name
-                let cdn_path = format!("{}{}", item.path, item.object_name);
-                let url = format!(
-                    "https://{}/{}",
-                    state.bunny_cdn_host,
-                    cdn_path.trim_start_matches('/')
-                );
-                debug!("Generated CDN URL: {}", url);
-                Some(url)
-            };
-
-            FileResponse {
-                name: item.object_name,
-                dir_path: public_path,
-                size: item.length,
-                created_at,
-                modified_at,
-                is_directory: item.is_directory,
-                direct_url,
-            }
-        })
+        .map(|item| FileResponse::from_stored(item, &storage))
         .collect();
 
     info!("Returning {} files", files.len());
@@ -173,47 +94,214 @@ pub async fn browse_files(
 ///
 /// DELETE /api/files/browse/*file_path
 ///
-/// Deletes the specified file or directory from Bunny storage.
+/// Deletes the specified file or directory from the storage, with the variants of an image.
+/// With Bunny storage, the worker is then asked to purge them from the CDN cache.
 /// Returns 204 No Content on success.
 pub async fn delete_file(
     Path(file_path): Path<String>,
-    State(state): State<AppState>,
+    auth_session: ApiAuth,
+    State(storage): State<StorageBackend>,
+    State(jobs): State<JobQueue>,
+    State(variants): State<VariantRegistry>,
 ) -> Result<impl IntoResponse, ApiError> {
+    require_perm(&auth_session, Permission::ManageFiles).await?;
     debug!("delete_file called with file_path: {}", file_path);
     info!("Deleting file at path: {}", file_path);
 
-    let url = format!(
-        "https://{}/{}/{}",
-        BUNNY_API_HOST, STORAGE_ZONE_NAME, file_path
-    );
-    debug!("Making DELETE request to Bunny API: {}", url);
-
-    let client = Client::new();
-    let response = client
-        .delete(&url)
-        .header("AccessKey", &state.bunny_api_key)
-        .send()
-        .await
-        .map_err(|e| {
-            error!("Failed to send DELETE request to Bunny API: {}", e);
-            ApiError::Bunny(e)
-        })?;
-
-    debug!("Bunny API DELETE response status: {}", response.status());
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response
-            .text()
+    storage
+        .stat(&file_path)
+        .await?
+        .ok_or(ApiError::ObjectNotFound("File".into()))?;
+    storage.delete(&file_path).await?;
+    info!("File deleted successfully");
+
+    let mut deleted = vec![file_path.clone()];
+    if images::has_variants(&file_path) {
+        // Pages stop pointing to the variants before they are gone
+        if let Err(e) = variants.forget(&file_path).await {
+            tracing::warn!("Failed to forget variants of {}: {}", file_path, e);
+        }
+        for path in images::variant_paths(&file_path) {
+            // Variants may be not generated yet
+            match storage.delete(&path).await {
+                Ok(()) => deleted.push(path),
+                Err(e) => debug!("Variant {} is not deleted: {}", path, e),
+            }
+        }
+    }
+    // The CDN would keep serving the deleted file until its cache expires
+    if let StorageBackend::Bunny(bunny) = &storage {
+        jobs.enqueue_or_warn(Task::PurgeCdnFiles {
+            paths: deleted.iter().map(|p| bunny.cdn_path(p)).collect(),
+        })
+        .await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Upload an image
+///
+/// PUT/POST /api/files/browse/*file_path
+///
+/// With a `multipart/form-data` body, the path is the target directory and the file name
+/// is taken from the first file field. Otherwise, the request body is the file content
+/// and the path includes the file name.
+/// If a file with the same name exists, a number is appended to the new one's name.
+/// Resized variants of the image are then generated by the worker.
+/// Returns 201 Created with the stored file.
+pub async fn upload_file(
+    Path(file_path): Path<String>,
+    auth_session: ApiAuth,
+    State(state): State<AppState>,
+    State(jobs): State<JobQueue>,
+    request: Request,
+) -> Result<impl IntoResponse, ApiError> {
+    require_perm(&auth_session, Permission::ManageFiles).await?;
+    debug!("upload_file called with file_path: {}", file_path);
+    let storage = &state.file_storage;
+    let is_multipart = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+    let file = if is_multipart {
+        let mut multipart = Multipart::from_request(request, &state)
             .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        error!("Bunny API error {}: {}", status, error_text);
-        return Err(ApiError::Other(format!(
-            "Bunny API error {}: {}",
-            status, error_text
+            .map_err(|e| ApiError::BadUpload(e.body_text()))?;
+        loop {
+            let field = multipart
+                .next_field()
+                .await
+                .map_err(|e| ApiError::BadUpload(e.body_text()))?
+                .ok_or(ApiError::BadUpload("No file in the form".into()))?;
+            let Some(file_name) = field.file_name().map(String::from) else {
+                continue;
+            };
+            break store_file(
+                storage,
+                &file_path,
+                &file_name,
+                field,
+                state.max_upload_size,
+            )
+            .await?;
+        }
+    } else {
+        let (dir_path, file_name) = file_path
+            .rsplit_once('/')
+            .unwrap_or(("", file_path.as_str()));
+        let source = request.into_body().into_data_stream();
+        store_file(storage, dir_path, file_name, source, state.max_upload_size).await?
+    };
+    info!("Uploaded file {}{}", file.dir_path, file.name);
+    let path = format!("{}{}", file.dir_path.trim_start_matches('/'), file.name);
+    if images::has_variants(&path) {
+        jobs.enqueue_or_warn(Task::GenerateImageVariants { path })
+            .await;
+    }
+    Ok((StatusCode::CREATED, Json(file)))
+}
+
+/// Stream a file to the storage, under `dir_path` (relative to the storage root).
+/// The file name is sanitized, made unique, and must have an image extension.
+pub async fn store_file<F, S, E>(
+    storage: &F,
+    dir_path: &str,
+    file_name: &str,
+    source: S,
+    max_size: usize,
+) -> Result<FileResponse, ApiError>
+where
+    F: FileStorage,
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    let dir_path = normalize_dir_path(dir_path)?;
+    let (stem, ext) = sanitize_file_name(file_name)?;
+    let mime = mime_guess::from_ext(&ext)
+        .first()
+        .filter(|m| UPLOADABLE_TYPES.contains(&m.essence_str()))
+        .ok_or_else(|| ApiError::UnsupportedMediaType(ext.clone()))?;
+    let name = make_unique_name(&stem, &ext);
+    let path = format!("{dir_path}{name}");
+    debug!("To upload {} as {}", file_name, path);
+
+    // Bytes are relayed through a channel so that we can count them while streaming
+    let (tx, rx) = mpsc::channel::<Result<Bytes, io::Error>>(4);
+    let content = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    })
+    .boxed();
+    let (uploaded, relayed) = tokio::join!(
+        storage.put(&path, mime.essence_str(), content),
+        relay_chunks(source, tx, max_size)
+    );
+    // If the source failed, the upload was aborted because of it
+    let size = relayed?;
+    uploaded?;
+
+    let now = Utc::now();
+    Ok(FileResponse {
+        name,
+        dir_path: format!("/{dir_path}"),
+        size: size as i64,
+        created_at: Some(now),
+        modified_at: Some(now),
+        is_directory: false,
+        direct_url: Some(storage.public_url(&path)),
+    })
+}
+
+async fn relay_chunks<S, E>(
+    source: S,
+    tx: mpsc::Sender<Result<Bytes, io::Error>>,
+    max_size: usize,
+) -> Result<usize, ApiError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    let mut source = pin!(source);
+    let mut size = 0;
+    while let Some(chunk) = source.next().await {
+        let error = match chunk {
+            Ok(chunk) if size + chunk.len() <= max_size => {
+                size += chunk.len();
+                if tx.send(Ok(chunk)).await.is_err() {
+                    // The upload request has ended, its own error will be reported
+                    break;
+                }
+                continue;
+            }
+            Ok(_) => ApiError::PayloadTooLarge(max_size),
+            Err(e) => ApiError::BadUpload(e.to_string()),
+        };
+        tx.send(Err(io::Error::other(error.to_string()))).await.ok();
+        return Err(error);
+    }
+    Ok(size)
+}
+
+/// Split file name to slugified stem and lowercase extension.
+fn sanitize_file_name(file_name: &str) -> Result<(String, String), ApiError> {
+    // Browsers may send the full path of the picked file
+    let base_name = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let (stem, ext) = base_name
+        .rsplit_once('.')
+        .ok_or_else(|| ApiError::UnsupportedMediaType(base_name.to_string()))?;
+    let stem = slugify(stem);
+    if stem.is_empty() {
+        return Err(ApiError::BadUpload(format!(
+            "Invalid file name: {file_name}"
         )));
     }
+    Ok((stem, ext.to_lowercase()))
+}
 
-    info!("File deleted successfully");
-    Ok(StatusCode::NO_CONTENT)
+/// Add a random suffix to the name, so that an upload never overwrites another file.
+/// Bunny Storage has no create-only write, and checking the folder first would race
+/// with other uploads of the same name.
+fn make_unique_name(stem: &str, ext: &str) -> String {
+    let suffix = Uuid::new_v4().simple().to_string();
+    format!("{stem}-{}.{ext}", &suffix[..8])
 }
@@ -9,17 +9,19 @@ use serde_json::{Map as JMap, Value};
 use uuid::Uuid;
 use validify::Validify;
 
+use super::auth::{ApiAuth, require_perm};
 use super::errors::ApiError;
 use super::paging::gen_pagination_links;
 use super::structs::{
     BookAuthorPatchData, BookCreateData, BookPatchData, NPaging, ObjectListResponse,
     PresentationCreateData, PresentationPatchData,
 };
-use crate::auth::AuthSession;
+use crate::auth::permissions::Permission;
 use crate::consts::DEFAULT_PAGE_SIZE;
 use crate::models::minors::{Book, BookAuthor};
 use crate::models::{MinimalObject, Presentation};
 use crate::stores;
+use crate::utils::page_cache::{CacheGroup, PageCache};
 
 pub async fn list_presentations(
     Query(paging): Query<NPaging>,
@@ -62,11 +64,12 @@ pub async fn get_presentation(
 
 pub async fn update_presentation_partial(
     WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<Json<Presentation>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     // Collect list of submitted fields
     let jdata: JMap<String, Value> =
         serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
@@ -98,15 +101,17 @@ pub async fn update_presentation_partial(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("Presentation".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(Json(presentation))
 }
 
 pub async fn create_presentation(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<Json<Presentation>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     // Collect list of submitted fields
     let jdata: JMap<String, Value> =
         serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
@@ -137,21 +142,24 @@ pub async fn create_presentation(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::Other("Failed to create Presentation".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(Json(p))
 }
 
 pub async fn delete_presentation(
     WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
 ) -> AxumResult<StatusCode> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     let q = "DELETE Presentation FILTER .id = <uuid>$0";
     let _p: MinimalObject = db
         .query_single(q, &(id,))
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("Presentation".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -195,12 +203,13 @@ pub async fn get_book_author(
 }
 
 pub async fn update_book_author_partial(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
     WithRejection(Json(mut post_data), _): WithRejection<Json<BookAuthorPatchData>, ApiError>,
 ) -> AxumResult<Json<BookAuthor>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     post_data.validify().map_err(ApiError::ValidationErrors)?;
     let q = "SELECT (
         UPDATE BookAuthor FILTER .id = <uuid>$0 SET {
@@ -213,30 +222,34 @@ pub async fn update_book_author_partial(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("BookAuthor".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(Json(author))
 }
 
 pub async fn delete_book_author(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
 ) -> AxumResult<StatusCode> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     let q = "DELETE BookAuthor FILTER .id = <uuid>$0";
     let _p: MinimalObject = db
         .query_single(q, &(id,))
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("BookAuthor".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn create_book_author(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
     WithRejection(Json(mut post_data), _): WithRejection<Json<BookAuthorPatchData>, ApiError>,
 ) -> AxumResult<Json<BookAuthor>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     post_data.validify().map_err(ApiError::ValidationErrors)?;
     let q = "SELECT (
         INSERT BookAuthor {
@@ -249,6 +262,7 @@ pub async fn create_book_author(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::Other("Failed to create BookAuthor".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(Json(author))
 }
 
@@ -292,27 +306,30 @@ pub async fn get_book(
 }
 
 pub async fn delete_book(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
 ) -> AxumResult<StatusCode> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     let q = "DELETE Book FILTER .id = <uuid>$0";
     let _p: MinimalObject = db
         .query_single(q, &(id,))
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("Book".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn update_book_partial(
     WithRejection(Path(id), _): WithRejection<Path<Uuid>, ApiError>,
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<Json<Book>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     // Collect list of submitted fields
     let jdata: JMap<String, Value> =
         serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
@@ -347,15 +364,17 @@ pub async fn update_book_partial(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("Book".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(Json(book))
 }
 
 pub async fn create_book(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<Json<Book>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     // Collect list of submitted fields
     let jdata: JMap<String, Value> =
         serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
@@ -386,5 +405,6 @@ pub async fn create_book(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::Other("Failed to create Book".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::Minors]).await;
     Ok(Json(book))
 }
@@ -8,6 +8,11 @@ pub mod errors;
 pub mod macros;
 pub mod minors;
 pub mod users;
+pub mod comments;
+pub mod tags;
+pub mod revisions;
+pub mod tokens;
+pub mod two_factor;
 pub mod files;
 
 #[cfg(test)]
@@ -1,64 +1,77 @@
 use std::num::NonZeroU16;
 
 use axum::extract::{OriginalUri, Path, Query, State};
+use axum::response::{IntoResponse, Response};
 use axum::{Json, http::StatusCode, response::Result as AxumResult};
 use axum_extra::extract::WithRejection;
+use chrono::{DateTime, Utc};
 use gel_tokio::Client as EdgeClient;
 use serde_json::{Map as JMap, Value};
 use tracing::debug;
 use uuid::Uuid;
 use validify::Validify;
 
+use super::auth::{ApiAuth, has_perm, require_perm};
 use super::errors::ApiError;
 use super::paging::gen_pagination_links;
 use super::structs::{
     BlogPostCreateData, BlogPostPatchData, NPaging, ObjectListResponse, OtherQuery,
+    TranslationLinkData,
 };
-use crate::auth::AuthSession;
+use crate::auth::permissions::Permission;
 use crate::consts::DEFAULT_PAGE_SIZE;
-use crate::models::{DetailedBlogPost, MediumBlogPost, MinimalObject};
+use crate::models::{DetailedBlogPost, MinimalObject, SearchedBlogPost, User};
 use crate::stores;
-use crate::types::EdgeSelectable;
-use crate::utils::split_search_query;
+use crate::types::{EdgeSelectable, RevisionRetention};
+use crate::utils::page_cache::{CacheGroup, PageCache};
+use crate::utils::search::make_search_tokens;
+use crate::worker::{JobQueue, Task};
 
 pub async fn list_posts(
     Query(paging): Query<NPaging>,
     Query(mut other_query): Query<OtherQuery>,
     OriginalUri(original_uri): OriginalUri,
     State(db): State<EdgeClient>,
-) -> AxumResult<Json<ObjectListResponse<MediumBlogPost>>> {
+) -> AxumResult<Response> {
     let NPaging { page, per_page } = paging;
     let page = page.unwrap_or(NonZeroU16::MIN);
     let per_page = per_page.unwrap_or(DEFAULT_PAGE_SIZE);
     let offset = ((page.get() - 1) * per_page as u16) as i64;
     let limit = per_page as i64;
     other_query.validify().map_err(ApiError::ValidationErrors)?;
-    let search_tokens = split_search_query(other_query.q.as_deref());
-    let lower_search_tokens: Option<Vec<String>> =
-        search_tokens.map(|v| v.into_iter().map(|s| s.to_lowercase()).collect());
+    let search_tokens = other_query
+        .q
+        .as_deref()
+        .map(make_search_tokens)
+        .unwrap_or_default();
     let cat = other_query.cat_id;
-    let count = stores::blog::count_search_result_posts(lower_search_tokens.as_ref(), cat, &db)
+    let count = stores::blog::count_search_result_posts(&search_tokens, cat, false, &db)
         .await
         .map_err(ApiError::GelQueryError)?;
-    let posts = stores::blog::get_blogposts(
-        lower_search_tokens.as_ref(),
-        cat,
-        Some(offset),
-        Some(limit),
-        &db,
-    )
-    .await
-    .map_err(ApiError::GelQueryError)?;
     let total_pages =
         NonZeroU16::new((count as f64 / per_page as f64).ceil() as u16).unwrap_or(NonZeroU16::MIN);
     let links = gen_pagination_links(&paging, count, original_uri);
+    // When searching, posts are sorted by relevance and come with highlighted snippets.
+    // The list has the same shape in both cases, only without snippets when not searching.
+    let posts: Vec<SearchedBlogPost> = if search_tokens.is_empty() {
+        stores::blog::get_blogposts(cat, Some(offset), Some(limit), &db)
+            .await
+            .map_err(ApiError::GelQueryError)?
+            .into_iter()
+            .map(SearchedBlogPost::from)
+            .collect()
+    } else {
+        stores::blog::search_blogposts(&search_tokens, cat, false, Some(offset), Some(limit), &db)
+            .await
+            .map_err(ApiError::GelQueryError)?
+    };
     let resp = ObjectListResponse {
         count,
         total_pages,
         links,
         objects: posts,
     };
-    Ok(Json(resp))
+    Ok(Json(resp).into_response())
 }
 
 pub async fn get_post(
@@ -74,10 +87,13 @@ pub async fn get_post(
 
 pub async fn delete_post(
     Path(post_id): Path<Uuid>,
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(cache): State<PageCache>,
 ) -> AxumResult<StatusCode> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let user = auth_session.user.clone().ok_or(ApiError::Unauthorized)?;
+    check_post_editable(post_id, &user, &auth_session, &db).await?;
     let q = "DELETE BlogPost FILTER .id = <uuid>$0";
     tracing::debug!("To query: {}", q);
     let _deleted_post: MinimalObject = db
@@ -85,16 +101,24 @@ pub async fn delete_post(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    cache
+        .invalidate_or_warn(&CacheGroup::of_post(post_id))
+        .await;
+    jobs.enqueue_or_warn(Task::RegenerateFeeds).await;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn update_post_partial(
     WithRejection(Path(post_id), _): WithRejection<Path<Uuid>, ApiError>,
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(retention): State<RevisionRetention>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<Json<DetailedBlogPost>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let user = auth_session.user.clone().ok_or(ApiError::Unauthorized)?;
+    check_post_editable(post_id, &user, &auth_session, &db).await?;
     // Collect list of submitted fields
     let jdata: JMap<String, Value> =
         serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
@@ -107,48 +131,106 @@ pub async fn update_post_partial(
         return Ok(Json(post));
     };
     // Check that data has invalid fields
-    let patch_data: BlogPostPatchData =
+    let mut patch_data: BlogPostPatchData =
         serde_json::from_value(value).map_err(ApiError::JsonExtractionError)?;
     let submitted_fields: Vec<&String> = jdata.keys().collect();
+    // Unpublishing or rescheduling needs the same permission as publishing.
+    // Admin forms send these fields with every save, so only a change of value counts.
+    let publishing = if jdata.contains_key("is_published") || jdata.contains_key("published_at") {
+        let current = stores::blog::get_post(post_id, &db)
+            .await
+            .map_err(ApiError::GelQueryError)?
+            .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+        let published_at = current.published_at.map(DateTime::<Utc>::from);
+        patch_data
+            .validate_schedule(&submitted_fields, current.is_published, published_at)
+            .map_err(ApiError::ValidationErrors)?;
+        patch_data.changes_publication(&submitted_fields, current.is_published, published_at)
+    } else {
+        false
+    };
+    check_post_fields(publishing, &jdata, patch_data.author, &user, &auth_session).await?;
+    // The new body is rendered according to the format stored in DB, if user doesn't change it.
+    if jdata.contains_key("body") && !jdata.contains_key("format") {
+        patch_data.format = stores::blog::get_post_format(post_id, &db)
+            .await
+            .map_err(ApiError::GelQueryError)?;
+    }
+    let content_changing = jdata.contains_key("title") || jdata.contains_key("body");
     let set_clause = patch_data.gen_set_clause(&submitted_fields);
-    let fields = DetailedBlogPost::fields_as_shape();
     let args = patch_data.make_edgedb_args(post_id, &submitted_fields);
-    let q = format!(
-        "SELECT (
-            UPDATE BlogPost
-            FILTER .id = <uuid>$id
-            SET {{
-                {set_clause}
-            }}
-        ) {fields}"
-    );
-    debug!("To query: {q}");
     debug!("Query with params: {args:#?}");
-    let updated_post: Option<DetailedBlogPost> = db
-        .query_single(&q, &args)
+    let updated_post: Option<DetailedBlogPost> = if content_changing {
+        stores::revision::update_post_with_revision(&set_clause, args, Some(user.id), &db).await
+    } else {
+        let fields = DetailedBlogPost::fields_as_shape();
+        let q = format!(
+            "SELECT (
+                UPDATE BlogPost
+                FILTER .id = <uuid>$id
+                SET {{
+                    {set_clause}
+                }}
+            ) {fields}"
+        );
+        debug!("To query: {q}");
+        db.query_single(&q, &args).await
+    }
+    .map_err(ApiError::GelQueryError)?;
+    let updated_post = updated_post.ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    if content_changing {
+        stores::revision::prune_revisions(post_id, &retention, &db)
+            .await
+            .map_err(ApiError::GelQueryError)?;
+    }
+    stores::blog::refresh_post_search_fields(post_id, &db)
         .await
         .map_err(ApiError::GelQueryError)?;
-    let updated_post = updated_post.ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    // Changing format without sending body means the stored HTML is rendered with the wrong engine.
+    if jdata.contains_key("format") && !jdata.contains_key("body") {
+        jobs.enqueue_or_warn(Task::RenderPostHtml { post_id }).await;
+    }
+    // The translations show the title of this post
+    let mut groups = CacheGroup::of_post(post_id);
+    groups.extend(
+        updated_post
+            .translations
+            .iter()
+            .map(|t| CacheGroup::Post(t.id)),
+    );
+    cache.invalidate_or_warn(&groups).await;
+    jobs.enqueue_or_warn(Task::RegenerateFeeds).await;
     Ok(Json(updated_post))
 }
 
 pub async fn create_post(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<(StatusCode, Json<DetailedBlogPost>)> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let user = require_perm(&auth_session, Permission::WritePost).await?;
     // Collect list of submitted fields
-    let jdata: JMap<String, Value> =
-        serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
+    let mut jdata: JMap<String, Value> =
+        serde_json::from_value(value).map_err(ApiError::JsonExtractionError)?;
     // User submitted no field to create BlogPost
     (!jdata.is_empty())
         .then_some(())
         .ok_or(ApiError::NotEnoughData)?;
+    // Posts are credited to whom creates them, unless told otherwise
+    jdata
+        .entry("author")
+        .or_insert_with(|| Value::String(user.id.to_string()));
     // Check that data has valid fields
-    let mut post_data: BlogPostCreateData =
-        serde_json::from_value(value).map_err(ApiError::JsonExtractionError)?;
+    let mut post_data: BlogPostCreateData = serde_json::from_value(Value::Object(jdata.clone()))
+        .map_err(ApiError::JsonExtractionError)?;
     post_data.validify().map_err(ApiError::ValidationErrors)?;
+    post_data
+        .validate_schedule()
+        .map_err(ApiError::ValidationErrors)?;
+    let publishing = post_data.is_published == Some(true) || post_data.published_at.is_some();
+    check_post_fields(publishing, &jdata, post_data.author, &user, &auth_session).await?;
     tracing::debug!("Post data: {:?}", post_data);
     let submitted_fields: Vec<&String> = jdata.keys().collect();
     let set_clause = post_data.gen_set_clause(&submitted_fields);
@@ -169,5 +251,122 @@ pub async fn create_post(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::Other("Failed to create BlogPost".into()))?;
+    stores::blog::refresh_post_search_fields(created_post.id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    stores::revision::record_revision(created_post.id, Some(user.id), &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    cache
+        .invalidate_or_warn(&CacheGroup::of_post(created_post.id))
+        .await;
+    jobs.enqueue_or_warn(Task::RegenerateFeeds).await;
     Ok((StatusCode::CREATED, Json(created_post)))
 }
+
+/// Mark another post as a translation of this one. Return this post with its translations.
+pub async fn link_translation(
+    WithRejection(Path(post_id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(cache): State<PageCache>,
+    WithRejection(Json(data), _): WithRejection<Json<TranslationLinkData>, ApiError>,
+) -> AxumResult<Json<DetailedBlogPost>> {
+    let user = auth_session.user.clone().ok_or(ApiError::Unauthorized)?;
+    data.validate_for(post_id)
+        .map_err(ApiError::ValidationErrors)?;
+    // Both posts join the same translation group
+    check_post_editable(post_id, &user, &auth_session, &db).await?;
+    check_post_editable(data.post_id, &user, &auth_session, &db).await?;
+    let this = stores::blog::get_post_translation(post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    let other = stores::blog::get_post_translation(data.post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    data.validate_locales(this.locale.as_deref(), other.locale.as_deref())
+        .map_err(ApiError::ValidationErrors)?;
+    let count = stores::blog::link_translation(post_id, data.post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    if count == 0 {
+        Err(ApiError::ObjectNotFound("BlogPost".into()))?;
+    }
+    let post = stores::blog::get_post(post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    // Every post of the group links to the others
+    let mut groups = vec![CacheGroup::Post(post_id), CacheGroup::Feeds];
+    groups.extend(post.translations.iter().map(|t| CacheGroup::Post(t.id)));
+    cache.invalidate_or_warn(&groups).await;
+    // Sitemap lists the translations as alternates
+    jobs.enqueue_or_warn(Task::RegenerateFeeds).await;
+    Ok(Json(post))
+}
+
+pub async fn unlink_translation(
+    WithRejection(Path(post_id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(cache): State<PageCache>,
+) -> AxumResult<StatusCode> {
+    let user = auth_session.user.clone().ok_or(ApiError::Unauthorized)?;
+    check_post_editable(post_id, &user, &auth_session, &db).await?;
+    // Get the group before leaving it, the other posts stop linking to this one
+    let post = stores::blog::get_post(post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    stores::blog::unlink_translation(post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    let mut groups = vec![CacheGroup::Post(post_id), CacheGroup::Feeds];
+    groups.extend(post.translations.iter().map(|t| CacheGroup::Post(t.id)));
+    cache.invalidate_or_warn(&groups).await;
+    jobs.enqueue_or_warn(Task::RegenerateFeeds).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Editors can change any post, authors only their own.
+pub async fn check_post_editable(
+    post_id: Uuid,
+    user: &User,
+    auth_session: &ApiAuth,
+    db: &EdgeClient,
+) -> Result<(), ApiError> {
+    if has_perm(auth_session, user, Permission::EditAnyPost).await? {
+        return Ok(());
+    }
+    if !has_perm(auth_session, user, Permission::WritePost).await? {
+        return Err(ApiError::Forbidden);
+    }
+    stores::blog::is_post_author(post_id, user.id, db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .then_some(())
+        .ok_or(ApiError::Forbidden)
+}
+
+/// Publishing and unpublishing need their own permission, and only editors can credit a post to someone else.
+async fn check_post_fields(
+    publishing: bool,
+    jdata: &JMap<String, Value>,
+    author: Option<Uuid>,
+    user: &User,
+    auth_session: &ApiAuth,
+) -> Result<(), ApiError> {
+    if publishing && !has_perm(auth_session, user, Permission::PublishPost).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let crediting_other = jdata.contains_key("author") && author != Some(user.id);
+    if crediting_other && !has_perm(auth_session, user, Permission::EditAnyPost).await? {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(())
+}
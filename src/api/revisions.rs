@@ -0,0 +1,136 @@
+use std::num::NonZeroU16;
+
+use axum::extract::{OriginalUri, Path, Query, State};
+use axum::{Json, response::Result as AxumResult};
+use axum_extra::extract::WithRejection;
+use gel_tokio::Client as EdgeClient;
+use uuid::Uuid;
+
+use super::auth::ApiAuth;
+use super::errors::ApiError;
+use super::paging::gen_pagination_links;
+use super::posts::check_post_editable;
+use super::structs::{NPaging, ObjectListResponse, RevisionDiffQuery};
+use crate::consts::DEFAULT_PAGE_SIZE;
+use crate::models::revisions::make_diffable_text;
+use crate::models::{DetailedBlogPost, MiniPostRevision, PostRevision};
+use crate::stores;
+use crate::types::RevisionRetention;
+use crate::utils::diff::unified_diff;
+use crate::utils::page_cache::{CacheGroup, PageCache};
+use crate::worker::{JobQueue, Task};
+
+/// List revisions of a post, newest first
+pub async fn list_revisions(
+    WithRejection(Path(post_id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    Query(paging): Query<NPaging>,
+    OriginalUri(original_uri): OriginalUri,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<ObjectListResponse<MiniPostRevision>>> {
+    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let page = paging.page.unwrap_or(NonZeroU16::MIN);
+    let per_page = paging.per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = ((page.get() - 1) * (per_page as u16)) as i64;
+    let limit = per_page as i64;
+    let revisions = stores::revision::get_revisions(post_id, Some(offset), Some(limit), &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    let count = stores::revision::count_revisions(post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    let total_pages =
+        NonZeroU16::new((count as f64 / per_page as f64).ceil() as u16).unwrap_or(NonZeroU16::MIN);
+    let links = gen_pagination_links(&paging, count, original_uri);
+    let resp = ObjectListResponse {
+        objects: revisions,
+        count,
+        total_pages,
+        links,
+    };
+    Ok(Json(resp))
+}
+
+pub async fn get_revision(
+    WithRejection(Path((post_id, revision_id)), _): WithRejection<Path<(Uuid, Uuid)>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<PostRevision>> {
+    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let revision = stores::revision::get_revision(post_id, revision_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("PostRevision".into()))?;
+    Ok(Json(revision))
+}
+
+/// Show the changes between two revisions, or from a revision to the current post, as unified diff.
+pub async fn diff_revisions(
+    WithRejection(Path(post_id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    Query(query): Query<RevisionDiffQuery>,
+    State(db): State<EdgeClient>,
+) -> AxumResult<String> {
+    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let old = stores::revision::get_revision(post_id, query.from, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("PostRevision".into()))?;
+    let (new_name, new_text) = match query.to {
+        Some(id) => {
+            let new = stores::revision::get_revision(post_id, id, &db)
+                .await
+                .map_err(ApiError::GelQueryError)?
+                .ok_or(ApiError::ObjectNotFound("PostRevision".into()))?;
+            (id.to_string(), new.to_diffable_text())
+        }
+        None => {
+            let post = stores::blog::get_post(post_id, &db)
+                .await
+                .map_err(ApiError::GelQueryError)?
+                .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+            let text = make_diffable_text(&post.title, post.body.as_deref());
+            ("current".to_string(), text)
+        }
+    };
+    let old_name = query.from.to_string();
+    let diff = unified_diff(&old.to_diffable_text(), &new_text, &old_name, &new_name);
+    Ok(diff)
+}
+
+/// Put the title and body of a revision back to the post.
+/// The restoration is recorded as a new revision, so it can be reverted, too.
+pub async fn restore_revision(
+    WithRejection(Path((post_id, revision_id)), _): WithRejection<Path<(Uuid, Uuid)>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(retention): State<RevisionRetention>,
+    State(cache): State<PageCache>,
+) -> AxumResult<Json<DetailedBlogPost>> {
+    let user = auth_session.user.clone().ok_or(ApiError::Unauthorized)?;
+    check_post_editable(post_id, &user, &auth_session, &db).await?;
+    let revision = stores::revision::get_revision(post_id, revision_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("PostRevision".into()))?;
+    let body = revision.body.as_deref().unwrap_or_default();
+    let html = revision.format.to_html(body);
+    let excerpt = revision.format.make_excerpt(body);
+    let post =
+        stores::revision::restore_revision(post_id, &revision, &html, &excerpt, Some(user.id), &db)
+            .await
+            .map_err(ApiError::GelQueryError)?
+            .ok_or(ApiError::ObjectNotFound("BlogPost".into()))?;
+    stores::revision::prune_revisions(post_id, &retention, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    stores::blog::refresh_post_search_fields(post_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    cache
+        .invalidate_or_warn(&CacheGroup::of_post(post_id))
+        .await;
+    jobs.enqueue_or_warn(Task::RegenerateFeeds).await;
+    Ok(Json(post))
+}
@@ -1,7 +1,13 @@
-use axum::routing::{Router, get, post};
+use axum::routing::{Router, delete, get, post};
 
 use super::auth;
+use super::comments;
 use super::files;
+use super::revisions;
+use super::tags;
+use super::tokens;
+use super::two_factor;
+use super::users;
 use super::views;
 use crate::types::AppState;
 
@@ -25,19 +31,67 @@ pub fn get_router() -> Router<AppState> {
         .delete(views::delete_book)
         .patch(views::update_book_partial);
 
+    let single_comment_router = get(comments::get_comment)
+        .patch(comments::update_comment_partial)
+        .delete(comments::delete_comment);
+
     Router::new()
         .route("/", get(views::root))
         .route("/login", post(auth::login))
         .route("/logout", post(auth::logout))
+        .route("/login/2fa", post(auth::login_second_factor))
+        .route("/login/unlock", post(auth::unlock_login))
+        .route("/password/forgot", post(auth::forgot_password))
+        .route("/password/reset", post(auth::reset_password))
         .route("/users/me", get(views::show_me))
+        .route("/users/me/password", post(users::change_password))
+        .route(
+            "/users/me/tokens/",
+            get(tokens::list_tokens).post(tokens::create_token),
+        )
+        .route("/users/me/tokens/{token_id}", delete(tokens::revoke_token))
+        .route("/users/me/2fa", get(two_factor::get_status))
+        .route("/users/me/2fa/setup", post(two_factor::start_setup))
+        .route("/users/me/2fa/enable", post(two_factor::enable))
+        .route("/users/me/2fa/disable", post(two_factor::disable))
+        .route(
+            "/users/me/2fa/recovery-codes",
+            post(two_factor::regenerate_recovery_codes),
+        )
         .route("/posts/", get(views::list_posts).post(views::create_post))
         .route("/posts/{post_id}", single_post_router)
+        .route(
+            "/posts/{post_id}/translation",
+            post(views::link_translation).delete(views::unlink_translation),
+        )
+        .route(
+            "/posts/{post_id}/revisions/",
+            get(revisions::list_revisions),
+        )
+        .route(
+            "/posts/{post_id}/revisions/diff",
+            get(revisions::diff_revisions),
+        )
+        .route(
+            "/posts/{post_id}/revisions/{revision_id}",
+            get(revisions::get_revision),
+        )
+        .route(
+            "/posts/{post_id}/revisions/{revision_id}/restore",
+            post(revisions::restore_revision),
+        )
         .route(
             "/categories/",
             get(views::list_categories).post(views::create_category),
         )
         .route("/categories/{category_id}", single_category_router)
-        .route("/users/", get(views::list_users))
+        .route("/cache/flush", post(views::flush_page_cache))
+        .route("/users/", get(users::list_users).post(users::create_user))
+        .route(
+            "/users/{user_id}",
+            get(users::get_user).patch(users::update_user_partial),
+        )
+        .route("/users/{user_id}/deactivate", post(users::deactivate_user))
         .route(
             "/presentations/",
             get(views::list_presentations).post(views::create_presentation),
@@ -50,6 +104,10 @@ pub fn get_router() -> Router<AppState> {
         .route("/book-authors/{id}", single_book_author_router)
         .route("/books/", get(views::list_books).post(views::create_book))
         .route("/books/{id}", single_book_router)
+        .route("/comments/", get(comments::list_comments))
+        .route("/comments/{id}", single_comment_router)
+        .route("/tags/", get(tags::list_tags))
+        .route("/tags/rename", post(tags::rename_tags))
         .route("/markdown-to-html/", post(views::convert_to_html))
         .route(
             "/markdown-to-html-document/",
@@ -1,18 +1,24 @@
 use std::collections::HashMap;
 use std::num::NonZeroU16;
 
+use chrono::{DateTime, Utc};
 use field_access::FieldAccess;
+use gel_protocol::model::Datetime as EDatetime;
 use gel_protocol::named_args;
 use gel_protocol::value::Value as EValue;
 use gel_protocol::value_opt::ValueOpt;
+use redact::Secret;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use validify::Validify;
+use validify::{Validate, ValidationError, ValidationErrors, Validify};
 
 use super::macros::append_set_statement;
-use crate::models::DocFormat;
+use crate::auth::hash_password;
+use crate::auth::structs::validate_password_as;
+use crate::models::tokens::{ApiToken, TokenScope};
+use crate::models::users::Role;
+use crate::models::{CommentStatus, DocFormat};
 use crate::types::ext::VecExt;
-use crate::utils::markdown::{make_excerpt, markdown_to_html};
 
 #[derive(Debug, Deserialize)]
 pub struct NPaging {
@@ -20,6 +26,75 @@ pub struct NPaging {
     pub per_page: Option<u8>,
 }
 
+#[derive(Debug, Deserialize, Default)]
+pub struct CommentListQuery {
+    pub page: Option<NonZeroU16>,
+    pub per_page: Option<u8>,
+    pub status: Option<CommentStatus>,
+}
+
+/// Revisions to compare. Without `to`, the `from` revision is compared with the current post.
+#[derive(Debug, Deserialize)]
+pub struct RevisionDiffQuery {
+    pub from: Uuid,
+    pub to: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommentPatchData {
+    pub status: CommentStatus,
+}
+
+/// Another post to be linked as translation
+#[derive(Debug, Deserialize)]
+pub struct TranslationLinkData {
+    pub post_id: Uuid,
+}
+
+impl TranslationLinkData {
+    pub fn validate_for(&self, post_id: Uuid) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.post_id == post_id {
+            let mut err = ValidationError::new_field_named("post_id", "self-translation");
+            err.set_location("post_id");
+            errors.add(err);
+        }
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+
+    /// Translations of a post must be in other languages
+    pub fn validate_locales(
+        &self,
+        locale: Option<&str>,
+        other_locale: Option<&str>,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if locale == other_locale {
+            let mut err = ValidationError::new_field_named("post_id", "same-locale");
+            err.set_location("post_id");
+            errors.add(err);
+        }
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+}
+
+/// Rename a tag, or merge several tags into one
+#[derive(Debug, Deserialize, Validify)]
+pub struct TagRenameData {
+    // Slugs of the tags to be renamed
+    #[validate(length(min = 1))]
+    pub from: Vec<String>,
+    // New keyword
+    #[modify(trim)]
+    #[validate(length(min = 1, max = 40))]
+    pub to: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ConvertQuery {
+    pub format: Option<DocFormat>,
+}
+
 #[derive(Debug, Deserialize, Default)]
 pub struct CategoryListQuery {
     pub page: Option<NonZeroU16>,
@@ -72,20 +147,56 @@ pub struct BlogPostPatchData {
     pub title: Option<String>,
     pub slug: Option<String>,
     pub is_published: Option<bool>,
+    pub published_at: Option<DateTime<Utc>>,
     pub format: Option<DocFormat>,
     pub body: Option<String>,
     pub locale: Option<String>,
     pub categories: Option<Vec<Uuid>>,
+    pub seo_keywords: Option<Vec<String>>,
     pub author: Option<Uuid>,
     pub og_image: Option<String>,
 }
 
 impl BlogPostPatchData {
+    /// A post with future `published_at` is scheduled, and must not be left as draft.
+    /// The fields which are not submitted keep their stored values, `is_published` and `published_at`.
+    pub fn validate_schedule(
+        &self,
+        submitted_fields: &Vec<&String>,
+        is_published: bool,
+        published_at: Option<DateTime<Utc>>,
+    ) -> Result<(), ValidationErrors> {
+        let is_published = if submitted_fields.contains("is_published") {
+            self.is_published.unwrap_or_default()
+        } else {
+            is_published
+        };
+        let published_at = if submitted_fields.contains("published_at") {
+            self.published_at
+        } else {
+            published_at
+        };
+        check_schedule(published_at, !is_published)
+    }
+
+    /// Whether the submitted fields change the publication state of a post,
+    /// given its current `is_published` and `published_at`.
+    pub fn changes_publication(
+        &self,
+        submitted_fields: &Vec<&String>,
+        is_published: bool,
+        published_at: Option<DateTime<Utc>>,
+    ) -> bool {
+        (submitted_fields.contains("is_published") && self.is_published != Some(is_published))
+            || (submitted_fields.contains("published_at") && self.published_at != published_at)
+    }
+
     pub fn gen_set_clause(&self, submitted_fields: &Vec<&String>) -> String {
         let mut lines = Vec::<&str>::new();
         append_set_statement!("title", "optional str", lines, submitted_fields);
         append_set_statement!("slug", "optional str", lines, submitted_fields);
         append_set_statement!("is_published", "optional bool", lines, submitted_fields);
+        append_set_statement!("published_at", "optional datetime", lines, submitted_fields);
         append_set_statement!("format", "optional DocFormat", lines, submitted_fields);
         if submitted_fields.contains("body") {
             // If user submitted "body" field, we will generate "html", "excerpt" and write, too
@@ -102,6 +213,9 @@ impl BlogPostPatchData {
             )";
             lines.push(line);
         }
+        if submitted_fields.contains("seo_keywords") && self.seo_keywords.is_some() {
+            lines.push("seo_keywords := array_unpack(<array<str>>$seo_keywords)");
+        }
         lines.join(&format!(",\n{}", " ".repeat(8)))
     }
 
@@ -122,12 +236,17 @@ impl BlogPostPatchData {
         if submitted_fields.contains("is_published") {
             hm.insert("is_published", self.is_published.into());
         }
+        if submitted_fields.contains("published_at") {
+            let published_at = self.published_at.and_then(|d| EDatetime::try_from(d).ok());
+            hm.insert("published_at", published_at.into());
+        }
         if submitted_fields.contains("format") {
             hm.insert("format", self.format.clone().into());
         }
         if submitted_fields.contains("body") {
-            let html = self.body.as_ref().map(|b| markdown_to_html(b));
-            let excerpt = self.body.as_ref().map(|b| make_excerpt(b));
+            let format = self.format.clone().unwrap_or_default();
+            let html = self.body.as_ref().map(|b| format.to_html(b));
+            let excerpt = self.body.as_ref().map(|b| format.make_excerpt(b));
             hm.insert("body", self.body.clone().into());
             hm.insert("html", html.into());
             hm.insert("excerpt", excerpt.into());
@@ -145,10 +264,31 @@ impl BlogPostPatchData {
             let categories: Vec<EValue> = categories.iter().map(|&i| EValue::Uuid(i)).collect();
             hm.insert("categories", categories.into());
         }
+        if let Some(keywords) = &self.seo_keywords {
+            let keywords: Vec<EValue> = keywords
+                .iter()
+                .map(|k| EValue::Str(k.trim().into()))
+                .collect();
+            hm.insert("seo_keywords", keywords.into());
+        }
         hm
     }
 }
 
+// A scheduled post goes live by itself when its time comes. It wouldn't if it is left as a draft.
+fn check_schedule(
+    published_at: Option<DateTime<Utc>>,
+    is_draft: bool,
+) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+    if is_draft && published_at.is_some_and(|d| d > Utc::now()) {
+        let mut err = ValidationError::new_field_named("published_at", "schedule-draft");
+        err.set_location("published_at");
+        errors.add(err);
+    }
+    errors.is_empty().then_some(()).ok_or(errors)
+}
+
 #[derive(Debug, Default, Deserialize, FieldAccess, Validify)]
 pub struct BlogPostCreateData {
     #[validate(length(min = 2))]
@@ -156,19 +296,27 @@ pub struct BlogPostCreateData {
     #[validate(length(min = 2))]
     pub slug: String,
     pub is_published: Option<bool>,
+    pub published_at: Option<DateTime<Utc>>,
     pub format: Option<DocFormat>,
     pub body: Option<String>,
     pub locale: Option<String>,
     pub categories: Option<Vec<Uuid>>,
+    pub seo_keywords: Option<Vec<String>>,
     pub author: Option<Uuid>,
     #[validate(url)]
     pub og_image: Option<String>,
 }
 
 impl BlogPostCreateData {
+    /// A post with future `published_at` is scheduled, so it must be created as published.
+    pub fn validate_schedule(&self) -> Result<(), ValidationErrors> {
+        check_schedule(self.published_at, !self.is_published.unwrap_or_default())
+    }
+
     pub fn gen_set_clause(&self, submitted_fields: &Vec<&String>) -> String {
         let mut lines = vec!["title := <str>$title", "slug := <str>$slug"];
         append_set_statement!("is_published", "optional bool", lines, submitted_fields);
+        append_set_statement!("published_at", "optional datetime", lines, submitted_fields);
         if submitted_fields.contains("body") {
             // If user submitted "body" field, we will generate "html", "excerpt" and write, too
             lines.push("body := <optional str>$body");
@@ -185,6 +333,9 @@ impl BlogPostCreateData {
             )";
             lines.push(line);
         }
+        if self.seo_keywords.is_some() {
+            lines.push("seo_keywords := array_unpack(<array<str>>$seo_keywords)");
+        }
         let sep = format!(",\n{}", " ".repeat(12));
         lines.join(&sep)
     }
@@ -197,10 +348,15 @@ impl BlogPostCreateData {
         if submitted_fields.contains("is_published") {
             hm.insert("is_published", self.is_published.into());
         }
+        if submitted_fields.contains("published_at") {
+            let published_at = self.published_at.and_then(|d| EDatetime::try_from(d).ok());
+            hm.insert("published_at", published_at.into());
+        }
         if submitted_fields.contains("body") {
             hm.insert("body", self.body.clone().into());
-            let html = self.body.as_ref().map(|v| markdown_to_html(v));
-            let excerpt = self.body.as_ref().map(|v| make_excerpt(v));
+            let format = self.format.clone().unwrap_or_default();
+            let html = self.body.as_ref().map(|v| format.to_html(v));
+            let excerpt = self.body.as_ref().map(|v| format.make_excerpt(v));
             hm.insert("html", html.into());
             hm.insert("excerpt", excerpt.into());
         }
@@ -220,6 +376,13 @@ impl BlogPostCreateData {
             let categories: Vec<EValue> = categories.iter().map(|&i| EValue::Uuid(i)).collect();
             hm.insert("categories", categories.into());
         }
+        if let Some(keywords) = &self.seo_keywords {
+            let keywords: Vec<EValue> = keywords
+                .iter()
+                .map(|k| EValue::Str(k.trim().into()))
+                .collect();
+            hm.insert("seo_keywords", keywords.into());
+        }
         hm
     }
 }
@@ -474,3 +637,289 @@ impl BookCreateData {
         hm
     }
 }
+
+// Same as the constraints in the database schema
+const MAX_USERNAME_LENGTH: usize = 50;
+const MAX_EMAIL_LENGTH: usize = 200;
+
+fn validate_user_fields(
+    username: Option<&str>,
+    email: Option<&str>,
+    errors: &mut ValidationErrors,
+) {
+    if let Some(username) = username {
+        let len = username.chars().count();
+        let code = if len < 2 {
+            Some("too-short")
+        } else if len > MAX_USERNAME_LENGTH {
+            Some("too-long")
+        } else {
+            None
+        };
+        if let Some(code) = code {
+            let mut err = ValidationError::new_field_named("username", code);
+            err.add_param("min", &2);
+            err.add_param("max", &MAX_USERNAME_LENGTH);
+            err.add_param("value", &username);
+            err.set_location("username");
+            errors.add(err);
+        }
+    }
+    if let Some(email) = email {
+        if !validify::validate_email(email) || email.len() > MAX_EMAIL_LENGTH {
+            let mut err = ValidationError::new_field_named("email", "email");
+            err.add_param("value", &email);
+            err.set_location("email");
+            errors.add(err);
+        }
+    }
+}
+
+fn validate_password_field(
+    field: &'static str,
+    value: &Secret<String>,
+    errors: &mut ValidationErrors,
+) {
+    if let Err(mut err) = validate_password_as(field, value) {
+        err.add_param("value", &"**redacted**");
+        err.set_location(field);
+        errors.add(err);
+    }
+}
+
+/// New user, created by an admin
+#[derive(Debug, Deserialize)]
+pub struct UserCreateData {
+    pub username: String,
+    pub email: String,
+    pub password: Secret<String>,
+    #[serde(default)]
+    pub role: Role,
+}
+
+// Like `LoginReqData`, `Validate` cannot be derived because of the `Secret` type.
+impl Validate for UserCreateData {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        validate_user_fields(Some(&self.username), Some(&self.email), &mut errors);
+        validate_password_field("password", &self.password, &mut errors);
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+}
+
+impl UserCreateData {
+    pub fn gen_set_clause(&self) -> String {
+        let lines = [
+            "username := <str>$username",
+            "email := <str>$email",
+            "password := <str>$password",
+            "role := <UserRole>$role",
+        ];
+        let sep = format!(",\n{}", " ".repeat(12));
+        lines.join(&sep)
+    }
+    pub fn make_edgedb_args(&self) -> HashMap<&str, ValueOpt> {
+        let hm = named_args! {
+            "username" => self.username.trim().to_string(),
+            "email" => self.email.trim().to_lowercase(),
+            "password" => hash_password(self.password.expose_secret()),
+            "role" => self.role,
+        };
+        hm
+    }
+}
+
+/// Changes to a user, by an admin. All the fields are required in database, so `null` is not accepted.
+#[derive(Debug, Deserialize)]
+pub struct UserPatchData {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub role: Option<Role>,
+    pub is_active: Option<bool>,
+}
+
+impl Validate for UserPatchData {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        validate_user_fields(self.username.as_deref(), self.email.as_deref(), &mut errors);
+        if let Some(password) = &self.password {
+            validate_password_field("password", password, &mut errors);
+        }
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+}
+
+impl UserPatchData {
+    /// Admins cannot deactivate themselves or drop their own admin role, not to be locked out.
+    pub fn validate_for_self(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.is_active == Some(false) {
+            let mut err = ValidationError::new_field_named("is_active", "self-lockout");
+            err.set_location("is_active");
+            errors.add(err);
+        }
+        if self.role.is_some_and(|r| r != Role::Admin) {
+            let mut err = ValidationError::new_field_named("role", "self-lockout");
+            err.set_location("role");
+            errors.add(err);
+        }
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+
+    pub fn gen_set_clause(&self) -> String {
+        let mut lines = Vec::<&str>::new();
+        if self.username.is_some() {
+            lines.push("username := <str>$username");
+        }
+        if self.email.is_some() {
+            lines.push("email := <str>$email");
+        }
+        if self.password.is_some() {
+            lines.push("password := <str>$password");
+        }
+        if self.role.is_some() {
+            lines.push("role := <UserRole>$role");
+        }
+        if self.is_active.is_some() {
+            lines.push("is_active := <bool>$is_active");
+        }
+        let sep = format!(",\n{}", " ".repeat(12));
+        lines.join(&sep)
+    }
+
+    pub fn make_edgedb_args(&self, id: Uuid) -> HashMap<&str, ValueOpt> {
+        let mut hm = named_args! {
+            "id" => id
+        };
+        if let Some(username) = &self.username {
+            hm.insert("username", username.trim().to_string().into());
+        }
+        if let Some(email) = &self.email {
+            hm.insert("email", email.trim().to_lowercase().into());
+        }
+        if let Some(password) = &self.password {
+            hm.insert("password", hash_password(password.expose_secret()).into());
+        }
+        if let Some(role) = self.role {
+            hm.insert("role", role.into());
+        }
+        if let Some(is_active) = self.is_active {
+            hm.insert("is_active", is_active.into());
+        }
+        hm
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordChangeData {
+    pub current_password: Secret<String>,
+    pub new_password: Secret<String>,
+}
+
+impl Validate for PasswordChangeData {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        validate_password_field("new_password", &self.new_password, &mut errors);
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordData {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetData {
+    pub token: String,
+    pub new_password: Secret<String>,
+}
+
+impl Validate for PasswordResetData {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        validate_password_field("new_password", &self.new_password, &mut errors);
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+}
+
+/// Email or IP address to lift the login lockout of
+#[derive(Debug, Deserialize)]
+pub struct LoginUnlockData {
+    pub email: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// New API token. Without `expires_at`, it is valid until revoked.
+#[derive(Debug, Deserialize, Validify)]
+pub struct ApiTokenCreateData {
+    #[modify(trim)]
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(length(min = 1))]
+    pub scopes: Vec<TokenScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ApiTokenCreateData {
+    pub fn validate_expiry(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.expires_at.is_some_and(|d| d <= Utc::now()) {
+            let mut err = ValidationError::new_field_named("expires_at", "past-expiry");
+            err.set_location("expires_at");
+            errors.add(err);
+        }
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+}
+
+/// The token is only shown here, it cannot be retrieved later
+#[derive(Debug, Serialize)]
+pub struct ApiTokenCreatedResponse {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiToken,
+}
+
+/// Answer to the right password of a user with two-factor authentication.
+/// The login is to be finished with `SecondFactorData`.
+#[derive(Debug, Serialize)]
+pub struct SecondFactorRequired {
+    pub second_factor_required: bool,
+}
+
+/// Second step of login, with a code from the authenticator app or a recovery code
+#[derive(Debug, Deserialize)]
+pub struct SecondFactorData {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeData {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpDisableData {
+    pub password: Secret<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpSetupResponse {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpStatusResponse {
+    pub enabled: bool,
+    pub recovery_codes_left: usize,
+}
+
+/// Recovery codes are only shown here, they cannot be retrieved later
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
@@ -0,0 +1,63 @@
+use axum::extract::State;
+use axum::{Json, response::Result as AxumResult};
+use axum_extra::extract::WithRejection;
+use gel_tokio::Client as EdgeClient;
+use validify::Validify;
+
+use super::auth::{ApiAuth, require_perm};
+use super::errors::ApiError;
+use super::structs::TagRenameData;
+use crate::auth::permissions::Permission;
+use crate::models::Tag;
+use crate::stores;
+use crate::utils::page_cache::{CacheGroup, PageCache};
+use crate::worker::{JobQueue, Task};
+
+pub async fn list_tags(
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<Vec<Tag>>> {
+    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let tags = stores::blog::get_tags(&db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    Ok(Json(tags))
+}
+
+/// Rename a tag, or merge some tags into one, by replacing the keywords in all posts.
+/// Return the updated list of tags.
+pub async fn rename_tags(
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(cache): State<PageCache>,
+    WithRejection(Json(mut data), _): WithRejection<Json<TagRenameData>, ApiError>,
+) -> AxumResult<Json<Vec<Tag>>> {
+    require_perm(&auth_session, Permission::ManageContent).await?;
+    data.validify().map_err(ApiError::ValidationErrors)?;
+    let tags = stores::blog::get_tags(&db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    let keywords: Vec<String> = tags
+        .into_iter()
+        .filter(|t| data.from.contains(&t.slug))
+        .flat_map(|t| t.keywords)
+        .collect();
+    if keywords.is_empty() {
+        Err(ApiError::ObjectNotFound("Tag".into()))?;
+    }
+    let count = stores::blog::rename_keywords(&keywords, &data.to, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    tracing::info!("Renamed {keywords:?} to '{}' in {count} posts", data.to);
+    // Keywords are part of search index and feeds
+    let task = Task::RefreshSearchIndex { category_id: None };
+    jobs.enqueue_or_warn(task).await;
+    jobs.enqueue_or_warn(Task::RegenerateFeeds).await;
+    // Tags are shown on the post pages and in the tag cloud of home page
+    cache.invalidate_or_warn(&[CacheGroup::All]).await;
+    let tags = stores::blog::get_tags(&db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    Ok(Json(tags))
+}
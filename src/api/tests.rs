@@ -1,8 +1,17 @@
 use http::Uri;
 use notzero::nz;
+use validify::Validate;
 
 use super::paging::gen_pagination_links;
-use super::structs::NPaging;
+use super::structs::{
+    BlogPostCreateData, BlogPostPatchData, NPaging, TranslationLinkData, UserPatchData,
+};
+use crate::auth::permissions::Permission;
+use crate::auth::reset::{password_fingerprint, token_matches};
+use crate::auth::structs::LoginReqData;
+use crate::models::User;
+use crate::models::tokens::TokenScope;
+use crate::models::users::Role;
 
 #[test]
 fn gen_next_url_when_per_page_is_missing() {
@@ -17,3 +26,190 @@ fn gen_next_url_when_per_page_is_missing() {
     );
     assert!(links.next == Some("/api/categories?page=2".to_string()));
 }
+
+#[test]
+fn future_post_cannot_be_created_as_draft() {
+    let published_at = Some(chrono::Utc::now() + chrono::Duration::days(1));
+    let mut data = BlogPostCreateData {
+        title: "Hello".into(),
+        slug: "hello".into(),
+        published_at,
+        ..Default::default()
+    };
+    assert!(data.validate_schedule().is_err());
+    data.is_published = Some(true);
+    assert!(data.validate_schedule().is_ok());
+}
+
+#[test]
+fn draft_cannot_be_scheduled_by_patch() {
+    let published_at = chrono::Utc::now() + chrono::Duration::days(1);
+    let data: BlogPostPatchData =
+        serde_json::from_value(serde_json::json!({ "published_at": published_at })).unwrap();
+    let field = "published_at".to_string();
+    let submitted_fields = vec![&field];
+    // The stored post is a draft, and stays so because `is_published` is not sent
+    assert!(
+        data.validate_schedule(&submitted_fields, false, None)
+            .is_err()
+    );
+    assert!(
+        data.validate_schedule(&submitted_fields, true, None)
+            .is_ok()
+    );
+    // Nor can a scheduled post be turned into draft
+    let data: BlogPostPatchData =
+        serde_json::from_value(serde_json::json!({ "is_published": false })).unwrap();
+    let field = "is_published".to_string();
+    let submitted_fields = vec![&field];
+    let stored_at = Some(published_at);
+    assert!(
+        data.validate_schedule(&submitted_fields, true, stored_at)
+            .is_err()
+    );
+    assert!(
+        data.validate_schedule(&submitted_fields, true, None)
+            .is_ok()
+    );
+}
+
+#[test]
+fn post_cannot_be_translation_of_itself() {
+    let post_id = uuid::Uuid::from_u128(1);
+    let data = TranslationLinkData { post_id };
+    assert!(data.validate_for(post_id).is_err());
+    assert!(data.validate_for(uuid::Uuid::from_u128(2)).is_ok());
+}
+
+#[test]
+fn translation_must_be_in_other_locale() {
+    let data = TranslationLinkData {
+        post_id: uuid::Uuid::from_u128(2),
+    };
+    assert!(data.validate_locales(Some("vi"), Some("vi")).is_err());
+    assert!(data.validate_locales(None, None).is_err());
+    assert!(data.validate_locales(Some("vi"), Some("en")).is_ok());
+    assert!(data.validate_locales(Some("vi"), None).is_ok());
+}
+
+#[test]
+fn only_admins_manage_users_and_files() {
+    let author = Permission::of_role(Role::Author);
+    assert!(author.contains(&Permission::WritePost));
+    assert!(!author.contains(&Permission::PublishPost));
+    let editor = Permission::of_role(Role::Editor);
+    assert!(editor.contains(&Permission::PublishPost));
+    assert!(!editor.contains(&Permission::ManageFiles));
+    assert!(!editor.contains(&Permission::ManageUsers));
+    let admin = Permission::of_role(Role::Admin);
+    assert!(admin.is_superset(&editor));
+    assert!(admin.contains(&Permission::ManageUsers));
+}
+
+#[test]
+fn author_cannot_unpublish() {
+    let author = Permission::of_role(Role::Author);
+    let data: BlogPostPatchData =
+        serde_json::from_value(serde_json::json!({ "is_published": false })).unwrap();
+    let is_published = "is_published".to_string();
+    let submitted_fields = vec![&is_published];
+    assert!(data.changes_publication(&submitted_fields, true, None));
+    assert!(!author.contains(&Permission::PublishPost));
+    // Saving the form again, without changing the state, is allowed
+    assert!(!data.changes_publication(&submitted_fields, false, None));
+}
+
+#[test]
+fn token_scopes_never_manage_users() {
+    let all_scopes = [TokenScope::Read, TokenScope::WritePosts, TokenScope::Files];
+    let perms: Vec<Permission> = all_scopes
+        .into_iter()
+        .flat_map(Permission::of_scope)
+        .copied()
+        .collect();
+    assert!(perms.contains(&Permission::PublishPost));
+    assert!(perms.contains(&Permission::ManageFiles));
+    assert!(!perms.contains(&Permission::ManageUsers));
+    assert!(Permission::of_scope(TokenScope::Read).is_empty());
+}
+
+#[test]
+fn superuser_is_admin() {
+    let user = User {
+        is_superuser: true,
+        ..Default::default()
+    };
+    assert_eq!(user.role, Role::Author);
+    assert_eq!(user.effective_role(), Role::Admin);
+}
+
+#[test]
+fn totp_secret_is_not_exposed() {
+    let user = User {
+        totp_secret: Some("JBSWY3DPEHPK3PXP".into()),
+        ..Default::default()
+    };
+    assert!(user.has_2fa());
+    let json = serde_json::to_value(&user).unwrap();
+    assert!(json.get("totp_secret").is_none());
+    assert!(json.get("password").is_none());
+}
+
+#[test]
+fn login_only_needs_password() {
+    let data: LoginReqData = serde_json::from_value(serde_json::json!({
+        "email": "quan@example.com",
+        "password": "short",
+    }))
+    .unwrap();
+    assert!(data.validate().is_ok());
+    let data: LoginReqData = serde_json::from_value(serde_json::json!({
+        "email": "quan@example.com",
+        "password": "",
+    }))
+    .unwrap();
+    assert!(data.validate().is_err());
+}
+
+#[test]
+fn user_patch_is_validated() {
+    let data: UserPatchData = serde_json::from_value(serde_json::json!({
+        "email": "not-an-email",
+        "password": "short",
+    }))
+    .unwrap();
+    let errors = data.validate().unwrap_err();
+    assert_eq!(errors.field_errors().len(), 2);
+    let data: UserPatchData = serde_json::from_value(serde_json::json!({
+        "username": "quan",
+        "password": "x".repeat(101),
+    }))
+    .unwrap();
+    assert!(data.validate().is_err());
+    // Length is counted in characters, not bytes
+    let data: UserPatchData =
+        serde_json::from_value(serde_json::json!({ "password": "mậtkhẩu" })).unwrap();
+    assert!(data.validate().is_err());
+    let data: UserPatchData =
+        serde_json::from_value(serde_json::json!({ "password": "ư".repeat(100) })).unwrap();
+    assert!(data.validate().is_ok());
+    let data: UserPatchData =
+        serde_json::from_value(serde_json::json!({ "role": "Editor" })).unwrap();
+    assert!(data.validate().is_ok());
+    assert_eq!(data.gen_set_clause(), "role := <UserRole>$role");
+    // An admin cannot drop their own admin role
+    assert!(data.validate_for_self().is_err());
+}
+
+#[test]
+fn reset_token_is_invalid_after_password_change() {
+    let mut user = User {
+        password: "argon2$argon2id$old".into(),
+        ..Default::default()
+    };
+    let fingerprint = password_fingerprint(&user);
+    assert!(!fingerprint.contains("argon2"));
+    assert!(token_matches(&user, &fingerprint));
+    user.password = "argon2$argon2id$new".into();
+    assert!(!token_matches(&user, &fingerprint));
+}
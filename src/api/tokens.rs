@@ -0,0 +1,73 @@
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Result as AxumResult;
+use axum_extra::extract::WithRejection;
+use gel_protocol::model::Datetime as EDatetime;
+use gel_tokio::Client as EdgeClient;
+use tracing::info;
+use uuid::Uuid;
+use validify::Validify;
+
+use super::errors::ApiError;
+use super::structs::{ApiTokenCreateData, ApiTokenCreatedResponse};
+use crate::auth::{AuthSession, tokens};
+use crate::models::tokens::ApiToken;
+use crate::stores;
+
+// Tokens are managed with the session only. A leaked token cannot be used to make more.
+
+pub async fn list_tokens(
+    auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<Vec<ApiToken>>> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let api_tokens = stores::token::list_tokens(user.id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    Ok(Json(api_tokens))
+}
+
+pub async fn create_token(
+    auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+    WithRejection(Json(mut data), _): WithRejection<Json<ApiTokenCreateData>, ApiError>,
+) -> AxumResult<(StatusCode, Json<ApiTokenCreatedResponse>)> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    data.validify().map_err(ApiError::ValidationErrors)?;
+    data.validate_expiry().map_err(ApiError::ValidationErrors)?;
+    let token = tokens::generate_token();
+    let expires_at = data.expires_at.and_then(|d| EDatetime::try_from(d).ok());
+    let info = stores::token::create_token(
+        user.id,
+        &data.name,
+        &tokens::hash_token(&token),
+        &data.scopes,
+        expires_at,
+        &db,
+    )
+    .await
+    .map_err(ApiError::GelQueryError)?;
+    info!(
+        "{} created API token {:?} with scopes {:?}",
+        user.email, info.name, info.scopes
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiTokenCreatedResponse { token, info }),
+    ))
+}
+
+pub async fn revoke_token(
+    WithRejection(Path(token_id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+) -> AxumResult<StatusCode> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    stores::token::revoke_token(token_id, user.id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("Token".into()))?;
+    info!("{} revoked API token {token_id}", user.email);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,158 @@
+// Enrolment of two-factor authentication, for the logged-in user.
+// Like API tokens, it is managed with the session only.
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Result as AxumResult;
+use axum_extra::extract::WithRejection;
+use djangohashers::check_password;
+use gel_tokio::Client as EdgeClient;
+use tower_sessions::Session;
+use tracing::info;
+use validify::{ValidationError, ValidationErrors};
+
+use super::errors::ApiError;
+use super::structs::{
+    RecoveryCodesResponse, TotpCodeData, TotpDisableData, TotpSetupResponse, TotpStatusResponse,
+};
+use crate::auth::{AuthSession, totp};
+use crate::stores;
+
+/// Validation error for a wrong TOTP or recovery code
+pub fn wrong_code(field: &'static str) -> ApiError {
+    let mut err = ValidationError::new_field_named(field, "wrong-code");
+    err.set_location(field);
+    let mut errors = ValidationErrors::new();
+    errors.add(err);
+    ApiError::ValidationErrors(errors)
+}
+
+fn make_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes = totp::generate_recovery_codes();
+    let hashes = codes.iter().map(|c| totp::hash_recovery_code(c)).collect();
+    (codes, hashes)
+}
+
+pub async fn get_status(
+    auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<TotpStatusResponse>> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let recovery_codes_left = stores::user::count_recovery_codes(user.id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    Ok(Json(TotpStatusResponse {
+        enabled: user.has_2fa(),
+        recovery_codes_left,
+    }))
+}
+
+/// Make a new secret, to be added to the authenticator app.
+/// It is kept in the session until confirmed by `enable`.
+pub async fn start_setup(
+    auth_session: AuthSession,
+    session: Session,
+) -> AxumResult<Json<TotpSetupResponse>> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    if user.has_2fa() {
+        Err(ApiError::Conflict(
+            "Two-factor authentication is already enabled".into(),
+        ))?;
+    }
+    let secret = totp::generate_secret();
+    let provisioning_uri =
+        totp::provisioning_uri(&secret, &user.email).map_err(|e| ApiError::Other(e.to_string()))?;
+    session
+        .insert(totp::SETUP_SECRET_KEY, &secret)
+        .await
+        .map_err(ApiError::Session)?;
+    Ok(Json(TotpSetupResponse {
+        secret,
+        provisioning_uri,
+    }))
+}
+
+/// Turn on two-factor authentication, once the app gives the right code for the new secret.
+/// Return the recovery codes.
+pub async fn enable(
+    auth_session: AuthSession,
+    session: Session,
+    State(db): State<EdgeClient>,
+    WithRejection(Json(data), _): WithRejection<Json<TotpCodeData>, ApiError>,
+) -> AxumResult<(StatusCode, Json<RecoveryCodesResponse>)> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let secret: String = session
+        .get(totp::SETUP_SECRET_KEY)
+        .await
+        .map_err(ApiError::Session)?
+        .ok_or(ApiError::NotEnoughData)?;
+    let Some(step) = totp::verify_code(&secret, &user.email, &data.code) else {
+        return Err(wrong_code("code").into());
+    };
+    let (recovery_codes, hashes) = make_recovery_codes();
+    stores::user::enable_totp(user.id, &secret, step, &hashes, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("User".into()))?;
+    session
+        .remove::<String>(totp::SETUP_SECRET_KEY)
+        .await
+        .map_err(ApiError::Session)?;
+    info!("Enabled two-factor authentication for {}", user.email);
+    Ok((
+        StatusCode::CREATED,
+        Json(RecoveryCodesResponse { recovery_codes }),
+    ))
+}
+
+/// Turn off two-factor authentication. The password is asked again, in case the session is stolen.
+pub async fn disable(
+    auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+    WithRejection(Json(data), _): WithRejection<Json<TotpDisableData>, ApiError>,
+) -> AxumResult<StatusCode> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let right_passwd =
+        check_password(data.password.expose_secret(), &user.password).unwrap_or_default();
+    if !right_passwd {
+        let mut err = ValidationError::new_field_named("password", "wrong-password");
+        err.set_location("password");
+        let mut errors = ValidationErrors::new();
+        errors.add(err);
+        Err(ApiError::ValidationErrors(errors))?;
+    }
+    stores::user::disable_totp(user.id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    info!("Disabled two-factor authentication for {}", user.email);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replace the recovery codes, when they are used up or leaked
+pub async fn regenerate_recovery_codes(
+    auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+    WithRejection(Json(data), _): WithRejection<Json<TotpCodeData>, ApiError>,
+) -> AxumResult<Json<RecoveryCodesResponse>> {
+    let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let secret = user
+        .totp_secret
+        .as_deref()
+        .ok_or_else(|| ApiError::Conflict("Two-factor authentication is not enabled".into()))?;
+    let accepted = match totp::verify_code(secret, &user.email, &data.code) {
+        Some(step) => stores::user::use_totp_step(user.id, step, &db)
+            .await
+            .map_err(ApiError::GelQueryError)?,
+        None => false,
+    };
+    if !accepted {
+        Err(wrong_code("code"))?;
+    }
+    let (recovery_codes, hashes) = make_recovery_codes();
+    stores::user::set_recovery_codes(user.id, &hashes, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
+    info!("Regenerated recovery codes for {}", user.email);
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
@@ -1,14 +1,181 @@
-use axum::response::Result as AxumResult;
 use axum::Json;
-use axum::extract::State;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Result as AxumResult;
+use axum_extra::extract::WithRejection;
+use djangohashers::check_password;
 use gel_tokio::Client as EdgeClient;
+use serde_json::Value;
+use tracing::{debug, info};
+use uuid::Uuid;
+use validify::{Validate, ValidationError, ValidationErrors};
 
-use crate::stores;
-use crate::models::users::MiniUser;
-
+use super::auth::{ApiAuth, require_perm};
 use super::errors::ApiError;
+use super::structs::{PasswordChangeData, UserCreateData, UserPatchData};
+use crate::auth::permissions::Permission;
+use crate::auth::{AuthSession, hash_password};
+use crate::models::User;
+use crate::models::users::MiniUser;
+use crate::stores;
+use crate::types::EdgeSelectable;
 
-pub async fn list_users(State(db): State<EdgeClient>) -> AxumResult<Json<Vec<MiniUser>>> {
-    let users = stores::user::list_mini_users(&db).await.map_err(ApiError::GelQueryError)?;
+pub async fn list_users(
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<Vec<MiniUser>>> {
+    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    let users = stores::user::list_mini_users(&db)
+        .await
+        .map_err(ApiError::GelQueryError)?;
     Ok(Json(users))
 }
+
+pub async fn get_user(
+    WithRejection(Path(user_id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<User>> {
+    require_perm(&auth_session, Permission::ManageUsers).await?;
+    let user = stores::user::get_user(user_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("User".into()))?;
+    Ok(Json(user))
+}
+
+pub async fn create_user(
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+    WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
+) -> AxumResult<(StatusCode, Json<User>)> {
+    require_perm(&auth_session, Permission::ManageUsers).await?;
+    let user_data: UserCreateData =
+        serde_json::from_value(value).map_err(ApiError::JsonExtractionError)?;
+    user_data.validate().map_err(ApiError::ValidationErrors)?;
+    let set_clause = user_data.gen_set_clause();
+    let args = user_data.make_edgedb_args();
+    let fields = User::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            INSERT User {{
+                {set_clause}
+            }}
+        ) {fields}"
+    );
+    debug!("To query: {q}");
+    let user: User = db
+        .query_single(&q, &args)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::Other("Failed to create User".into()))?;
+    info!("Created user {} with role {}", user.username, user.role);
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// Change a user. A new password logs the user out of their other sessions.
+pub async fn update_user_partial(
+    WithRejection(Path(user_id), _): WithRejection<Path<Uuid>, ApiError>,
+    mut auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+    WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
+) -> AxumResult<Json<User>> {
+    let current_user = require_perm(&ApiAuth::from(&auth_session), Permission::ManageUsers).await?;
+    let patch_data: UserPatchData =
+        serde_json::from_value(value).map_err(ApiError::JsonExtractionError)?;
+    patch_data.validate().map_err(ApiError::ValidationErrors)?;
+    let is_self = current_user.id == user_id;
+    if is_self {
+        patch_data
+            .validate_for_self()
+            .map_err(ApiError::ValidationErrors)?;
+    }
+    let set_clause = patch_data.gen_set_clause();
+    // User submitted no field to update
+    if set_clause.is_empty() {
+        let user = stores::user::get_user(user_id, &db)
+            .await
+            .map_err(ApiError::GelQueryError)?
+            .ok_or(ApiError::ObjectNotFound("User".into()))?;
+        return Ok(Json(user));
+    }
+    let args = patch_data.make_edgedb_args(user_id);
+    let fields = User::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            UPDATE User
+            FILTER .id = <uuid>$id
+            SET {{
+                {set_clause}
+            }}
+        ) {fields}"
+    );
+    debug!("To query: {q}");
+    let user: User = db
+        .query_single(&q, &args)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("User".into()))?;
+    // Keep the current session valid with the new password hash
+    if is_self && patch_data.password.is_some() {
+        relogin(&mut auth_session, &user).await?;
+    }
+    Ok(Json(user))
+}
+
+/// Deactivate a user, who can no longer log in. Their posts are kept.
+pub async fn deactivate_user(
+    WithRejection(Path(user_id), _): WithRejection<Path<Uuid>, ApiError>,
+    auth_session: ApiAuth,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<User>> {
+    let current_user = require_perm(&auth_session, Permission::ManageUsers).await?;
+    if current_user.id == user_id {
+        let mut err = ValidationError::new_field_named("is_active", "self-lockout");
+        err.set_location("is_active");
+        let mut errors = ValidationErrors::new();
+        errors.add(err);
+        Err(ApiError::ValidationErrors(errors))?;
+    }
+    let user = stores::user::deactivate_user(user_id, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("User".into()))?;
+    info!("Deactivated user {}", user.username);
+    Ok(Json(user))
+}
+
+/// Change the password of the current user. Their other sessions are logged out.
+pub async fn change_password(
+    mut auth_session: AuthSession,
+    State(db): State<EdgeClient>,
+    WithRejection(Json(data), _): WithRejection<Json<PasswordChangeData>, ApiError>,
+) -> AxumResult<Json<User>> {
+    let user = auth_session.user.clone().ok_or(ApiError::Unauthorized)?;
+    let right_passwd =
+        check_password(data.current_password.expose_secret(), &user.password).unwrap_or_default();
+    if !right_passwd {
+        let mut err = ValidationError::new_field_named("current_password", "wrong-password");
+        err.set_location("current_password");
+        let mut errors = ValidationErrors::new();
+        errors.add(err);
+        Err(ApiError::ValidationErrors(errors))?;
+    }
+    data.validate().map_err(ApiError::ValidationErrors)?;
+    let hashed = hash_password(data.new_password.expose_secret());
+    let user = stores::user::set_password(user.id, &hashed, &db)
+        .await
+        .map_err(ApiError::GelQueryError)?
+        .ok_or(ApiError::ObjectNotFound("User".into()))?;
+    relogin(&mut auth_session, &user).await?;
+    Ok(Json(user))
+}
+
+/// The session stores a hash of the password, so the session is renewed
+/// for it not to be logged out with the others.
+async fn relogin(auth_session: &mut AuthSession, user: &User) -> Result<(), ApiError> {
+    auth_session.login(user).await.map_err(|e| {
+        tracing::error!("Error renewing session: {}", e);
+        ApiError::Other("Failed to renew session".into())
+    })
+}
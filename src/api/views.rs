@@ -5,11 +5,13 @@ use axum::response::Html;
 use axum::{Json, http::StatusCode, response::Result as AxumResult};
 use axum_extra::extract::WithRejection;
 use gel_tokio::Client as EdgeClient;
+use minijinja::context;
 use serde_json::{Map as JMap, Value};
 use slugrs::slugify;
 use uuid::Uuid;
 use validify::Validify;
 
+use super::auth::{ApiAuth, require_perm};
 use super::errors::ApiError;
 pub use super::minors::{
     create_book, create_book_author, create_presentation, delete_book, delete_book_author,
@@ -18,21 +20,29 @@ pub use super::minors::{
     update_presentation_partial,
 };
 use super::paging::gen_pagination_links;
-pub use super::posts::{create_post, delete_post, get_post, list_posts, update_post_partial};
-use super::structs::{BlogCategoryCreateData, BlogCategoryPatchData, CategoryListQuery, ObjectListResponse};
-pub use super::users::list_users;
-use crate::auth::AuthSession;
+pub use super::posts::{
+    create_post, delete_post, get_post, link_translation, list_posts, unlink_translation,
+    update_post_partial,
+};
+use super::structs::{
+    BlogCategoryCreateData, BlogCategoryPatchData, CategoryListQuery, ConvertQuery,
+    ObjectListResponse,
+};
+use crate::auth::permissions::Permission;
 use crate::consts::DEFAULT_PAGE_SIZE;
-use crate::models::{BlogCategory, MinimalObject, User};
+use crate::models::{BlogCategory, DocFormat, MinimalObject, User};
 use crate::stores;
 use crate::types::{AppState, EdgeSelectable};
-use crate::utils::markdown::{markdown_to_html, markdown_to_html_document};
+use crate::utils::html::render_with;
+use crate::utils::markdown::markdown_to_html_document;
+use crate::utils::page_cache::{CacheGroup, PageCache};
+use crate::worker::{JobQueue, Task};
 
 pub async fn root() -> &'static str {
     "API root"
 }
 
-pub async fn show_me(auth_session: AuthSession) -> AxumResult<Json<User>> {
+pub async fn show_me(auth_session: ApiAuth) -> AxumResult<Json<User>> {
     tracing::info!("Current user: {:?}", auth_session.user);
     let user = auth_session.user.ok_or(ApiError::Unauthorized)?;
     Ok(Json(user))
@@ -85,10 +95,11 @@ pub async fn get_category(
 
 pub async fn delete_category(
     Path(category_id): Path<Uuid>,
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
 ) -> AxumResult<StatusCode> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     let q = "DELETE BlogCategory FILTER .id = <uuid>$0";
     tracing::debug!("To query: {}", q);
     let _deleted_cat: MinimalObject = db
@@ -96,16 +107,20 @@ pub async fn delete_category(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("BlogCategory".into()))?;
+    // Categories are in the navigation bar of every page
+    cache.invalidate_or_warn(&[CacheGroup::All]).await;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn update_category_partial(
     WithRejection(Path(category_id), _): WithRejection<Path<Uuid>, ApiError>,
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(jobs): State<JobQueue>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<Json<BlogCategory>> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     // Collect list of submitted fields
     let jdata: JMap<String, Value> =
         serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
@@ -140,15 +155,24 @@ pub async fn update_category_partial(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::ObjectNotFound("BlogCategory".into()))?;
+    // Category names are part of the search index of its posts
+    if jdata.contains_key("title") || jdata.contains_key("title_vi") {
+        let task = Task::RefreshSearchIndex {
+            category_id: Some(category_id),
+        };
+        jobs.enqueue_or_warn(task).await;
+    }
+    cache.invalidate_or_warn(&[CacheGroup::All]).await;
     Ok(Json(cat))
 }
 
 pub async fn create_category(
-    auth_session: AuthSession,
+    auth_session: ApiAuth,
     State(db): State<EdgeClient>,
+    State(cache): State<PageCache>,
     WithRejection(Json(value), _): WithRejection<Json<Value>, ApiError>,
 ) -> AxumResult<(StatusCode, Json<BlogCategory>)> {
-    auth_session.user.ok_or(ApiError::Unauthorized)?;
+    require_perm(&auth_session, Permission::ManageContent).await?;
     // Collect list of submitted fields
     let jdata: JMap<String, Value> =
         serde_json::from_value(value.clone()).map_err(ApiError::JsonExtractionError)?;
@@ -179,21 +203,44 @@ pub async fn create_category(
         .await
         .map_err(ApiError::GelQueryError)?
         .ok_or(ApiError::Other("Failed to create BlogCategory".into()))?;
+    cache.invalidate_or_warn(&[CacheGroup::All]).await;
     Ok((StatusCode::CREATED, Json(created_cat)))
 }
 
-pub async fn convert_to_html(body: String) -> AxumResult<Html<String>> {
-    let html = markdown_to_html(&body);
+/// Remove all pages from the page cache. Return the number of removed pages.
+pub async fn flush_page_cache(
+    auth_session: ApiAuth,
+    State(cache): State<PageCache>,
+) -> AxumResult<Json<i64>> {
+    require_perm(&auth_session, Permission::ManageContent).await?;
+    let count = cache.flush().await.map_err(ApiError::Redis)?;
+    tracing::info!("Flushed {count} cached pages");
+    Ok(Json(count))
+}
+
+pub async fn convert_to_html(
+    Query(query): Query<ConvertQuery>,
+    body: String,
+) -> AxumResult<Html<String>> {
+    let html = query.format.unwrap_or_default().to_html(&body);
     Ok(Html(html))
 }
 
 #[axum::debug_handler]
 pub async fn convert_to_html_document(
     State(app_state): State<AppState>,
+    Query(query): Query<ConvertQuery>,
     body: String,
 ) -> AxumResult<Html<String>> {
     let AppState { jinja, .. } = app_state;
-    let html = markdown_to_html_document(&body, jinja).unwrap_or_default();
+    let html = match query.format.unwrap_or_default() {
+        DocFormat::Md => markdown_to_html_document(&body, jinja),
+        format => {
+            let vcontext = context! { content => format.to_html(&body) };
+            render_with("mini-preview.jinja", vcontext, jinja)
+        }
+    }
+    .unwrap_or_default();
     Ok(Html(html))
 }
 
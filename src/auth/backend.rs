@@ -1,9 +1,12 @@
-use axum_login::{AuthUser, AuthnBackend, UserId};
+use std::collections::HashSet;
+
+use axum_login::{AuthUser, AuthnBackend, AuthzBackend, UserId};
 use djangohashers::check_password;
 use tracing::info;
 
+use super::permissions::Permission;
 use crate::models::User;
-use crate::stores::user::get_user_by_email;
+use crate::stores::user::{get_user, get_user_by_email};
 
 #[derive(Clone, Debug)]
 pub struct Backend {
@@ -39,8 +42,13 @@ impl AuthnBackend for Backend {
     ) -> Result<Option<Self::User>, Self::Error> {
         let user = if let Some(user) = get_user_by_email(&cred.email, &self.db).await? {
             let right_passwd = check_password(&cred.password, &user.password).unwrap_or_default();
-            info!("Right password? {}", right_passwd);
-            right_passwd.then_some(user)
+            if !right_passwd {
+                info!("Wrong password for {}.", cred.email);
+            } else if !user.is_active {
+                info!("User {} is deactivated.", cred.email);
+            }
+            // Deactivated users cannot log in
+            (right_passwd && user.is_active).then_some(user)
         } else {
             info!("User with {} is not found.", cred.email);
             None
@@ -50,8 +58,22 @@ impl AuthnBackend for Backend {
 
     async fn get_user(&self, user_id: &UserId<Self>) -> Result<Option<Self::User>, Self::Error> {
         info!("To load user with ID {:?}", user_id);
-        let q = "SELECT User {id, username, email, password, is_active, is_superuser} FILTER .id = <uuid>$0";
-        let user: Option<User> = self.db.query_single(q, &(user_id,)).await?;
-        Ok(user)
+        let user = get_user(*user_id, &self.db).await?;
+        // Sessions of deactivated users are ended
+        Ok(user.filter(|u| u.is_active))
+    }
+}
+
+impl AuthzBackend for Backend {
+    type Permission = Permission;
+
+    async fn get_user_permissions(
+        &self,
+        user: &Self::User,
+    ) -> Result<HashSet<Self::Permission>, Self::Error> {
+        if !user.is_active {
+            return Ok(HashSet::new());
+        }
+        Ok(Permission::of_role(user.effective_role()))
     }
 }
@@ -0,0 +1,221 @@
+// Protection of logging in against password guessing.
+// Attempts are counted in Redis per email and per client IP, before the password is checked.
+// Each attempt after a failure for an email waits longer than the previous one,
+// and too many failures lock the email or IP out for a while.
+
+use std::time::Duration;
+
+use fred::error::Error as FredError;
+use fred::prelude::*;
+use fred::types::{Expiration, SetOptions};
+
+const KEY_PREFIX: &str = "quanweb:login";
+/// Failures are forgotten after this time since the first one
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+pub const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+const MAX_FAILURES_PER_EMAIL: i64 = 5;
+// Many people can share an IP address, behind NAT
+const MAX_FAILURES_PER_IP: i64 = 20;
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+fn failures_key(kind: &str, id: &str) -> String {
+    format!("{KEY_PREFIX}:failures:{kind}:{id}")
+}
+
+fn lock_key(kind: &str, id: &str) -> String {
+    format!("{KEY_PREFIX}:lock:{kind}:{id}")
+}
+
+fn subjects<'a>(email: &'a str, ip: Option<&'a str>) -> Vec<(&'static str, &'a str)> {
+    let mut subjects = vec![("email", email)];
+    if let Some(ip) = ip {
+        subjects.push(("ip", ip));
+    }
+    subjects
+}
+
+/// Emails are case-insensitive, so they are counted in lowercase
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// How long to wait before checking the next attempt after n failed logins: 0.5s, 1s, 2s... up to `MAX_DELAY`.
+pub fn delay_for(failures: i64) -> Duration {
+    let exp = (failures - 1).clamp(0, 10) as u32;
+    Duration::from_millis(500 * 2u64.pow(exp)).min(MAX_DELAY)
+}
+
+/// Get the remaining time if the email or IP is locked out
+pub async fn get_lockout(
+    email: &str,
+    ip: Option<&str>,
+    redis: &Pool,
+) -> Result<Option<Duration>, FredError> {
+    let mut remaining = 0;
+    for (kind, id) in subjects(email, ip) {
+        let ttl: i64 = redis.ttl(lock_key(kind, id)).await?;
+        remaining = remaining.max(ttl);
+    }
+    Ok((remaining > 0).then(|| Duration::from_secs(remaining as u64)))
+}
+
+/// Result of claiming a login attempt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attempt {
+    /// The email or IP is locked out, for the remaining time
+    Locked(Duration),
+    /// The credentials can be checked, after waiting for the delay
+    Allowed { delay: Duration },
+}
+
+/// Count a login attempt before the credentials are checked, and tell whether it is allowed.
+/// The counter is incremented first, so that parallel attempts cannot all pass the check.
+/// Failed attempts are left counted. Successful ones are taken back by [`clear_failures`].
+pub async fn claim_attempt(
+    email: &str,
+    ip: Option<&str>,
+    redis: &Pool,
+) -> Result<Attempt, FredError> {
+    if let Some(remaining) = get_lockout(email, ip, redis).await? {
+        return Ok(Attempt::Locked(remaining));
+    }
+    let mut email_attempts = 0;
+    for (kind, id) in subjects(email, ip) {
+        let key = failures_key(kind, id);
+        let expiration = Expiration::EX(FAILURE_WINDOW.as_secs() as i64);
+        // Start the window on first attempt. NX makes sure it is not extended by later ones.
+        let _set: Option<String> = redis
+            .set(&key, 0, Some(expiration), Some(SetOptions::NX), false)
+            .await?;
+        let attempts: i64 = redis.incr(&key).await?;
+        let limit = if kind == "email" {
+            email_attempts = attempts;
+            MAX_FAILURES_PER_EMAIL
+        } else {
+            MAX_FAILURES_PER_IP
+        };
+        if attempts > limit {
+            let lock_expiration = Expiration::EX(LOCKOUT_DURATION.as_secs() as i64);
+            let _: () = redis
+                .set(lock_key(kind, id), 1, Some(lock_expiration), None, false)
+                .await?;
+            // The counter starts again after the lockout
+            let _: i64 = redis.del(&key).await?;
+            tracing::warn!("Login is locked out for {kind} {id} after {limit} failures");
+            return Ok(Attempt::Locked(LOCKOUT_DURATION));
+        }
+    }
+    // The attempts before this one have failed
+    let delay = if email_attempts > 1 {
+        delay_for(email_attempts - 1)
+    } else {
+        Duration::ZERO
+    };
+    Ok(Attempt::Allowed { delay })
+}
+
+/// Forget the failures of an email, and take back the attempt from the IP, after a successful login
+pub async fn clear_failures(email: &str, ip: Option<&str>, redis: &Pool) -> Result<(), FredError> {
+    let _: i64 = redis.del(failures_key("email", email)).await?;
+    release_ip_attempt(ip, redis).await
+}
+
+/// Take back an attempt from the IP, when it turns out not to be a failure.
+/// Logging in with two factors claims two attempts, this is for the one of the password step.
+pub async fn release_ip_attempt(ip: Option<&str>, redis: &Pool) -> Result<(), FredError> {
+    let Some(ip) = ip else {
+        return Ok(());
+    };
+    let key = failures_key("ip", ip);
+    let left: i64 = redis.decr(&key).await?;
+    // The counter may have expired meanwhile, then DECR made a new one, without expiry
+    if left <= 0 {
+        let _: i64 = redis.del(&key).await?;
+    }
+    Ok(())
+}
+
+/// Lift the lockout of an email or IP, and forget their failures.
+/// Return the number of removed lockouts.
+pub async fn unlock(email: Option<&str>, ip: Option<&str>, redis: &Pool) -> Result<i64, FredError> {
+    let mut subjects = Vec::new();
+    if let Some(email) = email {
+        subjects.push(("email", email));
+    }
+    if let Some(ip) = ip {
+        subjects.push(("ip", ip));
+    }
+    let mut count = 0;
+    for (kind, id) in subjects {
+        let _: i64 = redis.del(failures_key(kind, id)).await?;
+        let deleted: i64 = redis.del(lock_key(kind, id)).await?;
+        count += deleted;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_increases() {
+        assert_eq!(delay_for(1), Duration::from_millis(500));
+        assert_eq!(delay_for(2), Duration::from_secs(1));
+        assert_eq!(delay_for(4), Duration::from_secs(4));
+        assert_eq!(delay_for(9), MAX_DELAY);
+        assert_eq!(delay_for(0), Duration::from_millis(500));
+    }
+
+    async fn get_count(kind: &str, id: &str, redis: &Pool) -> Option<i64> {
+        redis.get(failures_key(kind, id)).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server"]
+    async fn test_failed_attempts_are_delayed() {
+        let redis = crate::db::get_redis_pool().await.unwrap();
+        let email = format!("{}@example.com", uuid::Uuid::new_v4());
+        let ip = uuid::Uuid::new_v4().to_string();
+        let first = claim_attempt(&email, Some(&ip), &redis).await.unwrap();
+        assert_eq!(
+            first,
+            Attempt::Allowed {
+                delay: Duration::ZERO
+            }
+        );
+        // The first one was not cleared, so it is a failure
+        let second = claim_attempt(&email, Some(&ip), &redis).await.unwrap();
+        assert_eq!(
+            second,
+            Attempt::Allowed {
+                delay: delay_for(1)
+            }
+        );
+        assert_eq!(get_count("ip", &ip, &redis).await, Some(2));
+        unlock(Some(&email), Some(&ip), &redis).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server"]
+    async fn test_two_factor_login_leaves_no_ip_attempt() {
+        let redis = crate::db::get_redis_pool().await.unwrap();
+        let email = format!("{}@example.com", uuid::Uuid::new_v4());
+        let ip = uuid::Uuid::new_v4().to_string();
+        // Someone else failed from the same IP
+        claim_attempt("other@example.com", Some(&ip), &redis)
+            .await
+            .unwrap();
+        // Password step
+        claim_attempt(&email, Some(&ip), &redis).await.unwrap();
+        release_ip_attempt(Some(&ip), &redis).await.unwrap();
+        // Second factor step
+        claim_attempt(&email, Some(&ip), &redis).await.unwrap();
+        clear_failures(&email, Some(&ip), &redis).await.unwrap();
+        assert_eq!(get_count("email", &email, &redis).await, None);
+        assert_eq!(get_count("ip", &ip, &redis).await, Some(1));
+        unlock(Some("other@example.com"), Some(&ip), &redis)
+            .await
+            .unwrap();
+    }
+}
@@ -1,6 +1,23 @@
 pub mod backend;
+pub mod lockout;
+pub mod permissions;
+pub mod reset;
 pub mod structs;
+pub mod tokens;
+pub mod totp;
 
 use backend::Backend;
+use djangohashers::{Algorithm, make_password_with_algorithm};
+use sha2::{Digest, Sha256};
 
 pub type AuthSession = axum_login::AuthSession<Backend>;
+
+/// Hash a new password, in the format Django uses
+pub fn hash_password(password: &str) -> String {
+    make_password_with_algorithm(password, Algorithm::Argon2)
+}
+
+/// Hash a secret token, so that it can be stored and looked up without being revealed
+pub fn sha256_hex(value: &str) -> String {
+    format!("{:x}", Sha256::digest(value.as_bytes()))
+}
@@ -0,0 +1,51 @@
+use std::collections::HashSet;
+
+use crate::models::tokens::TokenScope;
+use crate::models::users::Role;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    /// Create posts and edit one's own posts
+    WritePost,
+    /// Edit and delete posts of other users
+    EditAnyPost,
+    /// Publish, unpublish or schedule posts
+    PublishPost,
+    /// Manage categories, tags, comments, books and presentations
+    ManageContent,
+    ManageFiles,
+    ManageUsers,
+}
+
+impl Permission {
+    pub fn of_role(role: Role) -> HashSet<Self> {
+        let perms: &[Self] = match role {
+            Role::Author => &[Self::WritePost],
+            Role::Editor => &[
+                Self::WritePost,
+                Self::EditAnyPost,
+                Self::PublishPost,
+                Self::ManageContent,
+            ],
+            Role::Admin => &[
+                Self::WritePost,
+                Self::EditAnyPost,
+                Self::PublishPost,
+                Self::ManageContent,
+                Self::ManageFiles,
+                Self::ManageUsers,
+            ],
+        };
+        perms.iter().copied().collect()
+    }
+
+    /// Permissions which an API token of this scope can use, if its owner has them.
+    /// Managing content and users is left to the admin UI.
+    pub fn of_scope(scope: TokenScope) -> &'static [Self] {
+        match scope {
+            TokenScope::Read => &[],
+            TokenScope::WritePosts => &[Self::WritePost, Self::EditAnyPost, Self::PublishPost],
+            TokenScope::Files => &[Self::ManageFiles],
+        }
+    }
+}
@@ -0,0 +1,58 @@
+// Single-use tokens to reset forgotten passwords.
+// Only the hash of a token is kept in Redis, with the fingerprint of the password at the time
+// it was issued. Once the password changes, by this reset or else, the token no longer matches.
+
+use std::time::Duration;
+
+use fred::error::Error as FredError;
+use fred::prelude::*;
+use fred::types::Expiration;
+use libpassgen::{Pool as CharPool, generate_password};
+use uuid::Uuid;
+
+use super::sha256_hex;
+use crate::conf::ALPHANUMERIC;
+use crate::models::User;
+
+const KEY_PREFIX: &str = "quanweb:password-reset";
+pub const TOKEN_TTL: Duration = Duration::from_secs(30 * 60);
+
+fn token_key(token: &str) -> String {
+    format!("{KEY_PREFIX}:{}", sha256_hex(token))
+}
+
+/// Identify the current password of the user, without revealing it
+pub fn password_fingerprint(user: &User) -> String {
+    sha256_hex(&user.password)
+}
+
+/// Make a new reset token for the user. It is valid for `TOKEN_TTL`.
+pub async fn issue_reset_token(user: &User, redis: &Pool) -> Result<String, FredError> {
+    let pool: CharPool = ALPHANUMERIC.parse().unwrap_or_default();
+    let token = generate_password(&pool, 48);
+    let value = format!("{}:{}", user.id, password_fingerprint(user));
+    let expiration = Expiration::EX(TOKEN_TTL.as_secs() as i64);
+    let _: () = redis
+        .set(token_key(&token), value, Some(expiration), None, false)
+        .await?;
+    Ok(token)
+}
+
+/// Use up a reset token. Return the ID of its user, and the fingerprint of the password
+/// when the token was issued, to be checked by `token_matches`.
+pub async fn consume_reset_token(
+    token: &str,
+    redis: &Pool,
+) -> Result<Option<(Uuid, String)>, FredError> {
+    let value: Option<String> = redis.getdel(token_key(token)).await?;
+    let parsed = value.and_then(|v| {
+        let (id, fingerprint) = v.split_once(':')?;
+        Some((Uuid::parse_str(id).ok()?, fingerprint.to_string()))
+    });
+    Ok(parsed)
+}
+
+/// Tell if the password of the user is still the one when the token was issued
+pub fn token_matches(user: &User, fingerprint: &str) -> bool {
+    password_fingerprint(user) == fingerprint
+}
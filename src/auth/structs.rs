@@ -22,21 +22,36 @@ impl validify::Validate for LoginReqData {
             err.set_location("email");
             errors.add(err);
         }
-        match validate_password(&self.password) {
-            Ok(()) => (),
-            Err(mut err) => {
-                let field = err.field_name().unwrap().to_string();
-                err.set_location(field);
-                err.add_param("value", &"**redacted**");
-                errors.add(err);
-            }
-        };
+        // The length limits are for new passwords only, older ones may not meet them.
+        if self.password.expose_secret().is_empty() {
+            let mut err = ValidationError::new_field_named("password", "required");
+            err.set_location("password");
+            errors.add(err);
+        }
         errors.is_empty().then_some(()).ok_or(errors)
     }
 }
 
-pub fn validate_password(value: &Secret<String>) -> Result<(), ValidationError> {
-    (value.expose_secret().len() >= 8)
-        .then_some(())
-        .ok_or_else(|| ValidationError::new_field_named("password", "too-short"))
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// Passwords are hashed with Argon2, which is costly for very long input
+pub const MAX_PASSWORD_LENGTH: usize = 100;
+
+/// Check a new password which is submitted in a field of given name.
+/// The length is counted in characters, not bytes, for the limits to be the same in all languages.
+pub fn validate_password_as(
+    field: &'static str,
+    value: &Secret<String>,
+) -> Result<(), ValidationError> {
+    let len = value.expose_secret().chars().count();
+    let code = if len < MIN_PASSWORD_LENGTH {
+        "too-short"
+    } else if len > MAX_PASSWORD_LENGTH {
+        "too-long"
+    } else {
+        return Ok(());
+    };
+    let mut err = ValidationError::new_field_named(field, code);
+    err.add_param("min", &MIN_PASSWORD_LENGTH);
+    err.add_param("max", &MAX_PASSWORD_LENGTH);
+    Err(err)
 }
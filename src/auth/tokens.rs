@@ -0,0 +1,58 @@
+// Personal API tokens, for scripts and clients which cannot use the session cookie.
+// They are sent in the "Authorization: Bearer <token>" header.
+
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
+use libpassgen::{Pool as CharPool, generate_password};
+
+use super::sha256_hex;
+use crate::conf::ALPHANUMERIC;
+
+/// Tokens are prefixed, so that they are easy to spot in leaked config files
+pub const TOKEN_PREFIX: &str = "qw_";
+const TOKEN_LENGTH: usize = 40;
+
+/// Make a new token. Only its hash, from `hash_token`, is to be stored.
+pub fn generate_token() -> String {
+    let pool: CharPool = ALPHANUMERIC.parse().unwrap_or_default();
+    format!("{TOKEN_PREFIX}{}", generate_password(&pool, TOKEN_LENGTH))
+}
+
+pub fn hash_token(token: &str) -> String {
+    sha256_hex(token)
+}
+
+/// Get the token from the "Authorization" header, if it is of "Bearer" scheme
+pub fn get_bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_get_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(get_bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer qw_abc"));
+        assert_eq!(get_bearer_token(&headers), Some("qw_abc"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic abc"));
+        assert_eq!(get_bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("bearer  "));
+        assert_eq!(get_bearer_token(&headers), None);
+    }
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + TOKEN_LENGTH);
+        assert_ne!(hash_token(&token), token);
+    }
+}
@@ -0,0 +1,140 @@
+// Two-factor authentication with time-based one-time passwords (RFC 6238),
+// as generated by authenticator apps, and single-use recovery codes for when the phone is lost.
+
+use chrono::{TimeDelta, Utc};
+use libpassgen::{Pool as CharPool, generate_password};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP, TotpUrlError};
+use uuid::Uuid;
+
+use super::sha256_hex;
+
+const ISSUER: &str = "QuanWeb";
+pub const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_CHARS: &str = "abcdefghjkmnpqrstuvwxyz23456789";
+/// Session key of the user who passed the password step, waiting for the second factor
+pub const PENDING_LOGIN_KEY: &str = "pending_2fa_login";
+/// Session key of the secret being enrolled, until the user confirms it with a code
+pub const SETUP_SECRET_KEY: &str = "pending_2fa_secret";
+const PENDING_LOGIN_TTL: TimeDelta = TimeDelta::minutes(5);
+/// Seconds during which a code is valid
+const TIME_STEP: u64 = 30;
+
+/// User who gave the right password, but not yet the second factor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub user_id: Uuid,
+    pub email: String,
+    pub expires_at: i64,
+}
+
+impl PendingLogin {
+    pub fn new(user_id: Uuid, email: String) -> Self {
+        let expires_at = (Utc::now() + PENDING_LOGIN_TTL).timestamp();
+        Self {
+            user_id,
+            email,
+            expires_at,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now().timestamp() > self.expires_at
+    }
+}
+
+/// Make a new secret, in Base32 as typed into authenticator apps
+pub fn generate_secret() -> String {
+    match Secret::generate_secret().to_encoded() {
+        Secret::Encoded(s) => s,
+        Secret::Raw(_) => unreachable!("Secret is just encoded"),
+    }
+}
+
+fn make_totp(secret: &str, email: &str) -> Result<TOTP, TotpUrlError> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|_e| TotpUrlError::Secret(secret.to_string()))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        TIME_STEP,
+        bytes,
+        Some(ISSUER.to_string()),
+        email.to_string(),
+    )
+}
+
+/// The "otpauth://" URI, to be shown as QR code for authenticator apps to scan
+pub fn provisioning_uri(secret: &str, email: &str) -> Result<String, TotpUrlError> {
+    make_totp(secret, email).map(|t| t.get_url())
+}
+
+/// Check a code from the authenticator app, and return the time step it belongs to.
+/// The previous and next codes are also accepted, for clocks which are a bit off.
+/// A code stays valid for some time, so the caller must also check that the step comes after
+/// the last accepted one, for the code not to be replayed.
+pub fn verify_code(secret: &str, email: &str, code: &str) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let totp = make_totp(secret, email).ok()?;
+    let current = u64::try_from(Utc::now().timestamp()).ok()? / TIME_STEP;
+    [current - 1, current, current + 1]
+        .into_iter()
+        .find(|step| totp.generate(step * TIME_STEP) == code)
+        .and_then(|step| i64::try_from(step).ok())
+}
+
+/// Make a new set of recovery codes, like "k7dq-m2xa". Only their hashes are to be stored.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let pool: CharPool = RECOVERY_CODE_CHARS.parse().unwrap_or_default();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = generate_password(&pool, 8);
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared regardless of case, spaces and dashes
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    sha256_hex(&normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_code() {
+        let secret = generate_secret();
+        let email = "quan@example.com";
+        let uri = provisioning_uri(&secret, email).unwrap();
+        assert!(uri.starts_with("otpauth://totp/QuanWeb:"));
+        assert!(uri.contains(&secret));
+        let code = make_totp(&secret, email)
+            .unwrap()
+            .generate_current()
+            .unwrap();
+        let current = Utc::now().timestamp() / TIME_STEP as i64;
+        let step = verify_code(&secret, email, &code).unwrap();
+        assert!((current - 1..=current).contains(&step));
+        assert_eq!(verify_code(&secret, email, "000000x"), None);
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), 9);
+        assert_eq!(
+            hash_recovery_code(&codes[0]),
+            hash_recovery_code(&codes[0].to_uppercase().replace('-', " "))
+        );
+    }
+}
@@ -1,9 +1,16 @@
+use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
+use futures_util::StreamExt;
 use gel_protocol::named_args;
 use miette::{miette, IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use regex::{Captures, Regex};
+use reqwest::header::{CONTENT_TYPE, REFERER};
+use sha2::{Digest, Sha256};
 use syntect::highlighting::ThemeSet;
 use syntect::html::css_for_theme_with_class_style;
 use tracing::debug;
@@ -17,9 +24,20 @@ use uuid::Uuid;
 use quanweb::conf;
 use quanweb::consts::SYNTECT_CLASS_STYLE;
 use quanweb::db;
+use quanweb::models::MinBodyBlogPost;
+use quanweb::models::feeds::DEFAULT_SITE_URL;
+use quanweb::storage::{FileStorage, StorageBackend};
+use quanweb::stores;
+use quanweb::thingsup::{config_highlighting, config_media};
+use quanweb::types::EdgeSelectable;
+use quanweb::utils::images::{self, VariantRegistry};
+use quanweb::utils::page_cache::{CacheGroup, PageCache};
 
 const OUTPUT_PATH: &str = "static/css/syntect.css";
 const SYNTECT_THEME: &str = "base16-ocean.dark";
+// Some image hosts refuse to serve clients which don't look like a browser
+const BROWSER_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0";
 
 /// Some tools for QuanWeb
 #[derive(Debug, Clone, Parser)]
@@ -37,6 +55,36 @@ enum Commands {
     TryUpdateCategory {
         id: Uuid,
     },
+    /// Copy images which posts hotlink from other hosts to our storage, and make the posts use the copies
+    MigrateExternalImages {
+        /// Host of the images to migrate. Can be repeated.
+        #[arg(long = "host", default_values = ["i.imgur.com"])]
+        hosts: Vec<String>,
+        /// Folder in our storage to put the images in
+        #[arg(long, default_value = "blogs/imgur")]
+        dest: String,
+        /// Only report what would be done, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageStatus {
+    /// Already in our storage, from a previous run
+    Existing,
+    ToDownload,
+    Downloaded,
+}
+
+impl ImageStatus {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Existing => "existing",
+            Self::ToDownload => "to download",
+            Self::Downloaded => "downloaded",
+        }
+    }
 }
 
 fn config_logging() {
@@ -107,6 +155,257 @@ async fn try_update_category(id: Uuid) -> Result<()> {
     Ok(())
 }
 
+fn external_image_regex(hosts: &[String]) -> Result<Regex> {
+    let hosts: Vec<String> = hosts.iter().map(|h| regex::escape(h)).collect();
+    // Stop at the characters which end a URL in Markdown, reStructuredText and HTML
+    let pattern = format!(r#"https?://(?:{})/[^\s"'()\[\]<>`]+"#, hosts.join("|"));
+    Regex::new(&pattern).into_diagnostic()
+}
+
+/// Get a file name from the image URL, keeping only the safe characters.
+/// A short hash of the whole URL is added, so that images with the same name from different hosts
+/// or folders don't overwrite each other.
+fn image_file_name(url: &str) -> Option<String> {
+    let last = url.split(['?', '#']).next()?.rsplit('/').next()?;
+    let name: String = last
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' => c,
+            _ => '-',
+        })
+        .collect();
+    let name = name.trim_matches(['.', '-']);
+    if name.is_empty() {
+        return None;
+    }
+    let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+    let hash = &hash[..8];
+    let name = match name.rsplit_once('.') {
+        Some((stem, ext)) if has_image_extension(name) => format!("{stem}-{hash}.{ext}"),
+        _ => format!("{name}-{hash}"),
+    };
+    Some(name)
+}
+
+fn has_image_extension(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_stem, ext)| {
+        mime_guess::from_ext(ext)
+            .first()
+            .is_some_and(|m| m.type_() == mime_guess::mime::IMAGE)
+    })
+}
+
+fn extension_for(content_type: &str) -> Option<&'static str> {
+    let ext = match content_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/avif" => "avif",
+        "image/svg+xml" => "svg",
+        _ => return None,
+    };
+    Some(ext)
+}
+
+/// Tell, before downloading, if there is nothing more to do with an image:
+/// it was copied by a previous run, or it is only to be reported in dry run.
+async fn check_image(
+    name: &str,
+    dest: &str,
+    storage: &StorageBackend,
+    dry_run: bool,
+) -> Result<Option<(String, ImageStatus)>> {
+    // Without extension in the URL, the path is only known after seeing the content type
+    let known_path = has_image_extension(name).then(|| format!("{dest}/{name}"));
+    if let Some(path) = known_path.as_deref() {
+        if storage.stat(path).await.into_diagnostic()?.is_some() {
+            return Ok(Some((path.to_string(), ImageStatus::Existing)));
+        }
+    }
+    if dry_run {
+        let path = known_path.unwrap_or_else(|| format!("{dest}/{name}.*"));
+        return Ok(Some((path, ImageStatus::ToDownload)));
+    }
+    Ok(None)
+}
+
+/// Copy one image to our storage, if it is not there yet.
+/// The stored path only depends on the URL, so running again reuses the copies.
+async fn migrate_image(
+    url: &str,
+    dest: &str,
+    storage: &StorageBackend,
+    variants: &VariantRegistry,
+    http: &reqwest::Client,
+    dry_run: bool,
+) -> Result<(String, ImageStatus)> {
+    let name = image_file_name(url).ok_or(miette!("No file name in URL"))?;
+    if let Some(done) = check_image(&name, dest, storage, dry_run).await? {
+        return Ok(done);
+    }
+    let resp = http
+        .get(url)
+        .header(REFERER, format!("{DEFAULT_SITE_URL}/"))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .into_diagnostic()?;
+    // Imgur redirects deleted images to a placeholder
+    if resp.url().path().ends_with("/removed.png") {
+        return Err(miette!("Image was removed from the host"));
+    }
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    if !content_type.starts_with("image/") {
+        return Err(miette!("Not an image, but {content_type:?}"));
+    }
+    let path = if has_image_extension(&name) {
+        format!("{dest}/{name}")
+    } else {
+        let ext =
+            extension_for(&content_type).ok_or(miette!("Unsupported image type {content_type}"))?;
+        let path = format!("{dest}/{name}.{ext}");
+        if storage.stat(&path).await.into_diagnostic()?.is_some() {
+            return Ok((path, ImageStatus::Existing));
+        }
+        path
+    };
+    let content = resp
+        .bytes_stream()
+        .map(|r| r.map_err(io::Error::other))
+        .boxed();
+    storage
+        .put(&path, &content_type, content)
+        .await
+        .into_diagnostic()?;
+    if images::has_variants(&path) {
+        images::store_variants(&path, storage)
+            .await
+            .into_diagnostic()?;
+        variants.record(&path).await.into_diagnostic()?;
+    }
+    Ok((path, ImageStatus::Downloaded))
+}
+
+async fn migrate_external_images(hosts: Vec<String>, dest: String, dry_run: bool) -> Result<()> {
+    let config = conf::get_config().map_err(|e| miette!("Error loading config: {e}"))?;
+    config_highlighting(&config);
+    let storage =
+        conf::get_file_storage(&config).map_err(|e| miette!("Error getting file storage: {e}"))?;
+    config_media(&storage);
+    let client = db::get_gel_client(&config).await.map_err(|e| {
+        debug!("{e:?}");
+        miette!("Failed to create Gel client")
+    })?;
+    let redis_pool = db::get_redis_pool()
+        .await
+        .map_err(|_e| miette!("Error connecting to Redis"))?;
+    let variants = VariantRegistry::new(client.clone());
+    let page_cache = PageCache::new(redis_pool);
+    let retention = conf::get_revision_retention(&config)
+        .map_err(|e| miette!("Error getting revision retention: {e}"))?;
+    let http = reqwest::Client::builder()
+        .user_agent(BROWSER_USER_AGENT)
+        .build()
+        .into_diagnostic()?;
+    let re_url = external_image_regex(&hosts)?;
+    let dest = dest.trim_matches('/');
+
+    let fields = MinBodyBlogPost::fields_as_shape();
+    let q = format!(
+        "SELECT BlogPost {fields}
+        FILTER any(contains(.body, array_unpack(<array<str>>$0)))
+        ORDER BY .created_at"
+    );
+    debug!("To query: {q}");
+    let posts: Vec<MinBodyBlogPost> = client
+        .query(&q, &(hosts.clone(),))
+        .await
+        .map_err(|e| miette!("Failed to fetch posts: {e}"))?;
+    eprintln!(
+        "Found {} posts mentioning {}",
+        posts.len(),
+        hosts.join(", ")
+    );
+
+    let mut failures = 0;
+    for post in posts {
+        let body = post.body.unwrap_or_default();
+        let mut urls: Vec<&str> = re_url.find_iter(&body).map(|m| m.as_str()).collect();
+        urls.sort_unstable();
+        urls.dedup();
+        if urls.is_empty() {
+            continue;
+        }
+        println!("Post '{}' ({})", post.title.blue(), post.id);
+        let mut new_urls = HashMap::new();
+        for url in urls {
+            match migrate_image(url, dest, &storage, &variants, &http, dry_run).await {
+                Ok((path, status)) => {
+                    println!("  {url} -> {path} ({})", status.label().green());
+                    if status != ImageStatus::ToDownload {
+                        new_urls.insert(url, storage.public_url(&path));
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    println!("  {url} {}", format!("failed: {e}").red());
+                }
+            }
+        }
+        if dry_run || new_urls.is_empty() {
+            continue;
+        }
+        // Images which failed are left as is, to be retried in the next run
+        let new_body = re_url.replace_all(&body, |caps: &Captures| {
+            new_urls
+                .get(&caps[0])
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_string())
+        });
+        let html = post.format.to_html(&new_body);
+        let excerpt = post.format.make_excerpt(&new_body);
+        let set_clause = "body := <str>$body, html := <str>$html, excerpt := <str>$excerpt";
+        let args = named_args! {
+            "id" => post.id,
+            "body" => new_body.into_owned(),
+            "html" => html,
+            "excerpt" => excerpt
+        };
+        stores::revision::update_post_with_revision(set_clause, args, None, &client)
+            .await
+            .map_err(|e| miette!("Failed to update post {}: {e}", post.id))?;
+        stores::revision::prune_revisions(post.id, &retention, &client)
+            .await
+            .map_err(|e| miette!("Failed to prune revisions of post {}: {e}", post.id))?;
+        stores::blog::refresh_post_search_fields(post.id, &client)
+            .await
+            .map_err(|e| miette!("Failed to refresh search fields of post {}: {e}", post.id))?;
+        page_cache
+            .invalidate_or_warn(&CacheGroup::of_post(post.id))
+            .await;
+    }
+    if failures > 0 {
+        eprintln!(
+            "{}",
+            format!("{failures} images could not be migrated").yellow()
+        );
+    }
+    if dry_run {
+        eprintln!("Dry run, nothing was changed.");
+    } else {
+        eprintln!("🎉 Done!");
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let opts = ToolOptions::parse();
     config_logging();
@@ -118,6 +417,98 @@ fn main() -> Result<()> {
             let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(async { try_update_category(id).await })?;
         }
+        Commands::MigrateExternalImages {
+            hosts,
+            dest,
+            dry_run,
+        } => {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async { migrate_external_images(hosts, dest, dry_run).await })?;
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quanweb::storage::local::LocalStorage;
+
+    #[test]
+    fn test_image_file_name() {
+        let name = image_file_name("https://i.imgur.com/AbC123.jpg").unwrap();
+        let (stem, ext) = name.rsplit_once('.').unwrap();
+        assert_eq!(ext, "jpg");
+        let (stem, hash) = stem.rsplit_once('-').unwrap();
+        assert_eq!(stem, "AbC123");
+        assert_eq!(hash.len(), 8);
+        // Same name, different folder
+        assert_ne!(
+            image_file_name("https://i.imgur.com/a/AbC123.jpg").unwrap(),
+            name
+        );
+        // Stable between runs
+        assert_eq!(
+            image_file_name("https://i.imgur.com/AbC123.jpg").unwrap(),
+            name
+        );
+        let name = image_file_name("https://example.com/photos/my photo?size=large").unwrap();
+        assert!(name.starts_with("my-photo-"));
+        assert!(!name.contains('.'));
+        assert_eq!(image_file_name("https://example.com/"), None);
+    }
+
+    #[test]
+    fn test_external_image_regex() {
+        let re = external_image_regex(&["i.imgur.com".into(), "example.com".into()]).unwrap();
+        let body = r#"![A](https://i.imgur.com/a.png) <img src="http://example.com/b/c.jpg">
+            `https://i.imgur.com/d.png`, (https://iximgur.com/e.png) https://quan-images.b-cdn.net/f.png"#;
+        let urls: Vec<&str> = re.find_iter(body).map(|m| m.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://i.imgur.com/a.png",
+                "http://example.com/b/c.jpg",
+                "https://i.imgur.com/d.png",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_image_skips_migrated() {
+        let root = std::env::temp_dir().join(format!("quanweb-tools-{}", std::process::id()));
+        let storage = StorageBackend::Local(LocalStorage::new(root.clone()));
+        let name = image_file_name("https://i.imgur.com/AbC123.jpg").unwrap();
+        let path = format!("blogs/imgur/{name}");
+        // Not copied yet
+        let status = check_image(&name, "blogs/imgur", &storage, false)
+            .await
+            .unwrap();
+        assert_eq!(status, None);
+        let status = check_image(&name, "blogs/imgur", &storage, true)
+            .await
+            .unwrap();
+        assert_eq!(status, Some((path.clone(), ImageStatus::ToDownload)));
+        // Copied by a previous run
+        fs::create_dir_all(root.join("blogs/imgur")).unwrap();
+        fs::write(root.join(&path), b"jpeg").unwrap();
+        for dry_run in [false, true] {
+            let status = check_image(&name, "blogs/imgur", &storage, dry_run)
+                .await
+                .unwrap();
+            assert_eq!(status, Some((path.clone(), ImageStatus::Existing)));
+        }
+        // Without extension, the copy is only looked up after the download
+        let status = check_image("AbC123-1a2b3c4d", "blogs/imgur", &storage, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            status,
+            Some((
+                "blogs/imgur/AbC123-1a2b3c4d.*".into(),
+                ImageStatus::ToDownload
+            ))
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,12 +1,42 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
 use libpassgen::{Pool, generate_password};
 use miette::{Report, miette};
 
 use config::{Config, ConfigError, File};
 
+use crate::mail::Mailer;
+use crate::mail::file::FileMailer;
+use crate::mail::smtp::SmtpMailer;
+use crate::models::feeds::DEFAULT_SITE_URL;
+use crate::storage::StorageBackend;
+use crate::storage::bunny::BunnyStorage;
+use crate::storage::local::LocalStorage;
+use crate::types::{HighlightMode, MailKind, RevisionRetention, StorageKind};
+
 pub const KEY_SECRET: &str = "secret_key";
 pub const KEY_EDGEDB_INSTANCE: &str = "edgedb_instance";
 pub const KEY_BUNNY_API_KEY: &str = "bunny_api_key";
 pub const KEY_BUNNY_CDN_HOST: &str = "bunny_cdn_host";
+// Account-level key, needed by the Bunny purge API (the storage key is not accepted there)
+pub const KEY_BUNNY_ACCOUNT_API_KEY: &str = "bunny_account_api_key";
+// Can be changed to point to a mock server when testing
+pub const KEY_BUNNY_STORAGE_URL: &str = "bunny_storage_url";
+pub const KEY_FILE_STORAGE: &str = "file_storage";
+pub const KEY_MEDIA_ROOT: &str = "media_root";
+pub const KEY_MAX_UPLOAD_MB: &str = "max_upload_mb";
+pub const KEY_CODE_HIGHLIGHTING: &str = "code_highlighting";
+pub const KEY_REVISIONS_KEPT: &str = "revisions_kept";
+pub const KEY_REVISIONS_MAX_AGE_DAYS: &str = "revisions_max_age_days";
+pub const KEY_MAIL_BACKEND: &str = "mail_backend";
+pub const KEY_MAIL_DIR: &str = "mail_dir";
+pub const KEY_MAIL_FROM: &str = "mail_from";
+pub const KEY_SMTP_URL: &str = "smtp_url";
+pub const KEY_PASSWORD_RESET_URL: &str = "password_reset_url";
+pub const KEY_SITE_URL: &str = "site_url";
+pub const KEY_METRICS_TOKEN: &str = "metrics_token";
+pub const KEY_OTLP_ENDPOINT: &str = "otlp_endpoint";
 pub const DEFAULT_PORT: u16 = 3721;
 pub const ALPHANUMERIC: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 
@@ -21,6 +51,24 @@ pub fn get_config() -> Result<Config, ConfigError> {
     Config::builder()
         .set_default(KEY_SECRET, fallback_secret)?
         .set_default(KEY_BUNNY_API_KEY, "")?
+        .set_default(KEY_BUNNY_ACCOUNT_API_KEY, "")?
+        .set_default(KEY_BUNNY_STORAGE_URL, "https://sg.storage.bunnycdn.com")?
+        .set_default(KEY_FILE_STORAGE, "bunny")?
+        .set_default(KEY_MEDIA_ROOT, "media")?
+        .set_default(KEY_MAX_UPLOAD_MB, 10)?
+        .set_default(KEY_CODE_HIGHLIGHTING, "client")?
+        .set_default(KEY_REVISIONS_KEPT, 50)?
+        .set_default(KEY_REVISIONS_MAX_AGE_DAYS, 0)?
+        .set_default(KEY_MAIL_BACKEND, "smtp")?
+        .set_default(KEY_MAIL_DIR, "")?
+        .set_default(KEY_MAIL_FROM, "QuanWeb <noreply@quan.hoabinh.vn>")?
+        .set_default(KEY_METRICS_TOKEN, "")?
+        .set_default(KEY_OTLP_ENDPOINT, "")?
+        .set_default(KEY_SITE_URL, DEFAULT_SITE_URL)?
+        .set_default(
+            KEY_PASSWORD_RESET_URL,
+            "https://quan.hoabinh.vn/ladmin/reset-password",
+        )?
         .add_source(File::with_name("base_settings.toml").required(true))
         .add_source(File::with_name("custom_settings.toml").required(false))
         .add_source(File::with_name(".secrets.toml").required(false))
@@ -45,3 +93,111 @@ pub fn get_bunny_cdn_host(config: &Config) -> Result<String, ConfigError> {
         .get_string(KEY_BUNNY_CDN_HOST)
         .map(|s| s.trim_end_matches('/').into())
 }
+
+pub fn get_bunny_account_api_key(config: &Config) -> Result<String, ConfigError> {
+    config.get_string(KEY_BUNNY_ACCOUNT_API_KEY)
+}
+
+pub fn get_bunny_storage_url(config: &Config) -> Result<String, ConfigError> {
+    config
+        .get_string(KEY_BUNNY_STORAGE_URL)
+        .map(|s| s.trim_end_matches('/').into())
+}
+
+pub fn get_file_storage(config: &Config) -> Result<StorageBackend, ConfigError> {
+    let value = config.get_string(KEY_FILE_STORAGE)?;
+    let kind = StorageKind::from_str(&value)
+        .map_err(|_e| ConfigError::Message(format!("Invalid {KEY_FILE_STORAGE}: {value}")))?;
+    let backend = match kind {
+        StorageKind::Bunny => StorageBackend::Bunny(BunnyStorage::new(
+            get_bunny_storage_url(config)?,
+            get_bunny_api_key(config)?,
+            get_bunny_cdn_host(config)?,
+        )),
+        StorageKind::Local => {
+            let root = config.get_string(KEY_MEDIA_ROOT)?;
+            StorageBackend::Local(LocalStorage::new(PathBuf::from(root)))
+        }
+    };
+    Ok(backend)
+}
+
+/// Get the maximum size of uploaded files, in bytes
+pub fn get_max_upload_size(config: &Config) -> Result<usize, ConfigError> {
+    let mb = config.get_int(KEY_MAX_UPLOAD_MB)?;
+    usize::try_from(mb)
+        .ok()
+        .filter(|&n| n > 0)
+        .map(|n| n * 1024 * 1024)
+        .ok_or_else(|| ConfigError::Message(format!("Invalid {KEY_MAX_UPLOAD_MB}: {mb}")))
+}
+
+pub fn get_code_highlighting(config: &Config) -> Result<HighlightMode, ConfigError> {
+    let value = config.get_string(KEY_CODE_HIGHLIGHTING)?;
+    HighlightMode::from_str(&value)
+        .map_err(|_e| ConfigError::Message(format!("Invalid {KEY_CODE_HIGHLIGHTING}: {value}")))
+}
+
+pub fn get_revision_retention(config: &Config) -> Result<RevisionRetention, ConfigError> {
+    let kept = config.get_int(KEY_REVISIONS_KEPT)?;
+    let max_age_days = config.get_int(KEY_REVISIONS_MAX_AGE_DAYS)?;
+    let kept = u16::try_from(kept)
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| ConfigError::Message(format!("Invalid {KEY_REVISIONS_KEPT}: {kept}")))?;
+    let max_age_days = u16::try_from(max_age_days).map_err(|_e| {
+        ConfigError::Message(format!(
+            "Invalid {KEY_REVISIONS_MAX_AGE_DAYS}: {max_age_days}"
+        ))
+    })?;
+    Ok(RevisionRetention { kept, max_age_days })
+}
+
+pub fn get_mailer(config: &Config) -> Result<Mailer, ConfigError> {
+    let value = config.get_string(KEY_MAIL_BACKEND)?;
+    let kind = MailKind::from_str(&value)
+        .map_err(|_e| ConfigError::Message(format!("Invalid {KEY_MAIL_BACKEND}: {value}")))?;
+    let mailer = match kind {
+        MailKind::Smtp => {
+            let url = config.get_string(KEY_SMTP_URL).map_err(|_e| {
+                ConfigError::Message(format!(
+                    "Missing {KEY_SMTP_URL}. For local development, set {KEY_MAIL_BACKEND} to 'file' instead"
+                ))
+            })?;
+            let from = config.get_string(KEY_MAIL_FROM)?;
+            let mailer = SmtpMailer::new(&url, &from)
+                .map_err(|e| ConfigError::Message(format!("Invalid SMTP config: {e}")))?;
+            Mailer::Smtp(mailer)
+        }
+        MailKind::File => {
+            let dir = config.get_string(KEY_MAIL_DIR)?;
+            let dir = (!dir.is_empty()).then(|| PathBuf::from(dir));
+            Mailer::File(FileMailer::new(dir))
+        }
+    };
+    Ok(mailer)
+}
+
+/// Get the page of admin UI where users set a new password, with the token in the query string
+pub fn get_password_reset_url(config: &Config) -> Result<String, ConfigError> {
+    config.get_string(KEY_PASSWORD_RESET_URL)
+}
+
+/// Get the public URL of the site, like "https://quan.hoabinh.vn", without trailing slash
+pub fn get_site_url(config: &Config) -> Result<String, ConfigError> {
+    config
+        .get_string(KEY_SITE_URL)
+        .map(|s| s.trim_end_matches('/').into())
+}
+
+/// Get the token which Prometheus has to send to scrape "/metrics", if it is set
+pub fn get_metrics_token(config: &Config) -> Result<Option<String>, ConfigError> {
+    let token = config.get_string(KEY_METRICS_TOKEN)?;
+    Ok(Some(token).filter(|s| !s.is_empty()))
+}
+
+/// Get the URL of the OpenTelemetry collector to export traces to, if it is set
+pub fn get_otlp_endpoint(config: &Config) -> Result<Option<String>, ConfigError> {
+    let url = config.get_string(KEY_OTLP_ENDPOINT)?;
+    Ok(Some(url).filter(|s| !s.is_empty()))
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use syntect::html::ClassStyle;
 
 #[allow(dead_code)]
@@ -5,11 +7,18 @@ pub const DB_NAME: &str = "quanweb";
 pub const DEFAULT_PAGE_SIZE: u8 = 10;
 pub const STATIC_URL: &str = "/static";
 pub const UNCATEGORIZED_URL: &str = "/category/_uncategorized/";
+// Number of tags to show in the tag cloud on home page
+pub const HOME_TAG_CLOUD_SIZE: usize = 30;
 #[allow(dead_code)]
 pub const SYNTECT_CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "st-" };
 pub const KEY_LANG: &str = "lang";
+// Template context entry, listing the image URLs whose variants are stored
+pub const KEY_STORED_IMAGES: &str = "stored_images";
 pub const DEFAULT_LANG: &str = "en";
 pub const ALPINE_HIGHLIGHTING_APP: &str = "need_highlight";
 pub const ALPINE_ORIG_CODE_ELM: &str = "orig_code";
 // Given by comrak
 pub const ATTR_CODEFENCE_EXTRA: &str = "data-meta";
+// How long browsers and CDNs may reuse public pages and feeds before asking again
+pub const PAGE_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+pub const FEED_MAX_AGE: Duration = Duration::from_secs(30 * 60);
@@ -1,11 +1,15 @@
+use async_trait::async_trait;
 use config::Config;
 use fred::error::Error as FredError;
 use gel_errors::ErrorKind;
 use gel_errors::kinds::ConfigurationError;
 use gel_tokio::{InstanceName, TlsSecurity};
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, SessionStore};
 use tower_sessions_redis_store::{RedisStore, fred::prelude::*};
 
 use crate::conf::KEY_EDGEDB_INSTANCE;
+use crate::utils::metrics::record_session_store_error;
 
 pub async fn get_gel_client(app_config: &Config) -> Result<gel_tokio::Client, gel_tokio::Error> {
     let instance_name = app_config.get_string(KEY_EDGEDB_INSTANCE).map_err(|e| {
@@ -22,13 +26,50 @@ pub async fn get_gel_client(app_config: &Config) -> Result<gel_tokio::Client, ge
     Ok(gel_tokio::Client::new(&config))
 }
 
-pub async fn get_redis_store() -> Result<RedisStore<Pool>, FredError> {
+pub async fn get_redis_pool() -> Result<Pool, FredError> {
     let pool = Pool::new(fred::types::config::Config::default(), None, None, None, 2)?;
     let _redis_conn = pool.connect();
     pool.wait_for_connect().await?;
     tracing::debug!("Connected to Redis");
-    let store = RedisStore::new(pool);
-    Ok(store)
+    Ok(pool)
 }
 
+// The session store shares the pool with the job queue, so that we only open one set of connections.
+pub fn get_redis_store(pool: Pool) -> MeteredStore<RedisStore<Pool>> {
+    MeteredStore(RedisStore::new(pool))
+}
+
+/// Session store which counts the errors of the inner one
+#[derive(Debug, Clone)]
+pub struct MeteredStore<S>(pub S);
+
+#[async_trait]
+impl<S: SessionStore + Clone> SessionStore for MeteredStore<S> {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        self.0
+            .create(record)
+            .await
+            .inspect_err(|_e| record_session_store_error("create"))
+    }
 
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.0
+            .save(record)
+            .await
+            .inspect_err(|_e| record_session_store_error("save"))
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        self.0
+            .load(session_id)
+            .await
+            .inspect_err(|_e| record_session_store_error("load"))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.0
+            .delete(session_id)
+            .await
+            .inspect_err(|_e| record_session_store_error("delete"))
+    }
+}
@@ -0,0 +1,85 @@
+// Serve the public pages to anonymous visitors from the Redis page cache.
+// Only the responses of handlers which set their cache groups are stored.
+
+use axum::body::{Body, to_bytes};
+use axum::extract::{Request, State};
+use axum::http::header::{ACCEPT, CACHE_CONTROL};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tower_sessions::Session;
+
+use crate::auth::AuthSession;
+use crate::consts::{DEFAULT_LANG, KEY_LANG, STATIC_URL};
+use crate::utils::http_cache::Validators;
+use crate::utils::page_cache::{CacheGroups, CachedPage, PageCache, page_key};
+
+fn is_public(headers: &HeaderMap) -> bool {
+    headers
+        .get(CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("public"))
+}
+
+// AI agents asking for Markdown are redirected by the post handler, the HTML page is not for them.
+fn wants_markdown(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/markdown"))
+}
+
+pub async fn serve_cached(
+    State(cache): State<PageCache>,
+    auth_session: AuthSession,
+    session: Session,
+    validators: Validators,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Static files are not worth a trip to Redis
+    if request.method() != Method::GET
+        || request.uri().path().starts_with(STATIC_URL)
+        || auth_session.user.is_some()
+        || wants_markdown(request.headers())
+    {
+        return next.run(request).await;
+    }
+    let lang = session
+        .get::<String>(KEY_LANG)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(DEFAULT_LANG.into());
+    let Some(key) = page_key(&lang, request.uri()) else {
+        return next.run(request).await;
+    };
+    match cache.get(&key).await {
+        Ok(Some(page)) => return page.respond(&validators),
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Failed to get cached page {key}: {e}"),
+    }
+
+    let response = next.run(request).await;
+    let Some(CacheGroups(groups)) = response.extensions().get::<CacheGroups>().cloned() else {
+        return response;
+    };
+    if response.status() != StatusCode::OK || !is_public(response.headers()) {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    // The pages are rendered in memory already, so there is no need to limit the size
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to read body of {key}: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Some(page) = CachedPage::new(&parts.headers, &bytes) {
+        if let Err(e) = cache.put(&key, &page, &groups).await {
+            tracing::warn!("Failed to cache page {key}: {e}");
+        }
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
@@ -1,3 +1,4 @@
+pub mod cache;
 pub mod structs;
 pub mod routes;
 pub mod views;
@@ -11,6 +11,7 @@ pub fn get_router() -> Router<AppState> {
     Router::new()
         .route("/", get(views::home))
         .route("/posts/", get(views::list_recent_posts))
+        .route("/search/", get(views::search_posts))
         .route(
             &format!("{STATIC_URL}/{{*file}}"),
             get(views::static_handler),
@@ -26,7 +27,25 @@ pub fn get_router() -> Router<AppState> {
             get(views::blog::list_uncategorized_posts),
         )
         .route("/category/{category}/", get(views::blog::list_posts))
+        .route(
+            "/category/{category}/feeds.atom",
+            get(views::feeds::gen_category_atom_feeds),
+        )
+        .route(
+            "/category/{category}/feeds.json",
+            get(views::feeds::gen_category_json_feeds),
+        )
+        .route("/tag/{tag}/", get(views::blog::list_posts_by_tag))
+        .route(
+            "/tag/{tag}/feeds.atom",
+            get(views::feeds::gen_tag_atom_feeds),
+        )
+        .route(
+            "/tag/{tag}/feeds.json",
+            get(views::feeds::gen_tag_json_feeds),
+        )
         .route("/preview/{id}", get(views::blog::preview_post))
+        .route("/comments/", post(views::comments::post_comment))
         .route(
             "/blog/{*rest}",
             get(views::old_urls::redirect_old_blog_view),
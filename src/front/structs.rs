@@ -1,10 +1,14 @@
 use std::num::NonZeroU16;
 
 use serde::Deserialize;
+use uuid::Uuid;
+use validify::Validify;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct PostPageParams {
     pub cat: Option<String>,
+    // Result of comment submission, to show message after redirecting back to the post
+    pub comment: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -24,7 +28,29 @@ impl LaxPaging {
     }
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SearchParams {
+    pub q: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct SetLangReq {
     pub lang: String,
 }
+
+#[derive(Debug, Clone, Deserialize, Validify)]
+pub struct CommentForm {
+    pub post_id: Uuid,
+    #[modify(trim)]
+    #[validate(length(min = 1, max = 100))]
+    pub author_name: String,
+    #[modify(trim)]
+    #[validate(email, length(max = 200))]
+    pub author_email: String,
+    #[modify(trim)]
+    #[validate(length(min = 2, max = 5000))]
+    pub body: String,
+    // Honeypot field. It is hidden from humans, so it is only filled by spam bots.
+    #[serde(default)]
+    pub website: String,
+}
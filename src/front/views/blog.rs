@@ -1,9 +1,12 @@
+use std::collections::HashSet;
 use std::num::NonZeroU16;
 
 use axum::extract::{OriginalUri, Path, Query, State};
 use axum::http::StatusCode;
-use axum::response::{Html, Result as AxumResult};
+use axum::response::{Html, Response, Result as AxumResult};
 use axum_extra::TypedHeader;
+use chrono::{DateTime, Utc};
+use gel_tokio::Client as EdgeClient;
 use headers_accept::Accept;
 use http::header::LOCATION;
 use indexmap::indexmap;
@@ -16,12 +19,28 @@ use uuid::Uuid;
 
 use super::super::structs::{LaxPaging, PostPageParams};
 use crate::auth::AuthSession;
-use crate::consts::{DEFAULT_LANG, DEFAULT_PAGE_SIZE, KEY_LANG};
+use crate::consts::{DEFAULT_LANG, DEFAULT_PAGE_SIZE, KEY_LANG, KEY_STORED_IMAGES};
 use crate::errors::PageError;
+use crate::models::{DetailedBlogPost, PostTranslation};
 use crate::stores;
 use crate::stores::blog::{get_detailed_post_by_slug, get_next_post, get_previous_post};
-use crate::types::{AppState, HtmlOrMd, Paginator};
+use crate::types::{AppState, Paginator};
 use crate::utils::html::render_with;
+use crate::utils::http_cache::{CachePolicy, Cached, Validators};
+use crate::utils::images::{VariantRegistry, img_sources, use_stored_variants};
+use crate::utils::page_cache::CacheGroup;
+
+/// Make the images of a post use their resized variants, if they are made.
+/// Return the image URLs whose variants are stored, for the templates.
+async fn use_image_variants(post: &mut DetailedBlogPost, db: &EdgeClient) -> HashSet<String> {
+    let sources = post.html.as_deref().map(img_sources).unwrap_or_default();
+    let urls = sources.into_iter().chain(post.og_image.as_deref());
+    let stored = VariantRegistry::new(db.clone()).stored_urls(urls).await;
+    if let Some(html) = &post.html {
+        post.html = Some(use_stored_variants(html, &stored));
+    }
+    stored
+}
 
 // If the client requests with `Accept: text/markdown` (indicating that it is an AI agent), we will redirect to the ".md" page,
 // which returns content in Markdown format. Otherwise, we serve HTML.
@@ -31,9 +50,10 @@ pub async fn show_post(
     Query(params): Query<PostPageParams>,
     // Value of `Accept` header
     TypedHeader(accept): TypedHeader<Accept>,
+    validators: Validators,
     session: Session,
     State(state): State<AppState>,
-) -> AxumResult<HtmlOrMd> {
+) -> AxumResult<Response> {
     let AppState { db, jinja, .. } = state;
     let (slug, is_md) = match slug_ext.split_at_checked(slug_ext.len() - 3) {
         Some((slug, ".md")) => (slug, true),
@@ -50,17 +70,25 @@ pub async fn show_post(
                 .into());
         };
     };
-    let post = get_detailed_post_by_slug(slug, &db)
+    let mut post = get_detailed_post_by_slug(slug, &db)
         .await
         .map_err(PageError::GelQueryError)?
         .ok_or((StatusCode::NOT_FOUND, "No post at this URL"))?;
+    // Readers are only pointed to the translations which are live
+    post.translations.retain(PostTranslation::is_live);
+    let user = auth_session.user;
+    let no_tracking = !post.is_published || user.is_some();
+    let policy = CachePolicy::for_page(no_tracking);
+    let post_updated_at = post.last_modified();
     if is_md {
         // Get the markdown body or return empty string if not available.
         let markdown_body = post.to_markdown_doc();
-        return Ok(HtmlOrMd::Md(markdown_body));
+        let cached = Cached::new(markdown_body, policy)
+            .with_content_type("text/plain; charset=utf-8")
+            .last_modified(Some(post_updated_at))
+            .cache_groups([CacheGroup::Post(post.id)]);
+        return Ok(cached.respond(&validators));
     }
-    let user = auth_session.user;
-    let no_tracking = !post.is_published || user.is_some();
     let cat = match params.cat {
         Some(slug) => stores::blog::get_category_by_slug(&slug, &db)
             .await
@@ -78,14 +106,27 @@ pub async fn show_post(
     let categories = stores::blog::get_blog_categories(None, None, false, &db)
         .await
         .map_err(PageError::GelQueryError)?;
+    let comments = stores::comment::get_approved_comments(post.id, &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    // New comments change the page, too
+    let last_modified = comments
+        .iter()
+        .map(|c| DateTime::<Utc>::from(c.created_at))
+        .chain([post_updated_at])
+        .max();
     let lang = session
         .get::<String>(KEY_LANG)
         .await
         .ok()
         .flatten()
         .unwrap_or(DEFAULT_LANG.into());
+    let stored_images = use_image_variants(&mut post, &db).await;
     let mut vcontext = indexmap! {
         "post" => MJValue::from_serialize(&post),
+        KEY_STORED_IMAGES => MJValue::from_serialize(&stored_images),
+        "comments" => MJValue::from_serialize(&comments),
+        "comment_result" => MJValue::from(params.comment),
         "prev_post" => MJValue::from_serialize(&prev_post),
         "next_post" => MJValue::from_serialize(&next_post),
         "categories" => MJValue::from_serialize(&categories),
@@ -96,7 +137,11 @@ pub async fn show_post(
         vcontext.insert("cat", MJValue::from_serialize(&cat));
     }
     let content = render_with("blog/post.jinja", vcontext, jinja)?;
-    Ok(HtmlOrMd::Hm(content))
+    let cached = Cached::new(content, policy)
+        .last_modified(last_modified)
+        .vary_cookie()
+        .cache_groups([CacheGroup::Post(post.id)]);
+    Ok(cached.respond(&validators))
 }
 
 pub async fn list_posts(
@@ -104,13 +149,20 @@ pub async fn list_posts(
     Path(cat_slug): Path<String>,
     OriginalUri(current_url): OriginalUri,
     Query(paging): Query<LaxPaging>,
+    validators: Validators,
     session: Session,
     State(state): State<AppState>,
-) -> AxumResult<Html<String>> {
+) -> AxumResult<Response> {
     let AppState { db, jinja, .. } = state;
     let current_page = paging.get_page_as_number();
     let page_size = DEFAULT_PAGE_SIZE;
     let offset = ((current_page.get() - 1) * page_size as u16) as i64;
+    let lang = session
+        .get::<String>(KEY_LANG)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(DEFAULT_LANG.into());
     let cat = stores::blog::get_category_by_slug(&cat_slug, &db)
         .await
         .map_err(PageError::GelQueryError)?
@@ -119,12 +171,13 @@ pub async fn list_posts(
         Some(cat_slug),
         Some(offset),
         Some(page_size as i64),
+        Some(&lang),
         &db,
     )
     .await
     .map_err(PageError::GelQueryError)?;
     tracing::debug!("To count posts under category {}", cat.id);
-    let total = stores::blog::count_blogposts_under_category(cat.id, &db)
+    let total = stores::blog::count_blogposts_under_category(cat.id, Some(&lang), &db)
         .await
         .map_err(PageError::GelQueryError)?;
     let total_pages = NonZeroU16::try_from((total as f64 / page_size as f64).ceil() as u16)
@@ -139,16 +192,80 @@ pub async fn list_posts(
     let categories = stores::blog::get_blog_categories(None, None, false, &db)
         .await
         .map_err(PageError::GelQueryError)?;
+    let last_modified = stores::blog::get_last_updated_post(&db)
+        .await
+        .map_err(PageError::GelQueryError)?
+        .map(|p| p.last_modified());
+    let no_tracking = auth_session.user.is_some();
+    let context = context!(
+        posts => posts,
+        cat => cat,
+        pagelink_items => pagelink_items,
+        next_page_url => next_page_url,
+        prev_page_url => prev_page_url,
+        categories => categories,
+        lang => lang,
+        no_tracking => no_tracking);
+    let content = render_with("blog/post_list.jinja", context, jinja)?;
+    let policy = CachePolicy::for_page(no_tracking);
+    let cached = Cached::new(content, policy)
+        .last_modified(last_modified)
+        .vary_cookie()
+        .cache_groups([CacheGroup::PostLists]);
+    Ok(cached.respond(&validators))
+}
+
+/// List posts having a tag. A tag gathers the keywords which have the same slug.
+pub async fn list_posts_by_tag(
+    auth_session: AuthSession,
+    Path(tag_slug): Path<String>,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    session: Session,
+    State(state): State<AppState>,
+) -> AxumResult<Html<String>> {
+    let AppState { db, jinja, .. } = state;
+    let current_page = paging.get_page_as_number();
+    let page_size = DEFAULT_PAGE_SIZE;
+    let offset = ((current_page.get() - 1) * page_size as u16) as i64;
     let lang = session
         .get::<String>(KEY_LANG)
         .await
         .ok()
         .flatten()
         .unwrap_or(DEFAULT_LANG.into());
+    let tag = stores::blog::get_tag_by_slug(&tag_slug, &db)
+        .await
+        .map_err(PageError::GelQueryError)?
+        .ok_or((StatusCode::NOT_FOUND, "No post at this URL"))?;
+    let posts = stores::blog::get_published_posts_by_keywords(
+        &tag.keywords,
+        Some(offset),
+        Some(page_size as i64),
+        Some(&lang),
+        &db,
+    )
+    .await
+    .map_err(PageError::GelQueryError)?;
+    let total = stores::blog::count_published_posts_by_keywords(&tag.keywords, Some(&lang), &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let total_pages = NonZeroU16::try_from((total as f64 / page_size as f64).ceil() as u16)
+        .unwrap_or(NonZeroU16::MIN);
+    let paginator = Paginator {
+        current_page,
+        total_pages,
+    };
+    let pagelink_items = paginator.generate_items();
+    let next_page_url = paginator.next_url(&current_url);
+    let prev_page_url = paginator.previous_url(&current_url);
+    let categories = stores::blog::get_blog_categories(None, None, false, &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
     let no_tracking = auth_session.user.is_some();
     let context = context!(
         posts => posts,
-        cat => cat,
+        tag => tag,
         pagelink_items => pagelink_items,
         next_page_url => next_page_url,
         prev_page_url => prev_page_url,
@@ -171,7 +288,7 @@ pub async fn preview_post(
     // - For guests, redirect to canonical URL if the post is in "published" state, otherwise throwing PermissionDenied.
     let user = auth_session.user;
     let AppState { db, jinja, .. } = state;
-    let post = stores::blog::get_post(id, &db)
+    let mut post = stores::blog::get_post(id, &db)
         .await
         .map_err(PageError::GelQueryError)?
         .ok_or((StatusCode::NOT_FOUND, "No post at this URL"))?;
@@ -199,7 +316,8 @@ pub async fn preview_post(
         .ok()
         .flatten()
         .unwrap_or(DEFAULT_LANG.into());
-    let context = context!(post => post, prev_post => prev_post, next_post => next_post, lang => lang, no_tracking => true);
+    let stored_images = use_image_variants(&mut post, &db).await;
+    let context = context!(post => post, prev_post => prev_post, next_post => next_post, lang => lang, stored_images => stored_images, no_tracking => true);
     let content = render_with("blog/post.jinja", context, jinja)?;
     Ok(Html(content))
 }
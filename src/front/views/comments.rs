@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use axum::extract::{Form, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Redirect, Result as AxumResult};
+use validify::Validify;
+
+use super::super::structs::CommentForm;
+use crate::errors::PageError;
+use crate::stores;
+use crate::stores::comment::NewComment;
+use crate::types::AppState;
+use crate::utils::markdown::comment_to_html;
+use crate::utils::ratelimit::{check_rate_limit, get_client_ip};
+
+// Each IP address can post this many comments in the window
+const COMMENT_RATE_LIMIT: u32 = 3;
+const COMMENT_RATE_WINDOW: Duration = Duration::from_secs(600);
+
+/// Receive a comment from the form under a blog post. The comment waits for admin's approval.
+/// We always redirect back to the post, with the result in "comment" query param.
+pub async fn post_comment(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Form(mut form): Form<CommentForm>,
+) -> AxumResult<Redirect> {
+    let AppState { db, redis, .. } = state;
+    let post = stores::blog::get_published_mini_post(form.post_id, &db)
+        .await
+        .map_err(PageError::GelQueryError)?
+        .ok_or((StatusCode::NOT_FOUND, "No post to comment on"))?;
+    let back_url = |result: &str| format!("{}?comment={result}#comment-form", post.get_view_url());
+    if !form.website.is_empty() {
+        // Let the bot believe that it succeeded
+        tracing::info!(
+            "Honeypot field is filled. Drop comment from {}",
+            form.author_name
+        );
+        return Ok(Redirect::to(&back_url("sent")));
+    }
+    // Invalid forms are not counted, so that readers can fix their comments
+    if let Err(e) = form.validify() {
+        tracing::debug!("Invalid comment: {e:?}");
+        return Ok(Redirect::to(&back_url("invalid")));
+    }
+    let ip_address = get_client_ip(&headers);
+    let allowed = match ip_address.as_deref() {
+        // Failing to count must not block readers, so we only log it.
+        Some(ip) => check_rate_limit(
+            &redis,
+            "comment",
+            ip,
+            COMMENT_RATE_LIMIT,
+            COMMENT_RATE_WINDOW,
+        )
+        .await
+        .inspect_err(|e| tracing::warn!("Failed to check rate limit: {e}"))
+        .unwrap_or(true),
+        // All such readers would share one counter, so they are not limited.
+        None => {
+            tracing::warn!("No client IP to limit comments by. Is the reverse proxy configured?");
+            true
+        }
+    };
+    if !allowed {
+        tracing::info!("{ip_address:?} posts comments too fast");
+        return Ok(Redirect::to(&back_url("too-many")));
+    }
+    let html = comment_to_html(&form.body);
+    let data = NewComment {
+        post_id: post.id,
+        author_name: &form.author_name,
+        author_email: &form.author_email,
+        body: &form.body,
+        html: &html,
+        ip_address: ip_address.as_deref(),
+    };
+    stores::comment::create_comment(&data, &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    Ok(Redirect::to(&back_url("sent")))
+}
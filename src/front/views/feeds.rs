@@ -1,61 +1,111 @@
 use std::num::NonZeroU16;
 
-use atom_syndication::{Entry, FeedBuilder, LinkBuilder};
-use axum::extract::{OriginalUri, Query, State};
-use axum::http::header;
-use axum::response::{IntoResponseParts, Json, Result as AxumResult};
+use atom_syndication::{Entry, Feed, FeedBuilder, LinkBuilder, Text};
+use axum::extract::{OriginalUri, Path, Query, State};
+use axum::response::{IntoResponseParts, Json, Response, Result as AxumResult};
 use axum_extra::extract::TypedHeader;
-use chrono::{TimeZone, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use gel_tokio::Client as EdgeClient;
 use headers::Host;
-use http::{HeaderName, StatusCode};
+use http::StatusCode;
 use http::{Uri, header::CONTENT_TYPE};
-use sitemap_writer::SitemapWriter;
+use uuid::{Uuid, uuid};
 
 use super::super::structs::LaxPaging;
-use crate::consts::DEFAULT_PAGE_SIZE;
+use crate::consts::{DEFAULT_PAGE_SIZE, FEED_MAX_AGE};
 use crate::errors::PageError;
+use crate::models::blogs::build_tag_view_url;
 use crate::models::feeds::{DEFAULT_SITE_URL, EntryExt, JsonFeed, JsonItem};
+use crate::models::{BlogCategory, MediumBlogPost, Tag};
 use crate::stores;
 use crate::types::{Paginator, ext::UriExt};
+use crate::utils::http_cache::{CachePolicy, Cached, Validators};
+use crate::utils::page_cache::CacheGroup;
+use crate::utils::sitemap::build_sitemap;
 
 // Generate from Python: uuid.uuid5(uuid.NAMESPACE_DNS, 'quan.hoabinh.vn'
-const SITE_UUID: &str = "4543aea6-ab17-5c18-9279-19e73529594d";
+const SITE_UUID: Uuid = uuid!("4543aea6-ab17-5c18-9279-19e73529594d");
+const SITE_TITLE: &str = "QuanWeb";
 
-pub async fn gen_atom_feeds(
-    TypedHeader(host): TypedHeader<Host>,
-    OriginalUri(current_url): OriginalUri,
-    Query(paging): Query<LaxPaging>,
-    State(db): State<EdgeClient>,
-) -> AxumResult<(impl IntoResponseParts, String)> {
-    let base_url: Uri = format!("https://{host}")
-        .parse()
-        .unwrap_or(Uri::from_static(DEFAULT_SITE_URL));
-    let current_page = paging.get_page_as_number();
-    let page_size = DEFAULT_PAGE_SIZE;
-    let offset = ((current_page.get() - 1) * page_size as u16) as i64;
-    let posts = stores::blog::get_published_posts(Some(offset), Some(page_size as i64), &db)
-        .await
-        .map_err(PageError::GelQueryError)?;
-    let total = stores::blog::count_all_published_posts(&db)
-        .await
-        .map_err(PageError::GelQueryError)?;
-    let total_pages = NonZeroU16::try_from((total as f64 / page_size as f64).ceil() as u16)
+/// The things which tell the site-wide feed and the feed of a category or tag apart
+struct FeedInfo {
+    id: Uuid,
+    title: String,
+    subtitle: Option<String>,
+    // The HTML page which lists the same posts
+    page_path: String,
+}
+
+impl FeedInfo {
+    fn site() -> Self {
+        Self {
+            id: SITE_UUID,
+            title: SITE_TITLE.to_string(),
+            subtitle: None,
+            page_path: "/".to_string(),
+        }
+    }
+
+    fn category(cat: &BlogCategory) -> Self {
+        let page_path = format!("/category/{}/", cat.slug);
+        Self {
+            // Derive from site UUID, so that the ID doesn't change between requests
+            id: Uuid::new_v5(&SITE_UUID, page_path.as_bytes()),
+            title: format!("{SITE_TITLE} - {}", cat.title),
+            subtitle: cat.summary_en.clone(),
+            page_path,
+        }
+    }
+
+    fn tag(tag: &Tag) -> Self {
+        let page_path = build_tag_view_url(&tag.slug);
+        Self {
+            id: Uuid::new_v5(&SITE_UUID, page_path.as_bytes()),
+            title: format!("{SITE_TITLE} - #{}", tag.name),
+            subtitle: None,
+            page_path,
+        }
+    }
+}
+
+fn make_paginator(paging: &LaxPaging, total: usize) -> Paginator {
+    let total_pages = NonZeroU16::try_from((total as f64 / DEFAULT_PAGE_SIZE as f64).ceil() as u16)
         .unwrap_or(NonZeroU16::MIN);
-    let paginator = Paginator {
-        current_page,
+    Paginator {
+        current_page: paging.get_page_as_number(),
         total_pages,
-    };
+    }
+}
+
+fn get_paging_offset(paging: &LaxPaging) -> (i64, i64) {
+    let page_size = DEFAULT_PAGE_SIZE;
+    let offset = ((paging.get_page_as_number().get() - 1) * page_size as u16) as i64;
+    (offset, page_size as i64)
+}
+
+fn build_atom_feed(
+    info: FeedInfo,
+    posts: Vec<MediumBlogPost>,
+    paginator: &Paginator,
+    base_url: &Uri,
+    current_url: &Uri,
+    updated_at: DateTime<Utc>,
+) -> Feed {
     let self_url = base_url.join(&current_url.to_string()).to_string();
-    let first_page_url = paginator.first_url(&current_url);
-    let last_page_url = paginator.last_url(&current_url);
-    let next_page_url = paginator.next_url(&current_url);
-    let prev_page_url = paginator.previous_url(&current_url);
+    let first_page_url = paginator.first_url(current_url);
+    let last_page_url = paginator.last_url(current_url);
+    let next_page_url = paginator.next_url(current_url);
+    let prev_page_url = paginator.previous_url(current_url);
     let mut links = vec![
         LinkBuilder::default()
             .rel("self".to_string())
             .href(self_url)
             .build(),
+        LinkBuilder::default()
+            .rel("alternate".to_string())
+            .mime_type(Some("text/html".into()))
+            .href(base_url.join(&info.page_path).to_string())
+            .build(),
         LinkBuilder::default()
             .rel("first".to_string())
             .href(base_url.join(&first_page_url).to_string())
@@ -82,54 +132,35 @@ pub async fn gen_atom_feeds(
         )
     }
     let mut entries: Vec<Entry> = posts.into_iter().map(Entry::from).collect();
-    entries.iter_mut().for_each(|e| e.prepend_url(&base_url));
-    let latest_post = stores::blog::get_last_updated_post(&db)
-        .await
-        .map_err(PageError::GelQueryError)?;
-    let updated_at = latest_post
-        .and_then(|p| p.updated_at.map(|d| d.into()))
-        .unwrap_or_else(|| Utc.with_ymd_and_hms(2013, 1, 1, 0, 0, 0).unwrap());
-    let feed = FeedBuilder::default()
-        .title("QuanWeb")
-        .id(format!("urn:uuid:{SITE_UUID}"))
+    entries.iter_mut().for_each(|e| e.prepend_url(base_url));
+    FeedBuilder::default()
+        .title(info.title)
+        .subtitle(info.subtitle.map(Text::plain))
+        .id(format!("urn:uuid:{}", info.id))
         .links(links)
         .updated(updated_at)
         .entries(entries)
-        .build();
-    Ok((
-        [(CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
-        feed.to_string(),
-    ))
+        .build()
 }
 
-pub async fn gen_json_feeds(
-    TypedHeader(host): TypedHeader<Host>,
-    OriginalUri(current_url): OriginalUri,
-    Query(paging): Query<LaxPaging>,
-    State(db): State<EdgeClient>,
-) -> AxumResult<Json<JsonFeed>> {
-    let base_url = format!("https://{host}");
-    let current_page = paging.get_page_as_number();
-    let page_size = DEFAULT_PAGE_SIZE;
-    let offset = ((current_page.get() - 1) * page_size as u16) as i64;
-    let posts = stores::blog::get_published_posts(Some(offset), Some(page_size as i64), &db)
-        .await
-        .map_err(PageError::GelQueryError)?;
-    let total = stores::blog::count_all_published_posts(&db)
-        .await
-        .map_err(PageError::GelQueryError)?;
-    let total_pages = NonZeroU16::try_from((total as f64 / page_size as f64).ceil() as u16)
-        .unwrap_or(NonZeroU16::MIN);
-    let paginator = Paginator {
-        current_page,
-        total_pages,
-    };
-    let next_page_url = paginator.next_url(&current_url);
+fn build_json_feed(
+    info: FeedInfo,
+    posts: Vec<MediumBlogPost>,
+    paginator: &Paginator,
+    base_url: &str,
+    current_url: &Uri,
+) -> JsonFeed {
+    let next_page_url = paginator.next_url(current_url);
     let mut feed = JsonFeed {
+        title: info.title,
+        home_page_url: Some(format!("{base_url}{}", info.page_path)),
         feed_url: Some(format!("{base_url}{current_url}")),
         next_url: next_page_url.map(|url| format!("{base_url}{url}")),
         ..Default::default()
     };
+    if info.subtitle.is_some() {
+        feed.description = info.subtitle;
+    }
     let mut items: Vec<JsonItem> = posts.into_iter().map(JsonItem::from).collect();
     items.iter_mut().for_each(|it| match it.url {
         Some(ref url) if url.starts_with('/') => {
@@ -138,31 +169,224 @@ pub async fn gen_json_feeds(
         _ => {}
     });
     feed.items = items;
+    feed
+}
+
+fn make_base_url(host: &Host) -> Uri {
+    format!("https://{host}")
+        .parse()
+        .unwrap_or(Uri::from_static(DEFAULT_SITE_URL))
+}
+
+// The time the feed was updated, as seen from the posts in it
+fn get_posts_updated_at(posts: &[MediumBlogPost]) -> DateTime<Utc> {
+    posts
+        .iter()
+        .map(|p| DateTime::<Utc>::from(p.updated_at.unwrap_or(p.created_at)))
+        .max()
+        .unwrap_or_else(|| Utc.with_ymd_and_hms(2013, 1, 1, 0, 0, 0).unwrap())
+}
+
+pub async fn gen_atom_feeds(
+    TypedHeader(host): TypedHeader<Host>,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    validators: Validators,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Response> {
+    let base_url = make_base_url(&host);
+    let (offset, limit) = get_paging_offset(&paging);
+    let posts = stores::blog::get_published_posts(Some(offset), Some(limit), None, &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let total = stores::blog::count_all_published_posts(None, &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let paginator = make_paginator(&paging, total);
+    let latest_post = stores::blog::get_last_updated_post(&db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let updated_at = latest_post
+        .map(|p| p.last_modified())
+        .unwrap_or_else(|| Utc.with_ymd_and_hms(2013, 1, 1, 0, 0, 0).unwrap());
+    let feed = build_atom_feed(
+        FeedInfo::site(),
+        posts,
+        &paginator,
+        &base_url,
+        &current_url,
+        updated_at,
+    );
+    let cached = Cached::new(feed.to_string(), CachePolicy::Public(FEED_MAX_AGE))
+        .with_content_type("application/atom+xml; charset=utf-8")
+        .last_modified(Some(updated_at))
+        .cache_groups([CacheGroup::Feeds]);
+    Ok(cached.respond(&validators))
+}
+
+pub async fn gen_json_feeds(
+    TypedHeader(host): TypedHeader<Host>,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<JsonFeed>> {
+    let base_url = format!("https://{host}");
+    let (offset, limit) = get_paging_offset(&paging);
+    let posts = stores::blog::get_published_posts(Some(offset), Some(limit), None, &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let total = stores::blog::count_all_published_posts(None, &db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let paginator = make_paginator(&paging, total);
+    let feed = build_json_feed(FeedInfo::site(), posts, &paginator, &base_url, &current_url);
+    Ok(Json(feed))
+}
+
+async fn get_category_feed_data(
+    cat_slug: String,
+    paging: &LaxPaging,
+    db: &EdgeClient,
+) -> AxumResult<(FeedInfo, Vec<MediumBlogPost>, Paginator)> {
+    let cat = stores::blog::get_category_by_slug(&cat_slug, db)
+        .await
+        .map_err(PageError::GelQueryError)?
+        .ok_or((StatusCode::NOT_FOUND, "No category at this URL"))?;
+    let (offset, limit) = get_paging_offset(paging);
+    let posts = stores::blog::get_published_posts_under_category(
+        Some(cat_slug),
+        Some(offset),
+        Some(limit),
+        None,
+        db,
+    )
+    .await
+    .map_err(PageError::GelQueryError)?;
+    let total = stores::blog::count_blogposts_under_category(cat.id, None, db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let paginator = make_paginator(paging, total);
+    Ok((FeedInfo::category(&cat), posts, paginator))
+}
+
+async fn get_tag_feed_data(
+    tag_slug: String,
+    paging: &LaxPaging,
+    db: &EdgeClient,
+) -> AxumResult<(FeedInfo, Vec<MediumBlogPost>, Paginator)> {
+    let tag = stores::blog::get_tag_by_slug(&tag_slug, db)
+        .await
+        .map_err(PageError::GelQueryError)?
+        .ok_or((StatusCode::NOT_FOUND, "No tag at this URL"))?;
+    let (offset, limit) = get_paging_offset(paging);
+    let posts = stores::blog::get_published_posts_by_keywords(
+        &tag.keywords,
+        Some(offset),
+        Some(limit),
+        None,
+        db,
+    )
+    .await
+    .map_err(PageError::GelQueryError)?;
+    let total = stores::blog::count_published_posts_by_keywords(&tag.keywords, None, db)
+        .await
+        .map_err(PageError::GelQueryError)?;
+    let paginator = make_paginator(paging, total);
+    Ok((FeedInfo::tag(&tag), posts, paginator))
+}
+
+pub async fn gen_category_atom_feeds(
+    Path(cat_slug): Path<String>,
+    TypedHeader(host): TypedHeader<Host>,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    State(db): State<EdgeClient>,
+) -> AxumResult<(impl IntoResponseParts, String)> {
+    let base_url = make_base_url(&host);
+    let (info, posts, paginator) = get_category_feed_data(cat_slug, &paging, &db).await?;
+    let updated_at = get_posts_updated_at(&posts);
+    let feed = build_atom_feed(info, posts, &paginator, &base_url, &current_url, updated_at);
+    Ok((
+        [(CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        feed.to_string(),
+    ))
+}
+
+pub async fn gen_category_json_feeds(
+    Path(cat_slug): Path<String>,
+    TypedHeader(host): TypedHeader<Host>,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<JsonFeed>> {
+    let base_url = format!("https://{host}");
+    let (info, posts, paginator) = get_category_feed_data(cat_slug, &paging, &db).await?;
+    let feed = build_json_feed(info, posts, &paginator, &base_url, &current_url);
+    Ok(Json(feed))
+}
+
+pub async fn gen_tag_atom_feeds(
+    Path(tag_slug): Path<String>,
+    TypedHeader(host): TypedHeader<Host>,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    State(db): State<EdgeClient>,
+) -> AxumResult<(impl IntoResponseParts, String)> {
+    let base_url = make_base_url(&host);
+    let (info, posts, paginator) = get_tag_feed_data(tag_slug, &paging, &db).await?;
+    let updated_at = get_posts_updated_at(&posts);
+    let feed = build_atom_feed(info, posts, &paginator, &base_url, &current_url, updated_at);
+    Ok((
+        [(CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        feed.to_string(),
+    ))
+}
+
+pub async fn gen_tag_json_feeds(
+    Path(tag_slug): Path<String>,
+    TypedHeader(host): TypedHeader<Host>,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    State(db): State<EdgeClient>,
+) -> AxumResult<Json<JsonFeed>> {
+    let base_url = format!("https://{host}");
+    let (info, posts, paginator) = get_tag_feed_data(tag_slug, &paging, &db).await?;
+    let feed = build_json_feed(info, posts, &paginator, &base_url, &current_url);
     Ok(Json(feed))
 }
 
 pub async fn gen_sitemaps(
+    validators: Validators,
     State(db): State<EdgeClient>,
-) -> AxumResult<(StatusCode, [(HeaderName, &'static str); 1], String)> {
-    let posts = stores::blog::get_all_published_mini_posts(&db)
+) -> AxumResult<Response> {
+    let posts = stores::blog::get_posts_for_sitemap(&db)
         .await
         .map_err(PageError::GelQueryError)?;
+    let last_modified = stores::blog::get_last_updated_post(&db)
+        .await
+        .map_err(PageError::GelQueryError)?
+        .map(|p| p.last_modified());
 
     let entries: Vec<_> = posts
         .iter()
         .map(|p| p.to_sitemap_entry(DEFAULT_SITE_URL))
         .collect();
-    let headers = [(header::CONTENT_TYPE, "application/xml")];
-    let xml = SitemapWriter::build(entries);
-    Ok((StatusCode::OK, headers, xml))
+    let xml = build_sitemap(&entries);
+    let cached = Cached::new(xml, CachePolicy::Public(FEED_MAX_AGE))
+        .with_content_type("application/xml")
+        .last_modified(last_modified)
+        .cache_groups([CacheGroup::Feeds]);
+    Ok(cached.respond(&validators))
 }
 
 pub async fn gen_llms_txt(
+    validators: Validators,
     State(db): State<EdgeClient>,
-) -> AxumResult<(StatusCode, [(HeaderName, &'static str); 1], String)> {
+) -> AxumResult<Response> {
     let posts = stores::blog::get_all_published_mini_posts(&db)
         .await
         .map_err(PageError::GelQueryError)?;
+    let last_modified = posts.iter().map(|p| p.last_modified()).max();
 
     let content_lines = vec![
         "# QuanWeb Blog Posts".to_string(),
@@ -180,6 +404,118 @@ pub async fn gen_llms_txt(
         .collect();
 
     let content = [content_lines, post_lines].concat().join("\n");
-    let headers = [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")];
-    Ok((StatusCode::OK, headers, content))
+    let cached = Cached::new(content, CachePolicy::Public(FEED_MAX_AGE))
+        .with_content_type("text/markdown; charset=utf-8")
+        .last_modified(last_modified)
+        .cache_groups([CacheGroup::Feeds]);
+    Ok(cached.respond(&validators))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_post() -> MediumBlogPost {
+        let created_at = Utc.with_ymd_and_hms(2024, 5, 20, 8, 0, 0).unwrap();
+        MediumBlogPost {
+            id: Uuid::from_u128(1),
+            title: "Hello".into(),
+            slug: "hello".into(),
+            locale: Some("en".into()),
+            excerpt: Some("<p>Hi there</p>".into()),
+            is_published: true,
+            published_at: created_at.try_into().ok(),
+            created_at: created_at.try_into().unwrap(),
+            updated_at: None,
+            categories: vec![],
+            seo_keywords: vec!["Rust".into()],
+            author: None,
+            comment_count: 0,
+        }
+    }
+
+    fn sample_tag() -> Tag {
+        Tag {
+            name: "Rust".into(),
+            slug: "rust".into(),
+            count: 1,
+            keywords: vec!["Rust".into()],
+        }
+    }
+
+    #[test]
+    fn test_feed_info_of_tag() {
+        let info = FeedInfo::tag(&sample_tag());
+        assert_eq!(info.title, "QuanWeb - #Rust");
+        assert_eq!(info.page_path, "/tag/rust/");
+        // The ID must be stable, and differ from the site one
+        assert_eq!(info.id, FeedInfo::tag(&sample_tag()).id);
+        assert_ne!(info.id, FeedInfo::site().id);
+    }
+
+    #[test]
+    fn test_build_atom_feed() {
+        let paginator = Paginator {
+            current_page: NonZeroU16::MIN,
+            total_pages: NonZeroU16::new(2).unwrap(),
+        };
+        let base_url = Uri::from_static("https://quan.hoabinh.vn");
+        let current_url = Uri::from_static("/tag/rust/feeds.atom");
+        let updated_at = Utc.with_ymd_and_hms(2024, 5, 21, 0, 0, 0).unwrap();
+        let feed = build_atom_feed(
+            FeedInfo::tag(&sample_tag()),
+            vec![sample_post()],
+            &paginator,
+            &base_url,
+            &current_url,
+            updated_at,
+        );
+        assert_eq!(feed.title.value, "QuanWeb - #Rust");
+        let link_of = |rel: &str| {
+            feed.links
+                .iter()
+                .find(|l| l.rel == rel)
+                .map(|l| l.href.clone())
+        };
+        assert_eq!(
+            link_of("alternate").as_deref(),
+            Some("https://quan.hoabinh.vn/tag/rust/")
+        );
+        assert_eq!(
+            link_of("next").as_deref(),
+            Some("https://quan.hoabinh.vn/tag/rust/feeds.atom?page=2")
+        );
+        assert_eq!(link_of("previous"), None);
+        assert_eq!(feed.entries.len(), 1);
+        assert_eq!(
+            feed.entries[0].links[0].href,
+            "https://quan.hoabinh.vn/post/2024/05/hello"
+        );
+    }
+
+    #[test]
+    fn test_build_json_feed() {
+        let paginator = Paginator::default();
+        let current_url = Uri::from_static("/feeds.json");
+        let feed = build_json_feed(
+            FeedInfo::site(),
+            vec![sample_post()],
+            &paginator,
+            "https://quan.hoabinh.vn",
+            &current_url,
+        );
+        assert_eq!(feed.title, "QuanWeb");
+        assert_eq!(
+            feed.feed_url.as_deref(),
+            Some("https://quan.hoabinh.vn/feeds.json")
+        );
+        assert_eq!(feed.next_url, None);
+        assert_eq!(feed.items.len(), 1);
+        let item = &feed.items[0];
+        assert_eq!(
+            item.url.as_deref(),
+            Some("https://quan.hoabinh.vn/post/2024/05/hello")
+        );
+        assert_eq!(item.summary.as_deref(), Some("Hi there"));
+    }
 }
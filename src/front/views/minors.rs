@@ -1,5 +1,5 @@
 use axum::extract::State;
-use axum::response::{Html, Result as AxumResult};
+use axum::response::{Response, Result as AxumResult};
 use minijinja::context;
 use tower_sessions::Session;
 
@@ -12,12 +12,15 @@ use crate::stores::{
 };
 use crate::types::AppState;
 use crate::utils::html::render_with;
+use crate::utils::http_cache::{CachePolicy, Cached, Validators};
+use crate::utils::page_cache::CacheGroup;
 
 pub async fn list_talks(
     auth_session: AuthSession,
+    validators: Validators,
     session: Session,
     State(state): State<AppState>,
-) -> AxumResult<Html<String>> {
+) -> AxumResult<Response> {
     let AppState { db, jinja, .. } = state;
     let presentations = get_all_talks(&db).await.map_err(PageError::GelQueryError)?;
     let lang = session
@@ -32,14 +35,18 @@ pub async fn list_talks(
         .map_err(PageError::GelQueryError)?;
     let ctx = context!(presentations, lang, categories, no_tracking,);
     let content = render_with("minors/talk_list.jinja", ctx, jinja)?;
-    Ok(Html(content))
+    let cached = Cached::new(content, CachePolicy::for_page(no_tracking))
+        .vary_cookie()
+        .cache_groups([CacheGroup::Minors]);
+    Ok(cached.respond(&validators))
 }
 
 pub async fn list_books(
     auth_session: AuthSession,
+    validators: Validators,
     session: Session,
     State(state): State<AppState>,
-) -> AxumResult<Html<String>> {
+) -> AxumResult<Response> {
     let AppState { db, jinja, .. } = state;
     let books = get_all_books(&db).await.map_err(PageError::GelQueryError)?;
     let lang = session
@@ -54,5 +61,8 @@ pub async fn list_books(
         .map_err(PageError::GelQueryError)?;
     let ctx = context!(books, lang, categories, no_tracking,);
     let content = render_with("minors/book_list.jinja", ctx, jinja)?;
-    Ok(Html(content))
+    let cached = Cached::new(content, CachePolicy::for_page(no_tracking))
+        .vary_cookie()
+        .cache_groups([CacheGroup::Minors]);
+    Ok(cached.respond(&validators))
 }
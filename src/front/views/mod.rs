@@ -1,4 +1,5 @@
 pub mod blog;
+pub mod comments;
 pub mod feeds;
 pub mod minors;
 pub mod old_urls;
@@ -7,19 +8,23 @@ use std::num::NonZeroU16;
 
 use axum::extract::Form;
 use axum::extract::{OriginalUri, Query, State};
-use axum::response::{Html, IntoResponse, Result as AxumResult};
+use axum::response::{Html, IntoResponse, Response, Result as AxumResult};
 use http::{HeaderName, StatusCode, Uri};
 use minijinja::context;
 use tower_sessions::Session;
 use unic_langid::LanguageIdentifier;
 
-use super::structs::{LaxPaging, SetLangReq};
+use super::structs::{LaxPaging, SearchParams, SetLangReq};
 use crate::auth::AuthSession;
-use crate::consts::{DEFAULT_LANG, DEFAULT_PAGE_SIZE, KEY_LANG, STATIC_URL};
+use crate::consts::{DEFAULT_LANG, DEFAULT_PAGE_SIZE, HOME_TAG_CLOUD_SIZE, KEY_LANG, STATIC_URL};
 pub use crate::errors::PageError;
 use crate::stores;
 use crate::types::{AppState, Paginator, StaticFile};
 use crate::utils::html::render_with;
+use crate::utils::http_cache::{CachePolicy, Cached, Validators};
+use crate::utils::images::VariantRegistry;
+use crate::utils::page_cache::CacheGroup;
+use crate::utils::search::make_search_tokens;
 
 pub async fn fallback_view() -> (StatusCode, &'static str) {
     (StatusCode::NOT_FOUND, "Not found")
@@ -27,36 +32,55 @@ pub async fn fallback_view() -> (StatusCode, &'static str) {
 
 pub async fn home(
     auth_session: AuthSession,
+    validators: Validators,
     _session: Session,
     State(state): State<AppState>,
-) -> AxumResult<Html<String>> {
+) -> AxumResult<Response> {
     let AppState { db, jinja, .. } = state;
+    let lang = _session
+        .get::<String>(KEY_LANG)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(DEFAULT_LANG.into());
     let categories = stores::blog::get_blog_categories(None, None, false, &db)
         .await
         .map_err(PageError::GelQueryError)?;
     // Get featured categories with their latest posts for the new home page design
-    let featured_categories = stores::blog::get_featured_categories_with_posts(&db)
+    let featured_categories = stores::blog::get_featured_categories_with_posts(&lang, &db)
         .await
         .map_err(PageError::GelQueryError)?;
     // Get 6 latest posts for the latest posts section
-    let latest_posts = stores::blog::get_latest_posts_for_home(&db)
+    let latest_posts = stores::blog::get_latest_posts_for_home(&lang, &db)
         .await
         .map_err(PageError::GelQueryError)?;
-    let no_tracking = auth_session.user.is_some();
-    let lang = _session
-        .get::<String>(KEY_LANG)
+    // Covers use their resized variants, once they are made
+    let covers = latest_posts.iter().filter_map(|p| p.og_image.as_deref());
+    let stored_images = VariantRegistry::new(db.clone()).stored_urls(covers).await;
+    let mut tags = stores::blog::get_tags(&db)
         .await
-        .ok()
-        .flatten()
-        .unwrap_or(DEFAULT_LANG.into());
+        .map_err(PageError::GelQueryError)?;
+    tags.truncate(HOME_TAG_CLOUD_SIZE);
+    let last_modified = stores::blog::get_last_updated_post(&db)
+        .await
+        .map_err(PageError::GelQueryError)?
+        .map(|p| p.last_modified());
+    let no_tracking = auth_session.user.is_some();
     let context = context!(
         lang => lang,
         categories => categories,
         featured_categories => featured_categories,
         latest_posts => latest_posts,
+        stored_images => stored_images,
+        tags => tags,
         no_tracking => no_tracking);
     let content = render_with("home.jinja", context, jinja)?;
-    Ok(Html(content))
+    let policy = CachePolicy::for_page(no_tracking);
+    let cached = Cached::new(content, policy)
+        .last_modified(last_modified)
+        .vary_cookie()
+        .cache_groups([CacheGroup::PostLists]);
+    Ok(cached.respond(&validators))
 }
 
 pub async fn static_handler(uri: Uri) -> impl IntoResponse {
@@ -80,7 +104,13 @@ pub async fn list_recent_posts(
 ) -> AxumResult<Html<String>> {
     let AppState { db, jinja, .. } = state;
     let current_page = paging.get_page_as_number();
-    let total = stores::blog::count_all_published_posts(&db)
+    let lang = session
+        .get::<String>(KEY_LANG)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(DEFAULT_LANG.into());
+    let total = stores::blog::count_all_published_posts(Some(&lang), &db)
         .await
         .map_err(PageError::GelQueryError)?;
     let page_size = DEFAULT_PAGE_SIZE;
@@ -95,9 +125,68 @@ pub async fn list_recent_posts(
     let next_page_url = paginator.next_url(&current_url);
     let prev_page_url = paginator.previous_url(&current_url);
     let offset = ((current_page.get() - 1) * (page_size as u16)) as i64;
-    let posts = stores::blog::get_published_posts(Some(offset), Some(page_size as i64), &db)
+    let posts =
+        stores::blog::get_published_posts(Some(offset), Some(page_size as i64), Some(&lang), &db)
+            .await
+            .map_err(PageError::GelQueryError)?;
+    let categories = stores::blog::get_blog_categories(None, None, false, &db)
         .await
         .map_err(PageError::GelQueryError)?;
+    let no_tracking = auth_session.user.is_some();
+    let context = context!(
+        lang => lang,
+        posts => posts,
+        categories => categories,
+        pagelink_items => pagelink_items,
+        next_page_url => next_page_url,
+        prev_page_url => prev_page_url,
+        no_tracking => no_tracking);
+    let content = render_with("posts_list.jinja", context, jinja)?;
+    Ok(Html(content))
+}
+
+/// Full-text search over published posts
+pub async fn search_posts(
+    auth_session: AuthSession,
+    OriginalUri(current_url): OriginalUri,
+    Query(paging): Query<LaxPaging>,
+    Query(params): Query<SearchParams>,
+    session: Session,
+    State(state): State<AppState>,
+) -> AxumResult<Html<String>> {
+    let AppState { db, jinja, .. } = state;
+    let q = params.q.as_deref().map(str::trim).unwrap_or_default();
+    let search_tokens = make_search_tokens(q);
+    let current_page = paging.get_page_as_number();
+    let page_size = DEFAULT_PAGE_SIZE;
+    let (total, posts) = if search_tokens.is_empty() {
+        (0, vec![])
+    } else {
+        let total = stores::blog::count_search_result_posts(&search_tokens, None, true, &db)
+            .await
+            .map_err(PageError::GelQueryError)?;
+        let offset = ((current_page.get() - 1) * (page_size as u16)) as i64;
+        let posts = stores::blog::search_blogposts(
+            &search_tokens,
+            None,
+            true,
+            Some(offset),
+            Some(page_size as i64),
+            &db,
+        )
+        .await
+        .map_err(PageError::GelQueryError)?;
+        (total, posts)
+    };
+    let total_pages = NonZeroU16::try_from((total as f64 / page_size as f64).ceil() as u16)
+        .unwrap_or(NonZeroU16::MIN);
+    let paginator = Paginator {
+        current_page,
+        total_pages,
+    };
+    let pagelink_items = paginator.generate_items();
+    let next_page_url = paginator.next_url(&current_url);
+    let prev_page_url = paginator.previous_url(&current_url);
     let categories = stores::blog::get_blog_categories(None, None, false, &db)
         .await
         .map_err(PageError::GelQueryError)?;
@@ -110,13 +199,15 @@ pub async fn list_recent_posts(
         .unwrap_or(DEFAULT_LANG.into());
     let context = context!(
         lang => lang,
+        q => q,
+        count => total,
         posts => posts,
         categories => categories,
         pagelink_items => pagelink_items,
         next_page_url => next_page_url,
         prev_page_url => prev_page_url,
         no_tracking => no_tracking);
-    let content = render_with("posts_list.jinja", context, jinja)?;
+    let content = render_with("search.jinja", context, jinja)?;
     Ok(Html(content))
 }
 
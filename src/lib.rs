@@ -2,7 +2,10 @@ pub mod conf;
 pub mod consts;
 pub mod db;
 pub mod errors;
+pub mod mail;
 pub mod models;
+pub mod storage;
+pub mod stores;
 pub mod thingsup;
 pub mod types;
 pub mod utils;
@@ -0,0 +1,70 @@
+// Write emails to files or stdout instead of sending them, for local testing.
+
+use std::path::PathBuf;
+
+use chrono::Utc;
+use tokio::fs;
+use tokio::io::{self, AsyncWriteExt};
+
+use super::{Email, MailError, MailSender};
+
+#[derive(Debug, Clone)]
+pub struct FileMailer {
+    /// Each email is saved as a ".eml" file in this directory. Without it, emails are printed.
+    pub dir: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+}
+
+fn format_email(email: &Email) -> String {
+    format!(
+        "To: {}\nSubject: {}\nDate: {}\n\n{}\n",
+        email.to,
+        email.subject,
+        Utc::now().to_rfc2822(),
+        email.body
+    )
+}
+
+impl MailSender for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let content = format_email(email);
+        let Some(dir) = &self.dir else {
+            let mut stdout = io::stdout();
+            stdout.write_all(content.as_bytes()).await?;
+            stdout.flush().await?;
+            return Ok(());
+        };
+        fs::create_dir_all(dir).await?;
+        let name = format!("{}.eml", Utc::now().format("%Y%m%d-%H%M%S-%f"));
+        fs::write(dir.join(name), content).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_email_to_dir() {
+        let dir = std::env::temp_dir().join(format!("quanweb-mail-{}", std::process::id()));
+        let mailer = FileMailer::new(Some(dir.clone()));
+        let email = Email {
+            to: "quan@example.com".into(),
+            subject: "Reset your password".into(),
+            body: "Open this link".into(),
+        };
+        mailer.send(&email).await.unwrap();
+        let mut entries = fs::read_dir(&dir).await.unwrap();
+        let entry = entries.next_entry().await.unwrap().unwrap();
+        let content = fs::read_to_string(entry.path()).await.unwrap();
+        assert!(content.starts_with("To: quan@example.com\nSubject: Reset your password\n"));
+        assert!(content.ends_with("\n\nOpen this link\n"));
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
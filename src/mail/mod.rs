@@ -0,0 +1,53 @@
+// Sending emails, like password reset links.
+// SMTP is used in production, the file backend is for development and tests.
+
+pub mod file;
+pub mod smtp;
+
+use std::future::Future;
+use std::io;
+
+use thiserror::Error;
+
+use file::FileMailer;
+use smtp::SmtpMailer;
+
+#[derive(Debug, Error)]
+pub enum MailError {
+    #[error("Invalid address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("Failed to build email: {0}")]
+    Build(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A plain text email
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait MailSender {
+    fn send(&self, email: &Email) -> impl Future<Output = Result<(), MailError>> + Send;
+}
+
+/// The backend chosen by the "mail_backend" config
+#[derive(Debug, Clone)]
+pub enum Mailer {
+    Smtp(SmtpMailer),
+    File(FileMailer),
+}
+
+impl MailSender for Mailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        match self {
+            Self::Smtp(m) => m.send(email).await,
+            Self::File(m) => m.send(email).await,
+        }
+    }
+}
@@ -5,50 +5,76 @@ mod consts;
 mod db;
 mod errors;
 mod front;
+mod mail;
 mod models;
+mod storage;
 mod stores;
+#[cfg(test)]
+mod tests;
 mod thingsup;
 mod types;
 mod utils;
+mod worker;
 
+use std::collections::HashSet;
 use std::fs::Permissions;
 use std::os::unix::fs::PermissionsExt;
 use std::{fs, path::PathBuf};
 
 use auth::backend::Backend;
-use axum::routing::Router;
+use axum::routing::{Router, get};
 use axum_login::AuthManagerLayerBuilder;
 use clap::Parser;
 use miette::{IntoDiagnostic, miette};
 use owo_colors::OwoColorize;
 use tokio::net::{TcpListener, UnixListener};
 use tokio::signal;
+use tokio::sync::watch;
+use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
 use tower_sessions::SessionManagerLayer;
 use tracing::info;
 
-use thingsup::{AppOptions, Commands, config_jinja, config_logging, get_binding_addr};
+use storage::local::MEDIA_URL_PREFIX;
+use storage::{FileStorage, StorageBackend};
+use thingsup::{
+    AppOptions, Commands, config_highlighting, config_jinja, config_logging, config_media,
+    get_binding_addr, parse_binding_addr,
+};
 use types::{AppState, BindingAddr};
+use utils::health::{HEALTHZ_PATH, READYZ_PATH, healthz, readyz};
+use utils::images::{self, VariantRegistry};
+use utils::metrics::{METRICS_PATH, MetricsState, install_recorder, serve_metrics, track_http};
+use utils::page_cache::PageCache;
+use utils::telemetry::{make_request_span, name_request_span, shutdown_tracing};
 
 #[tokio::main]
 async fn main() -> miette::Result<()> {
     let app_opts = AppOptions::parse();
     config_logging(&app_opts);
 
-    match &app_opts.command {
-        Commands::Serve { bind } => serve_web(bind.as_deref()).await,
+    let result = match &app_opts.command {
+        Commands::Serve { bind, metrics_bind } => {
+            serve_web(bind.as_deref(), metrics_bind.as_deref()).await
+        }
         Commands::RegenerateHtml => regenerate_html_all_posts().await,
+        Commands::ReindexSearch => reindex_search_all_posts().await,
         Commands::Worker => run_worker().await,
-    }
+        Commands::GenerateImageVariants { dir } => {
+            generate_image_variants_all(dir.as_deref()).await
+        }
+    };
+    shutdown_tracing();
+    result
 }
 
-async fn serve_web(bind: Option<&str>) -> miette::Result<()> {
+async fn serve_web(bind: Option<&str>, metrics_bind: Option<&str>) -> miette::Result<()> {
     let config = conf::get_config().map_err(|e| miette!("Error loading config: {e}"))?;
     // The bind option accepts:
     // - TCP addresses like "127.0.0.1:3000" or ":3000"
     // - Unix socket paths like "unix:/tmp/thingsup.sock"
     let addr = get_binding_addr(bind);
-    let redis_store = db::get_redis_store()
+    let redis_pool = db::get_redis_pool()
         .await
         .map_err(|_e| miette!("Error connecting to Redis"))?;
 
@@ -56,38 +82,82 @@ async fn serve_web(bind: Option<&str>) -> miette::Result<()> {
         info!("{e:?}");
         miette!("Failed to create Gel client")
     })?;
+    config_highlighting(&config);
     let jinja = config_jinja().into_diagnostic()?;
     
-    // Get Bunny API key and CDN host from config
-    let bunny_api_key = conf::get_bunny_api_key(&config)
-        .map_err(|e| miette!("Error getting Bunny API key: {e}"))?
-        .clone();
-    let bunny_cdn_host = conf::get_bunny_cdn_host(&config)
-        .map_err(|e| miette!("Error getting Bunny CDN host: {e}"))?
-        .clone();
+    let file_storage =
+        conf::get_file_storage(&config).map_err(|e| miette!("Error getting file storage: {e}"))?;
+    config_media(&file_storage);
+    let max_upload_size = conf::get_max_upload_size(&config)
+        .map_err(|e| miette!("Error getting max upload size: {e}"))?;
     
+    let revision_retention = conf::get_revision_retention(&config)
+        .map_err(|e| miette!("Error getting revision retention: {e}"))?;
+    let mailer = conf::get_mailer(&config).map_err(|e| miette!("Error getting mailer: {e}"))?;
+    let password_reset_url = conf::get_password_reset_url(&config)
+        .map_err(|e| miette!("Error getting password reset URL: {e}"))?;
+    let metrics_token = conf::get_metrics_token(&config)
+        .map_err(|e| miette!("Error getting metrics token: {e}"))?;
+    let metrics_handle = install_recorder().into_diagnostic()?;
+
     let app_state = AppState {
         db: client.clone(),
         jinja,
-        bunny_api_key,
-        bunny_cdn_host,
+        file_storage: file_storage.clone(),
+        max_upload_size,
+        redis: redis_pool.clone(),
+        revision_retention,
+        mailer,
+        password_reset_url,
     };
-    let session_layer = SessionManagerLayer::new(redis_store);
+    let session_layer = SessionManagerLayer::new(db::get_redis_store(redis_pool));
 
     // Auth service
     let backend = Backend { db: client };
     let auth_layer = AuthManagerLayerBuilder::new(backend, session_layer).build();
 
-    let home_router: Router<AppState> = front::routes::get_router();
+    // Anonymous visitors are served from the page cache
+    let home_router: Router<AppState> = front::routes::get_router().layer(
+        axum::middleware::from_fn_with_state(app_state.clone(), front::cache::serve_cached),
+    );
     let api_router: Router<AppState> = api::get_router().with_state(app_state.clone());
 
-    let app = Router::new()
-        .merge(home_router)
-        .nest("/_api", api_router)
+    let mut app = Router::new().merge(home_router).nest("/_api", api_router);
+    // Files uploaded to the local storage
+    if let StorageBackend::Local(local) = &file_storage {
+        app = app.nest_service(MEDIA_URL_PREFIX, ServeDir::new(&local.root));
+    }
+    // Without token, the metrics are only served on their own address
+    if metrics_token.is_some() {
+        let state = MetricsState {
+            handle: metrics_handle.clone(),
+            token: metrics_token,
+        };
+        app = app.route(METRICS_PATH, get(serve_metrics).with_state(state));
+    }
+    if let Some(metrics_bind) = metrics_bind {
+        let addr = parse_binding_addr(metrics_bind)
+            .ok_or_else(|| miette!("Invalid metrics address: {metrics_bind}"))?;
+        let state = MetricsState {
+            handle: metrics_handle,
+            token: None,
+        };
+        let metrics_app = Router::new()
+            .route(METRICS_PATH, get(serve_metrics))
+            .with_state(state);
+        serve_metrics_on(addr, metrics_app).await?;
+    }
+    let app = app
+        .route_layer(axum::middleware::from_fn(track_http))
+        .route_layer(axum::middleware::from_fn(name_request_span))
+        // Out of the page cache, they must tell the truth about Redis.
+        // Added after the route layers, so that the frequent probes don't swamp the request metrics.
+        .route(HEALTHZ_PATH, get(healthz))
+        .route(READYZ_PATH, get(readyz))
         .fallback(front::views::fallback_view)
         .with_state(app_state)
         .layer(auth_layer)
-        .layer(TraceLayer::new_for_http());
+        .layer(TraceLayer::new_for_http().make_span_with(make_request_span));
 
     let main_service = app.into_make_service();
     match addr {
@@ -113,12 +183,45 @@ async fn serve_web(bind: Option<&str>) -> miette::Result<()> {
     Ok(())
 }
 
-async fn regenerate_html_all_posts() -> miette::Result<()> {
-    use crate::utils::markdown::markdown_to_html;
+/// Serve the metrics on their own address, in background
+async fn serve_metrics_on(addr: BindingAddr<'_>, metrics_app: Router) -> miette::Result<()> {
+    let service = metrics_app.into_make_service();
+    match addr {
+        BindingAddr::Unix(p) => {
+            let lt = UnixListener::bind(p).into_diagnostic()?;
+            tracing::info!("Serving metrics on {}", addr);
+            let p = p.to_path_buf();
+            tokio::spawn(async move {
+                let server =
+                    axum::serve(lt, service).with_graceful_shutdown(on_shutdown_signal(Some(p)));
+                if let Err(e) = server.await {
+                    tracing::error!("Metrics server stopped: {e}");
+                }
+            });
+        }
+        BindingAddr::Tcp(s) => {
+            let lt = TcpListener::bind(s).await.into_diagnostic()?;
+            tracing::info!("Serving metrics on http://{}", addr);
+            tokio::spawn(async move {
+                let server =
+                    axum::serve(lt, service).with_graceful_shutdown(on_shutdown_signal(None));
+                if let Err(e) = server.await {
+                    tracing::error!("Metrics server stopped: {e}");
+                }
+            });
+        }
+    }
+    Ok(())
+}
 
+async fn regenerate_html_all_posts() -> miette::Result<()> {
     tracing::info!("Regenerating HTML for blog posts...");
 
     let config = conf::get_config().map_err(|e| miette!("Error loading config: {e}"))?;
+    config_highlighting(&config);
+    let storage =
+        conf::get_file_storage(&config).map_err(|e| miette!("Error getting file storage: {e}"))?;
+    config_media(&storage);
     let client = db::get_gel_client(&config).await.map_err(|e| {
         info!("{e:?}");
         miette!("Failed to create Gel client")
@@ -133,8 +236,9 @@ async fn regenerate_html_all_posts() -> miette::Result<()> {
 
     for post in posts {
         let body = post.body.unwrap_or_default();
-        let html = markdown_to_html(&body);
-        stores::blog::update_post_html(&client, post.id, &html)
+        let html = post.format.to_html(&body);
+        let excerpt = post.format.make_excerpt(&body);
+        stores::blog::update_post_html(&client, post.id, &html, &excerpt)
             .await
             .map_err(|e| miette!("Failed to update post {}: {}", post.id, e))?;
         println!("Regenerated HTML for post '{}' ({})", post.title.blue(), post.id);
@@ -144,13 +248,141 @@ async fn regenerate_html_all_posts() -> miette::Result<()> {
     Ok(())
 }
 
+async fn reindex_search_all_posts() -> miette::Result<()> {
+    tracing::info!("Rebuilding search index for blog posts...");
+
+    let config = conf::get_config().map_err(|e| miette!("Error loading config: {e}"))?;
+    let client = db::get_gel_client(&config).await.map_err(|e| {
+        info!("{e:?}");
+        miette!("Failed to create Gel client")
+    })?;
+
+    let posts = stores::blog::get_posts_for_search_index(None, &client)
+        .await
+        .map_err(|e| miette!("Failed to fetch posts: {e}"))?;
+
+    tracing::info!("Found {} posts to index", posts.len());
+
+    for post in posts {
+        stores::blog::update_post_search_fields(&client, &post)
+            .await
+            .map_err(|e| miette!("Failed to update post {}: {}", post.id, e))?;
+        println!("Indexed post '{}' ({})", post.title.blue(), post.id);
+    }
+
+    println!("{}", "Search index rebuilt!".green());
+    Ok(())
+}
+
 async fn run_worker() -> miette::Result<()> {
     tracing::info!("Starting background worker...");
-    
-    // TODO: Implement worker using apalis_redis
-    // This requires proper Redis client configuration compatible with apalis
-    tracing::info!("Worker not yet implemented");
-    
+
+    let config = conf::get_config().map_err(|e| miette!("Error loading config: {e}"))?;
+    config_highlighting(&config);
+    let redis_pool = db::get_redis_pool()
+        .await
+        .map_err(|_e| miette!("Error connecting to Redis"))?;
+    let client = db::get_gel_client(&config).await.map_err(|e| {
+        info!("{e:?}");
+        miette!("Failed to create Gel client")
+    })?;
+    let bunny_cdn_host = conf::get_bunny_cdn_host(&config)
+        .map_err(|e| miette!("Error getting Bunny CDN host: {e}"))?;
+    let bunny_account_api_key = conf::get_bunny_account_api_key(&config)
+        .map_err(|e| miette!("Error getting Bunny account API key: {e}"))?;
+    let site_url =
+        conf::get_site_url(&config).map_err(|e| miette!("Error getting site URL: {e}"))?;
+    let storage =
+        conf::get_file_storage(&config).map_err(|e| miette!("Error getting file storage: {e}"))?;
+    config_media(&storage);
+    let ctx = worker::TaskContext {
+        db: client.clone(),
+        http: reqwest::Client::new(),
+        bunny_cdn_host,
+        bunny_account_api_key,
+        site_url,
+        storage,
+        page_cache: PageCache::new(redis_pool.clone()),
+        image_variants: VariantRegistry::new(client),
+    };
+    let queue = worker::JobQueue::new(redis_pool.clone());
+    // Posts from before the search fields were added are not found by search until indexed
+    match stores::blog::count_unindexed_posts(&ctx.db).await {
+        Ok(0) => {}
+        Ok(n) => {
+            tracing::info!("{} posts are not in the search index yet, to rebuild it", n);
+            queue
+                .enqueue_or_warn(worker::Task::RefreshSearchIndex { category_id: None })
+                .await;
+        }
+        Err(e) => tracing::error!("Failed to count unindexed posts: {}", e),
+    }
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        on_shutdown_signal(None).await;
+        shutdown_tx.send(true).unwrap_or_default();
+    });
+    let scheduler = tokio::spawn(worker::scheduler::run_scheduler(
+        redis_pool,
+        queue.clone(),
+        ctx.db.clone(),
+        shutdown_rx.clone(),
+    ));
+    worker::process_jobs(queue, ctx, shutdown_rx).await;
+    scheduler.await.unwrap_or_default();
+    Ok(())
+}
+
+async fn generate_image_variants_all(dir: Option<&str>) -> miette::Result<()> {
+    let config = conf::get_config().map_err(|e| miette!("Error loading config: {e}"))?;
+    let storage =
+        conf::get_file_storage(&config).map_err(|e| miette!("Error getting file storage: {e}"))?;
+    let client = db::get_gel_client(&config).await.map_err(|e| {
+        info!("{e:?}");
+        miette!("Failed to create Gel client")
+    })?;
+    let registry = VariantRegistry::new(client);
+    let mut dirs = vec![dir.unwrap_or_default().to_string()];
+    let mut count = 0;
+    while let Some(dir) = dirs.pop() {
+        let files = storage
+            .list(&dir)
+            .await
+            .map_err(|e| miette!("Failed to list {dir}: {e}"))?;
+        let names: HashSet<String> = files.iter().map(|f| f.path()).collect();
+        for file in files {
+            let path = file.path();
+            if file.is_directory {
+                dirs.push(path);
+                continue;
+            }
+            if !images::has_variants(&path) {
+                continue;
+            }
+            // Skip the ones whose variants are all there, only making sure that they are recorded
+            let stored = images::variant_paths(&path)
+                .iter()
+                .all(|p| names.contains(p));
+            if !stored {
+                match images::store_variants(&path, &storage).await {
+                    Ok(n) => {
+                        println!("Generated {} variants for {}", n, path.blue());
+                        count += 1;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to generate variants for {}: {}", path.red(), e);
+                        continue;
+                    }
+                }
+            }
+            registry
+                .record(&path)
+                .await
+                .map_err(|e| miette!("Failed to record variants of {path}: {e}"))?;
+        }
+    }
+    println!("{}", format!("Done with {count} images!").green());
     Ok(())
 }
 
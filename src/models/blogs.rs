@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::str::FromStr;
 
 use atom_syndication::{
@@ -11,15 +12,19 @@ use gel_protocol::model::Datetime as EDatetime;
 use gel_protocol::value::Value as EValue;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JValue;
-use sitemap_writer::SitemapUrl;
+use slugrs::slugify;
 use strum::{Display, EnumString, IntoStaticStr};
 use uuid::Uuid;
 
-use super::feeds::{JsonAuthor, JsonItem};
+use super::feeds::{DEFAULT_SITE_URL, JsonAuthor, JsonItem};
 use super::users::MiniUser;
 use crate::types::EdgeSelectable;
 use crate::types::conversions::{serialize_edge_datetime, serialize_optional_edge_datetime};
-use crate::utils::html::strip_tags;
+use crate::utils::html::{html_to_text, strip_tags};
+use crate::utils::markdown::{make_excerpt, markdown_to_html};
+use crate::utils::rst::{make_rst_excerpt, rst_to_html};
+use crate::utils::search::{fold_text, make_snippet};
+use crate::utils::sitemap::{AlternateLink, SitemapUrl};
 
 #[derive(
     Debug,
@@ -40,6 +45,23 @@ pub enum DocFormat {
     Rst,
 }
 
+impl DocFormat {
+    /// Convert post body, which is written in this format, to HTML
+    pub fn to_html(&self, body: &str) -> String {
+        match self {
+            Self::Md => markdown_to_html(body),
+            Self::Rst => rst_to_html(body),
+        }
+    }
+
+    pub fn make_excerpt(&self, body: &str) -> String {
+        match self {
+            Self::Md => make_excerpt(body),
+            Self::Rst => make_rst_excerpt(body),
+        }
+    }
+}
+
 impl From<&JValue> for DocFormat {
     fn from(v: &JValue) -> Self {
         match v {
@@ -66,26 +88,33 @@ pub struct MiniBlogPost {
     pub created_at: EDatetime,
     #[serde(serialize_with = "serialize_optional_edge_datetime")]
     pub updated_at: Option<EDatetime>,
+    #[serde(serialize_with = "serialize_optional_edge_datetime")]
+    pub published_at: Option<EDatetime>,
+}
+
+/// The time of the last change of a post, to be used as `Last-Modified`.
+/// A scheduled post changes the pages when it goes live, without being updated.
+fn last_change(
+    created_at: EDatetime,
+    updated_at: Option<EDatetime>,
+    published_at: Option<EDatetime>,
+) -> DateTime<Utc> {
+    let updated_at = DateTime::<Utc>::from(updated_at.unwrap_or(created_at));
+    published_at
+        .map(DateTime::<Utc>::from)
+        .filter(|t| *t <= Utc::now())
+        .map_or(updated_at, |t| t.max(updated_at))
 }
 
 impl MiniBlogPost {
-    pub fn to_sitemap_entry(&self, base_url: &str) -> SitemapUrl {
-        let created_at = DateTime::<Utc>::from(self.created_at);
-        let loc = format!(
-            "{}/post/{}/{}",
-            base_url,
-            created_at.format("%Y/%m"),
-            self.slug
-        );
-        let lastmod = self
-            .updated_at
-            .map(DateTime::<Utc>::from)
-            .map(|d| format!("{}", d.format("%Y-%m-%d")));
-        SitemapUrl {
-            loc,
-            lastmod,
-            ..Default::default()
-        }
+    /// The time of the last change, to be used as `Last-Modified`
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        last_change(self.created_at, self.updated_at, self.published_at)
+    }
+
+    pub fn get_view_url(&self) -> String {
+        let created_at: DateTime<Utc> = self.created_at.into();
+        build_post_view_url(created_at, &self.slug)
     }
 
     /// Generate the URL for this blog post with .md extension
@@ -107,6 +136,108 @@ impl EdgeSelectable for MiniBlogPost {
     }
 }
 
+// Another language version of a post. Drafts are included, so check `is_live()` before showing it to readers.
+#[serde_with::apply(
+    EDatetime => #[serde(serialize_with = "serialize_edge_datetime")],
+    Option<EDatetime> => #[serde(serialize_with = "serialize_optional_edge_datetime")],
+)]
+#[derive(Debug, Clone, Serialize, Queryable, FieldNames)]
+pub struct PostTranslation {
+    pub id: Uuid,
+    pub title: String,
+    pub slug: String,
+    pub locale: Option<String>,
+    pub is_published: bool,
+    pub published_at: Option<EDatetime>,
+    pub created_at: EDatetime,
+}
+
+impl PostTranslation {
+    pub fn is_live(&self) -> bool {
+        self.is_published
+            && self
+                .published_at
+                .is_none_or(|t| DateTime::<Utc>::from(t) <= Utc::now())
+    }
+
+    pub fn get_view_url(&self) -> String {
+        let created_at: DateTime<Utc> = self.created_at.into();
+        build_post_view_url(created_at, &self.slug)
+    }
+
+    /// Shape element to get the other posts in the same translation group.
+    pub fn siblings_as_shape_element() -> String {
+        let fields = Self::FIELDS.join(", ");
+        format!(
+            "translations := (
+                WITH group_id := .translation_group, post_id := .id
+                SELECT DETACHED BlogPost {{ {fields} }}
+                FILTER .translation_group = group_id AND .id != post_id
+                ORDER BY .locale
+            )"
+        )
+    }
+}
+
+// Data of a published post to put in sitemap, with its translations as alternate links.
+#[derive(Debug, Clone, Queryable, FieldNames)]
+pub struct SitemapBlogPost {
+    pub slug: String,
+    pub locale: Option<String>,
+    pub created_at: EDatetime,
+    pub updated_at: Option<EDatetime>,
+    pub translations: Vec<PostTranslation>,
+}
+
+impl SitemapBlogPost {
+    pub fn to_sitemap_entry(&self, base_url: &str) -> SitemapUrl {
+        let created_at = DateTime::<Utc>::from(self.created_at);
+        let loc = format!("{base_url}{}", build_post_view_url(created_at, &self.slug));
+        let lastmod = self
+            .updated_at
+            .map(DateTime::<Utc>::from)
+            .map(|d| format!("{}", d.format("%Y-%m-%d")));
+        let translations: Vec<&PostTranslation> = self
+            .translations
+            .iter()
+            .filter(|t| t.is_live() && t.locale.is_some())
+            .collect();
+        // Search engines expect the page itself among the alternates
+        let alternates = match (&self.locale, translations.is_empty()) {
+            (Some(locale), false) => {
+                let own = AlternateLink {
+                    hreflang: locale.clone(),
+                    href: loc.clone(),
+                };
+                let others = translations.into_iter().map(|t| AlternateLink {
+                    hreflang: t.locale.clone().unwrap_or_default(),
+                    href: format!("{base_url}{}", t.get_view_url()),
+                });
+                std::iter::once(own).chain(others).collect()
+            }
+            _ => vec![],
+        };
+        SitemapUrl {
+            loc,
+            lastmod,
+            alternates,
+        }
+    }
+}
+
+impl EdgeSelectable for SitemapBlogPost {
+    fn fields_as_shape() -> String {
+        let fields: Vec<String> = Self::FIELDS
+            .into_iter()
+            .map(|s| match s {
+                "translations" => PostTranslation::siblings_as_shape_element(),
+                _ => s.to_string(),
+            })
+            .collect();
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
 // Struct to represent a BlogPost in the database, with all fields to display in a detail page.
 #[serde_with::apply(
     EDatetime => #[serde(serialize_with = "serialize_edge_datetime")],
@@ -129,10 +260,17 @@ pub struct DetailedBlogPost {
     pub html: Option<String>,
     pub author: Option<MiniUser>,
     pub seo_description: Option<String>,
+    pub seo_keywords: Vec<String>,
     pub og_image: Option<String>,
+    pub translations: Vec<PostTranslation>,
 }
 
 impl DetailedBlogPost {
+    /// The time of the last change, to be used as `Last-Modified`
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        last_change(self.created_at, self.updated_at, self.published_at)
+    }
+
     pub fn get_canonical_url(&self) -> String {
         let created_at = DateTime::<Utc>::from(self.created_at);
         format!("/post/{}/{}", created_at.format("%Y/%m"), self.slug)
@@ -172,7 +310,9 @@ impl Default for DetailedBlogPost {
             html: None,
             author: None,
             seo_description: None,
+            seo_keywords: Vec::default(),
             og_image: None,
+            translations: Vec::default(),
         }
     }
 }
@@ -190,6 +330,7 @@ impl EdgeSelectable for DetailedBlogPost {
                     let user_shape = MiniUser::fields_as_shape();
                     format!("author: {user_shape}")
                 }
+                "translations" => PostTranslation::siblings_as_shape_element(),
                 _ => s.to_string(),
             })
             .collect();
@@ -213,9 +354,15 @@ pub struct MediumBlogPost {
     pub created_at: EDatetime,
     pub updated_at: Option<EDatetime>,
     pub categories: Vec<BlogCategory>,
+    pub seo_keywords: Vec<String>,
     pub author: Option<MiniUser>,
+    pub comment_count: i64,
 }
 
+// Only approved comments are visible to readers, so only they are counted
+const COMMENT_COUNT_SHAPE: &str =
+    "comment_count := count(.comments FILTER .status = CommentStatus.Approved)";
+
 /// Helper function to build post view URL from created_at and slug
 pub fn build_post_view_url(created_at: DateTime<Utc>, slug: &str) -> String {
     format!("/post/{}/{}", created_at.format("%Y/%m"), slug)
@@ -242,7 +389,9 @@ impl Default for MediumBlogPost {
             created_at,
             updated_at: None,
             categories: Vec::default(),
+            seo_keywords: Vec::default(),
             author: None,
+            comment_count: 0,
         }
     }
 }
@@ -260,6 +409,7 @@ impl EdgeSelectable for MediumBlogPost {
                     let user_shape = MiniUser::fields_as_shape();
                     format!("author: {user_shape}")
                 }
+                "comment_count" => COMMENT_COUNT_SHAPE.to_string(),
                 _ => s.to_string(),
             })
             .collect();
@@ -278,6 +428,7 @@ impl From<MediumBlogPost> for AtomEntry {
             created_at,
             updated_at,
             categories,
+            seo_keywords,
             author,
             ..
         } = value;
@@ -287,7 +438,8 @@ impl From<MediumBlogPost> for AtomEntry {
             .href(url)
             .mime_type(Some("text/html".into()))
             .build();
-        let categories: Vec<AtomCategory> = categories.into_iter().collect();
+        let mut categories: Vec<AtomCategory> = categories.into_iter().collect();
+        categories.extend(seo_keywords.into_iter().map(Tag::atom_category));
         let authors = if let Some(author) = author {
             vec![Person::from(author)]
         } else {
@@ -319,12 +471,18 @@ impl From<MediumBlogPost> for JsonItem {
             created_at,
             updated_at,
             categories,
+            seo_keywords,
             author,
             ..
         } = value;
         let entry_id = format!("urn:uuid:{id}");
         let updated_at: DateTime<Utc> = updated_at.unwrap_or(created_at).into();
-        let categories: Vec<String> = categories.into_iter().map(|c| c.title).collect();
+        let mut tags: Vec<String> = categories.into_iter().map(|c| c.title).collect();
+        for kw in seo_keywords {
+            if !tags.contains(&kw) {
+                tags.push(kw);
+            }
+        }
         let author = author.map(JsonAuthor::from);
         JsonItem {
             id: entry_id,
@@ -337,7 +495,7 @@ impl From<MediumBlogPost> for JsonItem {
             date_published: published_at.map(|d| DateTime::<Utc>::from(d).to_rfc3339()),
             date_modified: Some(updated_at.to_rfc3339()),
             author,
-            tags: Some(categories),
+            tags: Some(tags),
             language: locale,
         }
     }
@@ -378,6 +536,77 @@ impl FromIterator<BlogCategory> for Vec<AtomCategory> {
     }
 }
 
+/// Tag, made from the `seo_keywords` of published posts.
+/// Keywords which only differ in letter case or diacritics ("Rust", "rust") have the same slug,
+/// so they are grouped into one tag.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub slug: String,
+    // Number of published posts
+    pub count: i64,
+    // The keyword variants which make up this tag
+    #[serde(skip)]
+    pub keywords: Vec<String>,
+}
+
+impl Tag {
+    /// Group keywords (with the IDs of their posts) into tags.
+    /// A post having several variants of a keyword is counted once.
+    /// The tag name is taken from the most used variant. Tags are sorted by popularity.
+    pub fn group_keywords(keyword_posts: Vec<(String, Vec<Uuid>)>) -> Vec<Tag> {
+        let mut tags: Vec<Tag> = Vec::new();
+        let mut name_counts: Vec<usize> = Vec::new();
+        let mut post_ids: Vec<HashSet<Uuid>> = Vec::new();
+        for (keyword, ids) in keyword_posts {
+            let slug = slugify(keyword.trim());
+            if slug.is_empty() {
+                continue;
+            }
+            let count = ids.len();
+            let i = match tags.iter().position(|t| t.slug == slug) {
+                Some(i) => {
+                    if count > name_counts[i] {
+                        tags[i].name = keyword.trim().to_string();
+                        name_counts[i] = count;
+                    }
+                    tags[i].keywords.push(keyword);
+                    i
+                }
+                None => {
+                    tags.push(Tag {
+                        name: keyword.trim().to_string(),
+                        slug,
+                        count: 0,
+                        keywords: vec![keyword],
+                    });
+                    name_counts.push(count);
+                    post_ids.push(HashSet::new());
+                    tags.len() - 1
+                }
+            };
+            post_ids[i].extend(ids);
+            tags[i].count = post_ids[i].len() as i64;
+        }
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        tags
+    }
+
+    // Tags are put next to categories in Atom entries, with our tag pages as the scheme
+    fn atom_category(keyword: String) -> AtomCategory {
+        CategoryBuilder::default()
+            .term(slugify(keyword.trim()))
+            .scheme(Some(format!("{DEFAULT_SITE_URL}/tag/")))
+            .label(Some(keyword))
+            .build()
+    }
+}
+
+/// Helper function to build tag page URL from a keyword or a tag slug
+pub fn build_tag_view_url(keyword: &str) -> String {
+    format!("/tag/{}/", slugify(keyword.trim()))
+}
+
 /// Featured category with its 2 latest posts for home page display
 #[derive(Debug, Clone, Serialize)]
 pub struct FeaturedCategoryBlock {
@@ -422,6 +651,7 @@ pub struct MinBodyBlogPost {
     pub id: Uuid,
     pub title: String,
     pub body: Option<String>,
+    pub format: DocFormat,
 }
 
 impl EdgeSelectable for MinBodyBlogPost {
@@ -430,3 +660,172 @@ impl EdgeSelectable for MinBodyBlogPost {
         format!("{{ {fields} }}")
     }
 }
+
+// Struct to represent a BlogPost in full-text search results, and in the API listing of posts,
+// which has the same shape with or without search.
+// The `snippet` is not stored in the database, we fill it after querying. It is null when not searching.
+#[serde_with::apply(
+    EDatetime => #[serde(serialize_with = "serialize_edge_datetime")],
+    Option<EDatetime> => #[serde(serialize_with = "serialize_optional_edge_datetime")],
+)]
+#[derive(Debug, Clone, Serialize, Queryable, FieldNames)]
+pub struct SearchedBlogPost {
+    pub id: Uuid,
+    pub title: String,
+    pub slug: String,
+    pub locale: Option<String>,
+    pub excerpt: Option<String>,
+    pub is_published: bool,
+    pub published_at: Option<EDatetime>,
+    pub created_at: EDatetime,
+    pub updated_at: Option<EDatetime>,
+    pub categories: Vec<BlogCategory>,
+    pub seo_keywords: Vec<String>,
+    pub author: Option<MiniUser>,
+    pub comment_count: i64,
+    #[serde(skip)]
+    pub html: Option<String>,
+    pub snippet: Option<String>,
+}
+
+impl From<MediumBlogPost> for SearchedBlogPost {
+    fn from(post: MediumBlogPost) -> Self {
+        Self {
+            id: post.id,
+            title: post.title,
+            slug: post.slug,
+            locale: post.locale,
+            excerpt: post.excerpt,
+            is_published: post.is_published,
+            published_at: post.published_at,
+            created_at: post.created_at,
+            updated_at: post.updated_at,
+            categories: post.categories,
+            seo_keywords: post.seo_keywords,
+            author: post.author,
+            comment_count: post.comment_count,
+            html: None,
+            snippet: None,
+        }
+    }
+}
+
+impl SearchedBlogPost {
+    /// Make the highlighted snippet from post content, falling back to the excerpt.
+    pub fn fill_snippet(&mut self, search_tokens: &[String]) {
+        let source = self
+            .html
+            .as_deref()
+            .or(self.excerpt.as_deref())
+            .unwrap_or_default();
+        self.snippet = Some(make_snippet(&html_to_text(source), search_tokens));
+    }
+}
+
+impl EdgeSelectable for SearchedBlogPost {
+    fn fields_as_shape() -> String {
+        let fields: Vec<String> = Self::FIELDS
+            .into_iter()
+            .map(|s| match s {
+                "categories" => {
+                    let cat_shape = BlogCategory::fields_as_shape();
+                    format!("categories: {cat_shape}")
+                }
+                "author" => {
+                    let user_shape = MiniUser::fields_as_shape();
+                    format!("author: {user_shape}")
+                }
+                "comment_count" => COMMENT_COUNT_SHAPE.to_string(),
+                "snippet" => "snippet := <str>{}".to_string(),
+                _ => s.to_string(),
+            })
+            .collect();
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
+// Struct to represent a BlogPost with the fields to build its full-text search index
+#[derive(Debug, Clone, Queryable, FieldNames)]
+pub struct SearchSourceBlogPost {
+    pub id: Uuid,
+    pub title: String,
+    pub excerpt: Option<String>,
+    pub body: Option<String>,
+    pub seo_keywords: Vec<String>,
+    pub categories: Vec<BlogCategory>,
+}
+
+impl SearchSourceBlogPost {
+    /// Build the values for `search_title` and `search_text` fields.
+    /// The title field includes keywords and category names, which are weighted higher when ranking.
+    pub fn make_search_fields(&self) -> (String, String) {
+        let mut headline = vec![self.title.as_str()];
+        headline.extend(self.seo_keywords.iter().map(String::as_str));
+        for cat in &self.categories {
+            headline.push(cat.title.as_str());
+            headline.extend(cat.title_vi.as_deref());
+        }
+        let content = [self.excerpt.as_deref(), self.body.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(html_to_text)
+            .collect::<Vec<_>>()
+            .join("\n");
+        (fold_text(&headline.join("\n")), fold_text(&content))
+    }
+}
+
+impl EdgeSelectable for SearchSourceBlogPost {
+    fn fields_as_shape() -> String {
+        let fields: Vec<String> = Self::FIELDS
+            .into_iter()
+            .map(|s| match s {
+                "categories" => {
+                    let cat_shape = BlogCategory::fields_as_shape();
+                    format!("categories: {cat_shape}")
+                }
+                _ => s.to_string(),
+            })
+            .collect();
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_keywords() {
+        let post = Uuid::from_u128;
+        let tags = Tag::group_keywords(vec![
+            ("rust".into(), vec![post(1)]),
+            ("Rust".into(), vec![post(1), post(2), post(3)]),
+            ("Python".into(), vec![post(2)]),
+            ("  ".into(), vec![post(4)]),
+        ]);
+        assert_eq!(tags.len(), 2);
+        // Post 1 has both "rust" and "Rust"
+        assert_eq!(tags[0].slug, "rust");
+        assert_eq!(tags[0].count, 3);
+        assert_eq!(tags[0].name, "Rust");
+        assert_eq!(tags[0].keywords, ["rust", "Rust"]);
+        assert_eq!(tags[1].slug, "python");
+        assert_eq!(tags[1].count, 1);
+    }
+
+    #[test]
+    fn test_group_keywords_sorting() {
+        let post = Uuid::from_u128;
+        let tags = Tag::group_keywords(vec![
+            ("Go".into(), vec![post(1)]),
+            ("Web Dev".into(), vec![post(1), post(2)]),
+            ("web dev".into(), vec![post(3)]),
+            ("Axum".into(), vec![post(2)]),
+        ]);
+        let names: Vec<&str> = tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["Web Dev", "Axum", "Go"]);
+        assert_eq!(tags[0].slug, "web-dev");
+        assert_eq!(tags[0].count, 3);
+    }
+}
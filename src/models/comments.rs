@@ -0,0 +1,87 @@
+use field_names::FieldNames;
+use gel_derive::Queryable;
+use gel_protocol::model::Datetime as EDatetime;
+use gel_protocol::value::Value as EValue;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
+use uuid::Uuid;
+
+use super::MiniBlogPost;
+use crate::types::EdgeSelectable;
+use crate::types::conversions::serialize_edge_datetime;
+
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Default,
+    Clone,
+    Copy,
+    EnumString,
+    Display,
+    IntoStaticStr,
+    Serialize,
+    Deserialize,
+    Queryable,
+)]
+pub enum CommentStatus {
+    #[default]
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl From<CommentStatus> for EValue {
+    fn from(s: CommentStatus) -> Self {
+        let v: &str = s.into();
+        EValue::Enum(v.into())
+    }
+}
+
+// Comment as seen by admin, with the private fields
+#[derive(Debug, Clone, Serialize, Queryable, FieldNames)]
+pub struct Comment {
+    pub id: Uuid,
+    pub author_name: String,
+    pub author_email: String,
+    pub body: String,
+    pub html: Option<String>,
+    pub status: CommentStatus,
+    pub ip_address: Option<String>,
+    #[serde(serialize_with = "serialize_edge_datetime")]
+    pub created_at: EDatetime,
+    pub post: MiniBlogPost,
+}
+
+impl EdgeSelectable for Comment {
+    fn fields_as_shape() -> String {
+        let fields: Vec<String> = Self::FIELDS
+            .into_iter()
+            .map(|s| match s {
+                "post" => {
+                    let post_shape = MiniBlogPost::fields_as_shape();
+                    format!("post: {post_shape}")
+                }
+                _ => s.to_string(),
+            })
+            .collect();
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
+// Comment to show under a blog post. Email and IP address must not leak to the public page.
+#[derive(Debug, Clone, Serialize, Queryable, FieldNames)]
+pub struct PublicComment {
+    pub id: Uuid,
+    pub author_name: String,
+    pub html: Option<String>,
+    #[serde(serialize_with = "serialize_edge_datetime")]
+    pub created_at: EDatetime,
+}
+
+impl EdgeSelectable for PublicComment {
+    fn fields_as_shape() -> String {
+        let fields = Self::FIELDS.join(", ");
+        format!("{{ {fields} }}")
+    }
+}
@@ -1,10 +1,15 @@
 pub mod blogs;
+pub mod comments;
 pub mod feeds;
 pub mod minors;
+pub mod revisions;
+pub mod tokens;
 pub mod users;
 
-pub use blogs::{BlogCategory, DetailedBlogPost, DocFormat, FeaturedCategoryBlock, HomePagePost, MediumBlogPost, MiniBlogPost, MinBodyBlogPost};
+pub use blogs::{BlogCategory, DetailedBlogPost, DocFormat, FeaturedCategoryBlock, HomePagePost, MediumBlogPost, MiniBlogPost, MinBodyBlogPost, PostTranslation, SearchSourceBlogPost, SearchedBlogPost, SitemapBlogPost, Tag};
+pub use comments::{Comment, CommentStatus, PublicComment};
 pub use minors::Presentation;
+pub use revisions::{MiniPostRevision, PostRevision};
 pub use users::User;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, gel_derive::Queryable)]
@@ -0,0 +1,74 @@
+use field_names::FieldNames;
+use gel_derive::Queryable;
+use gel_protocol::model::Datetime as EDatetime;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::DocFormat;
+use super::users::MiniUser;
+use crate::types::EdgeSelectable;
+use crate::types::conversions::serialize_edge_datetime;
+
+// Revision in the history list, without the body
+#[derive(Debug, Clone, Serialize, Queryable, FieldNames)]
+pub struct MiniPostRevision {
+    pub id: Uuid,
+    pub title: String,
+    #[serde(serialize_with = "serialize_edge_datetime")]
+    pub created_at: EDatetime,
+    pub author: Option<MiniUser>,
+}
+
+impl EdgeSelectable for MiniPostRevision {
+    fn fields_as_shape() -> String {
+        let fields: Vec<String> = Self::FIELDS
+            .into_iter()
+            .map(|s| match s {
+                "author" => {
+                    let user_shape = MiniUser::fields_as_shape();
+                    format!("author: {user_shape}")
+                }
+                _ => s.to_string(),
+            })
+            .collect();
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Queryable, FieldNames)]
+pub struct PostRevision {
+    pub id: Uuid,
+    pub title: String,
+    pub body: Option<String>,
+    pub format: DocFormat,
+    #[serde(serialize_with = "serialize_edge_datetime")]
+    pub created_at: EDatetime,
+    pub author: Option<MiniUser>,
+}
+
+impl PostRevision {
+    pub fn to_diffable_text(&self) -> String {
+        make_diffable_text(&self.title, self.body.as_deref())
+    }
+}
+
+/// The text to compare between revisions. The title is included, because it is tracked, too.
+pub fn make_diffable_text(title: &str, body: Option<&str>) -> String {
+    format!("# {title}\n\n{}", body.unwrap_or_default())
+}
+
+impl EdgeSelectable for PostRevision {
+    fn fields_as_shape() -> String {
+        let fields: Vec<String> = Self::FIELDS
+            .into_iter()
+            .map(|s| match s {
+                "author" => {
+                    let user_shape = MiniUser::fields_as_shape();
+                    format!("author: {user_shape}")
+                }
+                _ => s.to_string(),
+            })
+            .collect();
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
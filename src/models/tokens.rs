@@ -0,0 +1,69 @@
+use field_names::FieldNames;
+use gel_derive::Queryable;
+use gel_protocol::model::Datetime as EDatetime;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
+use uuid::Uuid;
+
+use super::User;
+use crate::types::EdgeSelectable;
+use crate::types::conversions::{serialize_edge_datetime, serialize_optional_edge_datetime};
+
+/// What an API token can be used for. The token never gets more than its owner's role allows.
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Hash,
+    Clone,
+    Copy,
+    EnumString,
+    Display,
+    IntoStaticStr,
+    Serialize,
+    Deserialize,
+    Queryable,
+)]
+pub enum TokenScope {
+    /// Read drafts, revisions and other data which need login
+    Read,
+    /// Create, edit and publish posts
+    WritePosts,
+    /// Upload and delete files
+    Files,
+}
+
+// Token as listed to its owner. The token itself is only shown once, when created.
+#[derive(Debug, Clone, Serialize, Queryable, FieldNames)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    #[serde(serialize_with = "serialize_optional_edge_datetime")]
+    pub expires_at: Option<EDatetime>,
+    #[serde(serialize_with = "serialize_optional_edge_datetime")]
+    pub last_used_at: Option<EDatetime>,
+    #[serde(serialize_with = "serialize_edge_datetime")]
+    pub created_at: EDatetime,
+}
+
+impl EdgeSelectable for ApiToken {
+    fn fields_as_shape() -> String {
+        let fields = Self::FIELDS.join(", ");
+        format!("{{ {fields} }}")
+    }
+}
+
+// What a valid token gives to the request bearing it
+#[derive(Debug, Clone, Queryable)]
+pub struct ApiTokenGrant {
+    pub scopes: Vec<TokenScope>,
+    pub user: User,
+}
+
+impl EdgeSelectable for ApiTokenGrant {
+    fn fields_as_shape() -> String {
+        let user_shape = User::fields_as_shape();
+        format!("{{ scopes, user: {user_shape} }}")
+    }
+}
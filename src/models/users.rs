@@ -1,13 +1,15 @@
 use atom_syndication::{Person, PersonBuilder};
 use gel_derive::Queryable;
+use gel_protocol::value::Value as EValue;
 use field_names::FieldNames;
 use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
 use uuid::Uuid;
 
 use super::feeds::JsonAuthor;
 use crate::types::EdgeSelectable;
 
-#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, Queryable, FieldNames)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
@@ -16,6 +18,31 @@ pub struct User {
     pub password: String,
     pub is_active: bool,
     pub is_superuser: bool,
+    pub role: Role,
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+}
+
+impl EdgeSelectable for User {
+    fn fields_as_shape() -> String {
+        let fields = Self::FIELDS.join(", ");
+        format!("{{ {fields} }}")
+    }
+}
+
+impl User {
+    /// Superusers are admins, whatever role is stored for them
+    pub fn effective_role(&self) -> Role {
+        if self.is_superuser {
+            Role::Admin
+        } else {
+            self.role
+        }
+    }
+
+    pub fn has_2fa(&self) -> bool {
+        self.totp_secret.is_some()
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, Queryable, FieldNames)]
@@ -50,8 +77,34 @@ impl From<MiniUser> for JsonAuthor {
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Hash,
+    Default,
+    Clone,
+    Copy,
+    EnumString,
+    Display,
+    IntoStaticStr,
+    Serialize,
+    Deserialize,
+    Queryable,
+)]
 pub enum Role {
+    /// Manages users and files, on top of what editors can do
     Admin,
+    /// Edits and publishes any post, manages categories, comments etc.
+    Editor,
+    /// Writes their own posts, but cannot publish them
+    #[default]
+    Author,
+}
+
+impl From<Role> for EValue {
+    fn from(r: Role) -> Self {
+        let v: &str = r.into();
+        EValue::Enum(v.into())
+    }
 }
@@ -0,0 +1,225 @@
+// Thin client of Bunny Storage API: https://docs.bunny.net/reference/storage-api
+
+use axum::body::Bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::{Body, Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, debug, error, field::Empty, info_span};
+
+use super::{ByteStream, FileStorage, StorageError, StoredFile, split_file_path};
+use crate::utils::metrics::record_bunny_call;
+
+pub const STORAGE_ZONE_NAME: &str = "quan-images";
+
+/// Bunny API response structure
+#[derive(Serialize, Deserialize)]
+pub struct BunnyApiResponse {
+    #[serde(rename = "Guid")]
+    pub guid: String,
+    #[serde(rename = "StorageZoneName")]
+    pub storage_zone_name: String,
+    #[serde(rename = "Path")]
+    pub path: String,
+    #[serde(rename = "ObjectName")]
+    pub object_name: String,
+    #[serde(rename = "Length")]
+    pub length: i64,
+    #[serde(rename = "LastChanged")]
+    pub last_changed: String,
+    #[serde(rename = "IsDirectory")]
+    pub is_directory: bool,
+    #[serde(rename = "ServerId")]
+    pub server_id: i32,
+    #[serde(rename = "UserId")]
+    pub user_id: String,
+    #[serde(rename = "DateCreated")]
+    pub date_created: String,
+    #[serde(rename = "StorageZoneId")]
+    pub storage_zone_id: i32,
+}
+
+impl BunnyApiResponse {
+    pub fn into_stored_file(self, zone: &str) -> StoredFile {
+        // Bunny returns dates without timezone (e.g., "2026-02-22T06:46:45.179")
+        let parse_date = |s: &str| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|dt| DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc))
+        };
+        // Strip storage zone prefix from path (e.g., "/quan-images/blogs/" -> "blogs/")
+        let zone_prefix = format!("/{zone}/");
+        let dir_path = self
+            .path
+            .strip_prefix(&zone_prefix)
+            .unwrap_or(self.path.trim_start_matches('/'))
+            .to_string();
+        StoredFile {
+            created_at: parse_date(&self.date_created),
+            modified_at: parse_date(&self.last_changed),
+            name: self.object_name,
+            dir_path,
+            size: self.length.try_into().unwrap_or_default(),
+            is_directory: self.is_directory,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BunnyStorage {
+    /// Like "https://sg.storage.bunnycdn.com", or a mock server when testing
+    pub base_url: String,
+    pub zone: String,
+    pub access_key: String,
+    pub cdn_host: String,
+    client: Client,
+}
+
+impl BunnyStorage {
+    pub fn new(base_url: String, access_key: String, cdn_host: String) -> Self {
+        Self {
+            base_url,
+            zone: STORAGE_ZONE_NAME.to_string(),
+            access_key,
+            cdn_host,
+            client: Client::new(),
+        }
+    }
+
+    fn make_url(&self, path: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.base_url,
+            self.zone,
+            path.trim_start_matches('/')
+        )
+    }
+
+    /// Path of a file in the CDN, to purge it from the cache
+    pub fn cdn_path(&self, path: &str) -> String {
+        format!("{}/{}", self.zone, path.trim_start_matches('/'))
+    }
+}
+
+/// Send a request to Bunny API, in a span which tells how long it takes, and count it.
+pub async fn send_request(
+    operation: &'static str,
+    url: &str,
+    request: RequestBuilder,
+) -> Result<Response, reqwest::Error> {
+    let span = info_span!(
+        "bunny_api",
+        operation,
+        url,
+        otel.kind = "client",
+        http.response.status_code = Empty
+    );
+    let response = request.send().instrument(span.clone()).await;
+    if let Ok(r) = &response {
+        span.record("http.response.status_code", r.status().as_u16());
+    }
+    record_bunny_call(operation, &response);
+    response
+}
+
+impl FileStorage for BunnyStorage {
+    async fn list(&self, dir_path: &str) -> Result<Vec<StoredFile>, StorageError> {
+        let dir_path = dir_path.trim_end_matches('/');
+        let url = format!("{}/", self.make_url(dir_path));
+        debug!("Making request to Bunny API: {}", url);
+        let request = self.client.get(&url).header("AccessKey", &self.access_key);
+        let response = send_request("list", &url, request).await.map_err(|e| {
+            error!("Failed to send request to Bunny API: {}", e);
+            StorageError::Bunny(e)
+        })?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let response = check_response(response).await?;
+        let items: Vec<BunnyApiResponse> = response.json().await.map_err(|e| {
+            error!("Failed to parse Bunny API response: {}", e);
+            StorageError::Remote(format!("Failed to parse Bunny API response: {}", e))
+        })?;
+        debug!("Received {} items from Bunny API", items.len());
+        Ok(items
+            .into_iter()
+            .map(|item| item.into_stored_file(&self.zone))
+            .collect())
+    }
+
+    // Bunny doesn't tell about a single file, so we look for it in its directory.
+    async fn stat(&self, path: &str) -> Result<Option<StoredFile>, StorageError> {
+        let (dir_path, name) = split_file_path(path)?;
+        let items = self.list(&dir_path).await?;
+        Ok(items.into_iter().find(|f| f.name == name))
+    }
+
+    async fn get(&self, path: &str) -> Result<Bytes, StorageError> {
+        let url = self.make_url(path);
+        debug!("Making GET request to Bunny API: {}", url);
+        let request = self.client.get(&url).header("AccessKey", &self.access_key);
+        let response = send_request("get", &url, request).await.map_err(|e| {
+            error!("Failed to send GET request to Bunny API: {}", e);
+            StorageError::Bunny(e)
+        })?;
+        let response = check_response(response).await?;
+        Ok(response.bytes().await?)
+    }
+
+    async fn put(
+        &self,
+        path: &str,
+        content_type: &str,
+        content: ByteStream,
+    ) -> Result<(), StorageError> {
+        let url = self.make_url(path);
+        debug!("Making PUT request to Bunny API: {}", url);
+        let request = self
+            .client
+            .put(&url)
+            .header("AccessKey", &self.access_key)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(Body::wrap_stream(content));
+        let response = send_request("put", &url, request).await.map_err(|e| {
+            error!("Failed to send PUT request to Bunny API: {}", e);
+            StorageError::Bunny(e)
+        })?;
+        check_response(response).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let url = self.make_url(path);
+        debug!("Making DELETE request to Bunny API: {}", url);
+        let request = self
+            .client
+            .delete(&url)
+            .header("AccessKey", &self.access_key);
+        let response = send_request("delete", &url, request).await.map_err(|e| {
+            error!("Failed to send DELETE request to Bunny API: {}", e);
+            StorageError::Bunny(e)
+        })?;
+        check_response(response).await?;
+        Ok(())
+    }
+
+    fn public_url(&self, path: &str) -> String {
+        format!("https://{}/{}", self.cdn_host, self.cdn_path(path))
+    }
+}
+
+async fn check_response(response: Response) -> Result<Response, StorageError> {
+    debug!("Bunny API response status: {}", response.status());
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let error_text = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Unknown error".to_string());
+    error!("Bunny API error {}: {}", status, error_text);
+    Err(StorageError::Remote(format!(
+        "Bunny API error {}: {}",
+        status, error_text
+    )))
+}
@@ -0,0 +1,136 @@
+// Keep files in a local directory, which is served under "/media/".
+
+use std::path::{Path, PathBuf};
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use tokio::fs;
+use tokio::io::{self, AsyncWriteExt};
+use tracing::debug;
+
+use super::{
+    ByteStream, FileStorage, StorageError, StoredFile, normalize_dir_path, split_file_path,
+};
+
+pub const MEDIA_URL_PREFIX: &str = "/media";
+
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    pub root: PathBuf,
+    /// Where the root directory is served, like "/media"
+    pub url_prefix: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            url_prefix: MEDIA_URL_PREFIX.to_string(),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf, StorageError> {
+        let path = normalize_dir_path(path)?;
+        Ok(self.root.join(path.trim_end_matches('/')))
+    }
+}
+
+impl FileStorage for LocalStorage {
+    async fn list(&self, dir_path: &str) -> Result<Vec<StoredFile>, StorageError> {
+        let dir_path = normalize_dir_path(dir_path)?;
+        let full_path = self.root.join(&dir_path);
+        debug!("To list directory {}", full_path.display());
+        let mut entries = match fs::read_dir(&full_path).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Hidden files, including the ones being uploaded
+            if name.starts_with('.') {
+                continue;
+            }
+            files.push(make_stored_file(name, dir_path.clone(), &metadata));
+        }
+        files.sort_by(|a, b| (!a.is_directory, &a.name).cmp(&(!b.is_directory, &b.name)));
+        Ok(files)
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<StoredFile>, StorageError> {
+        let (dir_path, name) = split_file_path(path)?;
+        let full_path = self.root.join(&dir_path).join(&name);
+        match fs::metadata(&full_path).await {
+            Ok(metadata) => Ok(Some(make_stored_file(name, dir_path, &metadata))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<Bytes, StorageError> {
+        let (dir_path, name) = split_file_path(path)?;
+        let content = fs::read(self.root.join(dir_path).join(name)).await?;
+        Ok(Bytes::from(content))
+    }
+
+    async fn put(
+        &self,
+        path: &str,
+        _content_type: &str,
+        content: ByteStream,
+    ) -> Result<(), StorageError> {
+        let (dir_path, name) = split_file_path(path)?;
+        let dir = self.root.join(dir_path);
+        fs::create_dir_all(&dir).await?;
+        // Write to a temporary file first, so that an aborted upload doesn't leave a broken file
+        let target = dir.join(&name);
+        let partial = dir.join(format!(".{name}.part"));
+        debug!("To write file {}", target.display());
+        if let Err(e) = write_stream(&partial, content).await {
+            fs::remove_file(&partial).await.ok();
+            return Err(e.into());
+        }
+        fs::rename(&partial, &target).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let full_path = self.resolve(path)?;
+        if full_path == self.root {
+            return Err(StorageError::InvalidPath(path.to_string()));
+        }
+        debug!("To delete {}", full_path.display());
+        if fs::metadata(&full_path).await?.is_dir() {
+            fs::remove_dir_all(&full_path).await?;
+        } else {
+            fs::remove_file(&full_path).await?;
+        }
+        Ok(())
+    }
+
+    fn public_url(&self, path: &str) -> String {
+        format!("{}/{}", self.url_prefix, path.trim_start_matches('/'))
+    }
+}
+
+fn make_stored_file(name: String, dir_path: String, metadata: &std::fs::Metadata) -> StoredFile {
+    StoredFile {
+        name,
+        dir_path,
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        created_at: metadata.created().ok().map(DateTime::<Utc>::from),
+        modified_at: metadata.modified().ok().map(DateTime::<Utc>::from),
+        is_directory: metadata.is_dir(),
+    }
+}
+
+async fn write_stream(path: &Path, mut content: ByteStream) -> io::Result<()> {
+    let mut file = fs::File::create(path).await?;
+    while let Some(chunk) = content.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await
+}
@@ -0,0 +1,156 @@
+// Backends to keep the files managed by our files API.
+// Bunny storage is used in production, the local directory is for self-hosting and CI.
+
+pub mod bunny;
+pub mod local;
+
+use std::future::Future;
+use std::io;
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use thiserror::Error;
+
+use bunny::BunnyStorage;
+use local::LocalStorage;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("Invalid path: {0}")]
+    InvalidPath(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Bunny API error: {0}")]
+    Bunny(#[from] reqwest::Error),
+    /// The storage service responded with an error
+    #[error("{0}")]
+    Remote(String),
+}
+
+/// Content of a file to be stored
+pub type ByteStream = BoxStream<'static, Result<Bytes, io::Error>>;
+
+/// A file or directory in the storage
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub name: String,
+    /// Parent directory, relative to the storage root, like "blogs/2026/". Empty for the root.
+    pub dir_path: String,
+    pub size: u64,
+    pub created_at: Option<DateTime<Utc>>,
+    pub modified_at: Option<DateTime<Utc>>,
+    pub is_directory: bool,
+}
+
+impl StoredFile {
+    pub fn path(&self) -> String {
+        format!("{}{}", self.dir_path, self.name)
+    }
+}
+
+/// All paths are relative to the storage root, with or without the leading slash.
+pub trait FileStorage {
+    /// List files and sub-directories of a directory. A missing directory has no entries.
+    fn list(
+        &self,
+        dir_path: &str,
+    ) -> impl Future<Output = Result<Vec<StoredFile>, StorageError>> + Send;
+    /// Get info of a file or directory, `None` if it doesn't exist
+    fn stat(
+        &self,
+        path: &str,
+    ) -> impl Future<Output = Result<Option<StoredFile>, StorageError>> + Send;
+    /// Read the content of a file
+    fn get(&self, path: &str) -> impl Future<Output = Result<Bytes, StorageError>> + Send;
+    /// Save a file, replacing the existing one. Parent directories are created as needed.
+    fn put(
+        &self,
+        path: &str,
+        content_type: &str,
+        content: ByteStream,
+    ) -> impl Future<Output = Result<(), StorageError>> + Send;
+    /// Delete a file, or a directory with all its content
+    fn delete(&self, path: &str) -> impl Future<Output = Result<(), StorageError>> + Send;
+    /// URL for visitors to access the file
+    fn public_url(&self, path: &str) -> String;
+}
+
+/// The backend chosen by the "file_storage" config
+#[derive(Debug, Clone)]
+pub enum StorageBackend {
+    Bunny(BunnyStorage),
+    Local(LocalStorage),
+}
+
+impl FileStorage for StorageBackend {
+    async fn list(&self, dir_path: &str) -> Result<Vec<StoredFile>, StorageError> {
+        match self {
+            Self::Bunny(s) => s.list(dir_path).await,
+            Self::Local(s) => s.list(dir_path).await,
+        }
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<StoredFile>, StorageError> {
+        match self {
+            Self::Bunny(s) => s.stat(path).await,
+            Self::Local(s) => s.stat(path).await,
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<Bytes, StorageError> {
+        match self {
+            Self::Bunny(s) => s.get(path).await,
+            Self::Local(s) => s.get(path).await,
+        }
+    }
+
+    async fn put(
+        &self,
+        path: &str,
+        content_type: &str,
+        content: ByteStream,
+    ) -> Result<(), StorageError> {
+        match self {
+            Self::Bunny(s) => s.put(path, content_type, content).await,
+            Self::Local(s) => s.put(path, content_type, content).await,
+        }
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        match self {
+            Self::Bunny(s) => s.delete(path).await,
+            Self::Local(s) => s.delete(path).await,
+        }
+    }
+
+    fn public_url(&self, path: &str) -> String {
+        match self {
+            Self::Bunny(s) => s.public_url(path),
+            Self::Local(s) => s.public_url(path),
+        }
+    }
+}
+
+/// Turn "blogs//2026" to "blogs/2026/", the form to prepend to file names.
+/// Paths trying to get out of the storage root are rejected.
+pub fn normalize_dir_path(dir_path: &str) -> Result<String, StorageError> {
+    let parts: Vec<&str> = dir_path.split('/').filter(|p| !p.is_empty()).collect();
+    if parts
+        .iter()
+        .any(|p| *p == "." || *p == ".." || p.contains('\\'))
+    {
+        return Err(StorageError::InvalidPath(dir_path.to_string()));
+    }
+    Ok(parts.iter().map(|p| format!("{p}/")).collect())
+}
+
+/// Split a file path to its normalized parent directory and name
+pub fn split_file_path(path: &str) -> Result<(String, String), StorageError> {
+    let trimmed = path.trim_end_matches('/');
+    let (dir_path, name) = trimmed.rsplit_once('/').unwrap_or(("", trimmed));
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(StorageError::InvalidPath(path.to_string()));
+    }
+    Ok((normalize_dir_path(dir_path)?, name.to_string()))
+}
@@ -6,55 +6,106 @@ use gel_protocol::named_args;
 use gel_protocol::value::Value;
 use gel_protocol::value_opt::ValueOpt;
 use gel_tokio::{Client, Error};
+use slugrs::slugify;
 use smallvec::SmallVec;
 use str_macro::str;
-use tracing::{debug, info};
+use tracing::{debug, field::Empty, instrument};
 use uuid::Uuid;
 
+use super::log_query;
 use crate::models::{
-    BlogCategory, DetailedBlogPost, FeaturedCategoryBlock, HomePagePost, MediumBlogPost, MiniBlogPost, MinBodyBlogPost,
+    BlogCategory, DetailedBlogPost, DocFormat, FeaturedCategoryBlock, HomePagePost, MediumBlogPost,
+    MinBodyBlogPost, MiniBlogPost, MinimalObject, PostTranslation, SearchSourceBlogPost,
+    SearchedBlogPost, SitemapBlogPost, Tag,
 };
 use crate::types::EdgeSelectable;
 
+// Search condition. The tokens are folded (see `utils::search`), and bound to `search_tokens` in WITH block.
+const SEARCH_FILTER: &str =
+    "all(contains((.search_title ?? '') ++ ' ' ++ (.search_text ?? ''), search_tokens))";
+// A published post may be scheduled, i.e. its `published_at` is in the future.
+// It is hidden from the public until that time comes.
+pub const LIVE_FILTER: &str =
+    ".is_published = true AND ((.published_at <= datetime_of_statement()) ?? true)";
+const NOT_SCHEDULED_FILTER: &str = "((.published_at <= datetime_of_statement()) ?? true)";
+// Public lists are ordered by the time posts go live, so that a scheduled post comes on top
+// when it is published. Older posts may have no `published_at`.
+const PUBLISHED_ORDER: &str = "(.published_at ?? .created_at) DESC";
+// Among the live translations of an article, only the one in the reader's language (`$lang`) is listed.
+// Articles which don't have a version in that language are listed as usual.
+fn preferred_translation_filter() -> String {
+    format!(
+        "((.locale ?? '') = <str>$lang OR NOT EXISTS (
+            WITH group_id := .translation_group
+            SELECT DETACHED BlogPost
+            FILTER .translation_group = group_id AND .locale = <str>$lang AND {LIVE_FILTER}
+        ))"
+    )
+}
+
+// A match in title, keywords or category names weighs as much as 8 occurrences in content.
+const SEARCH_RANK: &str = "sum(
+    8 * <int64>contains(.search_title ?? '', search_tokens)
+    + (len(.search_text ?? '') - len(str_replace(.search_text ?? '', search_tokens, ''))) // len(search_tokens)
+)";
+
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn count_search_result_posts(
-    lower_search_tokens: Option<&Vec<String>>,
+    search_tokens: &[String],
     cat_id: Option<Uuid>,
+    published_only: bool,
     client: &Client,
 ) -> Result<usize, Error> {
-    let lower_search_tokens: Option<Vec<&str>> =
-        lower_search_tokens.map(|v| v.iter().map(|s| s.as_str()).collect());
     let mut kw_args = named_args! {};
     let mut filter_conds = vec![];
-    if let Some(words) = lower_search_tokens.clone() {
-        let words: Vec<_> = words.into_iter().map(Value::from).collect();
-        kw_args.insert("search_words", ValueOpt::from(words));
-        filter_conds.push(
-            "all(contains(str_lower(BlogPost.title), array_unpack(<array<str>>$search_words)))",
-        );
+    let mut with_line = "";
+    if !search_tokens.is_empty() {
+        let words: Vec<_> = search_tokens
+            .iter()
+            .map(|s| Value::from(s.as_str()))
+            .collect();
+        kw_args.insert("tokens", ValueOpt::from(words));
+        with_line = "WITH search_tokens := array_unpack(<array<str>>$tokens)";
+        filter_conds.push(SEARCH_FILTER);
     };
     if let Some(cat) = cat_id {
         kw_args.insert("cat_id", ValueOpt::from(cat));
         filter_conds.push("any(.categories.id = <uuid>$cat_id)");
     }
+    if published_only {
+        filter_conds.push(LIVE_FILTER);
+    }
     let filter_line = if filter_conds.is_empty() {
         Cow::from("")
     } else {
         Cow::from(format!("FILTER {}", filter_conds.join(" AND ")))
     };
-    let q = format!("SELECT count((SELECT BlogPost {filter_line}))");
-    debug!("To query: {}", q);
+    let q = format!("{with_line} SELECT count((SELECT BlogPost {filter_line}))");
+    log_query(&q);
     debug!("With args: {:?}", kw_args);
     let count: i64 = client.query_required_single(&q, &kw_args).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
-pub async fn count_all_published_posts(client: &Client) -> Result<usize, Error> {
-    let q = "SELECT count((SELECT BlogPost FILTER .is_published = true))";
-    tracing::debug!("To query: {}", q);
-    let count: i64 = client.query_required_single(q, &()).await?;
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn count_all_published_posts(
+    lang: Option<&str>,
+    client: &Client,
+) -> Result<usize, Error> {
+    let mut args: HashMap<&str, ValueOpt> = HashMap::new();
+    let mut filter_lines = vec![LIVE_FILTER.to_string()];
+    if let Some(lang) = lang {
+        args.insert("lang", lang.into());
+        filter_lines.push(preferred_translation_filter());
+    }
+    let filter_expr = filter_lines.join(" AND ");
+    let q = format!("SELECT count((SELECT BlogPost FILTER {filter_expr}))");
+    log_query(&q);
+    let count: i64 = client.query_required_single(&q, &args).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_post(post_id: Uuid, client: &Client) -> Result<Option<DetailedBlogPost>, Error> {
     // Note: For now, we cannot use Gel splats syntax because the returned field order
     // does not match DetailedBlogPost.
@@ -63,11 +114,12 @@ pub async fn get_post(post_id: Uuid, client: &Client) -> Result<Option<DetailedB
         "SELECT BlogPost {fields}
         FILTER .id = <uuid>$0"
     );
-    tracing::debug!("To query: {}", q);
+    log_query(&q);
     let post: Option<DetailedBlogPost> = client.query_single(&q, &(post_id,)).await?;
     Ok(post)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_detailed_post_by_slug(
     slug: &str,
     client: &Client,
@@ -77,15 +129,15 @@ pub async fn get_detailed_post_by_slug(
     let fields = DetailedBlogPost::fields_as_shape();
     let q = format!(
         "SELECT BlogPost {fields}
-        FILTER .slug = <str>$0"
+        FILTER .slug = <str>$0 AND {NOT_SCHEDULED_FILTER}"
     );
-    tracing::debug!("To query: {}", q);
+    log_query(&q);
     let post: Option<DetailedBlogPost> = client.query_single(&q, &(slug,)).await?;
     Ok(post)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_blogposts(
-    lower_search_tokens: Option<&Vec<String>>,
     cat_id: Option<Uuid>,
     offset: Option<i64>,
     limit: Option<i64>,
@@ -94,11 +146,6 @@ pub async fn get_blogposts(
     let mut kw_args = HashMap::new();
     let mut filter_conds = vec![];
     let mut paging_params: SmallVec<[_; 2]> = SmallVec::new();
-    if let Some(ss) = lower_search_tokens {
-        let v: Vec<&str> = ss.iter().map(|s| s.as_str()).collect();
-        kw_args.insert("tokens", ValueOpt::from(v));
-        filter_conds.push("all(contains(str_lower(.title), array_unpack(<array<str>>$tokens)))");
-    }
     if let Some(cat) = cat_id {
         kw_args.insert("cat_id", ValueOpt::from(cat));
         filter_conds.push("any(.categories.id = <uuid>$cat_id)");
@@ -123,19 +170,72 @@ pub async fn get_blogposts(
         {filter_line}
         ORDER BY .created_at DESC EMPTY FIRST {paging_expr}"
     );
-    debug!("To query: {q}");
+    log_query(&q);
     debug!("With args: {kw_args:?}");
     let posts: Vec<MediumBlogPost> = client.query(&q, &kw_args).await?;
     Ok(posts)
 }
 
+/// Full-text search over title, content, keywords and category names. Results are sorted by relevance.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn search_blogposts(
+    search_tokens: &[String],
+    cat_id: Option<Uuid>,
+    published_only: bool,
+    offset: Option<i64>,
+    limit: Option<i64>,
+    client: &Client,
+) -> Result<Vec<SearchedBlogPost>, Error> {
+    let mut kw_args = HashMap::new();
+    let mut filter_conds = vec![SEARCH_FILTER];
+    let mut paging_params: SmallVec<[_; 2]> = SmallVec::new();
+    let words: Vec<&str> = search_tokens.iter().map(|s| s.as_str()).collect();
+    kw_args.insert("tokens", ValueOpt::from(words));
+    if let Some(cat) = cat_id {
+        kw_args.insert("cat_id", ValueOpt::from(cat));
+        filter_conds.push("any(.categories.id = <uuid>$cat_id)");
+    }
+    if published_only {
+        filter_conds.push(LIVE_FILTER);
+    }
+    if let Some(offset) = offset {
+        kw_args.insert("offset", ValueOpt::from(offset));
+        paging_params.push(str!("OFFSET <int64>$offset"));
+    }
+    if let Some(limit) = limit {
+        kw_args.insert("limit", ValueOpt::from(limit));
+        paging_params.push(str!("LIMIT <int64>$limit"));
+    }
+    let filter_expr = filter_conds.join(" AND ");
+    let paging_expr = paging_params.join(" ");
+    let fields = SearchedBlogPost::fields_as_shape();
+    let q = format!(
+        "WITH search_tokens := array_unpack(<array<str>>$tokens)
+        SELECT BlogPost {fields}
+        FILTER {filter_expr}
+        ORDER BY {SEARCH_RANK} DESC THEN .created_at DESC EMPTY FIRST {paging_expr}"
+    );
+    log_query(&q);
+    debug!("With args: {kw_args:?}");
+    let mut posts: Vec<SearchedBlogPost> = client.query(&q, &kw_args).await?;
+    posts.iter_mut().for_each(|p| p.fill_snippet(search_tokens));
+    Ok(posts)
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_published_posts(
     offset: Option<i64>,
     limit: Option<i64>,
+    lang: Option<&str>,
     client: &Client,
 ) -> Result<Vec<MediumBlogPost>, Error> {
-    let mut args = HashMap::with_capacity(2);
+    let mut args = HashMap::with_capacity(3);
+    let mut filter_lines = vec![LIVE_FILTER.to_string()];
     let mut paging_lines: Vec<String> = Vec::with_capacity(2);
+    if let Some(lang) = lang {
+        args.insert("lang", ValueOpt::from(lang));
+        filter_lines.push(preferred_translation_filter());
+    }
     if let Some(offset) = offset {
         args.insert("offset", ValueOpt::from(offset));
         paging_lines.push(str!("OFFSET <int64>$offset"));
@@ -144,31 +244,38 @@ pub async fn get_published_posts(
         args.insert("limit", ValueOpt::from(limit));
         paging_lines.push(str!("LIMIT <int64>$limit"));
     }
+    let filter_expr = filter_lines.join(" AND ");
     let paging_expr = paging_lines.join(" ");
     let fields = MediumBlogPost::fields_as_shape();
     let q = format!(
         "SELECT BlogPost {fields}
-        FILTER .is_published = true ORDER BY .created_at DESC EMPTY FIRST {paging_expr}"
+        FILTER {filter_expr} ORDER BY {PUBLISHED_ORDER} {paging_expr}"
     );
-    info!("To query: {q}");
+    log_query(&q);
     let posts: Vec<MediumBlogPost> = client.query(&q, &args).await?;
     Ok(posts)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_published_posts_under_category(
     cat_slug: Option<String>,
     offset: Option<i64>,
     limit: Option<i64>,
+    lang: Option<&str>,
     client: &Client,
 ) -> Result<Vec<MediumBlogPost>, Error> {
-    let mut filter_lines = vec![".is_published = true"];
+    let mut filter_lines = vec![LIVE_FILTER.to_string()];
     let mut paging_lines: Vec<String> = Vec::with_capacity(2);
     let mut args: HashMap<&str, ValueOpt> = HashMap::new();
     if let Some(slug) = cat_slug {
         // The any() function is to solve the "possibly more than one element returned by an expression in a FILTER clause" warning.
-        filter_lines.push("any(.categories.slug = <str>$slug)");
+        filter_lines.push(str!("any(.categories.slug = <str>$slug)"));
         args.insert("slug", slug.into());
     }
+    if let Some(lang) = lang {
+        args.insert("lang", lang.into());
+        filter_lines.push(preferred_translation_filter());
+    }
     if let Some(offset) = offset {
         args.insert("offset", offset.into());
         paging_lines.push(str!("OFFSET <int64>$offset"));
@@ -183,22 +290,35 @@ pub async fn get_published_posts_under_category(
 
     let q = format!(
         "SELECT BlogPost {fields}
-        FILTER {filter_expr} ORDER BY .created_at DESC EMPTY FIRST {paging_expr}"
+        FILTER {filter_expr} ORDER BY {PUBLISHED_ORDER} {paging_expr}"
     );
-    tracing::debug!("To query: {}", q);
+    log_query(&q);
     tracing::debug!("With args: {:#?}", args);
     let posts: Vec<MediumBlogPost> = client.query(&q, &args).await?;
     Ok(posts)
 }
 
-pub async fn count_blogposts_under_category(id: Uuid, client: &Client) -> Result<usize, Error> {
-    let q = "
-    SELECT count((SELECT BlogPost FILTER .categories.id = <uuid>$0))";
-    tracing::debug!("To query: {}", q);
-    let count: i64 = client.query_required_single(q, &(id,)).await?;
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn count_blogposts_under_category(
+    id: Uuid,
+    lang: Option<&str>,
+    client: &Client,
+) -> Result<usize, Error> {
+    let mut args: HashMap<&str, ValueOpt> = HashMap::with_capacity(2);
+    args.insert("id", id.into());
+    let mut filter_lines = vec![LIVE_FILTER.to_string(), str!(".categories.id = <uuid>$id")];
+    if let Some(lang) = lang {
+        args.insert("lang", lang.into());
+        filter_lines.push(preferred_translation_filter());
+    }
+    let filter_expr = filter_lines.join(" AND ");
+    let q = format!("SELECT count((SELECT BlogPost FILTER {filter_expr}))");
+    log_query(&q);
+    let count: i64 = client.query_required_single(&q, &args).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_published_uncategorized_blogposts(
     offset: Option<i64>,
     limit: Option<i64>,
@@ -218,21 +338,24 @@ pub async fn get_published_uncategorized_blogposts(
     let fields = MediumBlogPost::fields_as_shape();
     let q = format!("
     SELECT BlogPost {fields}
-    FILTER .is_published = true AND NOT EXISTS .categories ORDER BY .created_at DESC EMPTY FIRST {paging_expr}");
-    debug!("To query: {q}");
+    FILTER {LIVE_FILTER} AND NOT EXISTS .categories ORDER BY {PUBLISHED_ORDER} {paging_expr}");
+    log_query(&q);
     debug!("With args: {args:#?}");
     let posts: Vec<MediumBlogPost> = client.query(&q, &args).await?;
     Ok(posts)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn count_published_uncategorized_posts(client: &Client) -> Result<usize, Error> {
-    let q = "
-    SELECT count((SELECT BlogPost FILTER .is_published = true AND NOT EXISTS .categories))";
-    debug!("To query: {q}");
-    let count: i64 = client.query_required_single(q, &()).await?;
+    let q = format!(
+        "SELECT count((SELECT BlogPost FILTER {LIVE_FILTER} AND NOT EXISTS .categories))"
+    );
+    log_query(&q);
+    let count: i64 = client.query_required_single(&q, &()).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_blog_categories(
     offset: Option<i64>,
     limit: Option<i64>,
@@ -253,27 +376,31 @@ pub async fn get_blog_categories(
         BlogCategory::fields_as_shape(),
         order_by
     );
+    log_query(&q);
     let categories: Vec<BlogCategory> = client.query(&q, &(offset, limit)).await?;
     Ok(categories)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_categories_count(client: &Client) -> Result<usize, Error> {
     let q = "SELECT count(BlogCategory)";
-    debug!("To query: {q}");
+    log_query(q);
     let count: i64 = client.query_required_single(q, &()).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_category(id: Uuid, client: &Client) -> Result<Option<BlogCategory>, Error> {
     let q = format!(
         "SELECT BlogCategory {} FILTER .id = <uuid>$0",
         BlogCategory::fields_as_shape()
     );
-    tracing::debug!("To query: {}", q);
+    log_query(&q);
     let cat: Option<BlogCategory> = client.query_single(&q, &(id,)).await?;
     Ok(cat)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_category_by_slug(
     slug: &str,
     client: &Client,
@@ -282,11 +409,132 @@ pub async fn get_category_by_slug(
         "SELECT BlogCategory {} FILTER .slug = <str>$0",
         BlogCategory::fields_as_shape()
     );
-    tracing::debug!("To query: {}", q);
+    log_query(&q);
     let cat: Option<BlogCategory> = client.query_single(&q, &(slug,)).await?;
     Ok(cat)
 }
 
+/// Get tags of published posts, most popular first. Tags are grouped from `seo_keywords`.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_tags(client: &Client) -> Result<Vec<Tag>, Error> {
+    let q = format!(
+        "WITH published := (SELECT BlogPost FILTER {LIVE_FILTER})
+    FOR kw IN DISTINCT published.seo_keywords
+    UNION (kw, array_agg((SELECT published FILTER kw IN .seo_keywords).id))"
+    );
+    log_query(&q);
+    let keyword_posts: Vec<(String, Vec<Uuid>)> = client.query(&q, &()).await?;
+    Ok(Tag::group_keywords(keyword_posts))
+}
+
+/// Get a tag of published posts. Only the keywords having this slug are counted.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_tag_by_slug(slug: &str, client: &Client) -> Result<Option<Tag>, Error> {
+    let q = format!("SELECT DISTINCT (SELECT BlogPost FILTER {LIVE_FILTER}).seo_keywords");
+    log_query(&q);
+    let keywords: Vec<String> = client.query(&q, &()).await?;
+    let keywords: Vec<String> = keywords
+        .into_iter()
+        .filter(|k| slugify(k.trim()) == slug)
+        .collect();
+    if keywords.is_empty() {
+        return Ok(None);
+    }
+    let q = format!(
+        "WITH published := (SELECT BlogPost FILTER {LIVE_FILTER})
+    FOR kw IN array_unpack(<array<str>>$0)
+    UNION (kw, array_agg((SELECT published FILTER kw IN .seo_keywords).id))"
+    );
+    log_query(&q);
+    let keyword_posts: Vec<(String, Vec<Uuid>)> = client.query(&q, &(keywords,)).await?;
+    Ok(Tag::group_keywords(keyword_posts).into_iter().next())
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_published_posts_by_keywords(
+    keywords: &[String],
+    offset: Option<i64>,
+    limit: Option<i64>,
+    lang: Option<&str>,
+    client: &Client,
+) -> Result<Vec<MediumBlogPost>, Error> {
+    let mut args: HashMap<&str, ValueOpt> = HashMap::with_capacity(4);
+    let mut filter_lines = vec![
+        LIVE_FILTER.to_string(),
+        str!("any(.seo_keywords IN array_unpack(<array<str>>$keywords))"),
+    ];
+    let mut paging_lines: Vec<String> = Vec::with_capacity(2);
+    let keywords: Vec<&str> = keywords.iter().map(String::as_str).collect();
+    args.insert("keywords", keywords.into());
+    if let Some(lang) = lang {
+        args.insert("lang", lang.into());
+        filter_lines.push(preferred_translation_filter());
+    }
+    if let Some(offset) = offset {
+        args.insert("offset", offset.into());
+        paging_lines.push(str!("OFFSET <int64>$offset"));
+    }
+    if let Some(limit) = limit {
+        args.insert("limit", limit.into());
+        paging_lines.push(str!("LIMIT <int64>$limit"));
+    }
+    let filter_expr = filter_lines.join(" AND ");
+    let paging_expr = paging_lines.join(" ");
+    let fields = MediumBlogPost::fields_as_shape();
+    let q = format!(
+        "SELECT BlogPost {fields}
+        FILTER {filter_expr}
+        ORDER BY {PUBLISHED_ORDER} {paging_expr}"
+    );
+    log_query(&q);
+    debug!("With args: {args:#?}");
+    let posts: Vec<MediumBlogPost> = client.query(&q, &args).await?;
+    Ok(posts)
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn count_published_posts_by_keywords(
+    keywords: &[String],
+    lang: Option<&str>,
+    client: &Client,
+) -> Result<usize, Error> {
+    let mut args: HashMap<&str, ValueOpt> = HashMap::with_capacity(2);
+    let mut filter_lines = vec![
+        LIVE_FILTER.to_string(),
+        str!("any(.seo_keywords IN array_unpack(<array<str>>$keywords))"),
+    ];
+    let keywords: Vec<&str> = keywords.iter().map(String::as_str).collect();
+    args.insert("keywords", keywords.into());
+    if let Some(lang) = lang {
+        args.insert("lang", lang.into());
+        filter_lines.push(preferred_translation_filter());
+    }
+    let filter_expr = filter_lines.join(" AND ");
+    let q = format!("SELECT count((SELECT BlogPost FILTER {filter_expr}))");
+    log_query(&q);
+    let count: i64 = client.query_required_single(&q, &args).await?;
+    Ok(count.try_into().unwrap_or(0))
+}
+
+/// Replace the keywords in `from` with `to`, in all posts. Used to rename or merge tags.
+/// Return the number of affected posts.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn rename_keywords(from: &[String], to: &str, client: &Client) -> Result<usize, Error> {
+    let q = "WITH old_keywords := array_unpack(<array<str>>$0)
+    SELECT count((
+        UPDATE BlogPost FILTER any(.seo_keywords IN old_keywords)
+        SET {
+            seo_keywords := DISTINCT ((SELECT .seo_keywords FILTER .seo_keywords NOT IN old_keywords) UNION <str>$1)
+        }
+    ))";
+    log_query(q);
+    let count: i64 = client
+        .query_required_single(q, &(from.to_vec(), to))
+        .await?;
+    Ok(count.try_into().unwrap_or(0))
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_previous_post(
     created_at: EDatetime,
     cat_slug: Option<&str>,
@@ -294,7 +542,7 @@ pub async fn get_previous_post(
 ) -> Result<Option<MiniBlogPost>, Error> {
     let mut filter_lines = vec![
         ".created_at < <datetime>$created_at",
-        ".is_published = true",
+        LIVE_FILTER,
     ];
     let mut args = named_args! {
         "created_at" => created_at
@@ -308,11 +556,12 @@ pub async fn get_previous_post(
 
     let q =
         format!("SELECT BlogPost {fields} FILTER {filter_expr} ORDER BY .created_at DESC LIMIT 1");
-    debug!("To query: {q}");
+    log_query(q);
     let post: Option<MiniBlogPost> = client.query_single(&q, &args).await?;
     Ok(post)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_next_post(
     created_at: EDatetime,
     cat_slug: Option<&str>,
@@ -320,7 +569,7 @@ pub async fn get_next_post(
 ) -> Result<Option<MiniBlogPost>, Error> {
     let mut filter_lines = vec![
         ".created_at > <datetime>$created_at",
-        ".is_published = true",
+        LIVE_FILTER,
     ];
     let mut args = named_args! {
         "created_at" => created_at
@@ -334,44 +583,92 @@ pub async fn get_next_post(
     let fields = MiniBlogPost::fields_as_shape();
     let q =
         format!("SELECT BlogPost {fields} FILTER {filter_expr} ORDER BY .created_at ASC LIMIT 1");
-    tracing::debug!("To query: {}", q);
+    log_query(q);
     let post: Option<MiniBlogPost> = client.query_single(&q, &args).await?;
     Ok(post)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_last_updated_post(client: &Client) -> Result<Option<MiniBlogPost>, Error> {
     let q = format!(
-        "SELECT BlogPost {} FILTER .is_published = true ORDER BY .updated_at DESC LIMIT 1",
+        "SELECT BlogPost {} FILTER {LIVE_FILTER}
+        ORDER BY max({{.created_at, .updated_at, .published_at}}) DESC LIMIT 1",
         MiniBlogPost::fields_as_shape()
     );
-    tracing::debug!("To query: {}", q);
+    log_query(&q);
     let post: Option<MiniBlogPost> = client.query_single(&q, &()).await?;
     Ok(post)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_mini_post_by_old_id(
     old_id: u32,
     client: &Client,
 ) -> Result<Option<MiniBlogPost>, Error> {
     let field_names = MiniBlogPost::fields_as_shape();
     let q = format!("SELECT BlogPost {field_names} FILTER .old_id = <int32>$0");
-    tracing::debug!("To query: {}", q);
+    log_query(&q);
     let post: Option<MiniBlogPost> = client.query_single(&q, &(old_id as i32,)).await?;
     Ok(post)
 }
 
-// Get mini data of all blog posts, for generating sitemaps
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_published_mini_post(
+    post_id: Uuid,
+    client: &Client,
+) -> Result<Option<MiniBlogPost>, Error> {
+    let field_names = MiniBlogPost::fields_as_shape();
+    let q = format!(
+        "SELECT BlogPost {field_names} FILTER .id = <uuid>$0 AND {LIVE_FILTER}"
+    );
+    log_query(&q);
+    client.query_single(&q, &(post_id,)).await
+}
+
+/// Get the scheduled posts whose time came in the (`since`, `until`] window.
+/// Posts which were published directly are excluded, because they were already handled when being saved.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_scheduled_posts_gone_live(
+    since: EDatetime,
+    until: EDatetime,
+    client: &Client,
+) -> Result<Vec<MiniBlogPost>, Error> {
+    let fields = MiniBlogPost::fields_as_shape();
+    let q = format!(
+        "SELECT BlogPost {fields}
+        FILTER .is_published = true
+            AND .published_at > <datetime>$0 AND .published_at <= <datetime>$1
+            AND .published_at > (.updated_at ?? .created_at)"
+    );
+    log_query(&q);
+    client.query(&q, &(since, until)).await
+}
+
+/// Get all published posts, with their translations, for generating sitemaps
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_posts_for_sitemap(client: &Client) -> Result<Vec<SitemapBlogPost>, Error> {
+    let fields = SitemapBlogPost::fields_as_shape();
+    let q = format!("SELECT BlogPost {fields} FILTER {LIVE_FILTER} ORDER BY {PUBLISHED_ORDER}");
+    log_query(&q);
+    client.query(&q, &()).await
+}
+
+// Get mini data of all blog posts, for llms.txt
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_published_mini_posts(client: &Client) -> Result<Vec<MiniBlogPost>, Error> {
     let field_names = MiniBlogPost::fields_as_shape();
     let q = format!(
-        "SELECT BlogPost {field_names} FILTER .is_published = true ORDER BY .created_at DESC"
+        "SELECT BlogPost {field_names} FILTER {LIVE_FILTER} ORDER BY {PUBLISHED_ORDER}"
     );
+    log_query(&q);
     client.query(&q, &()).await
 }
 
 /// Get featured categories with their 2 latest posts for home page display
 /// Categories are ordered by featured_order (NULLs last)
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_featured_categories_with_posts(
+    lang: &str,
     client: &Client,
 ) -> Result<Vec<FeaturedCategoryBlock>, Error> {
     // First, get all categories that have a featured_order (exists)
@@ -381,20 +678,27 @@ pub async fn get_featured_categories_with_posts(
          FILTER EXISTS .featured_order
          ORDER BY .featured_order ASC"
     );
+    log_query(&q);
     let categories: Vec<BlogCategory> = client.query(&q, &()).await?;
 
     // For each category, get its 2 latest posts
     let mut result = Vec::with_capacity(categories.len());
     let post_fields = MiniBlogPost::fields_as_shape();
+    let translation_filter = preferred_translation_filter();
 
     for category in categories {
         let q = format!(
             "SELECT BlogPost {post_fields}
-             FILTER .is_published = true AND any(.categories.id = <uuid>$0)
-             ORDER BY .created_at DESC
+             FILTER {LIVE_FILTER} AND {translation_filter} AND any(.categories.id = <uuid>$id)
+             ORDER BY {PUBLISHED_ORDER}
              LIMIT 2"
         );
-        let posts: Vec<MiniBlogPost> = client.query(&q, &(category.id,)).await?;
+        let args = named_args! {
+            "id" => category.id,
+            "lang" => lang
+        };
+        log_query(&q);
+        let posts: Vec<MiniBlogPost> = client.query(&q, &args).await?;
         result.push(FeaturedCategoryBlock {
             category,
             latest_posts: posts,
@@ -405,35 +709,174 @@ pub async fn get_featured_categories_with_posts(
 }
 
 /// Get the 6 latest published posts for home page display
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_latest_posts_for_home(
+    lang: &str,
     client: &Client,
 ) -> Result<Vec<HomePagePost>, Error> {
     let post_fields = HomePagePost::fields_as_shape();
+    let translation_filter = preferred_translation_filter();
     let q = format!(
         "SELECT BlogPost {post_fields}
-         FILTER .is_published = true
-         ORDER BY .created_at DESC
+         FILTER {LIVE_FILTER} AND {translation_filter}
+         ORDER BY {PUBLISHED_ORDER}
          LIMIT 6"
     );
-    client.query(&q, &()).await
+    log_query(&q);
+    client.query(&q, &named_args! { "lang" => lang }).await
 }
 
 /// Get all blog posts for HTML regeneration (including title for reporting)
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_posts_for_regeneration(
     client: &Client,
 ) -> Result<Vec<MinBodyBlogPost>, Error> {
-    let fields = "{id, title, body}";
+    let fields = MinBodyBlogPost::fields_as_shape();
     let q = format!("SELECT BlogPost {fields}");
+    log_query(&q);
     client.query(&q, &()).await
 }
 
-/// Update the HTML field of a blog post
+/// Update the HTML and excerpt fields of a blog post
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn update_post_html(
     client: &Client,
     post_id: Uuid,
     html: &str,
+    excerpt: &str,
 ) -> Result<(), Error> {
-    let q = "UPDATE BlogPost FILTER .id = <uuid>$0 SET { html := <str>$1 }";
-    client.execute(&q, &(post_id, html)).await?;
+    let q = "UPDATE BlogPost FILTER .id = <uuid>$0 SET { html := <str>$1, excerpt := <str>$2 }";
+    log_query(q);
+    client.execute(&q, &(post_id, html, excerpt)).await?;
     Ok(())
 }
+
+/// Get the fields of a post which matter to translation linking
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_post_translation(
+    post_id: Uuid,
+    client: &Client,
+) -> Result<Option<PostTranslation>, Error> {
+    let fields = PostTranslation::FIELDS.join(", ");
+    let q = format!("SELECT BlogPost {{ {fields} }} FILTER .id = <uuid>$0");
+    log_query(&q);
+    client.query_single(&q, &(post_id,)).await
+}
+
+/// Mark two posts as translations of each other. If any of them is already in a translation group,
+/// the groups are merged, so that all their posts are translations of each other.
+/// Return the number of updated posts, which is zero if any post is missing.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn link_translation(
+    post_id: Uuid,
+    other_id: Uuid,
+    client: &Client,
+) -> Result<usize, Error> {
+    let q = "
+    WITH
+        post := (SELECT BlogPost FILTER .id = <uuid>$0),
+        other := (SELECT BlogPost FILTER .id = <uuid>$1),
+        old_groups := {post.translation_group, other.translation_group},
+        group_id := other.translation_group ?? post.translation_group ?? uuid_generate_v4(),
+    SELECT count((
+        UPDATE BlogPost
+        FILTER (.id IN {post.id, other.id} OR .translation_group IN old_groups)
+            AND EXISTS post AND EXISTS other
+        SET { translation_group := group_id }
+    ))";
+    log_query(q);
+    let count: i64 = client
+        .query_required_single(q, &(post_id, other_id))
+        .await?;
+    Ok(count.try_into().unwrap_or(0))
+}
+
+/// Take a post out of its translation group
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn unlink_translation(
+    post_id: Uuid,
+    client: &Client,
+) -> Result<Option<MinimalObject>, Error> {
+    let q = "UPDATE BlogPost FILTER .id = <uuid>$0 SET { translation_group := {} }";
+    log_query(q);
+    client.query_single(q, &(post_id,)).await
+}
+
+/// Get the format of a blog post, to render its new body
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_post_format(post_id: Uuid, client: &Client) -> Result<Option<DocFormat>, Error> {
+    let q = "SELECT (SELECT BlogPost FILTER .id = <uuid>$0).format";
+    log_query(q);
+    client.query_single(q, &(post_id,)).await
+}
+
+/// Tell if the user is the author of the blog post
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn is_post_author(post_id: Uuid, user_id: Uuid, client: &Client) -> Result<bool, Error> {
+    let q = "SELECT EXISTS (SELECT BlogPost FILTER .id = <uuid>$0 AND .author.id = <uuid>$1)";
+    log_query(q);
+    client.query_required_single(q, &(post_id, user_id)).await
+}
+
+/// Get one blog post for HTML regeneration
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_post_for_regeneration(
+    post_id: Uuid,
+    client: &Client,
+) -> Result<Option<MinBodyBlogPost>, Error> {
+    let fields = MinBodyBlogPost::fields_as_shape();
+    let q = format!("SELECT BlogPost {fields} FILTER .id = <uuid>$0");
+    log_query(&q);
+    client.query_single(&q, &(post_id,)).await
+}
+
+/// Get blog posts with the source texts for the search index, either all or those under a category
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_posts_for_search_index(
+    cat_id: Option<Uuid>,
+    client: &Client,
+) -> Result<Vec<SearchSourceBlogPost>, Error> {
+    let fields = SearchSourceBlogPost::fields_as_shape();
+    let q = format!(
+        "SELECT BlogPost {fields}
+        FILTER (<optional uuid>$0 IN .categories.id) ?? true"
+    );
+    log_query(&q);
+    client.query(&q, &(cat_id,)).await
+}
+
+/// Count blog posts whose search fields have never been filled, like those created before the fields existed
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn count_unindexed_posts(client: &Client) -> Result<usize, Error> {
+    let q = "SELECT count((SELECT BlogPost FILTER (.search_title ?? '') = ''))";
+    log_query(q);
+    let count: i64 = client.query_required_single(q, &()).await?;
+    Ok(count.try_into().unwrap_or(0))
+}
+
+/// Update the folded text fields which are used by full-text search
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn update_post_search_fields(
+    client: &Client,
+    post: &SearchSourceBlogPost,
+) -> Result<(), Error> {
+    let (search_title, search_text) = post.make_search_fields();
+    let q = "UPDATE BlogPost FILTER .id = <uuid>$0 SET { search_title := <str>$1, search_text := <str>$2 }";
+    log_query(q);
+    client
+        .execute(q, &(post.id, search_title, search_text))
+        .await
+}
+
+/// Rebuild search fields of one post, after its content is changed
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn refresh_post_search_fields(post_id: Uuid, client: &Client) -> Result<(), Error> {
+    let fields = SearchSourceBlogPost::fields_as_shape();
+    let q = format!("SELECT BlogPost {fields} FILTER .id = <uuid>$0");
+    log_query(&q);
+    let post: Option<SearchSourceBlogPost> = client.query_single(&q, &(post_id,)).await?;
+    match post {
+        Some(post) => update_post_search_fields(client, &post).await,
+        None => Ok(()),
+    }
+}
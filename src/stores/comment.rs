@@ -0,0 +1,139 @@
+use gel_protocol::named_args;
+use gel_tokio::{Client, Error};
+use tracing::{field::Empty, instrument};
+use uuid::Uuid;
+
+use super::log_query;
+use crate::models::{Comment, CommentStatus, MinimalObject, PublicComment};
+use crate::types::EdgeSelectable;
+
+/// Data of a comment submitted by a reader. The HTML is rendered and sanitized by the caller.
+pub struct NewComment<'a> {
+    pub post_id: Uuid,
+    pub author_name: &'a str,
+    pub author_email: &'a str,
+    pub body: &'a str,
+    pub html: &'a str,
+    pub ip_address: Option<&'a str>,
+}
+
+/// Get the approved comments of a post, oldest first
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_approved_comments(
+    post_id: Uuid,
+    client: &Client,
+) -> Result<Vec<PublicComment>, Error> {
+    let fields = PublicComment::fields_as_shape();
+    let q = format!(
+        "SELECT Comment {fields}
+        FILTER .post.id = <uuid>$0 AND .status = CommentStatus.Approved
+        ORDER BY .created_at ASC"
+    );
+    log_query(&q);
+    client.query(&q, &(post_id,)).await
+}
+
+/// Create a pending comment. Return `None` if the post doesn't exist or is not published.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn create_comment(
+    data: &NewComment<'_>,
+    client: &Client,
+) -> Result<Option<MinimalObject>, Error> {
+    let q = "
+    WITH post := (SELECT BlogPost FILTER .id = <uuid>$0 AND .is_published = true)
+    SELECT (
+        FOR p IN post UNION (
+            INSERT Comment {
+                post := p,
+                author_name := <str>$1,
+                author_email := <str>$2,
+                body := <str>$3,
+                html := <str>$4,
+                ip_address := <optional str>$5,
+            }
+        )
+    ) { id } LIMIT 1";
+    log_query(q);
+    let args = (
+        data.post_id,
+        data.author_name,
+        data.author_email,
+        data.body,
+        data.html,
+        data.ip_address,
+    );
+    client.query_single(q, &args).await
+}
+
+/// Get comments for moderation, newest first, optionally filtered by status
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_comments(
+    status: Option<CommentStatus>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+    client: &Client,
+) -> Result<Vec<Comment>, Error> {
+    let fields = Comment::fields_as_shape();
+    let q = format!(
+        "SELECT Comment {fields}
+        FILTER (.status = <optional CommentStatus>$status) ?? true
+        ORDER BY .created_at DESC
+        OFFSET <optional int64>$offset LIMIT <optional int64>$limit"
+    );
+    log_query(&q);
+    // Enum values cannot be passed in tuple, so we use named arguments
+    let args = named_args! {
+        "status" => status,
+        "offset" => offset,
+        "limit" => limit
+    };
+    client.query(&q, &args).await
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn count_comments(
+    status: Option<CommentStatus>,
+    client: &Client,
+) -> Result<usize, Error> {
+    let q = "SELECT count(Comment FILTER (.status = <optional CommentStatus>$status) ?? true)";
+    let args = named_args! { "status" => status };
+    log_query(q);
+    let count: i64 = client.query_required_single(q, &args).await?;
+    Ok(count.try_into().unwrap_or(0))
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_comment(id: Uuid, client: &Client) -> Result<Option<Comment>, Error> {
+    let fields = Comment::fields_as_shape();
+    let q = format!("SELECT Comment {fields} FILTER .id = <uuid>$0");
+    log_query(&q);
+    client.query_single(&q, &(id,)).await
+}
+
+/// Approve or reject a comment
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn update_comment_status(
+    id: Uuid,
+    status: CommentStatus,
+    client: &Client,
+) -> Result<Option<Comment>, Error> {
+    let fields = Comment::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            UPDATE Comment FILTER .id = <uuid>$id SET {{ status := <CommentStatus>$status }}
+        ) {fields}"
+    );
+    log_query(&q);
+    let args = named_args! {
+        "id" => id,
+        "status" => status
+    };
+    client.query_single(&q, &args).await
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn delete_comment(id: Uuid, client: &Client) -> Result<Option<MinimalObject>, Error> {
+    let q = "DELETE Comment FILTER .id = <uuid>$0";
+    log_query(q);
+    client.query_single(q, &(id,)).await
+}
@@ -0,0 +1,30 @@
+use gel_tokio::{Client, Error};
+use tracing::{field::Empty, instrument};
+
+use super::log_query;
+
+/// Record that the variants of the image at this path are stored
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn record_stored_image(path: &str, client: &Client) -> Result<(), Error> {
+    let q = "INSERT StoredImage { path := <str>$0 } UNLESS CONFLICT ON .path";
+    log_query(q);
+    client.execute(q, &(path,)).await
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn forget_stored_image(path: &str, client: &Client) -> Result<(), Error> {
+    let q = "DELETE StoredImage FILTER .path = <str>$0";
+    log_query(q);
+    client.execute(q, &(path,)).await
+}
+
+/// Among the given paths, get the ones of the images whose variants are stored
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_stored_image_paths(
+    paths: Vec<String>,
+    client: &Client,
+) -> Result<Vec<String>, Error> {
+    let q = "SELECT StoredImage.path FILTER StoredImage.path IN array_unpack(<array<str>>$0)";
+    log_query(q);
+    client.query(q, &(paths,)).await
+}
@@ -1,8 +1,11 @@
 use gel_tokio::{Client, Error};
+use tracing::{field::Empty, instrument};
 use uuid::Uuid;
 
+use super::log_query;
 use crate::models::minors::{Book, BookAuthor, Presentation};
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_talks(client: &Client) -> Result<Vec<Presentation>, Error> {
     let q = "
     SELECT Presentation {
@@ -11,9 +14,11 @@ pub async fn get_all_talks(client: &Client) -> Result<Vec<Presentation>, Error>
         url,
         event,
     }";
+    log_query(q);
     client.query(q, &()).await
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_books(client: &Client) -> Result<Vec<Book>, Error> {
     let q = "
     SELECT Book {
@@ -25,9 +30,11 @@ pub async fn get_all_books(client: &Client) -> Result<Vec<Book>, Error> {
             name,
         }
     }";
+    log_query(q);
     client.query(q, &()).await
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_presentations(
     offset: Option<i64>,
     limit: Option<i64>,
@@ -41,22 +48,28 @@ pub async fn get_presentations(
         event,
     }
     ORDER BY .title DESC EMPTY FIRST OFFSET <optional int64>$0 LIMIT <optional int64>$1";
+    log_query(q);
     let presentations: Vec<Presentation> = client.query(q, &(offset, limit)).await?;
     Ok(presentations)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_presentations_count(client: &Client) -> Result<u16, Error> {
     let q = "SELECT count(Presentation)";
+    log_query(q);
     let count: i64 = client.query_required_single(q, &()).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_presentation(id: Uuid, client: &Client) -> Result<Option<Presentation>, Error> {
     let q = "SELECT Presentation { id, title, url, event } FILTER .id = <uuid>$0";
+    log_query(q);
     let object = client.query_single(q, &(id,)).await?;
     Ok(object)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_book_authors(
     offset: Option<i64>,
     limit: Option<i64>,
@@ -68,21 +81,27 @@ pub async fn get_book_authors(
         name,
     }
     ORDER BY .name ASC EMPTY FIRST OFFSET <optional int64>$0 LIMIT <optional int64>$1";
+    log_query(q);
     client.query(q, &(offset, limit)).await
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_book_authors_count(client: &Client) -> Result<usize, Error> {
     let q = "SELECT count(BookAuthor)";
+    log_query(q);
     let count: i64 = client.query_required_single(q, &()).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_book_author(id: Uuid, client: &Client) -> Result<Option<BookAuthor>, Error> {
     let q = "SELECT BookAuthor { id, name } FILTER .id = <uuid>$0";
+    log_query(q);
     let object = client.query_single(q, &(id,)).await?;
     Ok(object)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_books(
     offset: Option<i64>,
     limit: Option<i64>,
@@ -98,16 +117,20 @@ pub async fn get_books(
             name,
         }
     } ORDER BY .title ASC EMPTY FIRST OFFSET <optional int64>$0 LIMIT <optional int64>$1";
+    log_query(q);
     let books: Vec<Book> = client.query(q, &(offset, limit)).await?;
     Ok(books)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_all_books_count(client: &Client) -> Result<usize, Error> {
     let q = "SELECT count(Book)";
+    log_query(q);
     let count: i64 = client.query_required_single(q, &()).await?;
     Ok(count.try_into().unwrap_or(0))
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_book(id: Uuid, client: &Client) -> Result<Option<Book>, Error> {
     let q = "SELECT Book {
         id,
@@ -118,6 +141,7 @@ pub async fn get_book(id: Uuid, client: &Client) -> Result<Option<Book>, Error>
             name,
         }
     } FILTER .id = <uuid>$0";
+    log_query(q);
     let object = client.query_single(q, &(id,)).await?;
     Ok(object)
 }
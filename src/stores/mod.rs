@@ -1,3 +1,45 @@
 pub mod user;
 pub mod blog;
+pub mod comment;
+pub mod image;
 pub mod minors;
+pub mod revision;
+pub mod token;
+
+/// Log the query to run, and attach its text to the span of the store function, for tracing.
+/// The arguments are not attached, they may carry personal data.
+pub fn log_query(q: &str) {
+    tracing::debug!("To query: {q}");
+    tracing::Span::current().record("db.statement", q);
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{field::Empty, instrument};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::utils::telemetry::testing::SpanFields;
+
+    #[instrument(skip_all, fields(db.statement = Empty))]
+    fn find_user(_email: &str) {
+        log_query("SELECT User FILTER .email = <str>$0");
+    }
+
+    #[test]
+    fn test_query_is_recorded_without_arguments() {
+        let fields = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        tracing::subscriber::with_default(subscriber, || find_user("quan@example.com"));
+        assert_eq!(
+            fields.get("db.statement").as_deref(),
+            Some("SELECT User FILTER .email = <str>$0")
+        );
+        assert!(
+            fields
+                .values()
+                .iter()
+                .all(|v| !v.contains("quan@example.com"))
+        );
+    }
+}
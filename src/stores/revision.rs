@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use chrono::{TimeDelta, Utc};
+use gel_protocol::model::Datetime as EDatetime;
+use gel_protocol::named_args;
+use gel_protocol::value_opt::ValueOpt;
+use gel_tokio::{Client, Error};
+use tracing::{field::Empty, instrument};
+use uuid::Uuid;
+
+use super::log_query;
+use crate::models::{DetailedBlogPost, MiniPostRevision, MinimalObject, PostRevision};
+use crate::types::{EdgeSelectable, RevisionRetention};
+
+/// Save the current title and body of a post as a revision.
+/// Nothing is saved if they are the same as in the latest revision.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn record_revision(
+    post_id: Uuid,
+    author_id: Option<Uuid>,
+    client: &Client,
+) -> Result<Option<MinimalObject>, Error> {
+    let q = "
+    WITH
+        post := (SELECT BlogPost FILTER .id = <uuid>$0),
+        latest := (SELECT PostRevision FILTER .post = post ORDER BY .created_at DESC LIMIT 1),
+        changed := (
+            SELECT post
+            FILTER NOT EXISTS latest
+                OR .title != latest.title
+                OR (.body ?? '') != (latest.body ?? '')
+        ),
+    SELECT (
+        FOR p IN changed UNION (
+            INSERT PostRevision {
+                post := p,
+                title := p.title,
+                body := p.body,
+                format := p.format,
+                author := (SELECT User FILTER .id = <optional uuid>$1),
+            }
+        )
+    ) { id } LIMIT 1";
+    log_query(q);
+    client.query_single(q, &(post_id, author_id)).await
+}
+
+/// Update a post and save its new title and body as a revision, in one statement,
+/// so that no edit is left without its revision.
+/// A post which was written before revisions were tracked gets its old state saved first,
+/// so that its first edit can be reverted.
+/// The `args` must have the post ID as "id", besides the ones used in `set_clause`.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn update_post_with_revision(
+    set_clause: &str,
+    mut args: HashMap<&str, ValueOpt>,
+    author_id: Option<Uuid>,
+    client: &Client,
+) -> Result<Option<DetailedBlogPost>, Error> {
+    let fields = DetailedBlogPost::fields_as_shape();
+    let q = format!(
+        "
+    WITH
+        post := (SELECT BlogPost FILTER .id = <uuid>$id),
+        baseline := (
+            FOR p IN (SELECT post FILTER NOT EXISTS .revisions) UNION (
+                INSERT PostRevision {{
+                    post := p,
+                    title := p.title,
+                    body := p.body,
+                    format := p.format,
+                    author := p.author,
+                    created_at := p.updated_at ?? p.created_at,
+                }}
+            )
+        ),
+        updated := (
+            UPDATE post
+            SET {{
+                {set_clause}
+            }}
+        ),
+        latest := (SELECT PostRevision FILTER .post = post ORDER BY .created_at DESC LIMIT 1),
+        revision := (
+            FOR p IN (
+                SELECT updated
+                FILTER NOT EXISTS latest
+                    OR .title != latest.title
+                    OR (.body ?? '') != (latest.body ?? '')
+            ) UNION (
+                INSERT PostRevision {{
+                    post := p,
+                    title := p.title,
+                    body := p.body,
+                    format := p.format,
+                    author := (SELECT User FILTER .id = <optional uuid>$revision_author),
+                }}
+            )
+        ),
+    SELECT updated {fields}"
+    );
+    log_query(&q);
+    args.insert("revision_author", author_id.into());
+    client.query_single(&q, &args).await
+}
+
+/// Get revisions of a post, newest first
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_revisions(
+    post_id: Uuid,
+    offset: Option<i64>,
+    limit: Option<i64>,
+    client: &Client,
+) -> Result<Vec<MiniPostRevision>, Error> {
+    let fields = MiniPostRevision::fields_as_shape();
+    let q = format!(
+        "SELECT PostRevision {fields}
+        FILTER .post.id = <uuid>$0
+        ORDER BY .created_at DESC
+        OFFSET <optional int64>$1 LIMIT <optional int64>$2"
+    );
+    log_query(&q);
+    client.query(&q, &(post_id, offset, limit)).await
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn count_revisions(post_id: Uuid, client: &Client) -> Result<usize, Error> {
+    let q = "SELECT count(PostRevision FILTER .post.id = <uuid>$0)";
+    log_query(q);
+    let count: i64 = client.query_required_single(q, &(post_id,)).await?;
+    Ok(count.try_into().unwrap_or(0))
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_revision(
+    post_id: Uuid,
+    revision_id: Uuid,
+    client: &Client,
+) -> Result<Option<PostRevision>, Error> {
+    let fields = PostRevision::fields_as_shape();
+    let q = format!(
+        "SELECT PostRevision {fields}
+        FILTER .id = <uuid>$0 AND .post.id = <uuid>$1"
+    );
+    log_query(&q);
+    client.query_single(&q, &(revision_id, post_id)).await
+}
+
+/// Delete the revisions which are out of the retention policy. The latest one is always kept.
+/// Return the number of deleted revisions.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn prune_revisions(
+    post_id: Uuid,
+    retention: &RevisionRetention,
+    client: &Client,
+) -> Result<usize, Error> {
+    let cutoff = (retention.max_age_days > 0)
+        .then(|| Utc::now() - TimeDelta::days(retention.max_age_days.into()))
+        .and_then(|dt| EDatetime::try_from(dt).ok());
+    let q = "
+    WITH
+        post_revisions := (SELECT PostRevision FILTER .post.id = <uuid>$post_id),
+        recent := (SELECT post_revisions ORDER BY .created_at DESC LIMIT <int64>$kept),
+        latest := (SELECT post_revisions ORDER BY .created_at DESC LIMIT 1),
+    SELECT count((
+        DELETE post_revisions
+        FILTER .id NOT IN latest.id
+            AND (.id NOT IN recent.id OR ((.created_at < <optional datetime>$cutoff) ?? false))
+    ))";
+    log_query(q);
+    let args = named_args! {
+        "post_id" => post_id,
+        "kept" => i64::from(retention.kept),
+        "cutoff" => cutoff
+    };
+    let count: i64 = client.query_required_single(q, &args).await?;
+    Ok(count.try_into().unwrap_or(0))
+}
+
+/// Put the title and body of a revision back to the post. The HTML is rendered by the caller.
+/// The restoration is recorded as a new revision, so it can be reverted, too.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn restore_revision(
+    post_id: Uuid,
+    revision: &PostRevision,
+    html: &str,
+    excerpt: &str,
+    author_id: Option<Uuid>,
+    client: &Client,
+) -> Result<Option<DetailedBlogPost>, Error> {
+    let set_clause = "
+                title := <str>$title,
+                body := <optional str>$body,
+                format := <DocFormat>$format,
+                html := <str>$html,
+                excerpt := <str>$excerpt,";
+    // Enum values cannot be passed in tuple, so we use named arguments
+    let args = named_args! {
+        "id" => post_id,
+        "title" => revision.title.clone(),
+        "body" => revision.body.clone(),
+        "format" => revision.format.clone(),
+        "html" => html.to_string(),
+        "excerpt" => excerpt.to_string()
+    };
+    update_post_with_revision(set_clause, args, author_id, client).await
+}
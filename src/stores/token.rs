@@ -0,0 +1,85 @@
+use gel_protocol::model::Datetime as EDatetime;
+use gel_protocol::named_args;
+use gel_tokio::{Client, Error};
+use tracing::{field::Empty, instrument};
+use uuid::Uuid;
+
+use super::log_query;
+use crate::models::tokens::{ApiToken, ApiTokenGrant, TokenScope};
+use crate::types::EdgeSelectable;
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn list_tokens(user_id: Uuid, client: &Client) -> Result<Vec<ApiToken>, Error> {
+    let fields = ApiToken::fields_as_shape();
+    let q = format!(
+        "SELECT ApiToken {fields}
+        FILTER .user.id = <uuid>$0
+        ORDER BY .created_at DESC"
+    );
+    log_query(&q);
+    client.query(&q, &(user_id,)).await
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn create_token(
+    user_id: Uuid,
+    name: &str,
+    token_hash: &str,
+    scopes: &[TokenScope],
+    expires_at: Option<EDatetime>,
+    client: &Client,
+) -> Result<ApiToken, Error> {
+    let fields = ApiToken::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            INSERT ApiToken {{
+                user := (SELECT User FILTER .id = <uuid>$user_id),
+                name := <str>$name,
+                token_hash := <str>$token_hash,
+                scopes := <TokenScope>array_unpack(<array<str>>$scopes),
+                expires_at := <optional datetime>$expires_at,
+            }}
+        ) {fields}"
+    );
+    log_query(&q);
+    let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+    let args = named_args! {
+        "user_id" => user_id,
+        "name" => name.to_string(),
+        "token_hash" => token_hash.to_string(),
+        "scopes" => scopes,
+        "expires_at" => expires_at
+    };
+    client.query_required_single(&q, &args).await
+}
+
+/// Delete a token of the user. Return its ID if it existed.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn revoke_token(
+    token_id: Uuid,
+    user_id: Uuid,
+    client: &Client,
+) -> Result<Option<Uuid>, Error> {
+    let q = "SELECT (
+        DELETE ApiToken FILTER .id = <uuid>$0 AND .user.id = <uuid>$1
+    ).id";
+    log_query(q);
+    client.query_single(q, &(token_id, user_id)).await
+}
+
+/// Find the unexpired token of an active user, and mark it as used
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn use_token(token_hash: &str, client: &Client) -> Result<Option<ApiTokenGrant>, Error> {
+    let fields = ApiTokenGrant::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            UPDATE ApiToken
+            FILTER .token_hash = <str>$0
+                AND .user.is_active
+                AND ((.expires_at > datetime_current()) ?? true)
+            SET {{ last_used_at := datetime_current() }}
+        ) {fields}"
+    );
+    log_query(&q);
+    client.query_single(&q, &(token_hash,)).await
+}
@@ -1,16 +1,148 @@
+use super::log_query;
 use crate::models::{users::MiniUser, User};
+use crate::types::EdgeSelectable;
 use gel_tokio::{Client, Error};
-use tracing::debug;
+use tracing::{field::Empty, instrument};
+use uuid::Uuid;
 
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn get_user_by_email(email: &str, client: &Client) -> Result<Option<User>, Error> {
-    let q = "SELECT User {id, username, email, password, is_active, is_superuser} FILTER .email = <str>$0 LIMIT 1";
-    debug!("To query: {q}");
-    let user: Option<User> = client.query_single(q, &(email,)).await?;
+    let fields = User::fields_as_shape();
+    let q = format!("SELECT User {fields} FILTER .email = <str>$0 LIMIT 1");
+    log_query(&q);
+    let user: Option<User> = client.query_single(&q, &(email,)).await?;
     Ok(user)
 }
 
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn get_user(id: Uuid, client: &Client) -> Result<Option<User>, Error> {
+    let fields = User::fields_as_shape();
+    let q = format!("SELECT User {fields} FILTER .id = <uuid>$0");
+    log_query(&q);
+    client.query_single(&q, &(id,)).await
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
 pub async fn list_mini_users(client: &Client) -> Result<Vec<MiniUser>, Error> {
     let q = "SELECT User {id, username, email}";
+    log_query(q);
     let users: Vec<MiniUser> = client.query(q, &()).await?;
     Ok(users)
 }
+
+/// Save the hash of a new password. Other sessions of the user are then invalid.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn set_password(id: Uuid, hashed: &str, client: &Client) -> Result<Option<User>, Error> {
+    let fields = User::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            UPDATE User FILTER .id = <uuid>$0 SET {{ password := <str>$1 }}
+        ) {fields}"
+    );
+    log_query(&q);
+    client.query_single(&q, &(id, hashed)).await
+}
+
+/// Deactivated users can no longer log in, but their posts are kept.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn deactivate_user(id: Uuid, client: &Client) -> Result<Option<User>, Error> {
+    let fields = User::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            UPDATE User FILTER .id = <uuid>$0 SET {{ is_active := false }}
+        ) {fields}"
+    );
+    log_query(&q);
+    client.query_single(&q, &(id,)).await
+}
+
+/// Turn on two-factor authentication, with the hashes of new recovery codes.
+/// `step` is the time step of the code which confirmed the secret.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn enable_totp(
+    id: Uuid,
+    secret: &str,
+    step: i64,
+    code_hashes: &[String],
+    client: &Client,
+) -> Result<Option<User>, Error> {
+    let fields = User::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            UPDATE User FILTER .id = <uuid>$0
+            SET {{
+                totp_secret := <str>$1,
+                totp_last_step := <int64>$2,
+                totp_recovery_codes := array_unpack(<array<str>>$3),
+            }}
+        ) {fields}"
+    );
+    log_query(&q);
+    client
+        .query_single(&q, &(id, secret, step, code_hashes.to_vec()))
+        .await
+}
+
+/// Record the time step of an accepted TOTP code.
+/// Return `false` if a code of the same or a later step was accepted before, i.e. the code is replayed.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn use_totp_step(id: Uuid, step: i64, client: &Client) -> Result<bool, Error> {
+    let q = "SELECT (
+        UPDATE User FILTER .id = <uuid>$0 AND (.totp_last_step ?? -1) < <int64>$1
+        SET { totp_last_step := <int64>$1 }
+    ).id";
+    log_query(q);
+    let used: Option<Uuid> = client.query_single(q, &(id, step)).await?;
+    Ok(used.is_some())
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn disable_totp(id: Uuid, client: &Client) -> Result<Option<User>, Error> {
+    let fields = User::fields_as_shape();
+    let q = format!(
+        "SELECT (
+            UPDATE User FILTER .id = <uuid>$0
+            SET {{
+                totp_secret := {{}},
+                totp_recovery_codes := {{}},
+            }}
+        ) {fields}"
+    );
+    log_query(&q);
+    client.query_single(&q, &(id,)).await
+}
+
+/// Replace the recovery codes. The old ones can no longer be used.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn set_recovery_codes(
+    id: Uuid,
+    code_hashes: &[String],
+    client: &Client,
+) -> Result<Option<Uuid>, Error> {
+    let q = "SELECT (
+        UPDATE User FILTER .id = <uuid>$0
+        SET { totp_recovery_codes := array_unpack(<array<str>>$1) }
+    ).id";
+    log_query(q);
+    client.query_single(q, &(id, code_hashes.to_vec())).await
+}
+
+/// Use up a recovery code. Return `false` if the user doesn't have it.
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn use_recovery_code(id: Uuid, code_hash: &str, client: &Client) -> Result<bool, Error> {
+    let q = "SELECT (
+        UPDATE User FILTER .id = <uuid>$0 AND <str>$1 IN .totp_recovery_codes
+        SET { totp_recovery_codes -= <str>$1 }
+    ).id";
+    log_query(q);
+    let used: Option<Uuid> = client.query_single(q, &(id, code_hash)).await?;
+    Ok(used.is_some())
+}
+
+#[instrument(skip_all, fields(db.statement = Empty))]
+pub async fn count_recovery_codes(id: Uuid, client: &Client) -> Result<usize, Error> {
+    let q = "SELECT count((SELECT User FILTER .id = <uuid>$0).totp_recovery_codes)";
+    log_query(q);
+    let count: i64 = client.query_required_single(q, &(id,)).await?;
+    Ok(count.try_into().unwrap_or(0))
+}
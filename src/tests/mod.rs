@@ -5,7 +5,3 @@
 
 #[cfg(test)]
 pub mod test_files_api;
-
-// Re-export test utilities for use in other test modules
-#[cfg(test)]
-pub use test_files_api::*;
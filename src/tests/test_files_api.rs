@@ -2,8 +2,25 @@
 //!
 //! These tests verify the data structures and transformations used by the files API.
 
-use crate::api::files::views::{BunnyApiResponse, FileResponse};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use crate::api::errors::ApiError;
+use crate::api::files::views::{FileResponse, store_file};
+use crate::storage::bunny::{BunnyApiResponse, BunnyStorage, STORAGE_ZONE_NAME};
+use crate::storage::local::LocalStorage;
+use crate::storage::{FileStorage, StorageBackend, StorageError};
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::State,
+    http::{Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
 use chrono::{DateTime, Utc};
+use futures_util::stream;
+use tokio::net::TcpListener;
 
 /// Test deserializing Bunny API response from actual JSON sample file
 #[test]
@@ -22,7 +39,7 @@ fn test_bunny_api_response_from_json_file() {
     assert_eq!(dir.object_name, "2026");
     assert_eq!(dir.length, 0);
     assert_eq!(dir.last_changed, "2026-02-22T06:46:45.179");
-    assert_eq!(dir.is_directory, true);
+    assert!(dir.is_directory);
     assert_eq!(dir.server_id, 0);
     assert_eq!(dir.user_id, "e2bf15ba-704c-4dc6-92f7-a6e80a39fbd6");
     assert_eq!(dir.date_created, "2026-02-22T06:46:45.179");
@@ -31,7 +48,7 @@ fn test_bunny_api_response_from_json_file() {
     // Check second item (directory)
     let dir2 = &responses[1];
     assert_eq!(dir2.object_name, "imgur");
-    assert_eq!(dir2.is_directory, true);
+    assert!(dir2.is_directory);
 }
 
 /// Sample Bunny API response data for mocking
@@ -86,7 +103,7 @@ fn test_bunny_api_response_serialization() {
 fn test_file_response_structure() {
     let response = FileResponse {
         name: "test.svg".to_string(),
-        path: "/images/".to_string(),
+        dir_path: "/images/".to_string(),
         size: 2048,
         created_at: Some(
             DateTime::parse_from_rfc3339("2024-01-10T08:00:00Z")
@@ -105,7 +122,7 @@ fn test_file_response_structure() {
     let json = serde_json::to_value(&response).expect("Should serialize");
 
     assert_eq!(json["name"], "test.svg");
-    assert_eq!(json["path"], "/images/");
+    assert_eq!(json["dir_path"], "/images/");
     assert_eq!(json["size"], 2048);
     assert_eq!(json["is_directory"], false);
     assert_eq!(
@@ -126,7 +143,7 @@ fn test_file_response_structure() {
 fn test_directory_response_has_no_direct_url() {
     let response = FileResponse {
         name: "subfolder".to_string(),
-        path: "/".to_string(),
+        dir_path: "/".to_string(),
         size: 0,
         created_at: None,
         modified_at: None,
@@ -223,3 +240,255 @@ fn test_directory_has_no_cdn_url() {
 
     assert_eq!(direct_url, None);
 }
+
+/// Files kept by the mock Bunny storage, keyed by their path in the storage zone
+type MockFiles = Arc<Mutex<BTreeMap<String, Bytes>>>;
+
+/// Mimic Bunny Storage API, for the operations we use
+async fn mock_bunny(
+    State(files): State<MockFiles>,
+    method: Method,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    let zone_prefix = format!("/{STORAGE_ZONE_NAME}/");
+    let Some(path) = uri.path().strip_prefix(&zone_prefix) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut files = files.lock().unwrap();
+    match method {
+        Method::GET if path.is_empty() || path.ends_with('/') => {
+            let items: Vec<BunnyApiResponse> = files
+                .iter()
+                .filter_map(|(key, content)| {
+                    let name = key.strip_prefix(path).filter(|n| !n.contains('/'))?;
+                    Some(BunnyApiResponse {
+                        guid: "test".to_string(),
+                        storage_zone_name: STORAGE_ZONE_NAME.to_string(),
+                        path: format!("/{STORAGE_ZONE_NAME}/{path}"),
+                        object_name: name.to_string(),
+                        length: content.len() as i64,
+                        last_changed: "2026-02-22T06:46:45.179".to_string(),
+                        is_directory: false,
+                        server_id: 0,
+                        user_id: "user".to_string(),
+                        date_created: "2026-02-22T06:46:45.179".to_string(),
+                        storage_zone_id: 1,
+                    })
+                })
+                .collect();
+            Json(items).into_response()
+        }
+        Method::GET => match files.get(path) {
+            Some(content) => content.clone().into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+        Method::PUT => {
+            files.insert(path.to_string(), body);
+            StatusCode::CREATED.into_response()
+        }
+        Method::DELETE => match files.remove(path) {
+            Some(_) => StatusCode::OK.into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+        _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+    }
+}
+
+async fn start_mock_bunny() -> BunnyStorage {
+    let app = Router::new()
+        .fallback(mock_bunny)
+        .with_state(MockFiles::default());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    BunnyStorage::new(
+        format!("http://{addr}"),
+        "test".to_string(),
+        "quan-images.b-cdn.net".to_string(),
+    )
+}
+
+/// A fresh local storage in the temporary directory
+async fn make_local_storage(test_name: &str) -> LocalStorage {
+    let root = std::env::temp_dir().join(format!("quanweb-{test_name}-{}", std::process::id()));
+    tokio::fs::remove_dir_all(&root).await.ok();
+    LocalStorage::new(root)
+}
+
+/// The same test is run against every backend
+async fn all_backends(test_name: &str) -> [StorageBackend; 2] {
+    [
+        StorageBackend::Bunny(start_mock_bunny().await),
+        StorageBackend::Local(make_local_storage(test_name).await),
+    ]
+}
+
+async fn read_back(storage: &StorageBackend, path: &str) -> Vec<u8> {
+    match storage {
+        StorageBackend::Bunny(s) => {
+            let url = format!("{}/{}/{}", s.base_url, s.zone, path);
+            let response = reqwest::get(url).await.unwrap().error_for_status().unwrap();
+            response.bytes().await.unwrap().to_vec()
+        }
+        StorageBackend::Local(s) => tokio::fs::read(s.root.join(path)).await.unwrap(),
+    }
+}
+
+/// Feed the content in small chunks, like a request body
+fn chunked(content: &'static [u8]) -> impl futures_util::Stream<Item = Result<Bytes, Infallible>> {
+    stream::iter(content.chunks(512).map(|c| Ok(Bytes::from_static(c))))
+}
+
+#[tokio::test]
+async fn test_public_urls() {
+    let bunny = BunnyStorage::new(
+        "https://sg.storage.bunnycdn.com".to_string(),
+        "test".to_string(),
+        "quan-images.b-cdn.net".to_string(),
+    );
+    let local = make_local_storage("public-urls").await;
+
+    assert_eq!(
+        bunny.public_url("blogs/photo.jpg"),
+        "https://quan-images.b-cdn.net/quan-images/blogs/photo.jpg"
+    );
+    assert_eq!(
+        local.public_url("/blogs/photo.jpg"),
+        "/media/blogs/photo.jpg"
+    );
+}
+
+#[tokio::test]
+async fn test_upload_and_read_back() {
+    let content = include_bytes!("sample/org.gnome.Meld.svg");
+    for storage in all_backends("upload").await {
+        let file = store_file(
+            &storage,
+            "/blogs/2026",
+            "org.gnome.Meld.svg",
+            chunked(content),
+            1 << 20,
+        )
+        .await
+        .expect("Should upload");
+
+        assert!(file.name.starts_with("org-gnome-meld-"));
+        assert!(file.name.ends_with(".svg"));
+        assert_eq!(file.dir_path, "/blogs/2026/");
+        assert_eq!(file.size, content.len() as i64);
+        assert!(!file.is_directory);
+        let path = format!("blogs/2026/{}", file.name);
+        assert_eq!(file.direct_url, Some(storage.public_url(&path)));
+        let stored = read_back(&storage, &path).await;
+        assert_eq!(stored, content);
+    }
+}
+
+#[tokio::test]
+async fn test_upload_never_overwrites() {
+    let content = include_bytes!("sample/org.gnome.Devhelp.svg");
+    for storage in all_backends("collision").await {
+        let uploads = (0..3).map(|_| {
+            store_file(
+                &storage,
+                "icons",
+                "org.gnome.Devhelp.SVG",
+                chunked(content),
+                1 << 20,
+            )
+        });
+        // Uploads of the same name at the same time must not replace each other
+        let files = futures_util::future::try_join_all(uploads)
+            .await
+            .expect("Should upload");
+
+        let names: BTreeSet<String> = files.into_iter().map(|f| f.name).collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().all(|n| n.starts_with("org-gnome-devhelp-")));
+        assert_eq!(storage.list("icons").await.unwrap().len(), 3);
+    }
+}
+
+#[tokio::test]
+async fn test_upload_rejects_large_file() {
+    let content = include_bytes!("sample/org.gnome.Characters.svg");
+    for storage in all_backends("large-file").await {
+        let result = store_file(
+            &storage,
+            "icons",
+            "org.gnome.Characters.svg",
+            chunked(content),
+            1024,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ApiError::PayloadTooLarge(1024))));
+        assert!(storage.list("icons").await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_upload_rejects_non_image() {
+    for storage in all_backends("non-image").await {
+        let result = store_file(&storage, "icons", "notes.txt", chunked(b"Hello"), 1024).await;
+
+        assert!(matches!(result, Err(ApiError::UnsupportedMediaType(_))));
+        assert!(storage.list("icons").await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_upload_rejects_parent_dir() {
+    for storage in all_backends("parent-dir").await {
+        let result = store_file(&storage, "../other-zone", "a.png", chunked(b"PNG"), 1024).await;
+
+        assert!(matches!(
+            result,
+            Err(ApiError::Storage(StorageError::InvalidPath(_)))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_list_stat_and_delete() {
+    let samples: [(&str, &'static [u8]); 2] = [
+        (
+            "org.gnome.Extensions.svg",
+            include_bytes!("sample/org.gnome.Extensions.svg"),
+        ),
+        (
+            "org.gnome.Meld.svg",
+            include_bytes!("sample/org.gnome.Meld.svg"),
+        ),
+    ];
+    for storage in all_backends("list-delete").await {
+        let mut uploaded = Vec::new();
+        for (name, content) in samples {
+            let file = store_file(&storage, "icons", name, chunked(content), 1 << 20)
+                .await
+                .expect("Should upload");
+            uploaded.push(file.name);
+        }
+        let meld_path = format!("icons/{}", uploaded[1]);
+
+        let files: Vec<FileResponse> = storage
+            .list("icons/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|f| FileResponse::from_stored(f, &storage))
+            .collect();
+        let names: Vec<&String> = files.iter().map(|f| &f.name).collect();
+        assert_eq!(names, [&uploaded[0], &uploaded[1]]);
+        assert!(files.iter().all(|f| f.dir_path == "/icons/"));
+        assert_eq!(files[1].direct_url, Some(storage.public_url(&meld_path)));
+
+        let found = storage.stat(&meld_path).await.unwrap();
+        assert_eq!(found.map(|f| f.size), Some(samples[1].1.len() as u64));
+
+        storage.delete(&meld_path).await.unwrap();
+        assert!(storage.stat(&meld_path).await.unwrap().is_none());
+        assert_eq!(storage.list("icons").await.unwrap().len(), 1);
+    }
+}
@@ -3,16 +3,23 @@ use std::str::FromStr;
 use std::{env, io, net::Ipv4Addr, path::Path};
 
 use clap::Parser;
+use config::Config;
 use fluent_templates::static_loader;
 use minijinja::Environment;
 use tracing_subscriber::{
+    Layer,
     filter::{EnvFilter, LevelFilter},
     layer::SubscriberExt,
     util::SubscriberInitExt,
 };
 
-use crate::conf::DEFAULT_PORT;
-use crate::utils::jinja_extra;
+use crate::conf::{self, DEFAULT_PORT};
+use crate::models::feeds::DEFAULT_SITE_URL;
+use crate::storage::{FileStorage, StorageBackend};
+use crate::types::HighlightMode;
+use crate::utils::metrics::{GelQueryLayer, StoreSpanFilter};
+use crate::utils::telemetry::{ENV_OTLP_ENDPOINT, otlp_layer};
+use crate::utils::{images, jinja_extra, markdown};
 use crate::{consts::UNCATEGORIZED_URL, types::BindingAddr};
 
 // Constant for unix socket prefix
@@ -37,11 +44,23 @@ pub enum Commands {
             help = "Network address to bind, can be <port>, <ip:port>, or Unix socket with 'unix:' prefix like 'unix:/path/to/file'"
         )]
         bind: Option<String>,
+        #[arg(
+            long,
+            help = "Serve the Prometheus metrics on a separate address, in the same format as --bind"
+        )]
+        metrics_bind: Option<String>,
     },
-    /// Regenerate HTML body for blog posts (client-side highlighting)
+    /// Regenerate HTML body and excerpt for blog posts, according to their format
     RegenerateHtml,
+    /// Rebuild full-text search fields for all blog posts
+    ReindexSearch,
     /// Run the background worker
     Worker,
+    /// Generate resized variants for the images which were uploaded before we had them
+    GenerateImageVariants {
+        #[arg(help = "Directory in the file storage to scan, including sub-directories")]
+        dir: Option<String>,
+    },
 }
 
 /// Test if current process is connected with journald
@@ -73,28 +92,66 @@ pub fn config_logging(app_opt: &AppOptions) {
         .with_default_directive(LevelFilter::WARN.into())
         .parse_lossy(directives);
 
-    let registry = tracing_subscriber::registry().with(filter);
+    // Spans are exported to OpenTelemetry collector if its URL is given, by config or the standard env var.
+    // Logging is not ready yet, so errors are printed.
+    let otlp_endpoint = conf::get_config()
+        .ok()
+        .and_then(|c| conf::get_otlp_endpoint(&c).ok().flatten());
+    let otlp = if otlp_endpoint.is_some() || env::var_os(ENV_OTLP_ENDPOINT).is_some() {
+        otlp_layer(otlp_endpoint)
+            .inspect_err(|e| eprintln!("Failed to set up OTLP exporter: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    // The log filter is only applied to the output layer, so that the spans of store functions
+    // still reach the metrics and OTLP layers when logging at "warn" level.
+    let registry = tracing_subscriber::registry()
+        .with(GelQueryLayer.with_filter(StoreSpanFilter))
+        .with(otlp);
 
     if is_journald_connected() {
         if let Ok(journald_layer) = tracing_journald::layer() {
-            registry.with(journald_layer).init();
+            registry.with(journald_layer.with_filter(filter)).init();
         }
     } else {
-        registry.with(tracing_subscriber::fmt::layer()).init();
+        registry
+            .with(tracing_subscriber::fmt::layer().with_filter(filter))
+            .init();
     }
 }
 
+/// Apply "code_highlighting" config. Must be called before rendering any post or setting up Jinja.
+pub fn config_highlighting(config: &Config) {
+    let mode = conf::get_code_highlighting(config)
+        .inspect_err(|e| tracing::warn!("{e}. Fall back to client-side highlighting."))
+        .unwrap_or_default();
+    tracing::info!("Code highlighting mode: {mode}");
+    markdown::set_highlight_mode(mode);
+}
+
+/// Let the renderers know where our uploaded images are, to use their variants.
+pub fn config_media(storage: &StorageBackend) {
+    images::set_media_url_prefix(storage.public_url(""));
+}
+
 pub fn config_jinja() -> Result<Environment<'static>, io::Error> {
     let mut jinja = Environment::new();
     jinja.add_filter("debug_value", jinja_extra::debug_value);
     jinja.add_filter("post_detail_url", jinja_extra::post_detail_url);
     jinja.add_filter("category_url", jinja_extra::category_url);
+    jinja.add_filter("tag_url", jinja_extra::tag_url);
+    jinja.add_filter("image_variant", jinja_extra::image_variant);
     jinja.add_function("gen_element_attr", jinja_extra::gen_element_attr);
     jinja.add_function("add_url_param", jinja_extra::add_url_param);
     jinja.add_function("_f", jinja_extra::fluent);
     jinja.add_filter("striptags", jinja_extra::striptags);
     jinja.add_global("UNCATEGORIZED_URL", UNCATEGORIZED_URL);
+    jinja.add_global("SITE_URL", DEFAULT_SITE_URL);
     jinja.add_global("GIT_REVISION", env!("GIT_REVISION"));
+    // Posts whose code is highlighted at server side need the syntect stylesheet
+    let server_highlighting = markdown::get_highlight_mode() == HighlightMode::Server;
+    jinja.add_global("SERVER_HIGHLIGHTING", server_highlighting);
     #[cfg(debug_assertions)]
     jinja.add_global("running_locally", true);
     jinja.set_loader(jinja_extra::get_embedded_template);
@@ -108,18 +165,19 @@ pub fn get_listening_addr() -> Ipv4Addr {
     }
 }
 
-pub fn get_binding_addr(bind_opt: Option<&str>) -> BindingAddr<'_> {
-    let addr = if let Some(s) = bind_opt {
-        if let Some(sk_path) = s.strip_prefix(UNIX_SOCKET_PREFIX) {
-            Some(BindingAddr::Unix(Path::new(sk_path)))
-        } else if s.contains(':') {
-            SocketAddr::from_str(s).ok().map(BindingAddr::Tcp)
-        } else {
-            None
-        }
+/// Parse a Unix socket path with "unix:" prefix, or a TCP address like "127.0.0.1:3000"
+pub fn parse_binding_addr(s: &str) -> Option<BindingAddr<'_>> {
+    if let Some(sk_path) = s.strip_prefix(UNIX_SOCKET_PREFIX) {
+        Some(BindingAddr::Unix(Path::new(sk_path)))
+    } else if s.contains(':') {
+        SocketAddr::from_str(s).ok().map(BindingAddr::Tcp)
     } else {
         None
-    };
+    }
+}
+
+pub fn get_binding_addr(bind_opt: Option<&str>) -> BindingAddr<'_> {
+    let addr = bind_opt.and_then(parse_binding_addr);
     addr.unwrap_or_else(|| {
         BindingAddr::Tcp(SocketAddr::V4(SocketAddrV4::new(
             get_listening_addr(),
@@ -12,9 +12,10 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use axum::extract::FromRef;
 use axum::http::StatusCode;
 use axum::http::header::{CONTENT_TYPE, LAST_MODIFIED};
-use axum::response::{Html, IntoResponse, Response};
+use axum::response::{IntoResponse, Response};
 use chrono::{DateTime, Utc};
 
+use fred::prelude::Pool;
 use gel_tokio::Client;
 use http::Uri;
 use indexmap::IndexMap;
@@ -22,7 +23,10 @@ use minijinja::Environment;
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
 use smart_default::SmartDefault;
+use strum::{Display, EnumString};
 
+use crate::mail::Mailer;
+use crate::storage::StorageBackend;
 use crate::utils::urls::update_entry_in_query;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -64,8 +68,12 @@ impl From<IndexMap<&str, String>> for ApiErrorShape {
 pub struct AppState {
     pub db: Client,
     pub jinja: Environment<'static>,
-    pub bunny_api_key: String,
-    pub bunny_cdn_host: String,
+    pub file_storage: StorageBackend,
+    pub max_upload_size: usize,
+    pub redis: Pool,
+    pub revision_retention: RevisionRetention,
+    pub mailer: Mailer,
+    pub password_reset_url: String,
 }
 
 impl FromRef<AppState> for Client {
@@ -80,6 +88,49 @@ impl FromRef<AppState> for Environment<'static> {
     }
 }
 
+impl FromRef<AppState> for StorageBackend {
+    fn from_ref(state: &AppState) -> Self {
+        state.file_storage.clone()
+    }
+}
+
+impl FromRef<AppState> for Mailer {
+    fn from_ref(state: &AppState) -> Self {
+        state.mailer.clone()
+    }
+}
+
+impl FromRef<AppState> for Pool {
+    fn from_ref(state: &AppState) -> Self {
+        state.redis.clone()
+    }
+}
+
+/// How many post revisions to keep. Set by "revisions_kept" and "revisions_max_age_days" config.
+/// The latest revision is always kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RevisionRetention {
+    /// Number of latest revisions to keep for each post
+    pub kept: u16,
+    /// Revisions older than this are deleted. Zero means no age limit.
+    pub max_age_days: u16,
+}
+
+impl Default for RevisionRetention {
+    fn default() -> Self {
+        Self {
+            kept: 50,
+            max_age_days: 0,
+        }
+    }
+}
+
+impl FromRef<AppState> for RevisionRetention {
+    fn from_ref(state: &AppState) -> Self {
+        state.revision_retention
+    }
+}
+
 #[derive(Debug)]
 pub enum BindingAddr<'a> {
     Unix(&'a Path),
@@ -264,16 +315,35 @@ pub struct CodeFenceOptions {
     pub start_line: u8,
 }
 
-pub enum HtmlOrMd {
-    Hm(String),
-    Md(String),
+/// Where code blocks in posts get highlighted. Set by "code_highlighting" config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum HighlightMode {
+    /// Our AlpineJS app highlights the code with Shiki, in browser
+    #[default]
+    Client,
+    /// Code is highlighted with syntect when rendering HTML, styled by "syntect.css"
+    Server,
 }
 
-impl IntoResponse for HtmlOrMd {
-    fn into_response(self) -> Response {
-        match self {
-            Self::Hm(content) => Html::from(content).into_response(),
-            Self::Md(content) => content.into_response(),
-        }
-    }
+/// Where files of the files API are kept. Set by "file_storage" config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum StorageKind {
+    /// Bunny storage, served by its CDN
+    #[default]
+    Bunny,
+    /// The "media_root" directory, served under "/media/"
+    Local,
+}
+
+/// How emails are sent. Set by "mail_backend" config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum MailKind {
+    /// Written to the "mail_dir" directory, or printed if it is empty. For local development only.
+    File,
+    /// Sent to the "smtp_url" server
+    #[default]
+    Smtp,
 }
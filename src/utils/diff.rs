@@ -0,0 +1,29 @@
+use similar::TextDiff;
+
+/// Make a unified diff, like `diff -u`, between two texts.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "# Title\n\nFirst line\nSecond line\n";
+        let new = "# Title\n\nFirst line\nSecond line, changed\n";
+        let diff = unified_diff(old, new, "a", "b");
+        assert!(diff.starts_with("--- a\n+++ b\n"));
+        assert!(diff.contains("\n-Second line\n+Second line, changed\n"));
+    }
+
+    #[test]
+    fn test_unified_diff_same_text() {
+        assert_eq!(unified_diff("Same\n", "Same\n", "a", "b"), "");
+    }
+}
@@ -0,0 +1,115 @@
+// Endpoints for systemd, load balancers and uptime monitors:
+// - "/healthz": the process is alive and serving requests. It checks nothing else.
+// - "/readyz": Gel and Redis can be reached. It answers 503 if one of them fails.
+//   The reasons of failure are logged, not told to the caller.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use fred::prelude::*;
+use serde::Serialize;
+
+use crate::types::AppState;
+
+pub const HEALTHZ_PATH: &str = "/healthz";
+pub const READYZ_PATH: &str = "/readyz";
+/// Not to let the checker hang when a backend doesn't answer
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub ok: bool,
+    pub latency_ms: f64,
+    /// Only logged. The response is public, it must not reveal the details of the backends.
+    #[serde(skip)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Readiness {
+    pub ok: bool,
+    /// Git revision which the program was built from
+    pub revision: &'static str,
+    pub gel: CheckResult,
+    pub redis: CheckResult,
+}
+
+impl Readiness {
+    pub fn new(gel: CheckResult, redis: CheckResult) -> Self {
+        Self {
+            ok: gel.ok && redis.ok,
+            revision: env!("GIT_REVISION"),
+            gel,
+            redis,
+        }
+    }
+}
+
+impl IntoResponse for Readiness {
+    fn into_response(self) -> Response {
+        let status = if self.ok {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+async fn check<F, T, E>(future: F) -> CheckResult
+where
+    F: Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let start = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, future).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let error = match result {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("No response in {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    CheckResult {
+        ok: error.is_none(),
+        latency_ms,
+        error,
+    }
+}
+
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+pub async fn readyz(State(state): State<AppState>) -> Readiness {
+    let gel = check(state.db.query_required_single::<i64, _>("SELECT 1", &()));
+    let redis = check(state.redis.ping::<String>(None));
+    let (gel, redis) = tokio::join!(gel, redis);
+    if !gel.ok || !redis.ok {
+        tracing::warn!("Not ready. Gel: {:?}, Redis: {:?}", gel.error, redis.error);
+    }
+    Readiness::new(gel, redis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let passed = check(async { Ok::<_, String>(()) }).await;
+        assert!(passed.ok);
+        let failed = check(async { Err::<(), _>("Connection refused") }).await;
+        assert_eq!(failed.error.as_deref(), Some("Connection refused"));
+
+        let response = Readiness::new(passed.clone(), passed.clone()).into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json = serde_json::to_value(&failed).unwrap();
+        assert!(json.get("error").is_none());
+        let response = Readiness::new(passed, failed).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
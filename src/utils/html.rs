@@ -1,11 +1,13 @@
 use std::collections::HashSet;
 use std::sync::LazyLock;
+use std::time::Instant;
 
 use ammonia::Builder;
 use minijinja::Environment;
 use serde::ser::Serialize;
 
 pub use crate::errors::PageError;
+use crate::utils::metrics::record_template_render;
 
 pub fn strip_tags(html: &str) -> String {
     let builder: LazyLock<Builder> = LazyLock::new(|| {
@@ -16,12 +18,24 @@ pub fn strip_tags(html: &str) -> String {
     builder.clean(html).to_string()
 }
 
+/// Get plain text from HTML. Unlike [`strip_tags`], special characters are unescaped.
+pub fn html_to_text(html: &str) -> String {
+    strip_tags(html)
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
 pub fn render_with<S: Serialize>(
     template_name: &str,
     context: S,
     engine: Environment,
 ) -> Result<String, PageError> {
     let tpl = engine.get_template(template_name)?;
+    let start = Instant::now();
     let content = tpl.render(context)?;
+    record_template_render(template_name, start.elapsed());
     Ok(content)
 }
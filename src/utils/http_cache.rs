@@ -0,0 +1,215 @@
+// HTTP caching of the public pages and feeds: ETag, Last-Modified and Cache-Control,
+// and 304 answers when the client's copy is still fresh.
+
+use std::convert::Infallible;
+use std::time::{Duration, SystemTime};
+
+use axum::extract::FromRequestParts;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, VARY};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified};
+use sha2::{Digest, Sha256};
+
+use super::page_cache::{CacheGroup, CacheGroups};
+use crate::consts::PAGE_MAX_AGE;
+
+/// The conditional headers of a request
+#[derive(Debug, Default)]
+pub struct Validators {
+    pub if_none_match: Option<IfNoneMatch>,
+    pub if_modified_since: Option<IfModifiedSince>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Validators {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            if_none_match: parts.headers.typed_get(),
+            if_modified_since: parts.headers.typed_get(),
+        })
+    }
+}
+
+impl Validators {
+    /// Tell if the client has this version already.
+    /// As in RFC 9110, `If-Modified-Since` is only looked at when there is no `If-None-Match`.
+    pub fn is_fresh(&self, etag: &ETag, last_modified: Option<SystemTime>) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            return !if_none_match.precondition_passes(etag);
+        }
+        match (&self.if_modified_since, last_modified) {
+            (Some(since), Some(modified)) => !since.is_modified(modified),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Can be kept by browsers and shared caches for the given time
+    Public(Duration),
+    /// For logged-in users and pages which must not be tracked: only the browser keeps it,
+    /// and asks again before every use
+    Private,
+}
+
+impl CachePolicy {
+    /// HTML pages are private for logged-in users and the pages which must not be tracked
+    pub fn for_page(no_tracking: bool) -> Self {
+        if no_tracking {
+            Self::Private
+        } else {
+            Self::Public(PAGE_MAX_AGE)
+        }
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        match self {
+            Self::Public(max_age) => {
+                let value = format!("public, max-age={}", max_age.as_secs());
+                HeaderValue::from_str(&value).unwrap_or(HeaderValue::from_static("no-cache"))
+            }
+            Self::Private => HeaderValue::from_static("private, no-cache"),
+        }
+    }
+}
+
+fn etag_value(content: &[u8]) -> String {
+    let digest = Sha256::digest(content);
+    let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+/// Strong ETag, from the hash of the content
+pub fn make_etag(content: &[u8]) -> ETag {
+    etag_value(content)
+        .parse()
+        .expect("Hex digits make a valid entity tag")
+}
+
+/// A response to be cached, with validators made from its content
+pub struct Cached {
+    pub content_type: &'static str,
+    pub body: String,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub policy: CachePolicy,
+    /// Set when the content depends on the session, like the language chosen by the visitor
+    pub vary_cookie: bool,
+    /// Groups to store the response under in the page cache. Without them, it is not stored.
+    pub cache_groups: Vec<CacheGroup>,
+}
+
+impl Cached {
+    /// HTML, unless changed with `with_content_type`
+    pub fn new(body: String, policy: CachePolicy) -> Self {
+        Self {
+            content_type: "text/html; charset=utf-8",
+            body,
+            last_modified: None,
+            policy,
+            vary_cookie: false,
+            cache_groups: Vec::new(),
+        }
+    }
+
+    pub fn with_content_type(mut self, content_type: &'static str) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    pub fn last_modified(mut self, last_modified: Option<DateTime<Utc>>) -> Self {
+        self.last_modified = last_modified;
+        self
+    }
+
+    pub fn vary_cookie(mut self) -> Self {
+        self.vary_cookie = true;
+        self
+    }
+
+    pub fn cache_groups(mut self, groups: impl IntoIterator<Item = CacheGroup>) -> Self {
+        self.cache_groups.extend(groups);
+        self
+    }
+
+    /// Answer with 304 and no body if the client has this content already
+    pub fn respond(self, validators: &Validators) -> Response {
+        let etag = make_etag(self.body.as_bytes());
+        let last_modified = self.last_modified.map(SystemTime::from);
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(etag.clone());
+        if let Some(modified) = last_modified {
+            headers.typed_insert(LastModified::from(modified));
+        }
+        headers.insert(CACHE_CONTROL, self.policy.header_value());
+        if self.vary_cookie {
+            headers.insert(VARY, HeaderValue::from_static("Cookie"));
+        }
+        if validators.is_fresh(&etag, last_modified) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(self.content_type));
+        let mut response = (headers, self.body).into_response();
+        if !self.cache_groups.is_empty() {
+            response
+                .extensions_mut()
+                .insert(CacheGroups(self.cache_groups));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderName;
+    use axum::http::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn validators_from(name: HeaderName, value: &str) -> Validators {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        Validators {
+            if_none_match: headers.typed_get(),
+            if_modified_since: headers.typed_get(),
+        }
+    }
+
+    #[test]
+    fn test_etag_matching() {
+        let etag = make_etag(b"<p>Hello</p>");
+        assert_eq!(etag, make_etag(b"<p>Hello</p>"));
+        assert_ne!(etag, make_etag(b"<p>Hello!</p>"));
+        let validators = validators_from(IF_NONE_MATCH, &etag_value(b"<p>Hello</p>"));
+        assert!(validators.is_fresh(&etag, None));
+        let validators = validators_from(IF_NONE_MATCH, "\"other\"");
+        assert!(!validators.is_fresh(&etag, None));
+        assert!(!Validators::default().is_fresh(&etag, None));
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let etag = make_etag(b"feed");
+        let modified = SystemTime::from(Utc.with_ymd_and_hms(2025, 3, 1, 8, 0, 0).unwrap());
+        let validators = validators_from(IF_MODIFIED_SINCE, "Sat, 01 Mar 2025 08:00:00 GMT");
+        assert!(validators.is_fresh(&etag, Some(modified)));
+        let later = SystemTime::from(Utc.with_ymd_and_hms(2025, 3, 2, 8, 0, 0).unwrap());
+        assert!(!validators.is_fresh(&etag, Some(later)));
+        assert!(!validators.is_fresh(&etag, None));
+    }
+
+    #[test]
+    fn test_respond_not_modified() {
+        let cached = Cached::new("<p>Hi</p>".into(), CachePolicy::Private);
+        let tag = etag_value(b"<p>Hi</p>");
+        let resp = cached.respond(&validators_from(IF_NONE_MATCH, &tag));
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()[CACHE_CONTROL], "private, no-cache");
+        assert!(resp.headers().get(CONTENT_TYPE).is_none());
+    }
+}
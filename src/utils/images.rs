@@ -0,0 +1,356 @@
+// Resized and WebP variants of uploaded images.
+// Variants are stored next to the original, with the width in their names:
+// "blogs/photo.jpg" has "blogs/photo-320w.jpg", "blogs/photo-320w.webp" etc.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::sync::{LazyLock, OnceLock};
+
+use axum::body::Bytes;
+use axum::extract::FromRef;
+use futures_util::{StreamExt, stream};
+use gel_tokio::{Client as EdgeClient, Error as EdgeError};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageError, ImageFormat};
+use regex::{Captures, Regex};
+use thiserror::Error;
+
+use crate::storage::{FileStorage, StorageError};
+use crate::stores;
+use crate::types::AppState;
+
+pub const VARIANT_WIDTHS: [u32; 3] = [320, 768, 1280];
+/// Posts are shown in a column of about 768px on large screens
+const IMG_SIZES: &str = "(max-width: 768px) 100vw, 768px";
+
+static MEDIA_URL_PREFIX: OnceLock<String> = OnceLock::new();
+static RE_VARIANT_NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-\d+w\.[a-z]+$").unwrap());
+static RE_IMG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<img src="([^"]+)"([^>]*?)\s*/?>"#).unwrap());
+
+/// Set the URL where our uploaded files are served, like "https://quan-images.b-cdn.net/quan-images/".
+/// Only images under it are known to have variants. To be called once, at start-up.
+pub fn set_media_url_prefix(prefix: String) {
+    if let Err(prefix) = MEDIA_URL_PREFIX.set(prefix) {
+        tracing::warn!("Media URL prefix is already set. Ignore {prefix}");
+    }
+}
+
+pub fn get_media_url_prefix() -> Option<&'static str> {
+    MEDIA_URL_PREFIX
+        .get()
+        .map(String::as_str)
+        .filter(|s| !s.is_empty())
+}
+
+fn split_ext(path: &str) -> Option<(&str, &str)> {
+    path.rsplit_once('.')
+        .filter(|(stem, _ext)| !stem.is_empty() && !stem.ends_with('/'))
+}
+
+/// Tell if we make variants for this image. It can be a path or URL.
+/// SVG is not resized, and GIF may be animated, so they are left alone.
+pub fn has_variants(path: &str) -> bool {
+    if path.contains(['?', '#']) || RE_VARIANT_NAME.is_match(path) {
+        return false;
+    }
+    split_ext(path).is_some_and(|(_stem, ext)| {
+        matches!(
+            ext.to_ascii_lowercase().as_str(),
+            "jpg" | "jpeg" | "png" | "webp"
+        )
+    })
+}
+
+/// Path of the variant of given width and extension, like "blogs/photo-320w.webp"
+pub fn variant_path(path: &str, width: u32, ext: &str) -> String {
+    let stem = split_ext(path).map_or(path, |(stem, _ext)| stem);
+    format!("{stem}-{width}w.{ext}")
+}
+
+/// Paths of all variants of an image, whether they are generated or not
+pub fn variant_paths(path: &str) -> Vec<String> {
+    let Some((_stem, ext)) = split_ext(path) else {
+        return Vec::new();
+    };
+    let ext = ext.to_ascii_lowercase();
+    VARIANT_WIDTHS
+        .iter()
+        .flat_map(|&w| {
+            let mut paths = vec![variant_path(path, w, &ext)];
+            if ext != "webp" {
+                paths.push(variant_path(path, w, "webp"));
+            }
+            paths
+        })
+        .collect()
+}
+
+/// A generated variant, to be stored beside its original image
+pub struct ImageVariant {
+    pub path: String,
+    pub content_type: &'static str,
+    pub content: Vec<u8>,
+}
+
+/// Resize the image to each of `VARIANT_WIDTHS`, in its own format and WebP.
+/// Images are never enlarged. The variants wider than the original have the original size,
+/// so that all variants always exist.
+pub fn make_variants(path: &str, content: &[u8]) -> Result<Vec<ImageVariant>, ImageError> {
+    let format = ImageFormat::from_path(path)?;
+    let img = image::load_from_memory_with_format(content, format)?;
+    // Keep the extension as the original, "jpeg" or "jpg"
+    let ext = split_ext(path)
+        .map(|(_stem, e)| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    let mut variants = Vec::new();
+    for width in VARIANT_WIDTHS {
+        let resized = if img.width() > width {
+            img.resize(width, u32::MAX, FilterType::Lanczos3)
+        } else {
+            img.clone()
+        };
+        variants.push(ImageVariant {
+            path: variant_path(path, width, &ext),
+            content_type: format.to_mime_type(),
+            content: encode(&resized, format)?,
+        });
+        if format != ImageFormat::WebP {
+            variants.push(ImageVariant {
+                path: variant_path(path, width, "webp"),
+                content_type: ImageFormat::WebP.to_mime_type(),
+                content: encode(&resized, ImageFormat::WebP)?,
+            });
+        }
+    }
+    Ok(variants)
+}
+
+#[derive(Debug, Error)]
+pub enum VariantError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+}
+
+/// Make variants of an image in the storage and store them beside it.
+/// Return the number of variants.
+pub async fn store_variants(path: &str, storage: &impl FileStorage) -> Result<usize, VariantError> {
+    let content = storage.get(path).await?;
+    let image_path = path.to_string();
+    // Resizing and encoding are CPU-bound, not to block other tasks
+    let variants =
+        tokio::task::spawn_blocking(move || make_variants(&image_path, &content)).await??;
+    let count = variants.len();
+    for variant in variants {
+        let content = stream::iter([Ok(Bytes::from(variant.content))]).boxed();
+        storage
+            .put(&variant.path, variant.content_type, content)
+            .await?;
+    }
+    Ok(count)
+}
+
+/// Record, in the database, of the images whose variants are stored.
+/// Variants are made in background, and old images may have none, so our pages only point to
+/// the variants of the images which are recorded here.
+#[derive(Debug, Clone)]
+pub struct VariantRegistry {
+    db: EdgeClient,
+}
+
+impl FromRef<AppState> for VariantRegistry {
+    fn from_ref(state: &AppState) -> Self {
+        Self::new(state.db.clone())
+    }
+}
+
+impl VariantRegistry {
+    pub fn new(db: EdgeClient) -> Self {
+        Self { db }
+    }
+
+    /// Record that all variants of the image at this path (relative to the storage root) are stored
+    pub async fn record(&self, path: &str) -> Result<(), EdgeError> {
+        stores::image::record_stored_image(path, &self.db).await
+    }
+
+    pub async fn forget(&self, path: &str) -> Result<(), EdgeError> {
+        stores::image::forget_stored_image(path, &self.db).await
+    }
+
+    /// Among the given image URLs, get the ones of our images whose variants are stored.
+    /// Pages can be shown without variants, so errors are only logged.
+    pub async fn stored_urls<'a>(
+        &self,
+        urls: impl IntoIterator<Item = &'a str>,
+    ) -> HashSet<String> {
+        let Some(prefix) = get_media_url_prefix() else {
+            return HashSet::new();
+        };
+        let urls: Vec<&str> = urls
+            .into_iter()
+            .filter(|u| u.starts_with(prefix) && has_variants(u))
+            .collect();
+        if urls.is_empty() {
+            return HashSet::new();
+        }
+        let paths: Vec<String> = urls.iter().map(|u| u[prefix.len()..].to_string()).collect();
+        match stores::image::get_stored_image_paths(paths, &self.db).await {
+            Ok(found) => found.into_iter().map(|p| format!("{prefix}{p}")).collect(),
+            Err(e) => {
+                tracing::warn!("Failed to look up stored image variants: {e}");
+                HashSet::new()
+            }
+        }
+    }
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, ImageError> {
+    // JPEG has no alpha channel, and WebP encoder only accepts 8-bit pixels
+    let img = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8()),
+        ImageFormat::WebP if img.color().has_alpha() => DynamicImage::ImageRgba8(img.to_rgba8()),
+        ImageFormat::WebP => DynamicImage::ImageRgb8(img.to_rgb8()),
+        _ => img.clone(),
+    };
+    let mut buf = Cursor::new(Vec::new());
+    img.write_to(&mut buf, format)?;
+    Ok(buf.into_inner())
+}
+
+fn make_srcset(src: &str, ext: &str) -> String {
+    VARIANT_WIDTHS
+        .iter()
+        .map(|w| format!("{} {w}w", variant_path(src, *w, ext)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Make our images in a page use their variants, if they are in `stored` (URLs)
+pub fn use_stored_variants(html: &str, stored: &HashSet<String>) -> String {
+    match get_media_url_prefix() {
+        Some(prefix) => rewrite_img_tags(html, prefix, stored).into_owned(),
+        None => html.to_string(),
+    }
+}
+
+/// Get URL of the variant with given width, if the image is ours and has variants.
+/// The caller has to make sure that the variants are stored, with [`VariantRegistry`].
+pub fn variant_url(url: &str, width: u32) -> Option<String> {
+    let prefix = get_media_url_prefix()?;
+    if !url.starts_with(prefix) || !has_variants(url) {
+        return None;
+    }
+    let ext = split_ext(url)?.1.to_ascii_lowercase();
+    Some(variant_path(url, width, &ext))
+}
+
+/// Get the `src` of the `<img>` tags in HTML
+pub fn img_sources(html: &str) -> Vec<&str> {
+    RE_IMG
+        .captures_iter(html)
+        .filter_map(|caps| caps.get(1))
+        .map(|m| m.as_str())
+        .collect()
+}
+
+/// Make the `<img>` tags of our images use their variants, via `srcset` and `<picture>`.
+/// Only the images in `stored` (URLs), whose variants are known to exist, are changed.
+/// Other images, and the ones which already have `srcset`, are kept as is.
+pub fn rewrite_img_tags<'a>(
+    html: &'a str,
+    url_prefix: &str,
+    stored: &HashSet<String>,
+) -> Cow<'a, str> {
+    RE_IMG.replace_all(html, |caps: &Captures| {
+        let src = &caps[1];
+        let rest = &caps[2];
+        let ext = split_ext(src).map(|(_stem, e)| e.to_ascii_lowercase());
+        let is_ours = src.starts_with(url_prefix) && has_variants(src) && stored.contains(src);
+        let Some(ext) = ext.filter(|_e| is_ours && !rest.contains("srcset=")) else {
+            return caps[0].to_string();
+        };
+        let img = format!(
+            r#"<img src="{src}" srcset="{}" sizes="{IMG_SIZES}"{rest} />"#,
+            make_srcset(src, &ext)
+        );
+        if ext == "webp" {
+            return img;
+        }
+        format!(
+            r#"<picture><source type="image/webp" srcset="{}" sizes="{IMG_SIZES}" />{img}</picture>"#,
+            make_srcset(src, "webp")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: &str = "https://quan-images.b-cdn.net/quan-images/";
+
+    #[test]
+    fn test_variant_paths() {
+        assert!(has_variants("blogs/photo.JPG"));
+        assert!(!has_variants("blogs/photo-320w.jpg"));
+        assert!(!has_variants("icons/org.gnome.Meld.svg"));
+        assert!(!has_variants("blogs/.jpg"));
+        assert_eq!(
+            variant_path("blogs/photo.jpg", 320, "webp"),
+            "blogs/photo-320w.webp"
+        );
+        let paths = variant_paths("blogs/photo.png");
+        assert_eq!(paths.len(), 6);
+        assert!(paths.contains(&"blogs/photo-1280w.png".to_string()));
+        assert_eq!(variant_paths("blogs/photo.webp").len(), 3);
+    }
+
+    #[test]
+    fn test_make_variants() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(800, 400));
+        let original = encode(&img, ImageFormat::Png).unwrap();
+        let variants = make_variants("blogs/dark.png", &original).unwrap();
+        let paths: Vec<&str> = variants.iter().map(|v| v.path.as_str()).collect();
+        assert_eq!(paths, variant_paths("blogs/dark.png"));
+
+        let small = image::load_from_memory(&variants[0].content).unwrap();
+        assert_eq!((small.width(), small.height()), (320, 160));
+        // Not enlarged
+        let large = image::load_from_memory(&variants[4].content).unwrap();
+        assert_eq!(large.width(), 800);
+        assert_eq!(variants[5].content_type, "image/webp");
+    }
+
+    #[test]
+    fn test_rewrite_img_tags() {
+        let html = format!(
+            r#"<p><img src="{PREFIX}blogs/photo.jpg" alt="A photo" /> <img src="https://i.imgur.com/x.jpg" alt="" /></p>"#
+        );
+        let stored = HashSet::from([format!("{PREFIX}blogs/photo.jpg")]);
+        let rewritten = rewrite_img_tags(&html, PREFIX, &stored);
+        assert!(rewritten.starts_with(r#"<p><picture><source type="image/webp" srcset=""#));
+        assert!(rewritten.contains(&format!("{PREFIX}blogs/photo-320w.webp 320w, ")));
+        assert!(rewritten.contains(&format!(
+            r#"<img src="{PREFIX}blogs/photo.jpg" srcset="{PREFIX}blogs/photo-320w.jpg 320w"#
+        )));
+        assert!(rewritten.contains(r#"alt="A photo" /></picture>"#));
+        // Images from elsewhere are kept
+        assert!(rewritten.contains(r#"<img src="https://i.imgur.com/x.jpg" alt="" />"#));
+        // Not twice
+        assert_eq!(rewrite_img_tags(&rewritten, PREFIX, &stored), rewritten);
+    }
+
+    #[test]
+    fn test_rewrite_img_tags_needs_stored_variants() {
+        let html = format!(r#"<p><img src="{PREFIX}blogs/new.png" alt="" /></p>"#);
+        assert_eq!(img_sources(&html), [format!("{PREFIX}blogs/new.png")]);
+        let rewritten = rewrite_img_tags(&html, PREFIX, &HashSet::new());
+        assert_eq!(rewritten, html);
+    }
+}
@@ -9,11 +9,12 @@ use minijinja::{Error, ErrorKind, State};
 use regex::Regex;
 use unic_langid::LanguageIdentifier;
 
-use crate::consts::{DEFAULT_LANG, KEY_LANG};
-use crate::models::blogs::build_post_view_url;
+use crate::consts::{DEFAULT_LANG, KEY_LANG, KEY_STORED_IMAGES};
+use crate::models::blogs::{build_post_view_url, build_tag_view_url};
 use crate::thingsup::LOCALES;
 use crate::types::BundledTemplates;
 use crate::types::conversions::jinja_kwargs_to_fluent_args;
+use crate::utils::images;
 use crate::utils::urls::update_entry_in_query;
 
 pub fn debug_value(value: MJValue) -> &'static str {
@@ -53,6 +54,23 @@ pub fn category_url(slug: String) -> String {
     format!("/category/{}/", slug)
 }
 
+pub fn tag_url(keyword: String) -> String {
+    build_tag_view_url(&keyword)
+}
+
+/// Use the resized variant of our uploaded image, like a post's cover.
+/// The view has to list the image in "stored_images", when its variants are known to be stored.
+pub fn image_variant(state: &State, url: String, width: u32) -> String {
+    let stored = state.lookup(KEY_STORED_IMAGES).is_some_and(|v| {
+        v.try_iter()
+            .is_ok_and(|mut urls| urls.any(|u| u.as_str() == Some(url.as_str())))
+    });
+    if !stored {
+        return url;
+    }
+    images::variant_url(&url, width).unwrap_or(url)
+}
+
 pub fn gen_element_attr(name: &str, value: MJValue) -> String {
     match value.as_str() {
         Some(value) => format!("{}=\"{}\"", name, value),
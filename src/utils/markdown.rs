@@ -1,6 +1,8 @@
 use core::fmt;
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex, OnceLock};
 
 use comrak::adapters::SyntaxHighlighterAdapter;
 use comrak::html;
@@ -8,12 +10,47 @@ use comrak::options::{Extension, Plugins, Render, RenderPlugins};
 use comrak::{Options, markdown_to_html_with_plugins};
 use minijinja::{Environment, context};
 use serde_json5;
+use syntect::html::line_tokens_to_classed_spans;
+use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+use syntect::util::LinesWithEndings;
 
-use crate::consts::{ALPINE_HIGHLIGHTING_APP, ALPINE_ORIG_CODE_ELM, ATTR_CODEFENCE_EXTRA};
+use crate::consts::{
+    ALPINE_HIGHLIGHTING_APP, ALPINE_ORIG_CODE_ELM, ATTR_CODEFENCE_EXTRA, SYNTECT_CLASS_STYLE,
+};
 use crate::errors::PageError;
-use crate::types::CodeFenceOptions;
+use crate::types::{CodeFenceOptions, HighlightMode};
 use crate::utils::html::render_with;
 
+// Loading syntaxes takes a while, so we only do it once, when the first code block is highlighted.
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static HIGHLIGHT_MODE: OnceLock<HighlightMode> = OnceLock::new();
+
+/// Choose where code blocks are highlighted. To be called once, at start-up.
+pub fn set_highlight_mode(mode: HighlightMode) {
+    if HIGHLIGHT_MODE.set(mode).is_err() {
+        tracing::warn!("Highlight mode is already set. Ignore {mode}");
+    }
+}
+
+pub fn get_highlight_mode() -> HighlightMode {
+    HIGHLIGHT_MODE.get().copied().unwrap_or_default()
+}
+
+/// Create the highlighter adapter for the configured mode.
+/// A new one is needed for each document, because the syntect adapter keeps state between calls.
+pub fn make_highlighter() -> Box<dyn SyntaxHighlighterAdapter> {
+    match get_highlight_mode() {
+        HighlightMode::Client => Box::new(JsHighlightAdapter),
+        HighlightMode::Server => Box::new(SyntectHighlightAdapter::default()),
+    }
+}
+
+fn parse_codefence_opts(info_string: &str) -> CodeFenceOptions {
+    serde_json5::from_str(info_string)
+        .inspect_err(|e| tracing::warn!("Failed to parse codefence extra. {e}"))
+        .unwrap_or_default()
+}
+
 // A simple adapter that defers highlighting job to the client side
 pub struct JsHighlightAdapter;
 
@@ -54,9 +91,7 @@ impl SyntaxHighlighterAdapter for JsHighlightAdapter {
         let mut class_names = vec!["q-code"];
         if let Some(info_string) = attributes.get(ATTR_CODEFENCE_EXTRA) {
             tracing::info!("Attempt to parse: {}", info_string);
-            let codefence_opts: CodeFenceOptions = serde_json5::from_str(info_string.as_ref())
-                .inspect_err(|e| tracing::warn!("Failed to parse codefence extra. {e}"))
-                .unwrap_or_default();
+            let codefence_opts = parse_codefence_opts(info_string);
             if codefence_opts.lines {
                 class_names.push("q-with-lineno")
             }
@@ -76,6 +111,110 @@ impl SyntaxHighlighterAdapter for JsHighlightAdapter {
     }
 }
 
+// An adapter which highlights code at rendering time, producing <span> with syntect classes,
+// so that the code is colored even without JS (feeds, reader mode...).
+// The codefence options are given to `write_code_tag`, which is called before `write_highlighted`,
+// so we keep them here until the code comes.
+#[derive(Debug, Default)]
+pub struct SyntectHighlightAdapter {
+    codefence_opts: Mutex<Option<CodeFenceOptions>>,
+}
+
+impl SyntaxHighlighterAdapter for SyntectHighlightAdapter {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn fmt::Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> fmt::Result {
+        let opts = self
+            .codefence_opts
+            .lock()
+            .map(|mut o| o.take())
+            .unwrap_or_default()
+            .unwrap_or_default();
+        match highlight_code(code, lang, &opts) {
+            Ok(highlighted) => output.write_str(&highlighted),
+            Err(e) => {
+                tracing::warn!("Failed to highlight {lang:?} code. {e}");
+                html::escape(output, code)
+            }
+        }
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn fmt::Write,
+        mut attributes: HashMap<&'static str, Cow<'_, str>>,
+    ) -> fmt::Result {
+        // "st-code" gives the background and foreground colors of the syntect theme
+        let classname = "q-highlighted st-code not-prose p-4";
+        if let Some(class) = attributes.remove("class") {
+            attributes.insert("class", Cow::from(format!("{class} {classname}")));
+        } else {
+            attributes.insert("class", Cow::from(classname));
+        };
+        html::write_opening_tag(output, "pre", attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn fmt::Write,
+        mut attributes: HashMap<&'static str, Cow<'_, str>>,
+    ) -> fmt::Result {
+        let mut class_names = vec!["q-code"];
+        let codefence_opts = attributes
+            .get(ATTR_CODEFENCE_EXTRA)
+            .map(|s| parse_codefence_opts(s))
+            .unwrap_or_default();
+        if codefence_opts.lines {
+            class_names.push("q-with-lineno");
+        }
+        if let Ok(mut opts) = self.codefence_opts.lock() {
+            *opts = Some(codefence_opts);
+        }
+        let extra_class = class_names.join(" ");
+        if let Some(class) = attributes.remove("class") {
+            attributes.insert("class", Cow::from(format!("{class} {extra_class}")));
+        } else {
+            attributes.insert("class", Cow::from(extra_class));
+        };
+        html::write_opening_tag(output, "code", attributes)
+    }
+}
+
+/// Highlight code with syntect, wrapping tokens in <span> with classes following `SYNTECT_CLASS_STYLE`.
+/// If line numbers are requested, each line is prefixed with a `q-lineno` <span>.
+pub fn highlight_code(
+    code: &str,
+    lang: Option<&str>,
+    opts: &CodeFenceOptions,
+) -> Result<String, syntect::Error> {
+    let syntax_set = &*SYNTAX_SET;
+    let syntax = lang
+        .and_then(|l| syntax_set.find_syntax_by_token(l))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+    let mut parse_state = ParseState::new(syntax);
+    let mut scope_stack = ScopeStack::new();
+    let mut open_spans = 0;
+    let mut output = String::with_capacity(code.len() * 2);
+    for (i, line) in LinesWithEndings::from(code).enumerate() {
+        if opts.lines {
+            let lineno = opts.start_line as usize + i;
+            write!(output, "<span class=\"q-lineno\">{lineno}</span>")?;
+        }
+        let ops = parse_state.parse_line(line, syntax_set)?;
+        let (spans, delta) =
+            line_tokens_to_classed_spans(line, &ops, SYNTECT_CLASS_STYLE, &mut scope_stack)?;
+        open_spans += delta;
+        output.push_str(&spans);
+    }
+    for _ in 0..open_spans {
+        output.push_str("</span>");
+    }
+    Ok(output)
+}
+
 pub fn markdown_to_html(markdown: &str) -> String {
     let extension = Extension::builder().table(true).autolink(true).build();
     let render = Render::builder().full_info_string(true).build();
@@ -84,14 +223,23 @@ pub fn markdown_to_html(markdown: &str) -> String {
         render,
         ..Default::default()
     };
-    let adapter = JsHighlightAdapter;
+    let adapter = make_highlighter();
     let render = RenderPlugins::builder()
-        .codefence_syntax_highlighter(&adapter)
+        .codefence_syntax_highlighter(adapter.as_ref())
         .build();
     let plugins = Plugins::builder().render(render).build();
     markdown_to_html_with_plugins(markdown, &options, &plugins)
 }
 
+/// Render a reader's comment. Unlike posts, comments are written by strangers, so the HTML is sanitized.
+pub fn comment_to_html(markdown: &str) -> String {
+    let html = markdown_to_html(markdown);
+    ammonia::Builder::default()
+        .link_rel(Some("nofollow noopener noreferrer"))
+        .clean(&html)
+        .to_string()
+}
+
 pub fn make_excerpt(markdown: &str) -> String {
     let mut lines: Vec<&str> = markdown.lines().take(7).collect();
     // Count "code block" marker (```)
@@ -121,9 +269,9 @@ pub fn markdown_to_html_document(markdown: &str, engine: Environment) -> Result<
         render,
         ..Default::default()
     };
-    let adapter = JsHighlightAdapter;
+    let adapter = make_highlighter();
     let render = RenderPlugins::builder()
-        .codefence_syntax_highlighter(&adapter)
+        .codefence_syntax_highlighter(adapter.as_ref())
         .build();
     let plugins = Plugins::builder().render(render).build();
     let html = markdown_to_html_with_plugins(markdown, &options, &plugins);
@@ -132,3 +280,41 @@ pub fn markdown_to_html_document(markdown: &str, engine: Environment) -> Result<
     };
     render_with("mini-preview.jinja", vcontext, engine)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_code_with_line_numbers() {
+        let opts = CodeFenceOptions {
+            lines: true,
+            start_line: 9,
+        };
+        let html = highlight_code("x = 1 < 2\ny = '''a\nb'''\n", Some("python"), &opts).unwrap();
+        assert!(html.contains("<span class=\"q-lineno\">9</span>"));
+        assert!(html.contains("<span class=\"q-lineno\">11</span>"));
+        assert!(html.contains("st-python"));
+        assert!(html.contains("&lt;"));
+        assert_eq!(
+            html.matches("<span").count(),
+            html.matches("</span>").count()
+        );
+    }
+
+    #[test]
+    fn test_comment_to_html_is_sanitized() {
+        let html = comment_to_html("Hi <script>alert(1)</script> [me](https://example.com)");
+        assert!(!html.contains("<script"));
+        assert!(html.contains("rel=\"nofollow noopener noreferrer\""));
+    }
+
+    #[test]
+    fn test_highlight_code_unknown_language() {
+        let html = highlight_code("a <b>\n", Some("nosuchlang"), &CodeFenceOptions::default());
+        assert_eq!(
+            html.unwrap(),
+            "<span class=\"st-text st-plain\">a &lt;b&gt;\n</span>"
+        );
+    }
+}
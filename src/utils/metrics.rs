@@ -0,0 +1,174 @@
+// Prometheus metrics, exposed at "/metrics".
+// Metrics are recorded with the `metrics` facade. They cost nothing in the processes
+// which don't install the Prometheus recorder (worker, CLI commands).
+
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use headers::HeaderMapExt;
+use headers::authorization::{Authorization, Bearer};
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder, PrometheusHandle};
+use tracing::span::{Attributes, Id};
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+pub const METRICS_PATH: &str = "/metrics";
+pub const HTTP_REQUESTS: &str = "http_requests_total";
+pub const HTTP_REQUEST_DURATION: &str = "http_request_duration_seconds";
+pub const GEL_QUERIES: &str = "gel_queries_total";
+pub const GEL_QUERY_DURATION: &str = "gel_query_duration_seconds";
+pub const BUNNY_API_CALLS: &str = "bunny_api_calls_total";
+pub const TEMPLATE_RENDER_DURATION: &str = "template_render_duration_seconds";
+pub const SESSION_STORE_ERRORS: &str = "session_store_errors_total";
+
+/// Spans of the store functions, annotated with `#[instrument]`, are timed as Gel queries
+const STORES_TARGET: &str = "quanweb::stores";
+// From 1ms to 10s. Most of our pages take a few milliseconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Install the Prometheus recorder. To be called once, at start-up of the web server.
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets(&BUCKETS)?
+        .install_recorder()?;
+    // Histograms need to be drained from time to time, not to grow forever
+    let upkeep_handle = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep_handle.run_upkeep();
+        }
+    });
+    Ok(handle)
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsState {
+    pub handle: PrometheusHandle,
+    /// If set, scrapers have to send it as Bearer token
+    pub token: Option<String>,
+}
+
+fn is_same_secret(a: &str, b: &str) -> bool {
+    // Not to tell by response time how much of the token is right
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+pub async fn serve_metrics(State(state): State<MetricsState>, request: Request) -> Response {
+    if let Some(token) = &state.token {
+        let auth: Option<Authorization<Bearer>> = request.headers().typed_get();
+        if !auth.is_some_and(|a| is_same_secret(a.token(), token)) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    let content_type = HeaderValue::from_static("text/plain; version=0.0.4");
+    ([(CONTENT_TYPE, content_type)], state.handle.render()).into_response()
+}
+
+/// Middleware to count requests and measure their latency, per matched route.
+/// It must be added with `route_layer`, for the route to be known.
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("", |p| p.as_str())
+        .to_string();
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = start.elapsed().as_secs_f64();
+    let mut labels = vec![("method", method), ("route", route)];
+    histogram!(HTTP_REQUEST_DURATION, &labels[..]).record(elapsed);
+    labels.push(("status", response.status().as_u16().to_string()));
+    counter!(HTTP_REQUESTS, &labels[..]).increment(1);
+    response
+}
+
+/// Count a call to Bunny API, by its outcome
+pub fn record_bunny_call(
+    operation: &'static str,
+    result: &Result<reqwest::Response, reqwest::Error>,
+) {
+    let outcome = match result {
+        Ok(r) if r.status().is_success() => "success",
+        Ok(r) if r.status().is_client_error() => "client_error",
+        Ok(_) => "server_error",
+        Err(e) if e.is_timeout() => "timeout",
+        Err(_) => "network_error",
+    };
+    counter!(BUNNY_API_CALLS, "operation" => operation, "outcome" => outcome).increment(1);
+}
+
+pub fn record_template_render(template: &str, elapsed: Duration) {
+    histogram!(TEMPLATE_RENDER_DURATION, "template" => template.to_string())
+        .record(elapsed.as_secs_f64());
+}
+
+pub fn record_session_store_error(operation: &'static str) {
+    counter!(SESSION_STORE_ERRORS, "operation" => operation).increment(1);
+}
+
+struct SpanStart(Instant);
+
+/// Tracing layer to time the store functions, each of them runs one or a few Gel queries.
+/// The query is labelled with the function name.
+pub struct GelQueryLayer;
+
+impl<S> Layer<S> for GelQueryLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanStart(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(elapsed) = span.extensions().get::<SpanStart>().map(|s| s.0.elapsed()) else {
+            return;
+        };
+        let query = span.name();
+        counter!(GEL_QUERIES, "query" => query).increment(1);
+        histogram!(GEL_QUERY_DURATION, "query" => query).record(elapsed.as_secs_f64());
+    }
+}
+
+/// Only let the spans of store functions reach [`GelQueryLayer`], whatever the log level is
+pub struct StoreSpanFilter;
+
+impl<S> Filter<S> for StoreSpanFilter {
+    fn enabled(&self, meta: &Metadata<'_>, _ctx: &Context<'_, S>) -> bool {
+        meta.is_span() && meta.target().starts_with(STORES_TARGET)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_same_secret() {
+        assert!(is_same_secret("s3cret", "s3cret"));
+        assert!(!is_same_secret("s3cret", "s3cre"));
+        assert!(!is_same_secret("s3cret", "s3creT"));
+    }
+}
@@ -1,6 +1,17 @@
+pub mod diff;
+pub mod health;
 pub mod html;
+pub mod http_cache;
+pub mod images;
 pub mod jinja_extra;
 pub mod markdown;
+pub mod metrics;
+pub mod page_cache;
+pub mod ratelimit;
+pub mod rst;
+pub mod search;
+pub mod sitemap;
+pub mod telemetry;
 pub mod urls;
 
 pub fn split_search_query(query: Option<&str>) -> Option<Vec<&str>> {
@@ -9,6 +20,14 @@ pub fn split_search_query(query: Option<&str>) -> Option<Vec<&str>> {
     tokens.filter(|v| !v.is_empty())
 }
 
+/// Escape text to be put in HTML content or a quoted attribute.
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
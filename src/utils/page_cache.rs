@@ -0,0 +1,276 @@
+// Cache of the rendered public pages in Redis, for anonymous visitors.
+// - A page is stored under "quanweb:page:{lang}:{path}", with its headers and body.
+// - Each page is also recorded in the sets of the groups it depends on ("quanweb:pagegroup:*"),
+//   so that a change of content can remove only the pages which show it.
+// - Every page is in the "all" group, which is used to flush the whole cache.
+
+use std::num::NonZeroU16;
+use std::time::{Duration, SystemTime};
+
+use axum::extract::{FromRef, Query};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, LAST_MODIFIED, VARY};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use fred::error::{Error as FredError, ErrorKind};
+use fred::prelude::*;
+use fred::types::Expiration;
+use headers::{ETag, HeaderMapExt, LastModified};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::http_cache::Validators;
+use crate::types::AppState;
+
+const KEY_PAGE_PREFIX: &str = "quanweb:page";
+const KEY_GROUP_PREFIX: &str = "quanweb:pagegroup";
+/// Pages are invalidated when content changes, the expiry is only a safety net.
+pub const PAGE_CACHE_TTL: Duration = Duration::from_secs(15 * 60);
+/// Response headers which are kept in the cache. Others, like "set-cookie", must not be shared.
+const STORED_HEADERS: [HeaderName; 5] = [CONTENT_TYPE, CACHE_CONTROL, ETAG, LAST_MODIFIED, VARY];
+
+/// What a cached page depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheGroup {
+    /// The page of one post
+    Post(Uuid),
+    /// Home page and the lists of posts, by category or not
+    PostLists,
+    /// Atom feeds, sitemap and llms.txt
+    Feeds,
+    /// Lists of talks and books
+    Minors,
+    /// Every page. Categories, for example, are shown in the navigation bar of all pages.
+    All,
+}
+
+impl CacheGroup {
+    /// Groups to invalidate when a post changes: its page, the lists of posts and the feeds
+    pub fn of_post(post_id: Uuid) -> Vec<Self> {
+        vec![Self::Post(post_id), Self::PostLists, Self::Feeds]
+    }
+
+    pub fn key(&self) -> String {
+        match self {
+            Self::Post(id) => format!("{KEY_GROUP_PREFIX}:post:{id}"),
+            Self::PostLists => format!("{KEY_GROUP_PREFIX}:post-lists"),
+            Self::Feeds => format!("{KEY_GROUP_PREFIX}:feeds"),
+            Self::Minors => format!("{KEY_GROUP_PREFIX}:minors"),
+            Self::All => format!("{KEY_GROUP_PREFIX}:all"),
+        }
+    }
+}
+
+/// Response extension, set by handlers whose pages can be cached
+#[derive(Debug, Clone)]
+pub struct CacheGroups(pub Vec<CacheGroup>);
+
+/// Make the cache key of a page. Only the "page" parameter is kept, normalized
+/// the way the handlers read it, so that the same page is stored once.
+/// With any other parameter (e.g. "q" of search), the page is not to be cached,
+/// so that made-up query strings cannot fill the cache.
+pub fn page_key(lang: &str, uri: &Uri) -> Option<String> {
+    let Query(params) = Query::<Vec<(String, String)>>::try_from_uri(uri).ok()?;
+    let mut page = None;
+    for (name, value) in params {
+        match name.as_str() {
+            "page" if page.is_none() => page = Some(value.parse().unwrap_or(NonZeroU16::MIN)),
+            _ => return None,
+        }
+    }
+    let mut key = format!("{KEY_PAGE_PREFIX}:{lang}:{}", uri.path());
+    if let Some(page) = page.filter(|&p| p != NonZeroU16::MIN) {
+        key.push_str(&format!("?page={page}"));
+    }
+    Some(key)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl CachedPage {
+    /// Keep the content of a response. Return `None` if the body is not text.
+    pub fn new(headers: &HeaderMap, body: &[u8]) -> Option<Self> {
+        let body = String::from_utf8(body.to_vec()).ok()?;
+        let headers = STORED_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = headers.get(name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        Some(Self { headers, body })
+    }
+
+    pub fn header_map(&self) -> HeaderMap {
+        self.headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = HeaderName::try_from(name.as_str()).ok()?;
+                let value = HeaderValue::try_from(value.as_str()).ok()?;
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// Answer from the cache, with 304 if the client's copy is still fresh.
+    pub fn respond(self, validators: &Validators) -> Response {
+        let mut headers = self.header_map();
+        let etag: Option<ETag> = headers.typed_get();
+        let last_modified = headers.typed_get::<LastModified>().map(SystemTime::from);
+        if let Some(etag) = etag {
+            if validators.is_fresh(&etag, last_modified) {
+                headers.remove(CONTENT_TYPE);
+                return (StatusCode::NOT_MODIFIED, headers).into_response();
+            }
+        }
+        headers.insert("x-cache", HeaderValue::from_static("hit"));
+        (headers, self.body).into_response()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PageCache {
+    pool: Pool,
+}
+
+impl FromRef<AppState> for PageCache {
+    fn from_ref(state: &AppState) -> Self {
+        Self::new(state.redis.clone())
+    }
+}
+
+impl PageCache {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<CachedPage>, FredError> {
+        let raw: Option<String> = self.pool.get(key).await?;
+        // A page which cannot be parsed, after our format changes, is just a miss
+        Ok(raw.and_then(|s| serde_json::from_str(&s).ok()))
+    }
+
+    pub async fn put(
+        &self,
+        key: &str,
+        page: &CachedPage,
+        groups: &[CacheGroup],
+    ) -> Result<(), FredError> {
+        let raw = serde_json::to_string(page)
+            .map_err(|e| FredError::new(ErrorKind::Parse, e.to_string()))?;
+        let ttl = PAGE_CACHE_TTL.as_secs() as i64;
+        let _: () = self
+            .pool
+            .set(key, raw, Some(Expiration::EX(ttl)), None, false)
+            .await?;
+        for group in groups.iter().chain([&CacheGroup::All]) {
+            let group_key = group.key();
+            let _: i64 = self.pool.sadd(&group_key, key).await?;
+            // Outlive the pages in it, the set is refreshed whenever a page is added
+            let _: bool = self.pool.expire(&group_key, ttl * 2, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the pages of the given groups. Return the number of removed pages.
+    pub async fn invalidate(&self, groups: &[CacheGroup]) -> Result<i64, FredError> {
+        let mut count = 0;
+        for group in groups {
+            let group_key = group.key();
+            let pages: Vec<String> = self.pool.smembers(&group_key).await?;
+            if !pages.is_empty() {
+                count += self.pool.del::<i64, _>(pages).await?;
+            }
+            let _: i64 = self.pool.del(&group_key).await?;
+        }
+        tracing::debug!("Removed {count} cached pages of {groups:?}");
+        Ok(count)
+    }
+
+    /// Invalidate from a request handler. The content is already saved at this point,
+    /// so we only log the error.
+    pub async fn invalidate_or_warn(&self, groups: &[CacheGroup]) {
+        if let Err(e) = self.invalidate(groups).await {
+            tracing::warn!("Failed to invalidate cached pages of {:?}: {}", groups, e);
+        }
+    }
+
+    pub async fn flush(&self) -> Result<i64, FredError> {
+        self.invalidate(&[CacheGroup::All]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::IF_NONE_MATCH;
+
+    use super::*;
+    use crate::utils::http_cache::make_etag;
+
+    #[test]
+    fn test_keys() {
+        let key = |uri: &str| page_key("vi", &uri.parse().unwrap());
+        assert_eq!(
+            key("/blog/?page=2").unwrap(),
+            "quanweb:page:vi:/blog/?page=2"
+        );
+        assert_eq!(
+            key("/blog/?page=02").unwrap(),
+            "quanweb:page:vi:/blog/?page=2"
+        );
+        assert_eq!(key("/blog/?page=1").unwrap(), "quanweb:page:vi:/blog/");
+        assert_eq!(key("/blog/?page=x").unwrap(), "quanweb:page:vi:/blog/");
+        assert_eq!(key("/search/?q=+rust+&page=3"), None);
+        assert_eq!(key("/search/?q="), None);
+        assert_eq!(key("/blog/?page=2&utm_source=x"), None);
+        assert_eq!(key("/blog/?page=2&page=3"), None);
+        let id = Uuid::nil();
+        assert_eq!(
+            CacheGroup::Post(id).key(),
+            format!("quanweb:pagegroup:post:{id}")
+        );
+    }
+
+    #[test]
+    fn test_cached_page_keeps_only_shareable_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        headers.insert("set-cookie", HeaderValue::from_static("id=secret"));
+        let page = CachedPage::new(&headers, b"<p>Hello</p>").unwrap();
+        let restored = page.header_map();
+        assert!(restored.contains_key(CONTENT_TYPE));
+        assert!(!restored.contains_key("set-cookie"));
+    }
+
+    #[test]
+    fn test_cached_page_not_modified() {
+        let body = "<p>Hello</p>";
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        headers.typed_insert(make_etag(body.as_bytes()));
+        let page = CachedPage::new(&headers, body.as_bytes()).unwrap();
+
+        let response = page.clone().respond(&Validators::default());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-cache"], "hit");
+
+        let mut request_headers = HeaderMap::new();
+        let etag = page.header_map()[ETAG].clone();
+        request_headers.insert(IF_NONE_MATCH, etag);
+        let validators = Validators {
+            if_none_match: request_headers.typed_get(),
+            if_modified_since: None,
+        };
+        let response = page.respond(&validators);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+}
@@ -0,0 +1,71 @@
+// Fixed-window rate limiting. Hits are counted in Redis, so the limit is shared by all processes.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use fred::error::Error as FredError;
+use fred::prelude::*;
+use fred::types::{Expiration, SetOptions};
+
+const KEY_PREFIX: &str = "quanweb:ratelimit";
+
+/// Count one hit of `action` by `client` (IP address, username...).
+/// Return `false` if the client has done this action more than `limit` times in the current window.
+pub async fn check_rate_limit(
+    redis: &Pool,
+    action: &str,
+    client: &str,
+    limit: u32,
+    window: Duration,
+) -> Result<bool, FredError> {
+    let key = format!("{KEY_PREFIX}:{action}:{client}");
+    // Start the window on first hit. NX makes sure the counter is not reset by later hits.
+    let expiration = Expiration::EX(window.as_secs().max(1) as i64);
+    let _set: Option<String> = redis
+        .set(&key, 0, Some(expiration), Some(SetOptions::NX), false)
+        .await?;
+    let hits: i64 = redis.incr(&key).await?;
+    Ok(hits <= limit as i64)
+}
+
+/// Get IP address of the client. Our app runs behind a reverse proxy, so we read it from the headers
+/// which the proxy sets: "X-Real-IP", or else the last hop of "X-Forwarded-For", which is the address
+/// the proxy saw. The first hops of "X-Forwarded-For" are sent by the client and cannot be trusted.
+/// Values which are not IP addresses are ignored.
+pub fn get_client_ip(headers: &HeaderMap) -> Option<String> {
+    let real_ip = headers.get("x-real-ip").and_then(|v| v.to_str().ok());
+    let last_forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .next_back()
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.rsplit(',').next());
+    let ip: IpAddr = real_ip.or(last_forwarded)?.trim().parse().ok()?;
+    Some(ip.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_client_ip_trusts_proxy_hop() {
+        let mut headers = HeaderMap::new();
+        // The first hop is made up by the client, the last one is added by our proxy
+        headers.insert("x-forwarded-for", "1.2.3.4, 203.0.113.5".parse().unwrap());
+        assert_eq!(get_client_ip(&headers).as_deref(), Some("203.0.113.5"));
+        headers.insert("x-real-ip", "198.51.100.7".parse().unwrap());
+        assert_eq!(get_client_ip(&headers).as_deref(), Some("198.51.100.7"));
+        assert_eq!(get_client_ip(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_get_client_ip_rejects_invalid() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "x".repeat(60).parse().unwrap());
+        assert_eq!(get_client_ip(&headers), None);
+        headers.insert("x-real-ip", " 2001:db8::1 ".parse().unwrap());
+        assert_eq!(get_client_ip(&headers).as_deref(), Some("2001:db8::1"));
+    }
+}
@@ -0,0 +1,899 @@
+// Converter for the subset of reStructuredText which our old posts are written in.
+// Supported:
+// - Section titles, paragraphs, bullet / enumerated / definition lists, block quotes,
+//   literal blocks (after "::") and transitions.
+// - Directives: code-block (code, sourcecode), highlight, image, figure, admonitions (note, warning...)
+//   and raw HTML. Other directives and comments are dropped.
+// - Simple tables and grid tables (without spanning cells).
+// - Inline markup: emphasis, strong, inline literal, hyperlinks, hyperlink targets and common roles.
+// Code blocks go through the same highlighter adapter as Markdown, so they get the same markup.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use comrak::adapters::SyntaxHighlighterAdapter;
+use regex::Regex;
+
+use super::escape_html;
+use super::markdown::make_highlighter;
+use crate::consts::ATTR_CODEFENCE_EXTRA;
+
+const ADORNMENT_CHARS: &str = "=-~^\"'`#*+_:.<>";
+const ADMONITIONS: [&str; 9] = [
+    "attention",
+    "caution",
+    "danger",
+    "error",
+    "hint",
+    "important",
+    "note",
+    "tip",
+    "warning",
+];
+// Number of lines the excerpt is made from, same as Markdown posts
+const EXCERPT_LINES: usize = 7;
+
+static RE_TARGET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*\.\. _`?([^`:]+)`?:\s*(\S*)\s*$").unwrap());
+static RE_DIRECTIVE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\.\.\s+([\w-]+)::\s*(.*)$").unwrap());
+static RE_OPTION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^:([\w-]+):\s*(.*)$").unwrap());
+static RE_BULLET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([-*+•])( +|$)").unwrap());
+static RE_ENUMERATOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d+|#)[.)]( +|$)").unwrap());
+static RE_ROLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^:([\w-]+):`").unwrap());
+
+pub fn rst_to_html(source: &str) -> String {
+    let lines = prepare_lines(source);
+    let mut renderer = Renderer::new(&lines);
+    renderer.render_blocks(&lines);
+    renderer.out
+}
+
+/// Make excerpt from the first lines, without cutting in the middle of a block.
+pub fn make_rst_excerpt(source: &str) -> String {
+    let lines = prepare_lines(source);
+    let mut end = lines.len().min(EXCERPT_LINES);
+    while end < lines.len() && !is_blank(&lines[end]) {
+        end += 1;
+    }
+    // Include the indented content (of directive, list item...) which the last block owns
+    while end < lines.len() && (is_blank(&lines[end]) || indent_of(&lines[end]) > 0) {
+        end += 1;
+    }
+    let mut renderer = Renderer::new(&lines);
+    renderer.render_blocks(&lines[..end]);
+    renderer.out + "..."
+}
+
+fn prepare_lines(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .map(|l| l.replace('\t', "    ").trim_end().to_string())
+        .collect()
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Find the end of the block starting at `start`, which contains lines indented at least `min_indent`.
+/// Trailing blank lines are not included.
+fn find_indented_end(lines: &[String], start: usize, min_indent: usize) -> usize {
+    let mut end = start;
+    let mut last_content = start;
+    while end < lines.len() {
+        let line = &lines[end];
+        if !is_blank(line) {
+            if indent_of(line) < min_indent {
+                break;
+            }
+            last_content = end + 1;
+        }
+        end += 1;
+    }
+    last_content
+}
+
+/// Remove the common indentation
+fn dedent(lines: &[String]) -> Vec<String> {
+    let min_indent = lines
+        .iter()
+        .filter(|l| !is_blank(l))
+        .map(|l| indent_of(l))
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| l.get(min_indent..).unwrap_or_default().to_string())
+        .collect()
+}
+
+fn is_adornment(line: &str) -> bool {
+    let mut chars = line.chars();
+    match chars.next() {
+        Some(c) if ADORNMENT_CHARS.contains(c) => chars.all(|x| x == c),
+        _ => false,
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Columns of simple table, as char ranges. The border looks like "=====  ======".
+fn simple_table_columns(line: &str) -> Option<Vec<(usize, usize)>> {
+    if !line.starts_with('=') || !line.chars().all(|c| c == '=' || c == ' ') {
+        return None;
+    }
+    let mut columns = Vec::new();
+    let mut start = None;
+    for (i, c) in line.chars().chain(std::iter::once(' ')).enumerate() {
+        match (c, start) {
+            ('=', None) => start = Some(i),
+            (' ', Some(s)) => {
+                columns.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    (columns.len() > 1).then_some(columns)
+}
+
+fn is_grid_border(line: &str) -> bool {
+    line.starts_with("+-") || line.starts_with("+=")
+}
+
+fn char_slice(chars: &[char], start: usize, end: Option<usize>) -> String {
+    let end = end.unwrap_or(chars.len()).min(chars.len());
+    if start >= end {
+        return String::new();
+    }
+    chars[start..end].iter().collect()
+}
+
+fn write_table(out: &mut String, header: Option<Vec<String>>, rows: Vec<Vec<String>>) {
+    out.push_str("<table>\n");
+    if let Some(header) = header {
+        out.push_str("<thead>\n<tr>\n");
+        for cell in header {
+            out.push_str(&format!("<th>{cell}</th>\n"));
+        }
+        out.push_str("</tr>\n</thead>\n");
+    }
+    out.push_str("<tbody>\n");
+    for row in rows {
+        out.push_str("<tr>\n");
+        for cell in row {
+            out.push_str(&format!("<td>{cell}</td>\n"));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</tbody>\n</table>\n");
+}
+
+struct Renderer {
+    // Hyperlink targets, defined anywhere in the document
+    targets: HashMap<String, String>,
+    // Adornment styles of section titles, in order of appearance. The position is the heading level.
+    title_styles: Vec<(char, bool)>,
+    // Language for literal blocks, set by "highlight" directive
+    default_lang: Option<String>,
+    out: String,
+}
+
+impl Renderer {
+    fn new(lines: &[String]) -> Self {
+        let targets = lines
+            .iter()
+            .filter_map(|l| RE_TARGET.captures(l))
+            .map(|c| (normalize_name(&c[1]), c[2].to_string()))
+            .collect();
+        Self {
+            targets,
+            title_styles: Vec::new(),
+            default_lang: None,
+            out: String::new(),
+        }
+    }
+
+    /// Render nested blocks to a separate string
+    fn render_nested(&mut self, lines: &[String]) -> String {
+        let saved = std::mem::take(&mut self.out);
+        self.render_blocks(lines);
+        std::mem::replace(&mut self.out, saved)
+    }
+
+    fn render_blocks(&mut self, lines: &[String]) {
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i].as_str();
+            if is_blank(line) {
+                i += 1;
+                continue;
+            }
+            if indent_of(line) > 0 {
+                let end = find_indented_end(lines, i, 1);
+                let inner = self.render_nested(&dedent(&lines[i..end]));
+                self.out
+                    .push_str(&format!("<blockquote>\n{inner}</blockquote>\n"));
+                i = end;
+                continue;
+            }
+            let next = lines.get(i + 1).map(String::as_str);
+            i = if line == ".." || line.starts_with(".. ") {
+                self.render_explicit_markup(lines, i)
+            } else if is_adornment(line)
+                && line.len() >= 4
+                && next.is_none_or(is_blank)
+                && (i == 0 || is_blank(&lines[i - 1]))
+            {
+                self.out.push_str("<hr />\n");
+                i + 1
+            } else if is_adornment(line)
+                && next.is_some_and(|t| !is_blank(t))
+                && lines.get(i + 2).is_some_and(|u| u == line)
+            {
+                let title = next.unwrap_or_default().trim();
+                self.render_title(title, line, true);
+                i + 3
+            } else if next.is_some_and(|u| {
+                is_adornment(u) && (u.len() >= 3 || u.chars().count() >= line.chars().count())
+            }) {
+                self.render_title(line, next.unwrap_or_default(), false);
+                i + 2
+            } else if is_grid_border(line) {
+                self.render_grid_table(lines, i)
+            } else if let Some(columns) = simple_table_columns(line) {
+                self.render_simple_table(lines, i, &columns)
+            } else if RE_BULLET.is_match(line) {
+                self.render_list(lines, i, &RE_BULLET, false)
+            } else if RE_ENUMERATOR.is_match(line) {
+                self.render_list(lines, i, &RE_ENUMERATOR, true)
+            } else if next.is_some_and(|n| !is_blank(n) && indent_of(n) > 0)
+                && !line.ends_with("::")
+            {
+                self.render_definition_list(lines, i)
+            } else {
+                self.render_paragraph(lines, i)
+            };
+        }
+    }
+
+    fn render_title(&mut self, title: &str, adornment: &str, overline: bool) {
+        let style = (adornment.chars().next().unwrap_or('='), overline);
+        let level = match self.title_styles.iter().position(|&s| s == style) {
+            Some(pos) => pos + 1,
+            None => {
+                self.title_styles.push(style);
+                self.title_styles.len()
+            }
+        };
+        let level = level.min(6);
+        let content = self.render_inline(title);
+        self.out
+            .push_str(&format!("<h{level}>{content}</h{level}>\n"));
+    }
+
+    fn render_paragraph(&mut self, lines: &[String], start: usize) -> usize {
+        let mut end = start;
+        while end < lines.len() && !is_blank(&lines[end]) && indent_of(&lines[end]) == 0 {
+            end += 1;
+        }
+        let mut text = lines[start..end].join("\n");
+        // A paragraph ending with "::" introduces a literal block
+        let has_literal = text.ends_with("::");
+        if has_literal {
+            text.truncate(text.len() - 2);
+            if text.ends_with(|c: char| c.is_whitespace()) || text.is_empty() {
+                text = text.trim_end().to_string();
+            } else {
+                text.push(':');
+            }
+        }
+        if !text.is_empty() {
+            let content = self.render_inline(&text);
+            self.out.push_str(&format!("<p>{content}</p>\n"));
+        }
+        if !has_literal {
+            return end;
+        }
+        let mut body_start = end;
+        while body_start < lines.len() && is_blank(&lines[body_start]) {
+            body_start += 1;
+        }
+        if body_start >= lines.len() || indent_of(&lines[body_start]) == 0 {
+            return body_start;
+        }
+        let body_end = find_indented_end(lines, body_start, 1);
+        let code = dedent(&lines[body_start..body_end]).join("\n");
+        let lang = self.default_lang.clone();
+        self.write_code_block(lang.as_deref(), &code, None);
+        body_end
+    }
+
+    fn render_list(
+        &mut self,
+        lines: &[String],
+        start: usize,
+        marker: &Regex,
+        ordered: bool,
+    ) -> usize {
+        let mut items = Vec::new();
+        let mut first_number = None;
+        let mut i = start;
+        while i < lines.len() {
+            let Some(m) = marker.captures(&lines[i]) else {
+                break;
+            };
+            if ordered && first_number.is_none() {
+                first_number = Some(m[1].parse::<u32>().unwrap_or(1));
+            }
+            // The marker may be a multi-byte bullet, like "•", so the column is counted in characters.
+            // The following lines of the item are indented with spaces, to the same column.
+            let content_col = m[0].chars().count();
+            let end = find_indented_end(lines, i + 1, content_col.max(1));
+            let mut item_lines = vec![lines[i][m[0].len()..].to_string()];
+            item_lines.extend(
+                lines[i + 1..end]
+                    .iter()
+                    .map(|l| l.get(content_col..).unwrap_or_default().to_string()),
+            );
+            let html = self.render_nested(&item_lines);
+            // Single paragraph item is rendered without <p>, like Markdown tight list
+            let html = match html
+                .strip_prefix("<p>")
+                .and_then(|h| h.strip_suffix("</p>\n"))
+            {
+                Some(inner) if !inner.contains("<p>") => inner.to_string(),
+                _ => html,
+            };
+            items.push(html);
+            i = end;
+            // Items may be separated by blank lines
+            let mut next = i;
+            while next < lines.len() && is_blank(&lines[next]) {
+                next += 1;
+            }
+            if next < lines.len() && marker.is_match(&lines[next]) {
+                i = next;
+            } else {
+                break;
+            }
+        }
+        let tag = if ordered { "ol" } else { "ul" };
+        match first_number {
+            Some(n) if n > 1 => self.out.push_str(&format!("<ol start=\"{n}\">\n")),
+            _ => self.out.push_str(&format!("<{tag}>\n")),
+        }
+        for item in items {
+            self.out.push_str(&format!("<li>{item}</li>\n"));
+        }
+        self.out.push_str(&format!("</{tag}>\n"));
+        i
+    }
+
+    fn render_definition_list(&mut self, lines: &[String], start: usize) -> usize {
+        self.out.push_str("<dl>\n");
+        let mut i = start;
+        loop {
+            let term = self.render_inline(&lines[i]);
+            let end = find_indented_end(lines, i + 1, 1);
+            let definition = self.render_nested(&dedent(&lines[i + 1..end]));
+            self.out
+                .push_str(&format!("<dt>{term}</dt>\n<dd>\n{definition}</dd>\n"));
+            i = end;
+            let mut next = i;
+            while next < lines.len() && is_blank(&lines[next]) {
+                next += 1;
+            }
+            let is_term = next + 1 < lines.len()
+                && indent_of(&lines[next]) == 0
+                && !is_blank(&lines[next + 1])
+                && indent_of(&lines[next + 1]) > 0;
+            if !is_term {
+                break;
+            }
+            i = next;
+        }
+        self.out.push_str("</dl>\n");
+        i
+    }
+
+    fn render_grid_table(&mut self, lines: &[String], start: usize) -> usize {
+        let mut end = start;
+        while end < lines.len() && (lines[end].starts_with('+') || lines[end].starts_with('|')) {
+            end += 1;
+        }
+        let first: Vec<char> = lines[start].chars().collect();
+        let boundaries: Vec<usize> = first
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &c)| (c == '+').then_some(i))
+            .collect();
+        let mut header = None;
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut current: Vec<Vec<String>> = Vec::new();
+        for line in &lines[start + 1..end] {
+            if is_grid_border(line) {
+                let row = current
+                    .drain(..)
+                    .map(|parts| self.render_inline(&parts.join(" ")))
+                    .collect::<Vec<_>>();
+                if line.starts_with("+=") && header.is_none() {
+                    header = Some(row);
+                } else if !row.is_empty() {
+                    rows.push(row);
+                }
+                continue;
+            }
+            let chars: Vec<char> = line.chars().collect();
+            if current.is_empty() {
+                current = vec![Vec::new(); boundaries.len().saturating_sub(1)];
+            }
+            for (col, pair) in boundaries.windows(2).enumerate() {
+                let text = char_slice(&chars, pair[0] + 1, Some(pair[1]));
+                let text = text.trim();
+                if !text.is_empty() {
+                    current[col].push(text.to_string());
+                }
+            }
+        }
+        write_table(&mut self.out, header, rows);
+        end
+    }
+
+    fn render_simple_table(
+        &mut self,
+        lines: &[String],
+        start: usize,
+        columns: &[(usize, usize)],
+    ) -> usize {
+        let mut borders = vec![start];
+        let mut rows: Vec<(usize, Vec<Vec<String>>)> = Vec::new();
+        let mut i = start + 1;
+        while i < lines.len() {
+            let line = &lines[i];
+            if simple_table_columns(line).is_some() {
+                borders.push(i);
+                i += 1;
+                // The bottom border is followed by blank line or end of text
+                if lines.get(i).is_none_or(|l| is_blank(l)) {
+                    break;
+                }
+                continue;
+            }
+            if is_blank(line) {
+                i += 1;
+                continue;
+            }
+            let chars: Vec<char> = line.chars().collect();
+            let cells: Vec<String> = columns
+                .iter()
+                .enumerate()
+                .map(|(idx, &(s, e))| {
+                    // The last column can be longer than its border
+                    let e = (idx + 1 < columns.len()).then_some(e);
+                    char_slice(&chars, s, e).trim().to_string()
+                })
+                .collect();
+            match rows.last_mut() {
+                // Blank first column means this line continues the previous row
+                Some((_, prev)) if cells[0].is_empty() => {
+                    for (p, c) in prev.iter_mut().zip(cells) {
+                        if !c.is_empty() {
+                            p.push(c);
+                        }
+                    }
+                }
+                _ => rows.push((i, cells.into_iter().map(|c| vec![c]).collect())),
+            }
+            i += 1;
+        }
+        let header_end = (borders.len() > 2).then(|| borders[1]);
+        let mut header = None;
+        let mut body = Vec::new();
+        for (line_no, cells) in rows {
+            let row: Vec<String> = cells
+                .iter()
+                .map(|parts| self.render_inline(&parts.join(" ")))
+                .collect();
+            if header_end.is_some_and(|h| line_no < h) {
+                header = Some(row);
+            } else {
+                body.push(row);
+            }
+        }
+        write_table(&mut self.out, header, body);
+        i
+    }
+
+    fn render_explicit_markup(&mut self, lines: &[String], start: usize) -> usize {
+        let end = find_indented_end(lines, start + 1, 1);
+        let Some(caps) = RE_DIRECTIVE.captures(&lines[start]) else {
+            // Comments and hyperlink targets produce no output
+            return end;
+        };
+        let name = caps[1].to_lowercase();
+        let argument = caps[2].trim().to_string();
+        let block = dedent(&lines[start + 1..end]);
+        let mut options = HashMap::new();
+        let mut content_start = 0;
+        for line in &block {
+            let Some(opt) = RE_OPTION.captures(line) else {
+                break;
+            };
+            options.insert(opt[1].to_lowercase(), opt[2].trim().to_string());
+            content_start += 1;
+        }
+        while block.get(content_start).is_some_and(|l| is_blank(l)) {
+            content_start += 1;
+        }
+        let content = &block[content_start..];
+        match name.as_str() {
+            "code-block" | "code" | "sourcecode" => {
+                let lang = argument.split_whitespace().next();
+                let numbered =
+                    options.contains_key("linenos") || options.contains_key("number-lines");
+                let start_line = options
+                    .get("lineno-start")
+                    .or(options.get("number-lines"))
+                    .and_then(|s| s.parse::<u8>().ok());
+                let extra = (numbered || start_line.is_some())
+                    .then(|| format!("{{lines: true, start_line: {}}}", start_line.unwrap_or(1)));
+                let code = dedent(content).join("\n");
+                self.write_code_block(lang, code.trim_matches('\n'), extra);
+            }
+            "highlight" => {
+                self.default_lang = argument.split_whitespace().next().map(String::from);
+            }
+            "image" => {
+                let img = self.make_image(&argument, &options);
+                self.out.push_str(&format!("<p>{img}</p>\n"));
+            }
+            "figure" => {
+                let img = self.make_image(&argument, &options);
+                let caption_end = content
+                    .iter()
+                    .position(|l| is_blank(l))
+                    .unwrap_or(content.len());
+                let caption = self.render_inline(&content[..caption_end].join("\n"));
+                let legend = self.render_nested(&content[caption_end..]);
+                self.out.push_str(&format!(
+                    "<figure>\n{img}\n<figcaption>{caption}</figcaption>\n{legend}</figure>\n"
+                ));
+            }
+            "admonition" => {
+                let title = self.render_inline(&argument);
+                let inner = self.render_nested(content);
+                self.write_admonition("admonition", &title, &inner);
+            }
+            n if ADMONITIONS.contains(&n) => {
+                // The text can start right after "::"
+                let mut body = vec![argument.clone()];
+                body.extend(content.iter().cloned());
+                let inner = self.render_nested(&body);
+                let mut title = n.to_string();
+                title[..1].make_ascii_uppercase();
+                self.write_admonition(n, &title, &inner);
+            }
+            "raw" if argument == "html" => {
+                self.out.push_str(&content.join("\n"));
+                self.out.push('\n');
+            }
+            _ => {
+                tracing::debug!("Unsupported RST directive: {}", name);
+            }
+        }
+        end
+    }
+
+    fn make_image(&self, uri: &str, options: &HashMap<String, String>) -> String {
+        let uri = escape_html(&uri.split_whitespace().collect::<String>());
+        let alt = escape_html(options.get("alt").map(String::as_str).unwrap_or_default());
+        let mut attrs = format!("src=\"{uri}\" alt=\"{alt}\"");
+        for name in ["width", "height"] {
+            if let Some(v) = options.get(name) {
+                attrs.push_str(&format!(" {name}=\"{}\"", escape_html(v)));
+            }
+        }
+        if let Some(align) = options.get("align") {
+            attrs.push_str(&format!(" class=\"align-{}\"", escape_html(align)));
+        }
+        let img = format!("<img {attrs} />");
+        match options.get("target") {
+            Some(target) => format!("<a href=\"{}\">{img}</a>", escape_html(target)),
+            None => img,
+        }
+    }
+
+    fn write_admonition(&mut self, kind: &str, title: &str, inner: &str) {
+        self.out.push_str(&format!(
+            "<div class=\"admonition {kind}\">\n<p class=\"admonition-title\">{title}</p>\n{inner}</div>\n"
+        ));
+    }
+
+    fn write_code_block(&mut self, lang: Option<&str>, code: &str, extra: Option<String>) {
+        let adapter = make_highlighter();
+        let mut code_attrs: HashMap<&'static str, Cow<'_, str>> = HashMap::new();
+        if let Some(lang) = lang {
+            code_attrs.insert("class", Cow::from(format!("language-{lang}")));
+        }
+        if let Some(extra) = extra {
+            code_attrs.insert(ATTR_CODEFENCE_EXTRA, Cow::from(extra));
+        }
+        let code = format!("{code}\n");
+        // Writing to String never fails
+        let _ = adapter.write_pre_tag(&mut self.out, HashMap::new());
+        let _ = adapter.write_code_tag(&mut self.out, code_attrs);
+        let _ = adapter.write_highlighted(&mut self.out, lang, &code);
+        self.out.push_str("</code></pre>\n");
+    }
+
+    fn render_inline(&self, text: &str) -> String {
+        InlineParser {
+            chars: text.chars().collect(),
+            targets: &self.targets,
+        }
+        .render()
+    }
+}
+
+struct InlineParser<'a> {
+    chars: Vec<char>,
+    targets: &'a HashMap<String, String>,
+}
+
+impl InlineParser<'_> {
+    // Inline markup must start after whitespace or some punctuation, and not be followed by whitespace.
+    fn can_start(&self, i: usize, delim_len: usize) -> bool {
+        let before_ok = i == 0 || {
+            let c = self.chars[i - 1];
+            c.is_whitespace() || "'\"([{<-/:".contains(c)
+        };
+        let after_ok = self
+            .chars
+            .get(i + delim_len)
+            .is_some_and(|c| !c.is_whitespace());
+        before_ok && after_ok
+    }
+
+    fn starts_with(&self, i: usize, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(k, c)| self.chars.get(i + k) == Some(&c))
+    }
+
+    /// Find the end-string `delim`, searching from `from`.
+    fn find_end(&self, from: usize, delim: &str) -> Option<usize> {
+        let len = delim.chars().count();
+        (from..self.chars.len()).find(|&j| {
+            j > from
+                && self.starts_with(j, delim)
+                && !self.chars[j - 1].is_whitespace()
+                && self
+                    .chars
+                    .get(j + len)
+                    .is_none_or(|&c| c.is_whitespace() || "'\")]}>-/:.,;!?\\_`*".contains(c))
+        })
+    }
+
+    fn slice(&self, start: usize, end: usize) -> String {
+        self.chars[start..end].iter().collect()
+    }
+
+    fn make_link(&self, url: &str, text: &str) -> String {
+        format!("<a href=\"{}\">{}</a>", escape_html(url), escape_html(text))
+    }
+
+    /// Handle `text <url>`_, `name`_ and `interpreted text`. Return the HTML and the next position.
+    fn render_backquoted(&self, i: usize) -> Option<(String, usize)> {
+        let end = self.find_end(i + 1, "`")?;
+        let inner = self.slice(i + 1, end);
+        let mut next = end + 1;
+        let is_reference = self.chars.get(next) == Some(&'_');
+        if !is_reference {
+            return Some((format!("<em>{}</em>", escape_html(&inner)), next));
+        }
+        next += 1;
+        if self.chars.get(next) == Some(&'_') {
+            next += 1;
+        }
+        let html = match inner.rfind('<').filter(|_| inner.ends_with('>')) {
+            Some(pos) => {
+                let url = &inner[pos + 1..inner.len() - 1];
+                let text = inner[..pos].trim();
+                self.make_link(url, if text.is_empty() { url } else { text })
+            }
+            None => match self.targets.get(&normalize_name(&inner)) {
+                Some(url) => self.make_link(url, &inner),
+                None => escape_html(&inner),
+            },
+        };
+        Some((html, next))
+    }
+
+    fn render_role(&self, i: usize) -> Option<(String, usize)> {
+        let rest = self.slice(i, self.chars.len());
+        let caps = RE_ROLE.captures(&rest)?;
+        let role = caps[1].to_string();
+        let open = i + caps[0].chars().count();
+        let end = self.find_end(open, "`")?;
+        let inner = self.slice(open, end);
+        let html = match role.as_str() {
+            "code" | "literal" | "samp" | "file" | "command" | "kbd" => {
+                format!("<code>{}</code>", escape_html(&inner))
+            }
+            "emphasis" | "title-reference" => format!("<em>{}</em>", escape_html(&inner)),
+            "strong" => format!("<strong>{}</strong>", escape_html(&inner)),
+            "sup" | "superscript" => format!("<sup>{}</sup>", escape_html(&inner)),
+            "sub" | "subscript" => format!("<sub>{}</sub>", escape_html(&inner)),
+            // Sphinx cross references, like :ref:`Title <label>`, keep the title
+            _ => {
+                let title = match inner.rfind(" <") {
+                    Some(pos) if inner.ends_with('>') => &inner[..pos],
+                    _ => inner.as_str(),
+                };
+                escape_html(title)
+            }
+        };
+        Some((html, end + 1))
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut plain = String::new();
+        let mut i = 0;
+        let n = self.chars.len();
+        while i < n {
+            let c = self.chars[i];
+            if c == '\\' && i + 1 < n {
+                // Escaped whitespace is removed
+                if !self.chars[i + 1].is_whitespace() {
+                    plain.push(self.chars[i + 1]);
+                }
+                i += 2;
+                continue;
+            }
+            let rendered = if self.starts_with(i, "``") && self.can_start(i, 2) {
+                self.find_end(i + 2, "``").map(|end| {
+                    let code = self.slice(i + 2, end);
+                    (format!("<code>{}</code>", escape_html(&code)), end + 2)
+                })
+            } else if self.starts_with(i, "**") && self.can_start(i, 2) {
+                self.find_end(i + 2, "**").map(|end| {
+                    let text = self.slice(i + 2, end);
+                    (format!("<strong>{}</strong>", escape_html(&text)), end + 2)
+                })
+            } else if c == '*' && self.can_start(i, 1) {
+                self.find_end(i + 1, "*").map(|end| {
+                    let text = self.slice(i + 1, end);
+                    (format!("<em>{}</em>", escape_html(&text)), end + 1)
+                })
+            } else if c == '`' && self.can_start(i, 1) {
+                self.render_backquoted(i)
+            } else if c == ':' && self.can_start(i, 1) {
+                self.render_role(i)
+            } else if (self.starts_with(i, "http://") || self.starts_with(i, "https://"))
+                && (i == 0 || !self.chars[i - 1].is_alphanumeric())
+            {
+                let mut end = i;
+                while end < n
+                    && !self.chars[end].is_whitespace()
+                    && !"<>\"".contains(self.chars[end])
+                {
+                    end += 1;
+                }
+                // Trailing punctuation is not part of URL
+                while end > i && ".,;:!?)'".contains(self.chars[end - 1]) {
+                    end -= 1;
+                }
+                let url = self.slice(i, end);
+                Some((self.make_link(&url, &url), end))
+            } else if c == '_'
+                && i > 0
+                && self.chars[i - 1].is_alphanumeric()
+                && self
+                    .chars
+                    .get(i + 1)
+                    .is_none_or(|c| c.is_whitespace() || ".,;:!?)".contains(*c))
+            {
+                // Reference to a hyperlink target by a single word, like "Python_"
+                let word_start = plain
+                    .char_indices()
+                    .rfind(|&(_, c)| !c.is_alphanumeric() && c != '-')
+                    .map_or(0, |(p, c)| p + c.len_utf8());
+                let word = plain[word_start..].to_string();
+                self.targets.get(&normalize_name(&word)).map(|url| {
+                    plain.truncate(word_start);
+                    (self.make_link(url, &word), i + 1)
+                })
+            } else {
+                None
+            };
+            match rendered {
+                Some((html, next)) => {
+                    out.push_str(&escape_html(&plain));
+                    plain.clear();
+                    out.push_str(&html);
+                    i = next;
+                }
+                None => {
+                    plain.push(c);
+                    i += 1;
+                }
+            }
+        }
+        out.push_str(&escape_html(&plain));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rst_titles_get_levels_by_appearance() {
+        let html = rst_to_html("Title\n=====\n\nSection\n-------\n\nOther\n=====\n");
+        assert_eq!(html, "<h1>Title</h1>\n<h2>Section</h2>\n<h1>Other</h1>\n");
+    }
+
+    #[test]
+    fn test_rst_inline_markup_and_links() {
+        let source = "Use **Rust** and ``cargo``, see `docs <https://doc.rust-lang.org>`_ or Python_.\n\n.. _python: https://python.org\n";
+        let html = rst_to_html(source);
+        assert_eq!(
+            html,
+            "<p>Use <strong>Rust</strong> and <code>cargo</code>, see <a href=\"https://doc.rust-lang.org\">docs</a> or <a href=\"https://python.org\">Python</a>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_rst_lists_and_admonition() {
+        let html = rst_to_html("- one\n- two\n\n.. note:: Take care.\n");
+        assert_eq!(
+            html,
+            "<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n<div class=\"admonition note\">\n<p class=\"admonition-title\">Note</p>\n<p>Take care.</p>\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn test_rst_list_item_continues_after_multibyte_bullet() {
+        let html = rst_to_html("• một\n  hai\n• ba\n");
+        assert_eq!(html, "<ul>\n<li>một\nhai</li>\n<li>ba</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn test_rst_simple_table() {
+        let html = rst_to_html("===  ===\nA    B\n===  ===\n1    2\n===  ===\n");
+        assert!(html.contains("<thead>\n<tr>\n<th>A</th>\n<th>B</th>"));
+        assert!(html.contains("<tbody>\n<tr>\n<td>1</td>\n<td>2</td>"));
+    }
+
+    #[test]
+    fn test_rst_code_block_is_marked_for_highlighting() {
+        let html = rst_to_html(".. code-block:: python\n   :linenos:\n\n   print(1)\n");
+        assert!(html.contains("language-python"));
+        assert!(html.contains("{lines: true, start_line: 1}"));
+        assert!(html.contains("print(1)\n</code></pre>"));
+    }
+
+    #[test]
+    fn test_rst_excerpt_does_not_cut_directive() {
+        let source = "Line 1\n\nLine 3\n\nLine 5\n\n.. code-block:: sh\n\n   ls\n   pwd\n\nAfter\n";
+        let excerpt = make_rst_excerpt(source);
+        assert!(excerpt.contains("pwd"));
+        assert!(!excerpt.contains("After"));
+        assert!(excerpt.ends_with("..."));
+    }
+}
@@ -0,0 +1,146 @@
+// Helpers for our full-text search.
+// Texts are folded to lowercase ASCII before being stored in search fields or compared,
+// so that "tieng viet" matches "tiếng Việt".
+
+use deunicode::deunicode_char;
+
+use super::{escape_html, split_search_query};
+
+// Avoid building huge queries from pasted paragraphs
+pub const MAX_SEARCH_TOKENS: usize = 8;
+const SNIPPET_LENGTH: usize = 200;
+// How many characters to show before the first match
+const SNIPPET_LEADING: usize = 60;
+
+/// Fold one character to lowercase ASCII and pass each output character to `f`.
+fn fold_char(c: char, mut f: impl FnMut(char)) {
+    if c.is_ascii() {
+        return f(c.to_ascii_lowercase());
+    }
+    match deunicode_char(c) {
+        Some(s) if !s.is_empty() => s.chars().for_each(|a| f(a.to_ascii_lowercase())),
+        _ => c.to_lowercase().for_each(f),
+    }
+}
+
+/// Lowercase the text and strip diacritics, e.g. "Tiếng Việt" -> "tieng viet".
+pub fn fold_text(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    s.chars().for_each(|c| fold_char(c, |a| folded.push(a)));
+    folded
+}
+
+/// Turn user's search query to folded tokens, which can be matched against the search fields.
+/// Punctuation at both ends of a word is dropped, so "Python," finds "python".
+pub fn make_search_tokens(query: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let folded = fold_text(query);
+    for word in split_search_query(Some(&folded)).unwrap_or_default() {
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+        if word.is_empty() || tokens.iter().any(|t| t == word) {
+            continue;
+        }
+        tokens.push(word.to_string());
+        if tokens.len() >= MAX_SEARCH_TOKENS {
+            break;
+        }
+    }
+    tokens
+}
+
+/// Cut a fragment of `text` around the first match and wrap the matched words with `<mark>`.
+/// The result is HTML-escaped, ready to be rendered as is.
+pub fn make_snippet(text: &str, tokens: &[String]) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    // Folding may change the length of a character (e.g. "ß" -> "ss"), so we remember
+    // which original character every folded byte comes from.
+    let mut folded = String::with_capacity(text.len());
+    let mut origins: Vec<(usize, usize)> = Vec::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        let span = (i, i + c.len_utf8());
+        fold_char(c, |a| {
+            folded.push(a);
+            origins.extend(std::iter::repeat_n(span, a.len_utf8()));
+        });
+    }
+    let mut matches: Vec<(usize, usize)> = tokens
+        .iter()
+        .filter(|t| !t.is_empty())
+        .flat_map(|t| {
+            folded
+                .match_indices(t.as_str())
+                .map(|(i, m)| (origins[i].0, origins[i + m.len() - 1].1))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    matches.sort_unstable();
+    // Choose the window, in unit of characters
+    let first_match_char = matches
+        .first()
+        .map(|&(start, _)| text[..start].chars().count())
+        .unwrap_or(0);
+    let skip = first_match_char.saturating_sub(SNIPPET_LEADING);
+    let start = text.char_indices().nth(skip).map_or(text.len(), |(i, _)| i);
+    let end = text
+        .char_indices()
+        .nth(skip + SNIPPET_LENGTH)
+        .map_or(text.len(), |(i, _)| i);
+    let mut output = String::new();
+    if start > 0 {
+        output.push('…');
+    }
+    let mut cursor = start;
+    for (m_start, m_end) in matches {
+        // Skip matches outside the window, or overlapping the previous one
+        if m_start < cursor || m_end > end {
+            continue;
+        }
+        output.push_str(&escape_html(&text[cursor..m_start]));
+        output.push_str("<mark>");
+        output.push_str(&escape_html(&text[m_start..m_end]));
+        output.push_str("</mark>");
+        cursor = m_end;
+    }
+    output.push_str(&escape_html(&text[cursor..end]));
+    if end < text.len() {
+        output.push('…');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold_text_vietnamese() {
+        assert_eq!(fold_text("Tiếng Việt"), "tieng viet");
+        assert_eq!(fold_text("Đặng Đức"), "dang duc");
+    }
+
+    #[test]
+    fn test_make_search_tokens() {
+        let tokens = make_search_tokens("  Tiếng VIỆT, tiếng  ");
+        assert_eq!(tokens, vec!["tieng", "viet"]);
+        assert!(make_search_tokens(" -- ").is_empty());
+    }
+
+    #[test]
+    fn test_make_snippet_highlights_folded_match() {
+        let tokens = make_search_tokens("tieng viet");
+        let snippet = make_snippet("Học tiếng Việt <dễ> lắm", &tokens);
+        assert_eq!(
+            snippet,
+            "Học <mark>tiếng</mark> <mark>Việt</mark> &lt;dễ&gt; lắm"
+        );
+    }
+
+    #[test]
+    fn test_make_snippet_cuts_around_match() {
+        let text = format!("{} rust {}", "a ".repeat(100), "b ".repeat(200));
+        let snippet = make_snippet(&text, &["rust".to_string()]);
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with('…'));
+        assert!(snippet.contains("<mark>rust</mark>"));
+    }
+}
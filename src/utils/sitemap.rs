@@ -0,0 +1,64 @@
+// Sitemap with alternate language links, as described in
+// https://developers.google.com/search/docs/specialty/international/localized-versions#sitemap
+
+use super::escape_html;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AlternateLink {
+    pub hreflang: String,
+    pub href: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SitemapUrl {
+    pub loc: String,
+    pub lastmod: Option<String>,
+    pub alternates: Vec<AlternateLink>,
+}
+
+impl SitemapUrl {
+    fn to_xml(&self) -> String {
+        let mut lines = vec![format!("<loc>{}</loc>", escape_html(&self.loc))];
+        if let Some(lastmod) = &self.lastmod {
+            lines.push(format!("<lastmod>{}</lastmod>", escape_html(lastmod)));
+        }
+        lines.extend(self.alternates.iter().map(|a| {
+            format!(
+                r#"<xhtml:link rel="alternate" hreflang="{}" href="{}"/>"#,
+                escape_html(&a.hreflang),
+                escape_html(&a.href)
+            )
+        }));
+        format!("<url>{}</url>", lines.join(""))
+    }
+}
+
+pub fn build_sitemap(urls: &[SitemapUrl]) -> String {
+    let body: String = urls.iter().map(SitemapUrl::to_xml).collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" xmlns:xhtml="http://www.w3.org/1999/xhtml">{body}</urlset>"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_sitemap_with_alternates() {
+        let url = SitemapUrl {
+            loc: "https://quan.hoabinh.vn/post/2024/05/hello".into(),
+            lastmod: Some("2024-05-20".into()),
+            alternates: vec![AlternateLink {
+                hreflang: "vi".into(),
+                href: "https://quan.hoabinh.vn/post/2024/05/xin-chao".into(),
+            }],
+        };
+        let xml = build_sitemap(&[url]);
+        assert!(xml.contains("<loc>https://quan.hoabinh.vn/post/2024/05/hello</loc>"));
+        assert!(xml.contains("<lastmod>2024-05-20</lastmod>"));
+        assert!(xml.contains(
+            r#"<xhtml:link rel="alternate" hreflang="vi" href="https://quan.hoabinh.vn/post/2024/05/xin-chao"/>"#
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+// Export of traces to an OpenTelemetry collector (Jaeger etc.), over OTLP/HTTP.
+// Exported spans are:
+// - The HTTP requests, named after their matched routes.
+// - The store functions, with their EdgeQL queries. Query arguments are not exported.
+// - The calls to Bunny API.
+// Log events are not exported, some of them carry query arguments.
+
+use std::sync::OnceLock;
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::field::Empty;
+use tracing::{Span, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::registry::LookupSpan;
+
+pub const SERVICE_NAME: &str = "quanweb";
+/// Standard variable of OpenTelemetry SDKs, read by the exporter itself
+pub const ENV_OTLP_ENDPOINT: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Make the tracing layer which exports spans over OTLP/HTTP.
+/// The endpoint is the full URL, like "http://localhost:4318/v1/traces".
+/// If it is not given, the exporter reads it from the "OTEL_EXPORTER_OTLP_*" variables.
+pub fn otlp_layer<S>(endpoint: Option<String>) -> Result<impl Layer<S>, ExporterBuildError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut builder = SpanExporter::builder().with_http();
+    if let Some(endpoint) = endpoint {
+        builder = builder.with_endpoint(endpoint);
+    }
+    let exporter = builder.build()?;
+    let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+    if TRACER_PROVIDER.set(provider).is_err() {
+        tracing::warn!("Tracer provider is already set");
+    }
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(filter_fn(|meta| {
+            meta.is_span()
+                && (meta.target().starts_with("quanweb") || meta.target().starts_with("tower_http"))
+        }));
+    Ok(layer)
+}
+
+/// Send the remaining spans to the collector. To be called before the program exits.
+pub fn shutdown_tracing() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to shut down tracer provider: {e}");
+        }
+    }
+}
+
+/// Span of an HTTP request, for `TraceLayer`. The route is filled by [`name_request_span`].
+pub fn make_request_span(request: &Request) -> Span {
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        otel.kind = "server",
+        otel.name = Empty,
+        http.route = Empty,
+    )
+}
+
+/// Middleware to name the request span after the matched route, like "GET /category/{category}/",
+/// so that the requests of the same page are grouped in the collector.
+/// It must be added with `route_layer`, for the route to be known.
+pub async fn name_request_span(request: Request, next: Next) -> Response {
+    if let Some(route) = request.extensions().get::<MatchedPath>() {
+        let span = Span::current();
+        let name = format!("{} {}", request.method(), route.as_str());
+        span.record("http.route", route.as_str());
+        span.record("otel.name", name.as_str());
+    }
+    next.run(request).await
+}
+
+/// Tracing layer which keeps the fields of all spans, for tests to check what would be exported
+#[cfg(test)]
+pub mod testing {
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::Subscriber;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::layer::Context;
+
+    #[derive(Debug, Clone, Default)]
+    pub struct SpanFields(Arc<Mutex<HashMap<String, String>>>);
+
+    impl SpanFields {
+        pub fn get(&self, name: &str) -> Option<String> {
+            self.0.lock().unwrap().get(name).cloned()
+        }
+
+        pub fn values(&self) -> Vec<String> {
+            self.0.lock().unwrap().values().cloned().collect()
+        }
+    }
+
+    struct Visitor<'a>(&'a mut HashMap<String, String>);
+
+    impl Visit for Visitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for SpanFields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            attrs.record(&mut Visitor(&mut self.0.lock().unwrap()));
+        }
+
+        fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut Visitor(&mut self.0.lock().unwrap()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::middleware;
+    use axum::routing::get;
+    use tokio::net::TcpListener;
+    use tower_http::trace::TraceLayer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::testing::SpanFields;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_span_is_named_after_route() {
+        let fields = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        // The test runtime has one thread, so the server runs under this subscriber, too
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let app = Router::new()
+            .route("/category/{category}/", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn(name_request_span))
+            .layer(TraceLayer::new_for_http().make_span_with(make_request_span));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let resp = reqwest::get(format!("http://{addr}/category/rust/"))
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(
+            fields.get("http.route").as_deref(),
+            Some("/category/{category}/")
+        );
+        assert_eq!(
+            fields.get("otel.name").as_deref(),
+            Some("GET /category/{category}/")
+        );
+        assert_eq!(fields.get("uri").as_deref(), Some("/category/rust/"));
+    }
+}
@@ -0,0 +1,66 @@
+pub mod queue;
+pub mod scheduler;
+pub mod tasks;
+
+#[cfg(test)]
+mod tests;
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+pub use queue::JobQueue;
+pub use tasks::{Task, TaskContext};
+
+// How long to block on Redis waiting for a job, before checking the delayed jobs again
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Main loop of the worker. It returns when `shutdown` receives `true`.
+/// The job being run is always finished before we stop.
+pub async fn process_jobs(queue: JobQueue, ctx: TaskContext, mut shutdown: watch::Receiver<bool>) {
+    match queue.requeue_orphans().await {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Requeued {} jobs left from previous run", n),
+        Err(e) => tracing::error!("Failed to requeue orphan jobs: {}", e),
+    }
+    while !*shutdown.borrow() {
+        match queue.promote_due_jobs().await {
+            Ok(0) => {}
+            Ok(n) => tracing::debug!("{} delayed jobs are ready to retry", n),
+            Err(e) => tracing::error!("Failed to promote delayed jobs: {}", e),
+        }
+        let fetched = tokio::select! {
+            _ = shutdown.changed() => break,
+            r = queue.fetch(POLL_TIMEOUT) => r,
+        };
+        let (raw, job) = match fetched {
+            Ok(Some(j)) => j,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::error!("Failed to fetch job: {}", e);
+                tokio::time::sleep(POLL_TIMEOUT).await;
+                continue;
+            }
+        };
+        tracing::info!(
+            "Running job #{} (attempt {}): {:?}",
+            job.id,
+            job.attempts + 1,
+            job.task
+        );
+        let result = match tasks::execute(&job.task, &ctx).await {
+            Ok(()) => {
+                tracing::info!("Job #{} done", job.id);
+                queue.ack(&raw).await
+            }
+            Err(e) => {
+                tracing::error!("Job #{} failed: {}", job.id, e);
+                queue.fail(&raw, job, e.to_string()).await
+            }
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to update job state: {}", e);
+        }
+    }
+    tracing::info!("Worker stopped");
+}
@@ -0,0 +1,167 @@
+// A small reliable job queue on top of Redis lists.
+// - New jobs are pushed to the "queue" list.
+// - The worker atomically moves a job from "queue" to "processing" (BRPOPLPUSH), so that a job
+//   is not lost if the worker dies while running it. Orphans are moved back on next start.
+// - Failed jobs are parked in the "delayed" sorted set, scored by the time they may run again.
+// - Jobs which exhausted their attempts go to the "dead" list, for manual inspection.
+
+use std::time::Duration;
+
+use axum::extract::FromRef;
+use chrono::Utc;
+use fred::error::{Error as FredError, ErrorKind};
+use fred::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::tasks::Task;
+use crate::types::AppState;
+
+pub const KEY_QUEUE: &str = "quanweb:jobs:queue";
+pub const KEY_PROCESSING: &str = "quanweb:jobs:processing";
+pub const KEY_DELAYED: &str = "quanweb:jobs:delayed";
+pub const KEY_DEAD: &str = "quanweb:jobs:dead";
+const KEY_SEQUENCE: &str = "quanweb:jobs:seq";
+
+pub const MAX_ATTEMPTS: u32 = 5;
+const BACKOFF_BASE: Duration = Duration::from_secs(10);
+const BACKOFF_MAX: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub task: Task,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// Delay before retrying a job which has failed `attempts` times: 10s, 20s, 40s... capped at 1 hour.
+pub fn backoff_delay(attempts: u32) -> Duration {
+    let exp = attempts.saturating_sub(1).min(16);
+    BACKOFF_BASE.saturating_mul(1 << exp).min(BACKOFF_MAX)
+}
+
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    pool: Pool,
+}
+
+impl FromRef<AppState> for JobQueue {
+    fn from_ref(state: &AppState) -> Self {
+        Self::new(state.redis.clone())
+    }
+}
+
+impl JobQueue {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn enqueue(&self, task: Task) -> Result<u64, FredError> {
+        let id: u64 = self.pool.incr(KEY_SEQUENCE).await?;
+        let job = Job {
+            id,
+            task,
+            attempts: 0,
+            last_error: None,
+        };
+        let raw = serde_json::to_string(&job)
+            .map_err(|e| FredError::new(ErrorKind::Parse, e.to_string()))?;
+        let _len: i64 = self.pool.lpush(KEY_QUEUE, raw).await?;
+        tracing::debug!("Enqueued job #{}: {:?}", job.id, job.task);
+        Ok(id)
+    }
+
+    /// Enqueue a job from a request handler. Failing to enqueue must not fail the request,
+    /// so we only log the error.
+    pub async fn enqueue_or_warn(&self, task: Task) {
+        if let Err(e) = self.enqueue(task.clone()).await {
+            tracing::warn!("Failed to enqueue {:?}: {}", task, e);
+        }
+    }
+
+    /// Wait up to `timeout` for a job. The raw payload is returned together with the parsed job,
+    /// because we need it to remove the job from the "processing" list later.
+    pub async fn fetch(&self, timeout: Duration) -> Result<Option<(String, Job)>, FredError> {
+        let raw: Option<String> = self
+            .pool
+            .brpoplpush(KEY_QUEUE, KEY_PROCESSING, timeout.as_secs_f64())
+            .await?;
+        let Some(raw) = raw else {
+            return Ok(None);
+        };
+        match serde_json::from_str::<Job>(&raw) {
+            Ok(job) => Ok(Some((raw, job))),
+            Err(e) => {
+                tracing::error!("Malformed job payload {}: {}. Moving to dead list.", raw, e);
+                self.bury_raw(&raw).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Mark a job as done.
+    pub async fn ack(&self, raw: &str) -> Result<(), FredError> {
+        let _removed: i64 = self.pool.lrem(KEY_PROCESSING, 1, raw).await?;
+        Ok(())
+    }
+
+    /// Schedule a failed job for retry, or move it to the dead list if it has no attempts left.
+    pub async fn fail(&self, raw: &str, mut job: Job, error: String) -> Result<(), FredError> {
+        job.attempts += 1;
+        job.last_error = Some(error);
+        let updated = serde_json::to_string(&job)
+            .map_err(|e| FredError::new(ErrorKind::Parse, e.to_string()))?;
+        if job.attempts >= MAX_ATTEMPTS {
+            tracing::error!("Job #{} failed {} times. Giving up.", job.id, job.attempts);
+            let _len: i64 = self.pool.lpush(KEY_DEAD, updated).await?;
+        } else {
+            let delay = backoff_delay(job.attempts);
+            tracing::warn!("Job #{} failed, to retry in {:?}", job.id, delay);
+            let ready_at = Utc::now().timestamp() as f64 + delay.as_secs_f64();
+            let _added: i64 = self
+                .pool
+                .zadd(KEY_DELAYED, None, None, false, false, (ready_at, updated))
+                .await?;
+        }
+        self.ack(raw).await
+    }
+
+    /// Move the delayed jobs whose time has come back to the queue.
+    pub async fn promote_due_jobs(&self) -> Result<usize, FredError> {
+        let now = Utc::now().timestamp() as f64;
+        let due: Vec<String> = self
+            .pool
+            .zrangebyscore(KEY_DELAYED, "-inf", now, false, None)
+            .await?;
+        let mut count = 0;
+        for raw in due {
+            // Only the one who removes the job from the set can push it, in case there are many workers.
+            let removed: i64 = self.pool.zrem(KEY_DELAYED, raw.as_str()).await?;
+            if removed > 0 {
+                let _len: i64 = self.pool.lpush(KEY_QUEUE, raw).await?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Give back to the queue the jobs which were being processed when the worker stopped abruptly.
+    pub async fn requeue_orphans(&self) -> Result<usize, FredError> {
+        let mut count = 0;
+        loop {
+            let moved: Option<String> = self.pool.rpoplpush(KEY_PROCESSING, KEY_QUEUE).await?;
+            if moved.is_none() {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    async fn bury_raw(&self, raw: &str) -> Result<(), FredError> {
+        let _len: i64 = self.pool.lpush(KEY_DEAD, raw).await?;
+        self.ack(raw).await
+    }
+}
@@ -0,0 +1,75 @@
+// Scheduled posts go live by themselves, because the public queries compare `published_at` with
+// the current time. But what is derived from the list of published posts (feeds cached on CDN...)
+// needs refreshing at that moment, so this ticker looks for the posts which just went live.
+// The time of the last check is kept in Redis, so that posts which go live while the worker
+// is down are not missed.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use fred::prelude::*;
+use gel_protocol::model::Datetime as EDatetime;
+use gel_tokio::Client as EdgeClient;
+use tokio::sync::watch;
+
+use super::queue::JobQueue;
+use super::tasks::{Task, TaskError};
+use crate::stores;
+
+const KEY_LAST_CHECK: &str = "quanweb:scheduler:last_check";
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Run the ticker until `shutdown` receives `true`.
+pub async fn run_scheduler(
+    redis: Pool,
+    queue: JobQueue,
+    db: EdgeClient,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    while !*shutdown.borrow() {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = interval.tick() => {},
+        }
+        match enqueue_jobs_for_scheduled_posts(&redis, &queue, &db).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("{} scheduled posts went live", n),
+            Err(e) => tracing::error!("Failed to check scheduled posts: {}", e),
+        }
+    }
+    tracing::info!("Scheduler stopped");
+}
+
+/// Find the posts which went live since the last check, and enqueue the jobs to refresh their HTML and the feeds.
+pub async fn enqueue_jobs_for_scheduled_posts(
+    redis: &Pool,
+    queue: &JobQueue,
+    db: &EdgeClient,
+) -> Result<usize, TaskError> {
+    let now = Utc::now();
+    let last_check: Option<i64> = redis.get(KEY_LAST_CHECK).await?;
+    // On the very first run, there is nothing to catch up
+    let since = last_check
+        .and_then(|ts| DateTime::<Utc>::from_timestamp(ts, 0))
+        .unwrap_or(now);
+    let posts = match (EDatetime::try_from(since), EDatetime::try_from(now)) {
+        (Ok(since), Ok(until)) if last_check.is_some() => {
+            stores::blog::get_scheduled_posts_gone_live(since, until, db).await?
+        }
+        _ => vec![],
+    };
+    for post in &posts {
+        tracing::info!("Post '{}' ({}) is now live", post.title, post.id);
+        queue
+            .enqueue_or_warn(Task::RenderPostHtml { post_id: post.id })
+            .await;
+    }
+    if !posts.is_empty() {
+        queue.enqueue_or_warn(Task::RegenerateFeeds).await;
+    }
+    let _: () = redis
+        .set(KEY_LAST_CHECK, now.timestamp(), None, None, false)
+        .await?;
+    Ok(posts.len())
+}
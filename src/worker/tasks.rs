@@ -0,0 +1,161 @@
+use gel_tokio::Client as EdgeClient;
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::storage::StorageBackend;
+use crate::storage::bunny::send_request;
+use crate::stores;
+use crate::utils::images::{self, VariantError, VariantRegistry};
+use crate::utils::page_cache::{CacheGroup, PageCache};
+
+const BUNNY_PURGE_URL: &str = "https://api.bunny.net/purge";
+// Public URLs which are derived from the list of published posts
+const FEED_PATHS: [&str; 4] = ["/feeds.atom", "/feeds.json", "/sitemap.xml", "/llms.txt"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Task {
+    /// Re-render the `html` field of a post from its body
+    RenderPostHtml { post_id: Uuid },
+    /// Refresh feeds and sitemaps after the list of published posts changed
+    RegenerateFeeds,
+    /// Purge files from Bunny CDN cache. The paths are relative to the CDN host.
+    PurgeCdnFiles { paths: Vec<String> },
+    /// Rebuild full-text search fields of posts under a category, or all posts if no category is given
+    RefreshSearchIndex { category_id: Option<Uuid> },
+    /// Make resized and WebP variants of an uploaded image. The path is relative to the storage root.
+    GenerateImageVariants { path: String },
+}
+
+#[derive(Debug, Error)]
+pub enum TaskError {
+    #[error(transparent)]
+    GelQueryError(#[from] gel_errors::Error),
+    #[error(transparent)]
+    Redis(#[from] fred::error::Error),
+    #[error("Bunny API error: {0}")]
+    Bunny(#[from] reqwest::Error),
+    #[error("{0} not found")]
+    ObjectNotFound(String),
+    #[error("Failed to make image variants: {0}")]
+    ImageVariants(#[from] VariantError),
+}
+
+/// Things the tasks need to do their job
+#[derive(Debug, Clone)]
+pub struct TaskContext {
+    pub db: EdgeClient,
+    pub http: HttpClient,
+    pub bunny_cdn_host: String,
+    pub bunny_account_api_key: String,
+    /// Public URL of the site, without trailing slash
+    pub site_url: String,
+    pub storage: StorageBackend,
+    pub page_cache: PageCache,
+    pub image_variants: VariantRegistry,
+}
+
+pub async fn execute(task: &Task, ctx: &TaskContext) -> Result<(), TaskError> {
+    match task {
+        Task::RenderPostHtml { post_id } => render_post_html(*post_id, ctx).await,
+        Task::RegenerateFeeds => regenerate_feeds(ctx).await,
+        Task::PurgeCdnFiles { paths } => {
+            let urls: Vec<String> = paths
+                .iter()
+                .map(|p| {
+                    format!(
+                        "https://{}/{}",
+                        ctx.bunny_cdn_host,
+                        p.trim_start_matches('/')
+                    )
+                })
+                .collect();
+            purge_urls(&urls, ctx).await
+        }
+        Task::RefreshSearchIndex { category_id } => refresh_search_index(*category_id, ctx).await,
+        Task::GenerateImageVariants { path } => {
+            let count = images::store_variants(path, &ctx.storage).await?;
+            tracing::info!("Generated {} variants for {}", count, path);
+            // Pages start using the variants when their cache expires
+            ctx.image_variants.record(path).await?;
+            Ok(())
+        }
+    }
+}
+
+async fn render_post_html(post_id: Uuid, ctx: &TaskContext) -> Result<(), TaskError> {
+    let post = stores::blog::get_post_for_regeneration(post_id, &ctx.db)
+        .await?
+        .ok_or(TaskError::ObjectNotFound(format!("BlogPost {post_id}")))?;
+    let body = post.body.as_deref().unwrap_or_default();
+    let html = post.format.to_html(body);
+    let excerpt = post.format.make_excerpt(body);
+    stores::blog::update_post_html(&ctx.db, post.id, &html, &excerpt).await?;
+    tracing::info!("Regenerated HTML for post '{}' ({})", post.title, post.id);
+    ctx.page_cache
+        .invalidate(&[CacheGroup::Post(post.id)])
+        .await?;
+    Ok(())
+}
+
+async fn refresh_search_index(
+    category_id: Option<Uuid>,
+    ctx: &TaskContext,
+) -> Result<(), TaskError> {
+    let posts = stores::blog::get_posts_for_search_index(category_id, &ctx.db).await?;
+    for post in &posts {
+        stores::blog::update_post_search_fields(&ctx.db, post).await?;
+    }
+    tracing::info!("Refreshed search index of {} posts", posts.len());
+    Ok(())
+}
+
+async fn regenerate_feeds(ctx: &TaskContext) -> Result<(), TaskError> {
+    // Scheduled posts are published without going through the API, so we drop our own copies, too.
+    ctx.page_cache
+        .invalidate(&[CacheGroup::PostLists, CacheGroup::Feeds])
+        .await?;
+    // Feeds and sitemaps are generated on request, we only need to drop the stale copies from CDN.
+    let site_url = &ctx.site_url;
+    let mut urls: Vec<String> = FEED_PATHS
+        .iter()
+        .map(|p| format!("{site_url}{p}"))
+        .collect();
+    let categories = stores::blog::get_blog_categories(None, None, false, &ctx.db).await?;
+    for cat in categories {
+        let cat_url = format!("{site_url}/category/{}", cat.slug);
+        urls.push(format!("{cat_url}/feeds.atom"));
+        urls.push(format!("{cat_url}/feeds.json"));
+    }
+    let tags = stores::blog::get_tags(&ctx.db).await?;
+    for tag in tags {
+        let tag_url = format!("{site_url}/tag/{}", tag.slug);
+        urls.push(format!("{tag_url}/feeds.atom"));
+        urls.push(format!("{tag_url}/feeds.json"));
+    }
+    purge_urls(&urls, ctx).await
+}
+
+async fn purge_urls(urls: &[String], ctx: &TaskContext) -> Result<(), TaskError> {
+    if ctx.bunny_account_api_key.is_empty() {
+        tracing::warn!(
+            "Bunny account API key is not configured. Skip purging {:?}",
+            urls
+        );
+        return Ok(());
+    }
+    for url in urls {
+        tracing::debug!("To purge {} from Bunny CDN", url);
+        let request = ctx
+            .http
+            .post(BUNNY_PURGE_URL)
+            .query(&[("url", url.as_str())])
+            .header("AccessKey", &ctx.bunny_account_api_key);
+        send_request("purge", BUNNY_PURGE_URL, request)
+            .await?
+            .error_for_status()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use fred::prelude::*;
+
+use super::queue::{
+    Job, JobQueue, KEY_DEAD, KEY_DELAYED, KEY_PROCESSING, KEY_QUEUE, MAX_ATTEMPTS, backoff_delay,
+};
+use super::tasks::Task;
+
+// A database other than the default one, not to touch the jobs of a running worker
+const TEST_REDIS_DB: u8 = 15;
+
+#[test]
+fn job_survives_serialization() {
+    let job = Job {
+        id: 7,
+        task: Task::PurgeCdnFiles {
+            paths: vec!["blogs/2026/photo.jpg".into()],
+        },
+        attempts: 2,
+        last_error: None,
+    };
+    let raw = serde_json::to_string(&job).unwrap();
+    assert!(raw.contains(r#""kind":"purge_cdn_files""#));
+    let parsed: Job = serde_json::from_str(&raw).unwrap();
+    assert_eq!(parsed, job);
+}
+
+#[test]
+fn image_variants_task_keeps_path() {
+    let task = Task::GenerateImageVariants {
+        path: "blogs/2026/photo.jpg".into(),
+    };
+    let raw = serde_json::to_string(&task).unwrap();
+    assert_eq!(
+        raw,
+        r#"{"kind":"generate_image_variants","path":"blogs/2026/photo.jpg"}"#
+    );
+}
+
+#[test]
+fn backoff_grows_exponentially_then_caps() {
+    assert_eq!(backoff_delay(1), Duration::from_secs(10));
+    assert_eq!(backoff_delay(2), Duration::from_secs(20));
+    assert_eq!(backoff_delay(4), Duration::from_secs(80));
+    assert_eq!(backoff_delay(30), Duration::from_secs(3600));
+}
+
+async fn make_test_queue() -> (JobQueue, Pool) {
+    let config = fred::types::config::Config {
+        database: Some(TEST_REDIS_DB),
+        ..Default::default()
+    };
+    let pool = Pool::new(config, None, None, None, 1).unwrap();
+    let _conn = pool.connect();
+    pool.wait_for_connect().await.unwrap();
+    let _: i64 = pool
+        .del(vec![KEY_QUEUE, KEY_PROCESSING, KEY_DELAYED, KEY_DEAD])
+        .await
+        .unwrap();
+    (JobQueue::new(pool.clone()), pool)
+}
+
+// One test for the whole life of jobs, because they share the same Redis keys
+#[tokio::test]
+#[ignore = "needs a Redis server"]
+async fn failed_jobs_are_retried_then_buried() {
+    let (queue, pool) = make_test_queue().await;
+    let timeout = Duration::from_secs(1);
+    let id = queue.enqueue(Task::RegenerateFeeds).await.unwrap();
+    let (raw, job) = queue.fetch(timeout).await.unwrap().unwrap();
+    assert_eq!(job.id, id);
+    assert_eq!(job.task, Task::RegenerateFeeds);
+    assert_eq!(job.attempts, 0);
+    // The job being run is kept until it is done, and given back if the worker dies
+    let processing: i64 = pool.llen(KEY_PROCESSING).await.unwrap();
+    assert_eq!(processing, 1);
+    assert_eq!(queue.requeue_orphans().await.unwrap(), 1);
+    let (raw, job) = queue.fetch(timeout).await.unwrap().unwrap();
+    assert_eq!(job.id, id);
+
+    queue.fail(&raw, job, "Bunny is down".into()).await.unwrap();
+    let processing: i64 = pool.llen(KEY_PROCESSING).await.unwrap();
+    assert_eq!(processing, 0);
+    // It is delayed, not to be run again right away
+    let delayed: Vec<String> = pool
+        .zrangebyscore(KEY_DELAYED, "-inf", "+inf", false, None)
+        .await
+        .unwrap();
+    assert_eq!(delayed.len(), 1);
+    let ready_at: f64 = pool.zscore(KEY_DELAYED, &delayed[0]).await.unwrap();
+    let wait = ready_at - Utc::now().timestamp() as f64;
+    assert!(wait > 0.0 && wait <= backoff_delay(1).as_secs_f64());
+    assert_eq!(queue.promote_due_jobs().await.unwrap(), 0);
+    assert!(queue.fetch(timeout).await.unwrap().is_none());
+    // Make it due
+    let _: i64 = pool
+        .zadd(
+            KEY_DELAYED,
+            None,
+            None,
+            false,
+            false,
+            (0.0, delayed[0].as_str()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(queue.promote_due_jobs().await.unwrap(), 1);
+    let (raw, mut job) = queue.fetch(timeout).await.unwrap().unwrap();
+    assert_eq!(job.id, id);
+    assert_eq!(job.attempts, 1);
+    assert_eq!(job.last_error.as_deref(), Some("Bunny is down"));
+
+    // Out of attempts
+    job.attempts = MAX_ATTEMPTS - 1;
+    queue.fail(&raw, job, "Still down".into()).await.unwrap();
+    let delayed: i64 = pool.zcard(KEY_DELAYED).await.unwrap();
+    assert_eq!(delayed, 0);
+    let dead: Vec<String> = pool.lrange(KEY_DEAD, 0, -1).await.unwrap();
+    assert_eq!(dead.len(), 1);
+    let job: Job = serde_json::from_str(&dead[0]).unwrap();
+    assert_eq!(job.id, id);
+    assert_eq!(job.attempts, MAX_ATTEMPTS);
+    assert_eq!(job.last_error.as_deref(), Some("Still down"));
+}